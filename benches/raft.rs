@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use mynode::bench::{Entry, KVMemory, Log};
+
+fn entry(term: u64) -> Entry {
+    Entry {
+        term,
+        command: Some(vec![0x01; 64]),
+    }
+}
+
+fn append(c: &mut Criterion) {
+    c.bench_function("log/append", |b| {
+        let mut log = Log::new(KVMemory::new()).unwrap();
+        b.iter(|| log.append(entry(1)).unwrap())
+    });
+}
+
+fn splice(c: &mut Criterion) {
+    // Splices 100 entries from a new term over the tail of a 1000 entry log
+    c.bench_function("log/splice", |b| {
+        b.iter_batched(
+            || {
+                let mut log = Log::new(KVMemory::new()).unwrap();
+                for _ in 0..1000 {
+                    log.append(entry(1)).unwrap();
+                }
+                log
+            },
+            |mut log| {
+                log.splice(900, 1, (0..100).map(|_| entry(2)).collect())
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn get(c: &mut Criterion) {
+    let mut log = Log::new(KVMemory::new()).unwrap();
+    for _ in 0..1000 {
+        log.append(entry(1)).unwrap();
+    }
+    c.bench_function("log/get", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = i % 1000 + 1;
+            log.get(i).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, append, splice, get);
+criterion_main!(benches);
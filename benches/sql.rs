@@ -0,0 +1,70 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mynode::bench::{Embedded, Expression, Parser, Value};
+
+fn parse(c: &mut Criterion) {
+    let queries = &[
+        (
+            "select",
+            "SELECT 1 + 2 * 3 AS a, 'foo' AS b, NOT TRUE FROM movie",
+        ),
+        (
+            "create_table",
+            "CREATE TABLE name (id INTEGER PRIMARY KEY, string VARCHAR NOT NULL, \
+             number INTEGER, decimal FLOAT, bool BOOLEAN NULL)",
+        ),
+        (
+            "insert",
+            "INSERT INTO name VALUES (1, 'a', 2, 3.14, TRUE), (2, 'b', NULL, 2.72, FALSE)",
+        ),
+    ];
+    for (name, query) in queries {
+        c.bench_function(&format!("parse/{}", name), |b| {
+            b.iter(|| Parser::new(query).parse().unwrap())
+        });
+    }
+}
+
+fn evaluate(c: &mut Criterion) {
+    let constant = |i: i64| Box::new(Expression::Constant(Value::Integer(i)));
+    // (1 + 2) * 3 - 4! / 5 % 6
+    let expr = Expression::Subtract(
+        Box::new(Expression::Multiply(
+            Box::new(Expression::Add(constant(1), constant(2))),
+            constant(3),
+        )),
+        Box::new(Expression::Modulo(
+            Box::new(Expression::Divide(
+                Box::new(Expression::Factorial(constant(4))),
+                constant(5),
+            )),
+            constant(6),
+        )),
+    );
+    c.bench_function("expression/evaluate", |b| {
+        b.iter(|| expr.evaluate().unwrap())
+    });
+}
+
+fn insert(c: &mut Criterion) {
+    let engine = Embedded::memory();
+    engine
+        .query("CREATE TABLE name (id INTEGER PRIMARY KEY, string VARCHAR NOT NULL)")
+        .unwrap()
+        .count();
+    let mut id = 0;
+    c.bench_function("insert/100", |b| {
+        b.iter(|| {
+            let values = (0..100)
+                .map(|_| {
+                    id += 1;
+                    format!("({}, 'value')", id)
+                })
+                .collect::<Vec<_>>();
+            let query = format!("INSERT INTO name VALUES {}", values.join(", "));
+            engine.query(&query).unwrap().count()
+        })
+    });
+}
+
+criterion_group!(benches, parse, evaluate, insert);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mynode::bench::{File, KVMemory, Store};
+
+/// Benchmarks sets, gets and prefix scans against a store
+fn bench_store<S: Store>(c: &mut Criterion, name: &str, mut new: impl FnMut() -> S) {
+    let value = vec![0x01; 128];
+    c.bench_function(&format!("{}/set", name), |b| {
+        let mut s = new();
+        let mut i = 0;
+        b.iter(|| {
+            i += 1;
+            s.set(&format!("key{:08}", i), value.clone()).unwrap()
+        })
+    });
+
+    let mut s = new();
+    for i in 0..1000 {
+        s.set(&format!("key{:08}", i), value.clone()).unwrap();
+    }
+    c.bench_function(&format!("{}/get", name), |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % 1000;
+            s.get(&format!("key{:08}", i)).unwrap()
+        })
+    });
+    c.bench_function(&format!("{}/iter_prefix", name), |b| {
+        b.iter(|| s.iter_prefix("key").count())
+    });
+}
+
+fn kvmemory(c: &mut Criterion) {
+    bench_store(c, "kvmemory", KVMemory::new);
+}
+
+fn file(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut n = 0;
+    bench_store(c, "file", || {
+        n += 1;
+        File::open(dir.path().join(format!("store{}", n))).unwrap()
+    });
+    let path = dir.path().join("open");
+    let mut s = File::open(&path).unwrap();
+    for i in 0..1000 {
+        s.set(&format!("key{:08}", i), vec![0x01; 128]).unwrap();
+    }
+    s.compact().unwrap();
+    drop(s);
+    c.bench_function("file/open", |b| b.iter(|| File::open(&path).unwrap()));
+}
+
+criterion_group!(benches, kvmemory, file);
+criterion_main!(benches);
@@ -1,5 +1,7 @@
+#[cfg(feature = "server")]
 extern crate protoc_rust_grpc;
 
+#[cfg(feature = "server")]
 fn main() {
     let protobuf_sources = &[
         "protobuf/common.proto",
@@ -21,4 +23,31 @@ fn main() {
     for src in protobuf_sources {
         println!("cargo:rerun-if-changed={}", src);
     }
+
+    // Build info, reported by the Status RPC
+    let git_commit = std::process::Command::new("git")
+        .args(vec!["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    let build_time = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch,
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            .to_string(),
+    };
+    println!("cargo:rustc-env=MYNODE_GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=MYNODE_BUILD_TIME={}", build_time);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
 }
+
+// The Protobuf shims are only used by the server.
+#[cfg(not(feature = "server"))]
+fn main() {}
@@ -0,0 +1,95 @@
+//! A minimal replicated counter, showing how to embed the Raft consensus core
+//! with a custom state machine. It runs a single-node cluster with an
+//! in-memory Raft log, so no transport to peers is needed.
+
+use crossbeam_channel::{Receiver, Sender};
+use mynode::raft::{EventBus, Message, Raft, State, Transport};
+use mynode::{Error, KVMemory};
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A counter state machine. Mutations add a big-endian u64 to the counter
+/// and return the new value, while reads return the current value.
+#[derive(Debug, Default)]
+struct Counter {
+    value: u64,
+}
+
+impl State for Counter {
+    fn read(&self, _: Vec<u8>) -> Result<Vec<u8>, Error> {
+        Ok(self.value.to_be_bytes().to_vec())
+    }
+
+    fn mutate(&mut self, command: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let bytes = command
+            .try_into()
+            .map_err(|_| Error::Value("Expected 8-byte increment".into()))?;
+        self.value += u64::from_be_bytes(bytes);
+        Ok(self.value.to_be_bytes().to_vec())
+    }
+}
+
+/// A transport for a single-node cluster, which has no peers to talk to.
+/// Multi-node clusters need a network transport, like the gRPC transport used
+/// by mynode.
+struct Local {
+    rx: Receiver<Message>,
+    _tx: Sender<Message>,
+}
+
+impl Transport for Local {
+    fn receiver(&self) -> Receiver<Message> {
+        self.rx.clone()
+    }
+
+    fn send(&self, msg: Message) -> Result<(), Error> {
+        Err(Error::Network(format!("No peer {:?}", msg.to)))
+    }
+}
+
+/// Decodes a counter value
+fn decode(bytes: Vec<u8>) -> Result<u64, Error> {
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| Error::Value("Expected 8-byte value".into()))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn main() -> Result<(), Error> {
+    // Print the counter as entries are applied, via a post-apply hook. Noop
+    // entries have no output, and are skipped.
+    let events = Arc::new(EventBus::new());
+    events.hooks().register(
+        "print",
+        Box::new(|_, result| {
+            if let Ok(Ok(value)) = result.output.clone().map(decode) {
+                println!("Applied entry {}, counter is {}", result.index, value);
+            }
+        }),
+    )?;
+
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let raft = Raft::start(
+        "counter",
+        vec![],
+        Counter::default(),
+        KVMemory::new(),
+        Local { rx, _tx: tx },
+        events,
+    )?;
+
+    // Wait for the node to elect itself leader
+    let mut value = raft.mutate(1u64.to_be_bytes().to_vec());
+    while value.is_err() {
+        std::thread::sleep(Duration::from_millis(100));
+        value = raft.mutate(1u64.to_be_bytes().to_vec());
+    }
+    println!("Incremented counter to {}", decode(value?)?);
+    println!(
+        "Incremented counter to {}",
+        decode(raft.mutate(2u64.to_be_bytes().to_vec())?)?
+    );
+    println!("Counter is {}", decode(raft.read(vec![])?)?);
+    Ok(())
+}
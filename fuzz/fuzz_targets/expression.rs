@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mynode::Embedded;
+
+// Plans and evaluates arbitrary input as SELECT expressions on an in-memory
+// engine, which must error rather than panic, e.g. on overflow.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = std::str::from_utf8(data) {
+        let engine = Embedded::memory();
+        if let Ok(rows) = engine.query(&format!("SELECT {}", input)) {
+            for _ in rows {}
+        }
+    }
+});
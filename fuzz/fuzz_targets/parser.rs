@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mynode::bench::Parser;
+
+// Parses arbitrary input as an SQL statement, which must error rather than panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(query) = std::str::from_utf8(data) {
+        let _ = Parser::new(query).parse();
+    }
+});
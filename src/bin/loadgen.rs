@@ -0,0 +1,205 @@
+#[macro_use]
+extern crate clap;
+extern crate mynode;
+extern crate rand;
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), mynode::Error> {
+    let opts = app_from_crate!()
+        .about("Generates query load against a running cluster")
+        .arg(
+            clap::Arg::with_name("nodes")
+                .short("n")
+                .long("nodes")
+                .help("Comma-separated host:port addresses of nodes to connect to")
+                .takes_value(true)
+                .default_value("127.0.0.1:9605"),
+        )
+        .arg(
+            clap::Arg::with_name("clients")
+                .short("c")
+                .long("clients")
+                .help("Number of concurrent clients, spread across nodes")
+                .takes_value(true)
+                .default_value("8"),
+        )
+        .arg(
+            clap::Arg::with_name("duration")
+                .short("d")
+                .long("duration")
+                .help("Duration of the run in seconds")
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .arg(
+            clap::Arg::with_name("reads")
+                .short("r")
+                .long("reads")
+                .help("Fraction of queries which are reads, between 0 and 1")
+                .takes_value(true)
+                .default_value("0.9"),
+        )
+        .arg(
+            clap::Arg::with_name("table")
+                .short("t")
+                .long("table")
+                .help("Table to write to, created if missing")
+                .takes_value(true)
+                .default_value("loadgen"),
+        )
+        .arg(
+            clap::Arg::with_name("read-query")
+                .long("read-query")
+                .help("Query to run for reads, defaults to a scan of the table")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let nodes = opts
+        .value_of("nodes")
+        .unwrap()
+        .split(',')
+        .map(parse_address)
+        .collect::<Result<Vec<_>, _>>()?;
+    let clients: usize = opts.value_of("clients").unwrap().parse()?;
+    let duration = Duration::from_secs(opts.value_of("duration").unwrap().parse()?);
+    let reads: f64 = opts.value_of("reads").unwrap().parse()?;
+    if !(0.0..=1.0).contains(&reads) {
+        return Err(mynode::Error::Config(
+            "reads must be between 0 and 1".into(),
+        ));
+    }
+    let table = opts.value_of("table").unwrap().to_string();
+    let read_query = opts
+        .value_of("read-query")
+        .map(|q| q.to_string())
+        .unwrap_or_else(|| format!("SELECT * FROM {}", table));
+
+    let (host, port) = &nodes[0];
+    let client = mynode::Client::new(host, *port)?;
+    if !client.list_tables()?.contains(&table) {
+        run(
+            &client,
+            &format!(
+                "CREATE TABLE {} (id INTEGER PRIMARY KEY, value VARCHAR NOT NULL)",
+                table
+            ),
+        )?;
+    }
+
+    // Row IDs are allocated from the current time, to avoid collisions
+    // with previous runs.
+    let next_id = Arc::new(AtomicI64::new(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_micros() as i64)
+            .unwrap_or_default(),
+    ));
+    println!(
+        "Running {} clients against {} nodes for {}s with {:.0}% reads",
+        clients,
+        nodes.len(),
+        duration.as_secs(),
+        reads * 100.0
+    );
+    let start = Instant::now();
+    let workers = (0..clients)
+        .map(|i| {
+            let (host, port) = nodes[i % nodes.len()].clone();
+            let table = table.clone();
+            let read_query = read_query.clone();
+            let next_id = next_id.clone();
+            std::thread::spawn(move || -> Result<Stats, mynode::Error> {
+                let client = mynode::Client::new(&host, port)?;
+                let mut stats = Stats::default();
+                while start.elapsed() < duration {
+                    let (query, latencies) = if rand::random::<f64>() < reads {
+                        (read_query.clone(), &mut stats.reads)
+                    } else {
+                        let id = next_id.fetch_add(1, Ordering::SeqCst);
+                        (
+                            format!("INSERT INTO {} VALUES ({}, 'value {}')", table, id, id),
+                            &mut stats.writes,
+                        )
+                    };
+                    let query_start = Instant::now();
+                    match run(&client, &query) {
+                        Ok(()) => latencies.push(query_start.elapsed()),
+                        Err(_) => stats.errors += 1,
+                    }
+                }
+                Ok(stats)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut stats = Stats::default();
+    for worker in workers {
+        let s = worker.join().expect("Client thread panicked")?;
+        stats.reads.extend(s.reads);
+        stats.writes.extend(s.writes);
+        stats.errors += s.errors;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{:<8}{:>10}{:>12}{:>10}{:>10}{:>10}{:>10}",
+        "", "queries", "queries/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    report("reads", &mut stats.reads, elapsed);
+    report("writes", &mut stats.writes, elapsed);
+    println!("errors  {:>10}", stats.errors);
+    Ok(())
+}
+
+/// Query latencies and error counts of a client
+#[derive(Default)]
+struct Stats {
+    reads: Vec<Duration>,
+    writes: Vec<Duration>,
+    errors: u64,
+}
+
+/// Parses a host:port address
+fn parse_address(address: &str) -> Result<(String, u16), mynode::Error> {
+    let mut parts = address.trim().rsplitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(port), Some(host)) => Ok((host.to_string(), port.parse()?)),
+        _ => Err(mynode::Error::Config(format!(
+            "Invalid node address {}, expected host:port",
+            address
+        ))),
+    }
+}
+
+/// Runs a query and consumes its results
+fn run(client: &mynode::Client, query: &str) -> Result<(), mynode::Error> {
+    for row in client.query(query)? {
+        row?;
+    }
+    Ok(())
+}
+
+/// Prints throughput and latency percentiles for a set of queries
+fn report(name: &str, latencies: &mut [Duration], elapsed: f64) {
+    latencies.sort();
+    let percentile = |p: f64| -> f64 {
+        if latencies.is_empty() {
+            return 0.0;
+        }
+        let index = ((latencies.len() as f64 * p).ceil() as usize).max(1) - 1;
+        latencies[index].as_secs_f64() * 1000.0
+    };
+    println!(
+        "{:<8}{:>10}{:>12.1}{:>10.2}{:>10.2}{:>10.2}{:>10.2}",
+        name,
+        latencies.len(),
+        latencies.len() as f64 / elapsed,
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0)
+    );
+}
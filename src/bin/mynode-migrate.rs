@@ -0,0 +1,76 @@
+#[macro_use]
+extern crate clap;
+extern crate mynode;
+extern crate simplelog;
+
+fn main() -> Result<(), mynode::Error> {
+    let opts = app_from_crate!()
+        .about("Copies tables from one cluster to another")
+        .arg(
+            clap::Arg::with_name("from")
+                .long("from")
+                .help("Source node host:port")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("to")
+                .long("to")
+                .help("Target node host:port")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("table")
+                .long("table")
+                .help("Table to copy, can be given multiple times (default all)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("batch-size")
+                .long("batch-size")
+                .help("Number of rows per insert batch")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .arg(
+            clap::Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .help("Checkpoint file for resuming an interrupted migration")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    simplelog::SimpleLogger::init(
+        simplelog::LevelFilter::Info,
+        simplelog::ConfigBuilder::new()
+            .add_filter_allow_str("mynode")
+            .build(),
+    )?;
+
+    let from = connect(opts.value_of("from").unwrap())?;
+    let to = connect(opts.value_of("to").unwrap())?;
+    let mut migration = mynode::Migration::new(&from, &to)
+        .batch_size(opts.value_of("batch-size").unwrap().parse()?);
+    if let Some(path) = opts.value_of("checkpoint") {
+        migration = migration.checkpoint(std::path::Path::new(path));
+    }
+    let tables = match opts.values_of("table") {
+        Some(tables) => tables.map(|t| t.to_string()).collect(),
+        None => from.list_tables()?,
+    };
+    for table in tables {
+        migration.copy_table(&table)?;
+    }
+    Ok(())
+}
+
+/// Connects to a node at host:port
+fn connect(address: &str) -> Result<mynode::Client, mynode::Error> {
+    let (host, port) = address.rsplit_once(':').ok_or_else(|| {
+        mynode::Error::Config(format!("Invalid address {}, expected host:port", address))
+    })?;
+    mynode::Client::new(host, port.parse()?)
+}
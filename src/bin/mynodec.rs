@@ -1,13 +1,67 @@
 #[macro_use]
 extern crate clap;
+extern crate config;
 extern crate mynode;
 extern crate rustyline;
+#[macro_use]
+extern crate serde_derive;
+
+/// The default number of history entries to keep
+const DEFAULT_HISTORY_SIZE: usize = 1000;
 
+use rustyline::config::Configurer;
 use rustyline::error::ReadlineError;
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 fn main() -> Result<(), mynode::Error> {
     let opts = app_from_crate!()
-        .arg(clap::Arg::with_name("command").short("c"))
+        .arg(
+            clap::Arg::with_name("command")
+                .short("c")
+                .help("Command to execute, instead of starting the REPL")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("columnar")
+                .long("columnar")
+                .help("Fetch results in columnar encoding"),
+        )
+        .arg(
+            clap::Arg::with_name("embedded")
+                .long("embedded")
+                .help("Run against an embedded database in the given directory, without a node")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hedge")
+                .long("hedge")
+                .help("Replica host:port to send hedged read-only queries to")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hedge-budget")
+                .long("hedge-budget")
+                .help("Latency budget in milliseconds before hedging a query")
+                .takes_value(true)
+                .default_value("50"),
+        )
+        .arg(
+            clap::Arg::with_name("read-consistency")
+                .long("read-consistency")
+                .help("Read consistency of read-only queries, e.g. bounded-staleness:500")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("replica")
+                .long("replica")
+                .help("Replica host:port to spread bounded-staleness or eventual reads across")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             clap::Arg::with_name("headers")
                 .short("H")
@@ -32,15 +86,96 @@ fn main() -> Result<(), mynode::Error> {
                 .required(true)
                 .default_value("9605"),
         )
+        .arg(
+            clap::Arg::with_name("user")
+                .short("U")
+                .long("user")
+                .help("User to authenticate as, with the password in MYNODE_PASSWORD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("token")
+                .long("token")
+                .help("Token to authenticate with, also read from MYNODE_TOKEN")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("profile")
+                .short("P")
+                .long("profile")
+                .help("Connection profile from ~/.mynodec.toml")
+                .takes_value(true),
+        )
         .get_matches();
 
-    let mut mynode = MyNodeConsole::new(
-        opts.value_of("host").unwrap(),
-        opts.value_of("port").unwrap().parse()?,
-    )?;
-    if opts.is_present("headers") {
+    let mut settings = match std::env::var_os("HOME") {
+        Some(home) => Settings::load(&std::path::Path::new(&home).join(".mynodec.toml"))?,
+        None => Settings::default(),
+    };
+    let profile = match opts.value_of("profile") {
+        Some(name) => settings.profile(name)?,
+        None => Profile::default(),
+    };
+
+    // Command-line flags take precedence over the profile, then the settings.
+    let credentials = credentials(&opts, &profile)?;
+    let host = match profile.host {
+        Some(host) if opts.occurrences_of("host") == 0 => host,
+        _ => opts.value_of("host").unwrap().to_string(),
+    };
+    let port = match profile.port {
+        Some(port) if opts.occurrences_of("port") == 0 => port,
+        _ => opts.value_of("port").unwrap().parse()?,
+    };
+    let progress_shown = Arc::new(AtomicBool::new(false));
+    let db = if let Some(path) = opts.value_of("embedded") {
+        if opts.is_present("hedge") || opts.is_present("replica") {
+            return Err(mynode::Error::Config(
+                "Hedged queries and replicas are not supported in embedded mode".into(),
+            ));
+        }
+        Database::Embedded(mynode::Embedded::open(&mynode::DataDir::new(path))?)
+    } else {
+        let mut client = mynode::Client::new(&host, port)?
+            .columnar(opts.is_present("columnar") || profile.columnar.unwrap_or(settings.columnar))
+            .on_progress(show_progress(progress_shown.clone()));
+        if let Some(replica) = opts.value_of("hedge") {
+            let (host, port) = replica.rsplit_once(':').ok_or_else(|| {
+                mynode::Error::Config(format!(
+                    "Invalid hedge replica {}, expected host:port",
+                    replica
+                ))
+            })?;
+            let budget =
+                std::time::Duration::from_millis(opts.value_of("hedge-budget").unwrap().parse()?);
+            client = client.hedged(host, port.parse()?, budget)?;
+        }
+        if let Some(credentials) = credentials {
+            client = client.credentials(credentials);
+        }
+        if let Some(consistency) = opts.value_of("read-consistency") {
+            client = client.read_consistency(consistency.parse()?);
+        }
+        for replica in opts.values_of("replica").into_iter().flatten() {
+            let (host, port) = replica.rsplit_once(':').ok_or_else(|| {
+                mynode::Error::Config(format!("Invalid replica {}, expected host:port", replica))
+            })?;
+            client = client.read_replica(host, port.parse()?)?;
+        }
+        Database::Remote(client)
+    };
+    let mut mynode = MyNodeConsole::new(db, progress_shown);
+    if opts.is_present("headers") || profile.headers.unwrap_or(settings.headers) {
         mynode.show_headers = true
     }
+    if let Some(nullstr) = settings.nullstr {
+        mynode.format.nullstr = nullstr
+    }
+    mynode.format.precision = settings.precision;
+    mynode.format.max_width = settings.max_width;
+    mynode
+        .editor
+        .set_max_history_size(settings.history_size.unwrap_or(DEFAULT_HISTORY_SIZE));
 
     if let Some(command) = opts.value_of("command") {
         mynode.execute(&command)
@@ -49,24 +184,260 @@ fn main() -> Result<(), mynode::Error> {
     }
 }
 
+/// Returns the credentials to authenticate with, if any. Command-line flags
+/// and environment variables take precedence over the profile.
+fn credentials(
+    opts: &clap::ArgMatches,
+    profile: &Profile,
+) -> Result<Option<mynode::Credentials>, mynode::Error> {
+    let env = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+    let token = opts
+        .value_of("token")
+        .map(|t| t.to_string())
+        .or_else(|| env("MYNODE_TOKEN"))
+        .or_else(|| profile.token.clone());
+    let user = opts
+        .value_of("user")
+        .map(|u| u.to_string())
+        .or_else(|| profile.user.clone());
+    match (token, user) {
+        (Some(_), Some(_)) => Err(mynode::Error::Config(
+            "Authenticate with either a user or a token, not both".into(),
+        )),
+        (Some(token), None) => Ok(Some(mynode::Credentials::Token(token))),
+        (None, Some(user)) => {
+            let password = env("MYNODE_PASSWORD")
+                .or_else(|| profile.password.clone())
+                .ok_or_else(|| {
+                    mynode::Error::Config(format!(
+                        "No password for user {}, set MYNODE_PASSWORD",
+                        user
+                    ))
+                })?;
+            Ok(Some(mynode::Credentials::Password { user, password }))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// REPL settings, loaded from ~/.mynodec.toml
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Settings {
+    /// Whether to show column headers by default
+    headers: bool,
+    /// Whether to fetch results in columnar encoding by default
+    columnar: bool,
+    /// The number of history entries to keep
+    history_size: Option<usize>,
+    /// The string to display NULL values as
+    nullstr: Option<String>,
+    /// The number of decimal places to display floats with
+    precision: Option<usize>,
+    /// The maximum width of displayed values, beyond which they're truncated
+    max_width: Option<usize>,
+    /// Named connection profiles
+    profiles: HashMap<String, Profile>,
+}
+
+/// A named connection profile, overriding the default settings
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Profile {
+    host: Option<String>,
+    port: Option<u16>,
+    tls: bool,
+    user: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+    headers: Option<bool>,
+    columnar: Option<bool>,
+}
+
+impl Settings {
+    /// Loads settings from a TOML file, if it exists
+    fn load(path: &std::path::Path) -> Result<Self, mynode::Error> {
+        let mut c = config::Config::new();
+        c.merge(
+            config::File::from(path)
+                .format(config::FileFormat::Toml)
+                .required(false),
+        )?;
+        Ok(c.try_into()?)
+    }
+
+    /// Takes a named profile
+    fn profile(&mut self, name: &str) -> Result<Profile, mynode::Error> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or_else(|| mynode::Error::Config(format!("Unknown profile {}", name)))?;
+        if profile.tls {
+            return Err(mynode::Error::Config(format!(
+                "Profile {} enables TLS, which is not supported",
+                name
+            )));
+        }
+        Ok(profile)
+    }
+}
+
+/// The database the REPL runs queries against
+enum Database {
+    /// A remote node, via gRPC
+    Remote(mynode::Client),
+    /// An embedded database in a local data directory
+    Embedded(mynode::Embedded),
+}
+
+impl Database {
+    /// Runs a query, returning the result columns and rows
+    fn query(&self, query: &str) -> Result<(Vec<String>, Rows), mynode::Error> {
+        match self {
+            Database::Remote(client) => {
+                let resultset = client.query(query)?;
+                Ok((resultset.columns(), Box::new(resultset)))
+            }
+            Database::Embedded(engine) => Ok((Vec::new(), Box::new(engine.query(query)?))),
+        }
+    }
+
+    /// Lists database tables
+    fn list_tables(&self) -> Result<Vec<String>, mynode::Error> {
+        match self {
+            Database::Remote(client) => client.list_tables(),
+            Database::Embedded(engine) => engine.list_tables(),
+        }
+    }
+
+    /// Fetches the table schema as SQL
+    fn get_table(&self, table: &str) -> Result<String, mynode::Error> {
+        match self {
+            Database::Remote(client) => client.get_table(table),
+            Database::Embedded(engine) => engine.get_table(table),
+        }
+    }
+
+    /// Dumps the database schema as SQL statements
+    fn dump_schema(&self) -> Result<Vec<String>, mynode::Error> {
+        match self {
+            Database::Remote(client) => client.dump_schema(),
+            Database::Embedded(engine) => engine.dump_schema(),
+        }
+    }
+
+    /// Returns the remote client, or an error for embedded databases
+    fn client(&self) -> Result<&mynode::Client, mynode::Error> {
+        match self {
+            Database::Remote(client) => Ok(client),
+            Database::Embedded(_) => Err(mynode::Error::Value(
+                "Not available for embedded databases".into(),
+            )),
+        }
+    }
+}
+
+/// Query result rows
+type Rows = Box<dyn Iterator<Item = Result<mynode::Row, mynode::Error>>>;
+
+/// Returns a progress callback which displays a live query progress counter
+fn show_progress(shown: Arc<AtomicBool>) -> impl Fn(mynode::QueryProgress) {
+    move |progress| {
+        eprint!(
+            "\r\x1b[K{} rows scanned, {} rows returned",
+            progress.rows_scanned, progress.rows_returned
+        );
+        std::io::stderr().flush().ok();
+        shown.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Value display options
+struct Format {
+    /// The string to display NULL values as
+    nullstr: String,
+    /// The number of decimal places to display floats with, if any
+    precision: Option<usize>,
+    /// The maximum width of values, beyond which they're truncated, if any
+    max_width: Option<usize>,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self {
+            nullstr: "NULL".into(),
+            precision: None,
+            max_width: None,
+        }
+    }
+}
+
+impl Format {
+    /// Formats a value for display
+    fn value(&self, value: &mynode::Value) -> String {
+        let s = match (value, self.precision) {
+            (mynode::Value::Null, _) => self.nullstr.clone(),
+            (mynode::Value::Float(f), Some(precision)) if f.is_finite() => {
+                format!("{:.*}", precision, f)
+            }
+            (v, _) => v.to_string(),
+        };
+        match self.max_width {
+            Some(width) if s.chars().count() > width => {
+                let mut s: String = s.chars().take(width.saturating_sub(1)).collect();
+                s.push('…');
+                s
+            }
+            _ => s,
+        }
+    }
+}
+
+/// Parses an optional numeric display option, where off disables it
+fn parse_option(command: &str, value: &str) -> Result<Option<usize>, mynode::Error> {
+    match value {
+        "off" => Ok(None),
+        v => v.parse().map(Some).map_err(|_| {
+            mynode::Error::Parse(format!(
+                "{}: invalid value {}, expected number or off",
+                command, v
+            ))
+        }),
+    }
+}
+
 /// MyNode REPL
 struct MyNodeConsole {
-    client: mynode::Client,
+    db: Database,
     editor: rustyline::Editor<()>,
     history_path: Option<std::path::PathBuf>,
     show_headers: bool,
+    /// Value display options
+    format: Format,
+    /// Whether a query progress counter is currently displayed
+    progress_shown: Arc<AtomicBool>,
+    /// Named prepared statements, created via !prepare
+    prepared: HashMap<String, mynode::PreparedStatement>,
 }
 
 impl MyNodeConsole {
-    /// Creates a new ToySQL REPL for the given server host and port
-    fn new(host: &str, port: u16) -> Result<Self, mynode::Error> {
-        Ok(Self {
-            client: mynode::Client::new(host, port)?,
-            editor: rustyline::Editor::<()>::new(),
+    /// Creates a new ToySQL REPL for the given database, where progress_shown
+    /// is set by the database's progress callback, if any.
+    fn new(db: Database, progress_shown: Arc<AtomicBool>) -> Self {
+        let config = rustyline::Config::builder()
+            .history_ignore_dups(true)
+            .history_ignore_space(true)
+            .build();
+        Self {
+            db,
+            editor: rustyline::Editor::<()>::with_config(config),
             history_path: std::env::var_os("HOME")
                 .map(|home| std::path::Path::new(&home).join(".toysql.history")),
             show_headers: false,
-        })
+            format: Format::default(),
+            progress_shown,
+            prepared: HashMap::new(),
+        }
     }
 
     /// Executes a line of input
@@ -90,11 +461,18 @@ impl MyNodeConsole {
             };
         }
 
-        let status = self.client.status()?;
-        println!(
-            "Connected to node \"{}\" (version {}). Enter !help for instructions.",
-            status.id, status.version
-        );
+        match &self.db {
+            Database::Remote(client) => {
+                let status = client.handshake()?;
+                println!(
+                    "Connected to node \"{}\" (version {}). Enter !help for instructions.",
+                    status.id, status.version
+                );
+            }
+            Database::Embedded(_) => {
+                println!("Opened embedded database. Enter !help for instructions.")
+            }
+        }
 
         while let Some(input) = self.prompt()? {
             if let Err(err) = self.execute(&input) {
@@ -110,19 +488,32 @@ impl MyNodeConsole {
 
     /// Runs a query and displays the results
     fn execute_query(&mut self, query: &str) -> Result<(), mynode::Error> {
-        let resultset = self.client.query(query)?;
+        let (columns, rows) = self.db.query(query)?;
         if self.show_headers {
-            println!("{}", resultset.columns().join("|"));
+            println!("{}", columns.join("|"));
         }
-        for result in resultset {
-            let formatted: Vec<String> = result?.into_iter().map(|v| format!("{}", v)).collect();
+        for result in rows {
+            self.clear_progress();
+            let formatted: Vec<String> = result?.iter().map(|v| self.format.value(v)).collect();
             println!("{}", formatted.join("|"));
         }
+        self.clear_progress();
         Ok(())
     }
 
+    /// Clears the query progress counter, if displayed
+    fn clear_progress(&self) {
+        if self.progress_shown.swap(false, Ordering::SeqCst) {
+            eprint!("\r\x1b[K");
+        }
+    }
+
     /// Handles a REPL command (prefixed by !, e.g. !help)
     fn execute_command(&mut self, input: &str) -> Result<(), mynode::Error> {
+        let rest = input
+            .trim()
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest);
         let mut input = input.split_ascii_whitespace();
         let command = input
             .next()
@@ -143,6 +534,24 @@ impl MyNodeConsole {
         };
 
         match command {
+            "!dump" => {
+                getargs(0)?;
+                for statement in self.db.dump_schema()? {
+                    println!("{}\n", statement);
+                }
+            }
+            "!exec" => {
+                let (name, params) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let query = self
+                    .prepared
+                    .get(name)
+                    .ok_or_else(|| {
+                        mynode::Error::Value(format!("Unknown prepared statement {}", name))
+                    })?
+                    .bind(&mynode::PreparedStatement::parse_values(params)?)?;
+                self.execute_query(&query)?;
+            }
+            "!format" => println!("{}", mynode::format_query(rest)?),
             "!headers" => match getargs(1)?[0] {
                 "on" => {
                     self.show_headers = true;
@@ -161,37 +570,161 @@ impl MyNodeConsole {
             },
             "!help" => println!(
                 r#"
-Enter an SQL statement on a single line to execute it and display the result.
-Semicolons are not supported. The following !-commands are also available:
+Enter an SQL statement to execute it and display the result. Incomplete
+statements continue on the next line, and an empty line submits them as is.
+DDL statements separated by semicolons are applied atomically. Ctrl-R searches the command history. The
+following !-commands are also available:
+    !dump              Display the schema as SQL statements
+    !exec <name> [values...]
+                       Execute a prepared statement with comma-separated
+                       parameter values, e.g. !exec add 1, 'a'
+    !format <query>    Display a query formatted as canonical SQL
     !headers <on|off>  Toggles/enables/disables column headers display
     !help              This help message
+    !maxwidth <n|off>  Truncates displayed values to n characters
+    !nullstr <string>  Sets the string NULL values are displayed as
+    !precision <n|off> Displays floats with n decimal places
+    !prepare <name> <query>
+                       Prepare a statement with ? parameters, e.g.
+                       !prepare add INSERT INTO t VALUES (?, ?)
+    !status            Display server status
     !tables            List tables
     !table [table]     Display table schema, if it exists
 "#
             ),
+            "!maxwidth" => {
+                self.format.max_width = parse_option(command, getargs(1)?[0])?;
+                match self.format.max_width {
+                    Some(width) => println!("Values truncated to {} characters", width),
+                    None => println!("Value truncation disabled"),
+                }
+            }
+            "!nullstr" => {
+                let nullstr = rest.trim();
+                if nullstr.is_empty() {
+                    return Err(mynode::Error::Parse(format!(
+                        "{}: expected 1 args, got 0",
+                        command
+                    )));
+                }
+                let nullstr = nullstr
+                    .strip_prefix('\'')
+                    .and_then(|s| s.strip_suffix('\''))
+                    .unwrap_or(nullstr);
+                self.format.nullstr = nullstr.to_string();
+                println!("NULL values displayed as '{}'", nullstr);
+            }
+            "!precision" => {
+                self.format.precision = parse_option(command, getargs(1)?[0])?;
+                match self.format.precision {
+                    Some(precision) => println!("Floats displayed with {} decimals", precision),
+                    None => println!("Float precision disabled"),
+                }
+            }
+            "!prepare" => {
+                let (name, query) = rest.split_once(char::is_whitespace).ok_or_else(|| {
+                    mynode::Error::Parse("!prepare: expected name and query".into())
+                })?;
+                let prepared = mynode::PreparedStatement::new(query)?;
+                println!(
+                    "Prepared {} with {} parameters",
+                    name,
+                    prepared.parameters()
+                );
+                self.prepared.insert(name.to_string(), prepared);
+            }
+            "!status" => {
+                getargs(0)?;
+                let status = self.db.client()?.status()?;
+                println!("Node:                   {}", status.id);
+                println!("Version:                {}", status.version);
+                println!(
+                    "Protocol versions:      {}-{}",
+                    status.min_protocol_version, status.max_protocol_version
+                );
+                println!("Cluster ID:             {}", status.cluster_id);
+                println!("Query cache hits:       {}", status.query_cache_hits);
+                println!("Query cache misses:     {}", status.query_cache_misses);
+                println!("Statement cache hits:   {}", status.statement_cache_hits);
+                println!("Statement cache misses: {}", status.statement_cache_misses);
+                println!("Raft leader:            {}", status.raft_leader);
+                println!("Raft witness:           {}", status.witness);
+                println!("Raft commit index:      {}", status.raft_commit_index);
+                println!("Raft apply index:       {}", status.raft_apply_index);
+                println!("Raft elections:         {}", status.raft_elections);
+                println!(
+                    "Raft leader changes:    {} ({} last minute, {} last hour)",
+                    status.raft_leader_changes,
+                    status.raft_leader_changes_last_minute,
+                    status.raft_leader_changes_last_hour
+                );
+                for (peer, rtt) in &status.raft_peer_rtt {
+                    println!("Raft heartbeat RTT:     {} {:?}", peer, rtt);
+                }
+                println!("Checksums verified:     {}", status.checksums_verified);
+                println!("Checksum mismatches:    {}", status.checksum_mismatches);
+                println!("Disk available bytes:   {}", status.disk_available_bytes);
+                println!("Disk writes fenced:     {}", status.disk_fenced);
+                println!("State store bytes:      {}", status.state_store_bytes);
+                println!("Raft store bytes:       {}", status.raft_store_bytes);
+                println!("Uptime seconds:         {}", status.uptime_seconds);
+                println!("Git commit:             {}", status.git_commit);
+                println!("Build time:             {}", status.build_time);
+                println!("Features:               {}", status.features.join(", "));
+            }
             "!tables" => {
-                for table in self.client.list_tables()? {
+                for table in self.db.list_tables()? {
                     println!("{}", table)
                 }
             }
             "!table" => {
                 let args = getargs(1)?;
-                println!("{}", self.client.get_table(args[0])?);
+                println!("{}", self.db.get_table(args[0])?);
             }
             c => return Err(mynode::Error::Parse(format!("Unknown command {}", c))),
         }
         Ok(())
     }
 
-    /// Prompts the user for input
+    /// Prompts the user for input. Incomplete statements continue on the next
+    /// line, until the statement is complete or an empty line is entered.
     fn prompt(&mut self) -> Result<Option<String>, mynode::Error> {
-        match self.editor.readline("mynode> ") {
-            Ok(input) => {
-                self.editor.add_history_entry(&input);
-                Ok(Some(input.trim().to_string()))
+        let mut input = String::new();
+        loop {
+            let prompt = if input.is_empty() {
+                "mynode> "
+            } else {
+                "   ...> "
+            };
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    let line = line.trim();
+                    if line.is_empty() && !input.is_empty() {
+                        break;
+                    }
+                    if !input.is_empty() {
+                        input.push('\n');
+                    }
+                    input.push_str(line);
+                    if input.is_empty()
+                        || input.starts_with('!')
+                        || !mynode::is_incomplete_query(&input)
+                    {
+                        break;
+                    }
+                }
+                Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) if input.is_empty() => {
+                    return Ok(None)
+                }
+                // Discard the incomplete statement
+                Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => {
+                    return Ok(Some(String::new()))
+                }
+                Err(err) => return Err(err.into()),
             }
-            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(None),
-            Err(err) => Err(err.into()),
         }
+        // History entries are single lines
+        self.editor.add_history_entry(input.replace('\n', " "));
+        Ok(Some(input))
     }
 }
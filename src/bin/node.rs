@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[macro_use]
 extern crate clap;
@@ -8,18 +9,141 @@ extern crate mynode;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate signal_hook;
 extern crate simplelog;
 
+/// The default configuration file, which is optional such that nodes can be
+/// configured purely via NODE_* environment variables.
+const DEFAULT_CONFIG_FILE: &str = "/etc/node.yaml";
+
 fn main() -> Result<(), mynode::Error> {
     let args = get_app_args();
-    let cfg = Config::new(args.value_of("config").unwrap())?;
+    let file = args.value_of("config").unwrap().to_string();
+    let cfg = Config::new(&file)?;
+    cfg.validate()?;
+    let keyring = cfg.keyring()?;
+    let compression = cfg.compression.parse()?;
+    let mut data_dir = mynode::DataDir::new(&cfg.data_dir)
+        .compressed(compression)
+        .flush_threshold(cfg.store_flush_bytes);
+    if let Some(keyring) = &keyring {
+        data_dir = data_dir.encrypted(keyring.clone());
+    }
+    // Maintenance commands lock the data directory, to make sure the node is stopped.
+    let lock = || data_dir.lock();
+    match args.subcommand() {
+        ("check-config", _) => println!("Configuration is valid"),
+        ("init-data-dir", _) => {
+            let _lock = lock()?;
+            data_dir.init()?;
+            println!("Initialized data directory {}", cfg.data_dir);
+        }
+        ("backup", Some(args)) => {
+            let dir = args.value_of("dir").unwrap();
+            let _lock = lock()?;
+            data_dir.backup(dir)?;
+            println!("Backed up data directory {} to {}", cfg.data_dir, dir);
+        }
+        ("restore", Some(args)) => {
+            let dir = args.value_of("dir").unwrap();
+            let _lock = lock()?;
+            data_dir.restore(dir)?;
+            println!("Restored data directory {} from {}", cfg.data_dir, dir);
+        }
+        ("compact-store", _) => {
+            let _lock = lock()?;
+            data_dir.check_layout()?;
+            data_dir.compact()?;
+            println!("Compacted stores in data directory {}", cfg.data_dir);
+        }
+        ("check-raft-log", Some(args)) => {
+            let _lock = lock()?;
+            data_dir.check_layout()?;
+            if args.is_present("repair") {
+                let removed = data_dir.repair_raft_log()?;
+                println!(
+                    "Removed {} entries beyond the last valid entry",
+                    removed.len()
+                );
+            }
+            let report = data_dir.check_raft_log()?;
+            println!("{}", report);
+            if !report.problems.is_empty() {
+                return Err(mynode::Error::Value(format!(
+                    "Raft log in data directory {} has problems",
+                    cfg.data_dir
+                )));
+            }
+        }
+        ("dump-raft-log", _) => {
+            let _lock = lock()?;
+            data_dir.check_layout()?;
+            for line in data_dir.dump_raft_log()? {
+                println!("{}", line);
+            }
+        }
+        ("inspect-sql", Some(args)) => {
+            let _lock = lock()?;
+            data_dir.check_layout()?;
+            let repair = args.is_present("repair");
+            let report = data_dir.inspect_sql(repair)?;
+            println!("{}", report);
+            if repair && !report.orphans.is_empty() {
+                println!("Deleted {} orphaned rows", report.orphans.len());
+            }
+        }
+        ("migrate-data-dir", _) => {
+            let _lock = lock()?;
+            let from = data_dir.migrate()?;
+            println!(
+                "Migrated data directory {} from layout version {} to {}",
+                cfg.data_dir,
+                from,
+                mynode::LAYOUT_VERSION
+            );
+        }
+        _ => serve(file, cfg, keyring, compression)?,
+    }
+    Ok(())
+}
+
+/// Runs the node server
+fn serve(
+    file: String,
+    cfg: Config,
+    keyring: Option<Arc<mynode::Keyring>>,
+    compression: mynode::Compression,
+) -> Result<(), mynode::Error> {
     setup_log(&cfg)?;
+    let limiter = Arc::new(mynode::RateLimiter::new(cfg.limits()));
+    watch_reload(file, limiter.clone())?;
     mynode::Node {
         peers: cfg.parse_peers()?,
+        authenticator: cfg.authenticator()?,
+        admins: cfg.auth_admins.iter().cloned().collect(),
         id: cfg.id,
         addr: cfg.listen,
+        peer_addr: Some(cfg.peer_listen).filter(|a| !a.is_empty()),
         threads: cfg.threads,
         data_dir: cfg.data_dir,
+        read_only: cfg.read_only,
+        witness: cfg.witness,
+        limiter,
+        keyring,
+        compression,
+        store_flush_bytes: cfg.store_flush_bytes,
+        query_cache_bytes: cfg.query_cache_bytes,
+        statement_cache_size: cfg.statement_cache_size,
+        query_threads: cfg.query_threads,
+        query_queue_size: cfg.query_queue_size,
+        max_clock_offset: cfg.max_clock_offset_ms,
+        checksum_interval: cfg.checksum_interval_secs,
+        max_message_size: cfg.max_message_bytes,
+        max_command_size: cfg.max_command_bytes,
+        peer_timeout: cfg.peer_timeout_ms,
+        min_disk_free: cfg.min_disk_free_bytes,
+        audit_log_file: Some(cfg.audit_log_file).filter(|f| !f.is_empty()),
+        startup_check: cfg.startup_check,
     }
     .listen()
 }
@@ -32,7 +156,65 @@ fn get_app_args() -> clap::ArgMatches<'static> {
                 .long("config")
                 .help("Configuration file path")
                 .takes_value(true)
-                .default_value("/etc/node.yaml"),
+                .default_value(DEFAULT_CONFIG_FILE)
+                .global(true),
+        )
+        .subcommand(clap::SubCommand::with_name("serve").about("Runs the node (default)"))
+        .subcommand(
+            clap::SubCommand::with_name("check-config")
+                .about("Validates the configuration file and exits"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("init-data-dir")
+                .about("Creates the data directory and empty stores"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("backup")
+                .about("Copies the stores of a stopped node into a directory")
+                .arg(
+                    clap::Arg::with_name("dir")
+                        .help("Backup directory, must be empty")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("restore")
+                .about("Restores the stores of a stopped node from a backup directory")
+                .arg(
+                    clap::Arg::with_name("dir")
+                        .help("Backup directory")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("migrate-data-dir")
+                .about("Upgrades the data directory of a stopped node to the current layout"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("check-raft-log")
+                .about("Validates the Raft log of a stopped node and prints its state")
+                .arg(
+                    clap::Arg::with_name("repair")
+                        .long("repair")
+                        .help("Removes entries after the last valid entry, e.g. a torn write"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("dump-raft-log")
+                .about("Prints the Raft log entries of a stopped node, with decoded commands"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("inspect-sql")
+                .about("Lists the SQL tables and orphaned rows of a stopped node")
+                .arg(
+                    clap::Arg::with_name("repair")
+                        .long("repair")
+                        .help("Deletes orphaned rows"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("compact-store")
+                .about("Compacts the stores of a stopped node"),
         )
         .get_matches()
 }
@@ -40,12 +222,54 @@ fn get_app_args() -> clap::ArgMatches<'static> {
 fn setup_log(cfg: &Config) -> Result<(), mynode::Error> {
     let log_level = cfg.log_level.parse::<simplelog::LevelFilter>()?;
 
-    let mut log_config = simplelog::ConfigBuilder::new();
-    if log_level != simplelog::LevelFilter::Debug {
-        log_config.add_filter_allow_str("mynode");
+    // The logger accepts all levels, and the effective level is set via the
+    // global max level such that it can be changed on reload.
+    if cfg.log_format == "json" {
+        let logger = mynode::JsonLogger::new(&cfg.id)
+            .all_modules(log_level == simplelog::LevelFilter::Debug);
+        log::set_boxed_logger(Box::new(logger))?;
+    } else {
+        let mut log_config = simplelog::ConfigBuilder::new();
+        if log_level != simplelog::LevelFilter::Debug {
+            log_config.add_filter_allow_str("mynode");
+        }
+        simplelog::SimpleLogger::init(simplelog::LevelFilter::Trace, log_config.build())?;
     }
+    log::set_max_level(log_level);
+
+    // Log panics with a backtrace. Panics in query execution are returned to
+    // the client as errors and the node keeps serving, but they're still bugs.
+    std::panic::set_hook(Box::new(|info| {
+        log::error!(
+            "Thread {} {}\n{:?}",
+            std::thread::current().name().unwrap_or("<unnamed>"),
+            info,
+            backtrace::Backtrace::new()
+        );
+    }));
+    Ok(())
+}
 
-    simplelog::SimpleLogger::init(log_level, log_config.build())?;
+/// Reloads the configuration file on SIGHUP, applying the settings which can
+/// be changed at runtime: log_level, rate_limit_qps and max_concurrent_queries.
+/// Other settings require a restart.
+fn watch_reload(file: String, limiter: Arc<mynode::RateLimiter>) -> Result<(), mynode::Error> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            let result = Config::new(&file)
+                .map_err(mynode::Error::from)
+                .and_then(|cfg| {
+                    cfg.validate()?;
+                    log::set_max_level(cfg.log_level.parse()?);
+                    limiter.set_limits(cfg.limits())
+                });
+            match result {
+                Ok(()) => log::info!("Reloaded configuration from {}", file),
+                Err(err) => log::error!("Failed to reload configuration from {}: {}", file, err),
+            }
+        }
+    });
     Ok(())
 }
 
@@ -53,39 +277,396 @@ fn setup_log(cfg: &Config) -> Result<(), mynode::Error> {
 struct Config {
     id: String,
     listen: String,
+    peer_listen: String,
     threads: usize,
     log_level: String,
+    log_format: String,
     data_dir: String,
+    read_only: bool,
+    witness: bool,
+    rate_limit_qps: f64,
+    max_concurrent_queries: usize,
+    encryption_key_file: String,
+    compression: String,
+    store_flush_bytes: u64,
+    query_cache_bytes: usize,
+    statement_cache_size: usize,
+    query_threads: usize,
+    query_queue_size: usize,
+    max_clock_offset_ms: u64,
+    checksum_interval_secs: u64,
+    max_message_bytes: usize,
+    max_command_bytes: usize,
+    peer_timeout_ms: u64,
+    min_disk_free_bytes: u64,
+    audit_log_file: String,
+    startup_check: bool,
+    auth_backend: String,
+    auth_password_file: String,
+    auth_tokens: HashMap<String, String>,
+    auth_command: String,
+    auth_admins: Vec<String>,
+    #[serde(deserialize_with = "deserialize_peers")]
     peers: HashMap<String, String>,
 }
 
+/// Peers can be given either as a map of node IDs to addresses, or as a string
+/// of comma-separated id=address pairs (used by the NODE_PEERS environment
+/// variable), e.g. "node-b=172.20.0.102,node-c=172.20.0.103:9605".
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Peers {
+    Map(HashMap<String, String>),
+    List(String),
+}
+
+fn deserialize_peers<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match serde::Deserialize::deserialize(deserializer)? {
+        Peers::Map(peers) => Ok(peers),
+        Peers::List(list) => parse_peer_list(&list).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Parses a peer list of comma-separated id=address pairs
+fn parse_peer_list(list: &str) -> Result<HashMap<String, String>, String> {
+    let mut peers = HashMap::new();
+    for entry in list.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+        let mut parts = entry.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(id), Some(address)) if !id.trim().is_empty() && !address.trim().is_empty() => {
+                if peers
+                    .insert(id.trim().to_string(), address.trim().to_string())
+                    .is_some()
+                {
+                    return Err(format!("Peer {} is listed more than once", id.trim()));
+                }
+            }
+            _ => return Err(format!("Invalid peer {}, expected id=address", entry)),
+        }
+    }
+    Ok(peers)
+}
+
 impl Config {
     fn new(file: &str) -> Result<Self, config::ConfigError> {
         let mut c = config::Config::new();
         c.set_default("id", "node")?;
         c.set_default("listen", "0.0.0.0:9605")?;
+        c.set_default("peer_listen", "")?;
         c.set_default("threads", 4)?;
         c.set_default("log_level", "info")?;
+        c.set_default("log_format", "text")?;
         c.set_default("data_dir", "/var/lib/nodedb")?;
+        c.set_default("read_only", false)?;
+        c.set_default("witness", false)?;
+        c.set_default("rate_limit_qps", 0.0)?;
+        c.set_default("max_concurrent_queries", 0)?;
+        c.set_default("encryption_key_file", "")?;
+        c.set_default("compression", "none")?;
+        c.set_default("store_flush_bytes", 4 * 1024 * 1024)?;
+        c.set_default("query_cache_bytes", 0)?;
+        c.set_default("statement_cache_size", 1024)?;
+        c.set_default("query_threads", 4)?;
+        c.set_default("query_queue_size", 64)?;
+        c.set_default("max_clock_offset_ms", 500)?;
+        c.set_default("checksum_interval_secs", 0)?;
+        c.set_default("max_message_bytes", 4 * 1024 * 1024)?;
+        c.set_default("max_command_bytes", 1024 * 1024)?;
+        c.set_default("peer_timeout_ms", 5000)?;
+        c.set_default("min_disk_free_bytes", 256 * 1024 * 1024)?;
+        c.set_default("audit_log_file", "")?;
+        c.set_default("startup_check", true)?;
+        c.set_default("auth_backend", "none")?;
+        c.set_default("auth_password_file", "")?;
+        c.set_default("auth_tokens", HashMap::<String, String>::new())?;
+        c.set_default("auth_command", "")?;
+        c.set_default("auth_admins", Vec::<String>::new())?;
+        c.set_default("peers", HashMap::<String, String>::new())?;
 
-        c.merge(config::File::with_name(file))?;
+        c.merge(config::File::with_name(file).required(file != DEFAULT_CONFIG_FILE))?;
         c.merge(config::Environment::with_prefix("NODE"))?;
         c.try_into()
     }
 
-    fn parse_peers(&self) -> Result<HashMap<String, std::net::SocketAddr>, mynode::Error> {
-        let mut peers = HashMap::new();
-        for (id, address) in self.peers.iter() {
-            peers.insert(
-                id.clone(),
-                if let Ok(sa) = address.parse::<std::net::SocketAddr>() {
-                    sa
-                } else {
-                    let ip = address.parse::<std::net::IpAddr>()?;
-                    std::net::SocketAddr::new(ip, 9605)
-                },
-            );
+    /// Validates the configuration, returning a descriptive error for invalid settings
+    fn validate(&self) -> Result<(), mynode::Error> {
+        if self.id.is_empty() {
+            return Err(mynode::Error::Config("Node ID can't be empty".into()));
+        }
+        if self.listen.parse::<std::net::SocketAddr>().is_err() {
+            return Err(mynode::Error::Config(format!(
+                "Invalid listen address {}, expected host:port",
+                self.listen
+            )));
+        }
+        if !self.peer_listen.is_empty() {
+            if self.peer_listen.parse::<std::net::SocketAddr>().is_err() {
+                return Err(mynode::Error::Config(format!(
+                    "Invalid peer listen address {}, expected host:port",
+                    self.peer_listen
+                )));
+            }
+            if self.peer_listen == self.listen {
+                return Err(mynode::Error::Config(
+                    "peer_listen must differ from listen, or be empty to share it".into(),
+                ));
+            }
+        }
+        if self.threads == 0 {
+            return Err(mynode::Error::Config("threads must be at least 1".into()));
+        }
+        if self.query_threads == 0 {
+            return Err(mynode::Error::Config(
+                "query_threads must be at least 1".into(),
+            ));
+        }
+        if self.data_dir.is_empty() {
+            return Err(mynode::Error::Config("data_dir can't be empty".into()));
+        }
+        if self.max_message_bytes < 1024 {
+            return Err(mynode::Error::Config(
+                "max_message_bytes must be at least 1024".into(),
+            ));
+        }
+        // Leave room for the message and entry framing.
+        if self.max_command_bytes == 0 || self.max_command_bytes + 1024 > self.max_message_bytes {
+            return Err(mynode::Error::Config(
+                "max_command_bytes must be positive and at least 1024 below max_message_bytes"
+                    .into(),
+            ));
+        }
+        if self.witness && self.peers.is_empty() {
+            return Err(mynode::Error::Config(
+                "A witness node requires peers".into(),
+            ));
+        }
+        if self.log_level.parse::<simplelog::LevelFilter>().is_err() {
+            return Err(mynode::Error::Config(format!(
+                "Invalid log level {}, expected one of off, error, warn, info, debug or trace",
+                self.log_level
+            )));
+        }
+        if self.log_format != "text" && self.log_format != "json" {
+            return Err(mynode::Error::Config(format!(
+                "Invalid log format {}, expected text or json",
+                self.log_format
+            )));
+        }
+        if !self.rate_limit_qps.is_finite() || self.rate_limit_qps < 0.0 {
+            return Err(mynode::Error::Config(format!(
+                "Invalid rate_limit_qps {}, must be 0 (unlimited) or a positive number",
+                self.rate_limit_qps
+            )));
+        }
+        self.keyring()?;
+        self.authenticator()?;
+        if !self.auth_admins.is_empty() && self.auth_backend == "none" {
+            return Err(mynode::Error::Config(
+                "auth_admins requires an auth_backend".into(),
+            ));
+        }
+        self.compression.parse::<mynode::Compression>()?;
+        if self.peers.contains_key(&self.id) {
+            return Err(mynode::Error::Config(format!(
+                "Node {} can't be listed as its own peer",
+                self.id
+            )));
+        }
+        let peers = self.parse_peers()?;
+        let mut ids: Vec<&String> = peers.keys().collect();
+        ids.sort();
+        let mut addresses = HashMap::new();
+        for id in ids {
+            if let Some(other) = addresses.insert(&peers[id], id) {
+                return Err(mynode::Error::Config(format!(
+                    "Peers {} and {} have the same address {}",
+                    other, id, peers[id]
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn limits(&self) -> mynode::Limits {
+        mynode::Limits {
+            qps: self.rate_limit_qps,
+            concurrent_queries: self.max_concurrent_queries,
+        }
+    }
+
+    /// Loads the encryption keyring, if encryption at rest is enabled.
+    fn keyring(&self) -> Result<Option<Arc<mynode::Keyring>>, mynode::Error> {
+        if self.encryption_key_file.is_empty() {
+            return Ok(None);
+        }
+        let keyring = mynode::Keyring::load(&self.encryption_key_file).map_err(|err| {
+            mynode::Error::Config(format!(
+                "Failed to load encryption keys from {}: {}",
+                self.encryption_key_file, err
+            ))
+        })?;
+        Ok(Some(Arc::new(keyring)))
+    }
+
+    /// Builds the client authentication backend, if authentication is enabled.
+    fn authenticator(&self) -> Result<Option<Arc<dyn mynode::Authenticator>>, mynode::Error> {
+        let authenticator: Arc<dyn mynode::Authenticator> = match self.auth_backend.as_str() {
+            "none" => return Ok(None),
+            "password_file" => Arc::new(
+                mynode::PasswordFile::load(&self.auth_password_file).map_err(|err| {
+                    mynode::Error::Config(format!(
+                        "Failed to load passwords from {}: {}",
+                        self.auth_password_file, err
+                    ))
+                })?,
+            ),
+            "tokens" if self.auth_tokens.is_empty() => {
+                return Err(mynode::Error::Config(
+                    "auth_backend tokens requires auth_tokens".into(),
+                ))
+            }
+            "tokens" => Arc::new(mynode::StaticTokens::new(self.auth_tokens.clone())),
+            "command" => Arc::new(mynode::ExternalCommand::new(&self.auth_command)?),
+            backend => {
+                return Err(mynode::Error::Config(format!(
+                    "Invalid auth_backend {}, expected none, password_file, tokens or command",
+                    backend
+                )))
+            }
+        };
+        Ok(Some(authenticator))
+    }
+
+    /// Parses peer addresses. Hostnames are resolved by the node at runtime.
+    fn parse_peers(&self) -> Result<HashMap<String, mynode::PeerAddress>, mynode::Error> {
+        self.peers
+            .iter()
+            .map(|(id, address)| Ok((id.clone(), address.parse()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            id: "a".into(),
+            listen: "0.0.0.0:9605".into(),
+            peer_listen: "".into(),
+            threads: 4,
+            log_level: "info".into(),
+            log_format: "text".into(),
+            data_dir: "/var/lib/nodedb".into(),
+            read_only: false,
+            witness: false,
+            rate_limit_qps: 0.0,
+            max_concurrent_queries: 0,
+            encryption_key_file: "".into(),
+            compression: "none".into(),
+            store_flush_bytes: 4 * 1024 * 1024,
+            query_cache_bytes: 0,
+            statement_cache_size: 1024,
+            query_threads: 4,
+            query_queue_size: 64,
+            max_clock_offset_ms: 500,
+            checksum_interval_secs: 0,
+            max_message_bytes: 4 * 1024 * 1024,
+            max_command_bytes: 1024 * 1024,
+            peer_timeout_ms: 5000,
+            min_disk_free_bytes: 256 * 1024 * 1024,
+            audit_log_file: "".into(),
+            startup_check: true,
+            auth_backend: "none".into(),
+            auth_password_file: "".into(),
+            auth_tokens: HashMap::new(),
+            auth_command: "".into(),
+            auth_admins: Vec::new(),
+            peers: vec![
+                ("b".to_string(), "127.0.0.1:9606".to_string()),
+                ("c".to_string(), "127.0.0.1:9607".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn peer_list() {
+        assert_eq!(Ok(HashMap::new()), parse_peer_list(""));
+        assert_eq!(
+            Ok(vec![
+                ("b".to_string(), "172.20.0.102".to_string()),
+                ("c".to_string(), "172.20.0.103:9605".to_string()),
+            ]
+            .into_iter()
+            .collect()),
+            parse_peer_list(" b=172.20.0.102, c=172.20.0.103:9605,")
+        );
+        assert!(parse_peer_list("b").is_err());
+        assert!(parse_peer_list("=172.20.0.102").is_err());
+        assert!(parse_peer_list("b=").is_err());
+        assert!(parse_peer_list("b=172.20.0.102,b=172.20.0.103").is_err());
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(Ok(()), config().validate());
+        let mut c = config();
+        c.auth_backend = "tokens".into();
+        c.auth_tokens.insert("t0k3n".into(), "svc".into());
+        c.auth_admins.push("svc".into());
+        assert_eq!(Ok(()), c.validate());
+
+        let invalid: Vec<fn(&mut Config)> = vec![
+            |c| c.id = "".into(),
+            |c| c.listen = "localhost".into(),
+            |c| c.peer_listen = "localhost".into(),
+            |c| c.peer_listen = c.listen.clone(),
+            |c| c.threads = 0,
+            |c| c.query_threads = 0,
+            |c| c.data_dir = "".into(),
+            |c| c.max_command_bytes = 0,
+            |c| c.max_command_bytes = c.max_message_bytes,
+            |c| {
+                c.witness = true;
+                c.peers.clear();
+            },
+            |c| c.log_level = "verbose".into(),
+            |c| c.log_format = "logfmt".into(),
+            |c| c.rate_limit_qps = -1.0,
+            |c| c.encryption_key_file = "/nonexistent/keys".into(),
+            |c| c.compression = "gzip".into(),
+            |c| c.auth_backend = "ldap".into(),
+            |c| c.auth_backend = "password_file".into(),
+            |c| {
+                c.auth_backend = "password_file".into();
+                c.auth_password_file = "/nonexistent/passwords".into();
+            },
+            |c| c.auth_backend = "tokens".into(),
+            |c| c.auth_backend = "command".into(),
+            |c| c.auth_admins.push("root".into()),
+            |c| {
+                c.peers.insert("a".into(), "127.0.0.1:9608".into());
+            },
+            |c| {
+                c.peers.insert("d".into(), "127.0.0.1:9606".into());
+            },
+            |c| {
+                c.peers.insert("d".into(), "peer-d:port".into());
+            },
+        ];
+        for modify in invalid {
+            let mut c = config();
+            modify(&mut c);
+            match c.validate() {
+                Err(mynode::Error::Config(_)) => {}
+                result => panic!("Expected config error for {:?}, got {:?}", c, result),
+            }
         }
-        Ok(peers)
     }
 }
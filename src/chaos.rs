@@ -0,0 +1,210 @@
+use crate::Error;
+use std::time::Duration;
+
+#[cfg(feature = "chaos")]
+use crate::raft::{Message, Transport};
+#[cfg(feature = "chaos")]
+use crate::store::{Bytes, Direction, Range, Store};
+#[cfg(feature = "chaos")]
+use crossbeam_channel::Receiver;
+#[cfg(feature = "chaos")]
+use std::sync::{Arc, RwLock};
+
+/// Faults to inject into the operations of a layer, for chaos testing. Rates
+/// are fractions of operations between 0 and 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fault {
+    /// The fraction of operations which fail. Store operations return an
+    /// error, while transport messages are dropped like for an unreachable peer.
+    pub fail_rate: f64,
+    /// The fraction of operations which are delayed.
+    pub delay_rate: f64,
+    /// The delay of delayed operations.
+    pub delay: Duration,
+}
+
+impl Fault {
+    /// Validates the fault rates.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, rate) in &[("fail", self.fail_rate), ("delay", self.delay_rate)] {
+            if !(0.0..=1.0).contains(rate) {
+                return Err(Error::Value(format!(
+                    "Invalid {} rate {}, must be between 0 and 1",
+                    name, rate
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Injects the fault into an operation, sleeping if it is delayed and
+    /// returning true if it should fail.
+    #[cfg(feature = "chaos")]
+    fn inject(&self) -> bool {
+        if self.delay_rate > 0.0 && rand::random::<f64>() < self.delay_rate {
+            std::thread::sleep(self.delay);
+        }
+        self.fail_rate > 0.0 && rand::random::<f64>() < self.fail_rate
+    }
+}
+
+/// The faults currently injected into each layer, set at runtime via the
+/// SetFaults RPC.
+#[cfg(feature = "chaos")]
+#[derive(Debug, Default)]
+pub struct Faults {
+    store: RwLock<Fault>,
+    transport: RwLock<Fault>,
+}
+
+#[cfg(feature = "chaos")]
+impl Faults {
+    /// Sets the faults of a layer, either store or transport.
+    pub fn set(&self, target: &str, fault: Fault) -> Result<(), Error> {
+        fault.validate()?;
+        let layer = match target {
+            "store" => &self.store,
+            "transport" => &self.transport,
+            _ => {
+                return Err(Error::Value(format!(
+                    "Invalid fault target {}, expected store or transport",
+                    target
+                )))
+            }
+        };
+        warn!("Injecting {} faults: {:?}", target, fault);
+        *layer.write()? = fault;
+        Ok(())
+    }
+
+    /// Injects store faults, returning an error for failed operations.
+    fn store(&self) -> Result<(), Error> {
+        if self.store.read()?.inject() {
+            return Err(Error::IO("Injected store fault".into()));
+        }
+        Ok(())
+    }
+
+    /// Injects transport faults, returning true for dropped messages.
+    fn transport(&self) -> Result<bool, Error> {
+        Ok(self.transport.read()?.inject())
+    }
+}
+
+/// A store wrapper which injects faults into operations.
+#[cfg(feature = "chaos")]
+#[derive(Debug)]
+pub struct FaultyStore<S: Store> {
+    inner: S,
+    faults: Arc<Faults>,
+}
+
+#[cfg(feature = "chaos")]
+impl<S: Store> FaultyStore<S> {
+    /// Creates a new faulty store wrapping an inner store.
+    pub fn new(inner: S, faults: Arc<Faults>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[cfg(feature = "chaos")]
+impl<S: Store> Store for FaultyStore<S> {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        self.faults.store()?;
+        self.inner.cas(key, expected, value)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), Error> {
+        self.faults.store()?;
+        self.inner.delete(key)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        self.faults.store()?;
+        self.inner.get(key)
+    }
+
+    fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.faults.store()?;
+        self.inner.set(key, value)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        match self.faults.store() {
+            Ok(()) => self.inner.scan_prefix(prefix, direction, limit),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        self.faults.store()?;
+        self.inner.snapshot()
+    }
+}
+
+/// A Raft transport wrapper which injects faults into sent messages.
+#[cfg(feature = "chaos")]
+pub struct FaultyTransport<T: Transport> {
+    inner: T,
+    faults: Arc<Faults>,
+}
+
+#[cfg(feature = "chaos")]
+impl<T: Transport> FaultyTransport<T> {
+    /// Creates a new faulty transport wrapping an inner transport.
+    pub fn new(inner: T, faults: Arc<Faults>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[cfg(feature = "chaos")]
+impl<T: Transport> Transport for FaultyTransport<T> {
+    fn receiver(&self) -> Receiver<Message> {
+        self.inner.receiver()
+    }
+
+    fn send(&self, msg: Message) -> Result<(), Error> {
+        if self.faults.transport()? {
+            debug!("Dropping message to {:?} due to injected fault", msg.to);
+            return Ok(());
+        }
+        self.inner.send(msg)
+    }
+}
+
+#[cfg(all(test, feature = "chaos"))]
+mod tests {
+    use super::*;
+    use crate::store::KVMemory;
+
+    #[test]
+    fn faulty_store() {
+        let faults = Arc::new(Faults::default());
+        let mut s = FaultyStore::new(KVMemory::new(), faults.clone());
+        s.set("a", vec![0x01]).unwrap();
+
+        let fail = Fault {
+            fail_rate: 1.0,
+            ..Default::default()
+        };
+        faults.set("store", fail.clone()).unwrap();
+        assert_matches!(s.get("a"), Err(Error::IO(_)));
+        assert_matches!(s.set("b", vec![]), Err(Error::IO(_)));
+        assert_matches!(s.iter_prefix("").next(), Some(Err(Error::IO(_))));
+
+        faults.set("store", Fault::default()).unwrap();
+        assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+
+        assert_matches!(faults.set("disk", fail), Err(Error::Value(_)));
+        assert_matches!(
+            faults.set(
+                "store",
+                Fault {
+                    delay_rate: 2.0,
+                    ..Default::default()
+                }
+            ),
+            Err(Error::Value(_))
+        );
+    }
+}
@@ -2,45 +2,460 @@ use grpc::ClientStubExt;
 
 use proto::StoreService;
 
+use crate::chaos::Fault;
+use crate::handlers::auth::Credentials;
+use crate::handlers::columnar;
+use crate::handlers::limiter::CLIENT_ID_METADATA;
+use crate::handlers::store::{
+    MIN_APPLIED_INDEX_METADATA, PROGRESS_METADATA, PROTOCOL_VERSION, READ_CONSISTENCY_METADATA,
+    TIMEOUT_METADATA,
+};
 use crate::proto;
 use crate::proto::Field_oneof_value;
-use crate::serializer::deserialize;
+use crate::raft::ReadConsistency;
+use crate::serializer::wire::deserialize;
+use crate::sql::ast::Statement;
 use crate::sql::types::{Row, Value};
+use crate::sql::Parser;
 use crate::Error;
+use crossbeam_channel::RecvTimeoutError;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The timeout for establishing a connection to the server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a read replica is skipped after a failed query.
+const REPLICA_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A callback receiving query progress
+type ProgressFn = Arc<dyn Fn(QueryProgress) + Send + Sync>;
+
+/// The progress of a running query, as reported by the server
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryProgress {
+    pub rows_scanned: u64,
+    pub rows_returned: u64,
+}
 
 /// A Store client
 pub struct Client {
-    client: proto::StoreServiceClient,
+    client: Arc<proto::StoreServiceClient>,
+    id: String,
+    columnar: bool,
+    /// The read-your-writes token, see applied_index().
+    applied_index: Arc<AtomicU64>,
+    /// A replica to send hedged reads to, and the latency budget after which
+    /// they're sent, see hedged().
+    hedge: Option<(Arc<proto::StoreServiceClient>, Duration)>,
+    /// A callback for query progress, see on_progress().
+    progress: Option<ProgressFn>,
+    /// The server-side query timeout, see timeout().
+    timeout: Option<Duration>,
+    /// The read consistency of queries, see read_consistency().
+    consistency: Option<ReadConsistency>,
+    /// Replicas to spread reads across, see read_replica().
+    replicas: Vec<Replica>,
+    /// The round-robin position among the server and replicas.
+    next_replica: AtomicUsize,
+    /// Whether the server's protocol versions have been checked, see
+    /// handshake().
+    handshaken: AtomicBool,
+    /// The credentials sent with requests, see credentials().
+    credentials: Option<Credentials>,
+}
+
+/// A read replica, see Client::read_replica().
+struct Replica {
+    client: Arc<proto::StoreServiceClient>,
+    /// When a query to the replica last failed, if ever.
+    failed_at: Mutex<Option<Instant>>,
+    /// Whether the replica is a witness, once its status has been fetched.
+    witness: Mutex<Option<bool>>,
+}
+
+impl Replica {
+    /// Returns true unless the replica failed recently.
+    fn is_healthy(&self) -> bool {
+        match self.failed_at.lock().map(|failed_at| *failed_at) {
+            Ok(Some(failed_at)) => failed_at.elapsed() >= REPLICA_RETRY_INTERVAL,
+            _ => true,
+        }
+    }
 }
 
 impl Client {
     /// Creates a new client
     pub fn new(host: &str, port: u16) -> Result<Self, Error> {
         Ok(Self {
-            client: proto::StoreServiceClient::new_plain(host, port, grpc::ClientConf::new())?,
+            client: Arc::new(Self::connect(host, port)?),
+            id: uuid::Uuid::new_v4().to_string(),
+            columnar: false,
+            applied_index: Arc::new(AtomicU64::new(0)),
+            hedge: None,
+            progress: None,
+            timeout: None,
+            consistency: None,
+            replicas: Vec::new(),
+            next_replica: AtomicUsize::new(0),
+            handshaken: AtomicBool::new(false),
+            credentials: None,
         })
     }
 
+    /// Connects to a server
+    fn connect(host: &str, port: u16) -> Result<proto::StoreServiceClient, Error> {
+        Ok(proto::StoreServiceClient::new_plain(
+            host,
+            port,
+            grpc::ClientConf {
+                http: httpbis::ClientConf {
+                    connection_timeout: Some(CONNECT_TIMEOUT),
+                    ..Default::default()
+                },
+            },
+        )?)
+    }
+
+    /// Hedges read-only queries: if the server hasn't responded within the
+    /// latency budget, the query is also sent to the given replica, and the
+    /// first successful response is used. This masks slow replicas at the
+    /// cost of buffering hedged query results in memory.
+    pub fn hedged(mut self, host: &str, port: u16, budget: Duration) -> Result<Self, Error> {
+        self.hedge = Some((Arc::new(Self::connect(host, port)?), budget));
+        Ok(self)
+    }
+
+    /// Adds a replica to spread read-only queries across, round-robin with the
+    /// server, when the read consistency allows followers to serve them (see
+    /// read_consistency()). Mutations, cursors and stronger reads are always
+    /// sent to the server, which should be the leader to avoid proxying. A
+    /// replica whose query fails is skipped for 5 seconds, and the query is
+    /// retried on the server. Witnesses are skipped, since they forward all
+    /// queries to the leader.
+    pub fn read_replica(mut self, host: &str, port: u16) -> Result<Self, Error> {
+        self.replicas.push(Replica {
+            client: Arc::new(Self::connect(host, port)?),
+            failed_at: Mutex::new(None),
+            witness: Mutex::new(None),
+        });
+        Ok(self)
+    }
+
+    /// Sends credentials with every request, for servers which require
+    /// authentication. They're sent in plain text, like all traffic.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Requests query results in columnar encoding, which is more compact
+    /// for large result sets.
+    pub fn columnar(mut self, columnar: bool) -> Self {
+        self.columnar = columnar;
+        self
+    }
+
+    /// Requests periodic progress updates for queries, which are passed to the
+    /// callback while iterating over the results. This tells slow queries
+    /// apart from hung connections.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(QueryProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a timeout for queries, after which the server aborts them with an
+    /// error, even while they're scanning storage. Cursors are not affected.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the read consistency of read-only queries, overriding the
+    /// read_consistency session variable. Weaker consistencies let nodes serve
+    /// reads without a quorum round trip, at the risk of stale results.
+    /// Mutations always read at strong consistency.
+    pub fn read_consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.consistency = Some(consistency);
+        self
+    }
+
+    /// Returns the client's read-your-writes token: a Raft log index
+    /// reflecting all queries completed by the client. Queries wait for the
+    /// server to catch up with it, so they observe the client's prior writes.
+    pub fn applied_index(&self) -> u64 {
+        self.applied_index.load(Ordering::SeqCst)
+    }
+
+    /// Requires subsequent queries to observe at least the given applied index,
+    /// e.g. a token from another client, for causal consistency across clients.
+    pub fn observe_applied_index(&self, index: u64) {
+        self.applied_index.fetch_max(index, Ordering::SeqCst);
+    }
+
+    /// Checks that the server supports the client's protocol version, and
+    /// returns its status. This is done before the client's first query or
+    /// schema request, so that an incompatible server is rejected up front
+    /// rather than failing later with decoding errors. Servers predating
+    /// protocol versions are assumed to support version 1. Replicas are
+    /// assumed to run the same version as the server.
+    pub fn handshake(&self) -> Result<Status, Error> {
+        let status = self.status()?;
+        let (min, max) = match (status.min_protocol_version, status.max_protocol_version) {
+            (0, 0) => (1, 1),
+            versions => versions,
+        };
+        if PROTOCOL_VERSION < min || PROTOCOL_VERSION > max {
+            return Err(Error::Value(format!(
+                "Server {} (version {}) supports protocol versions {}-{}, but the client uses version {}",
+                status.id, status.version, min, max, PROTOCOL_VERSION
+            )));
+        }
+        self.handshaken.store(true, Ordering::SeqCst);
+        Ok(status)
+    }
+
+    /// Runs the handshake, unless it already succeeded.
+    fn ensure_handshake(&self) -> Result<(), Error> {
+        if !self.handshaken.load(Ordering::SeqCst) {
+            self.handshake()?;
+        }
+        Ok(())
+    }
+
     /// Runs a query
     pub fn query(&self, query: &str) -> Result<ResultSet, Error> {
-        let (metadata, iter) = self
-            .client
+        self.ensure_handshake()?;
+        let balanced = !self.replicas.is_empty()
+            && matches!(self.consistency, Some(c) if c.allows_followers());
+        let read = (balanced || self.hedge.is_some()) && Self::is_read(query);
+        let replica = if read && balanced {
+            self.next_replica()
+        } else {
+            None
+        };
+        let client = match replica {
+            Some(replica) => replica.client.clone(),
+            None => self.client.clone(),
+        };
+        if let (true, Some((hedge, budget))) = (read, &self.hedge) {
+            return self.query_hedged(query, client, hedge.clone(), *budget);
+        }
+        match (self.query_at(&client, query), replica) {
+            (Err(err), Some(replica)) => {
+                debug!("Read replica failed, retrying on server: {}", err);
+                *replica.failed_at.lock()? = Some(Instant::now());
+                self.query_at(&self.client, query)
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Picks the target of a balanced read, round-robin among the server and
+    /// healthy replicas. Returns None for the server.
+    fn next_replica(&self) -> Option<&Replica> {
+        let count = self.replicas.len() + 1;
+        for _ in 0..count {
+            match self.next_replica.fetch_add(1, Ordering::Relaxed) % count {
+                0 => return None,
+                i if self.is_readable(&self.replicas[i - 1]) => return Some(&self.replicas[i - 1]),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Returns true if a replica is healthy and not a witness. Whether it's a
+    /// witness is checked via its status on first use, and a failed check
+    /// counts as a failed query.
+    fn is_readable(&self, replica: &Replica) -> bool {
+        if !replica.is_healthy() {
+            return false;
+        }
+        let mut witness = match replica.witness.lock() {
+            Ok(witness) => witness,
+            Err(_) => return false,
+        };
+        if witness.is_none() {
+            let status = replica
+                .client
+                .status(self.request_options(), proto::StatusRequest::new())
+                .wait();
+            match status {
+                Ok((_, resp, _)) => *witness = Some(resp.witness),
+                Err(err) => {
+                    debug!("Read replica status failed: {}", err);
+                    if let Ok(mut failed_at) = replica.failed_at.lock() {
+                        *failed_at = Some(Instant::now());
+                    }
+                    return false;
+                }
+            }
+        }
+        *witness == Some(false)
+    }
+
+    /// Runs a query on the given server.
+    fn query_at(
+        &self,
+        client: &proto::StoreServiceClient,
+        query: &str,
+    ) -> Result<ResultSet, Error> {
+        let (metadata, iter) = client
             .query(
-                grpc::RequestOptions::new(),
+                self.query_options(),
+                proto::QueryRequest {
+                    query: query.to_owned(),
+                    ..Default::default()
+                },
+            )
+            .wait()?;
+        ResultSet::from_grpc(
+            metadata,
+            iter,
+            self.applied_index.clone(),
+            self.progress.clone(),
+        )
+    }
+
+    /// Opens a server-side cursor for a query, whose results are fetched a
+    /// batch of rows at a time. Unused cursors are closed by the server after
+    /// 5 minutes.
+    pub fn cursor(&self, query: &str) -> Result<Cursor<'_>, Error> {
+        self.ensure_handshake()?;
+        let (_, resp, _) = self
+            .client
+            .open_cursor(
+                self.query_options(),
                 proto::QueryRequest {
                     query: query.to_owned(),
                     ..Default::default()
                 },
             )
             .wait()?;
-        ResultSet::from_grpc(metadata, iter)
+        error_from_protobuf(resp.error)?;
+        Ok(Cursor {
+            client: self,
+            id: resp.id,
+            done: false,
+        })
+    }
+
+    /// Returns true if the query is a read-only query, which can be hedged or
+    /// balanced across replicas.
+    fn is_read(query: &str) -> bool {
+        matches!(Parser::new(query).parse(), Ok(Statement::Select { .. }))
+    }
+
+    /// Runs a hedged read-only query on the given server, see hedged().
+    fn query_hedged(
+        &self,
+        query: &str,
+        client: Arc<proto::StoreServiceClient>,
+        replica: Arc<proto::StoreServiceClient>,
+        budget: Duration,
+    ) -> Result<ResultSet, Error> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let send = |client: Arc<proto::StoreServiceClient>| {
+            let (tx, opts) = (tx.clone(), self.query_options());
+            let request = proto::QueryRequest {
+                query: query.to_owned(),
+                ..Default::default()
+            };
+            std::thread::spawn(move || tx.send(client.query(opts, request).into_future().wait()));
+        };
+        send(client);
+        let mut hedged = false;
+        let mut pending = 1;
+        loop {
+            let result = if hedged {
+                rx.recv()?
+            } else {
+                match rx.recv_timeout(budget) {
+                    Ok(result) => result,
+                    Err(RecvTimeoutError::Timeout) => {
+                        debug!("Hedging query after {:?}: {}", budget, query);
+                        send(replica.clone());
+                        hedged = true;
+                        pending += 1;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(Error::Internal("Hedged query channel disconnected".into()))
+                    }
+                }
+            };
+            pending -= 1;
+            match result {
+                Ok((metadata, rows, _)) => {
+                    let rows = Box::new(rows.into_iter().map(Ok));
+                    return ResultSet::from_grpc(
+                        metadata,
+                        rows,
+                        self.applied_index.clone(),
+                        self.progress.clone(),
+                    );
+                }
+                Err(err) if pending == 0 && hedged => return Err(err.into()),
+                // Hedge immediately if the first attempt fails
+                Err(_) if !hedged => {
+                    send(replica.clone());
+                    hedged = true;
+                    pending += 1;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Builds request options for a query
+    fn query_options(&self) -> grpc::RequestOptions {
+        let mut opts = self.request_options();
+        if self.columnar {
+            opts.metadata.add(
+                grpc::MetadataKey::from(columnar::ENCODING_METADATA),
+                columnar::COLUMNAR_ENCODING.as_bytes().to_vec().into(),
+            );
+        }
+        if self.progress.is_some() {
+            opts.metadata.add(
+                grpc::MetadataKey::from(PROGRESS_METADATA),
+                b"true".to_vec().into(),
+            );
+        }
+        if let Some(timeout) = self.timeout {
+            opts.metadata.add(
+                grpc::MetadataKey::from(TIMEOUT_METADATA),
+                timeout.as_millis().to_string().into_bytes().into(),
+            );
+        }
+        if let Some(consistency) = self.consistency {
+            opts.metadata.add(
+                grpc::MetadataKey::from(READ_CONSISTENCY_METADATA),
+                consistency.to_string().into_bytes().into(),
+            );
+        }
+        let applied_index = self.applied_index();
+        if applied_index > 0 {
+            opts.metadata.add(
+                grpc::MetadataKey::from(MIN_APPLIED_INDEX_METADATA),
+                applied_index.to_string().into_bytes().into(),
+            );
+        }
+        opts
     }
 
     /// Lists database tables
     pub fn list_tables(&self) -> Result<Vec<String>, Error> {
+        self.ensure_handshake()?;
         let (_, resp, _) = self
             .client
-            .list_tables(grpc::RequestOptions::new(), proto::Empty::new())
+            .list_tables(self.request_options(), proto::Empty::new())
             .wait()?;
         error_from_protobuf(resp.error)?;
         Ok(resp.name.to_vec())
@@ -48,10 +463,11 @@ impl Client {
 
     /// Fetches the table schema as SQL
     pub fn get_table(&self, table: &str) -> Result<String, Error> {
+        self.ensure_handshake()?;
         let (_, resp, _) = self
             .client
             .get_table(
-                grpc::RequestOptions::new(),
+                self.request_options(),
                 proto::GetTableRequest {
                     name: table.to_string(),
                     ..Default::default()
@@ -62,36 +478,213 @@ impl Client {
         Ok(resp.sql)
     }
 
+    /// Dumps the database schema as CREATE TABLE statements for all tables,
+    /// which can be run against another cluster to recreate the schema.
+    pub fn dump_schema(&self) -> Result<Vec<String>, Error> {
+        self.list_tables()?
+            .iter()
+            .map(|table| self.get_table(table))
+            .collect()
+    }
+
+    /// Sets the faults injected into a layer of the connected node (store or
+    /// transport), for chaos testing. The node must be built with the chaos
+    /// feature. A default Fault disables fault injection.
+    pub fn set_faults(&self, target: &str, fault: Fault) -> Result<(), Error> {
+        let (_, resp, _) = self
+            .client
+            .set_faults(
+                self.request_options(),
+                proto::SetFaultsRequest {
+                    target: target.to_string(),
+                    fail_rate: fault.fail_rate,
+                    delay_rate: fault.delay_rate,
+                    delay_ms: fault.delay.as_millis() as u64,
+                    ..Default::default()
+                },
+            )
+            .wait()?;
+        error_from_protobuf(resp.error)
+    }
+
+    /// Builds request options identifying and authenticating the client to
+    /// the server
+    fn request_options(&self) -> grpc::RequestOptions {
+        let mut opts = grpc::RequestOptions::new();
+        opts.metadata.add(
+            grpc::MetadataKey::from(CLIENT_ID_METADATA),
+            self.id.clone().into_bytes().into(),
+        );
+        if let Some(credentials) = &self.credentials {
+            credentials.to_metadata(&mut opts.metadata);
+        }
+        opts
+    }
+
     /// Checks server status
     pub fn status(&self) -> Result<Status, Error> {
         let (_, resp, _) = self
             .client
-            .status(grpc::RequestOptions::new(), proto::StatusRequest::new())
+            .status(self.request_options(), proto::StatusRequest::new())
             .wait()?;
         Ok(Status {
             id: resp.id,
             version: resp.version,
+            cluster_id: resp.cluster_id,
+            query_cache_hits: resp.query_cache_hits,
+            query_cache_misses: resp.query_cache_misses,
+            statement_cache_hits: resp.statement_cache_hits,
+            statement_cache_misses: resp.statement_cache_misses,
+            raft_leader: resp.raft_leader,
+            raft_commit_index: resp.raft_commit_index,
+            raft_apply_index: resp.raft_apply_index,
+            checksums_verified: resp.checksums_verified,
+            checksum_mismatches: resp.checksum_mismatches,
+            disk_available_bytes: resp.disk_available_bytes,
+            disk_fenced: resp.disk_fenced,
+            uptime_seconds: resp.uptime_seconds,
+            git_commit: resp.git_commit,
+            build_time: resp.build_time,
+            features: resp.features.into_vec(),
+            state_store_bytes: resp.state_store_bytes,
+            raft_store_bytes: resp.raft_store_bytes,
+            raft_elections: resp.raft_elections,
+            raft_leader_changes: resp.raft_leader_changes,
+            raft_leader_changes_last_minute: resp.raft_leader_changes_last_minute,
+            raft_leader_changes_last_hour: resp.raft_leader_changes_last_hour,
+            raft_peer_rtt: resp
+                .raft_peer_rtt_micros
+                .into_iter()
+                .map(|(peer, micros)| (peer, Duration::from_micros(micros)))
+                .collect(),
+            min_protocol_version: resp.min_protocol_version,
+            max_protocol_version: resp.max_protocol_version,
+            witness: resp.witness,
         })
     }
 }
 
+/// A server-side cursor, see Client::cursor(). The cursor is closed when
+/// dropped.
+pub struct Cursor<'a> {
+    client: &'a Client,
+    id: u64,
+    done: bool,
+}
+
+impl<'a> Cursor<'a> {
+    /// Fetches up to count rows. Fewer rows are only returned once the cursor
+    /// is exhausted, after which fetches return no rows.
+    pub fn fetch(&mut self, count: usize) -> Result<Vec<Row>, Error> {
+        if self.done {
+            return Ok(Vec::new());
+        }
+        let (_, resp, _) = self
+            .client
+            .client
+            .fetch(
+                self.client.request_options(),
+                proto::FetchRequest {
+                    id: self.id,
+                    count: count as u64,
+                    ..Default::default()
+                },
+            )
+            .wait()?;
+        if let Err(err) = error_from_protobuf(resp.error) {
+            // The server closes failed cursors
+            self.done = true;
+            return Err(err);
+        }
+        self.done = resp.done;
+        Ok(resp.row.into_iter().map(row_from_protobuf).collect())
+    }
+
+    /// Closes the cursor, discarding any remaining rows
+    pub fn close(mut self) -> Result<(), Error> {
+        self.done = true;
+        let (_, resp, _) = self
+            .client
+            .client
+            .close_cursor(
+                self.client.request_options(),
+                proto::CloseCursorRequest {
+                    id: self.id,
+                    ..Default::default()
+                },
+            )
+            .wait()?;
+        error_from_protobuf(resp.error)
+    }
+}
+
+impl<'a> Drop for Cursor<'a> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.done = true;
+            let request = proto::CloseCursorRequest {
+                id: self.id,
+                ..Default::default()
+            };
+            if let Err(err) = self
+                .client
+                .client
+                .close_cursor(self.client.request_options(), request)
+                .wait()
+            {
+                debug!("Failed to close cursor {}: {}", self.id, err);
+            }
+        }
+    }
+}
+
 pub struct ResultSet {
     columns: Vec<String>,
     rows: Box<dyn Iterator<Item = Result<proto::Row, grpc::Error>>>,
+    /// Rows remaining from the last columnar batch
+    batch: std::vec::IntoIter<Row>,
+    /// The client's read-your-writes token, updated from the result.
+    applied_index: Arc<AtomicU64>,
+    /// A callback for query progress updates.
+    progress: Option<ProgressFn>,
 }
 
 impl Iterator for ResultSet {
     type Item = Result<Row, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.rows.next()? {
-            Ok(row) => {
-                if let Err(err) = error_from_protobuf(row.error.clone()) {
-                    return Some(Err(err));
+        loop {
+            if let Some(row) = self.batch.next() {
+                return Some(Ok(row));
+            }
+            let mut row = match self.rows.next()? {
+                Ok(row) => row,
+                Err(err) => return Some(Err(err.into())),
+            };
+            if let Err(err) = error_from_protobuf(row.error.clone()) {
+                return Some(Err(err));
+            }
+            if row.applied_index > 0 {
+                self.applied_index
+                    .fetch_max(row.applied_index, Ordering::SeqCst);
+                continue;
+            }
+            if let Some(progress) = row.progress.take() {
+                if let Some(callback) = &self.progress {
+                    callback(QueryProgress {
+                        rows_scanned: progress.rows_scanned,
+                        rows_returned: progress.rows_returned,
+                    });
                 }
-                Some(Ok(row_from_protobuf(row)))
+                continue;
+            }
+            match row.batch.take() {
+                Some(batch) => match columnar::decode(batch) {
+                    Ok(rows) => self.batch = rows.into_iter(),
+                    Err(err) => return Some(Err(err)),
+                },
+                None => return Some(Ok(row_from_protobuf(row))),
             }
-            Err(err) => Some(Err(err.into())),
         }
     }
 }
@@ -100,15 +693,18 @@ impl ResultSet {
     fn from_grpc(
         metadata: grpc::Metadata,
         rows: Box<dyn std::iter::Iterator<Item = Result<proto::Row, grpc::Error>>>,
+        applied_index: Arc<AtomicU64>,
+        progress: Option<ProgressFn>,
     ) -> Result<Self, Error> {
-        let columns = deserialize(
-            metadata
-                .get("columns")
-                .map(|c| c.to_vec())
-                .unwrap_or_else(Vec::new),
-        )
-        .unwrap_or_else(|_| Vec::new());
-        Ok(Self { columns, rows })
+        let columns =
+            deserialize(metadata.get("columns").unwrap_or_default()).unwrap_or_else(|_| Vec::new());
+        Ok(Self {
+            columns,
+            rows,
+            batch: Vec::new().into_iter(),
+            applied_index,
+            progress,
+        })
     }
 
     pub fn columns(&self) -> Vec<String> {
@@ -120,11 +716,54 @@ impl ResultSet {
 pub struct Status {
     pub id: String,
     pub version: String,
+    pub cluster_id: String,
+    pub query_cache_hits: u64,
+    pub query_cache_misses: u64,
+    pub statement_cache_hits: u64,
+    pub statement_cache_misses: u64,
+    pub raft_leader: bool,
+    pub raft_commit_index: u64,
+    pub raft_apply_index: u64,
+    pub checksums_verified: u64,
+    pub checksum_mismatches: u64,
+    /// The available disk space in bytes, or 0 if not checked.
+    pub disk_available_bytes: u64,
+    /// Whether writes are rejected due to low disk space.
+    pub disk_fenced: bool,
+    /// The time since the node started, in seconds.
+    pub uptime_seconds: u64,
+    /// The Git commit the node was built from, or "unknown".
+    pub git_commit: String,
+    /// The time the node was built, as an RFC 3339 timestamp.
+    pub build_time: String,
+    /// The Cargo features the node was built with.
+    pub features: Vec<String>,
+    /// The on-disk size of the state machine store in bytes.
+    pub state_store_bytes: u64,
+    /// The on-disk size of the Raft log store in bytes.
+    pub raft_store_bytes: u64,
+    /// The number of elections started by the node.
+    pub raft_elections: u64,
+    /// The number of leader changes observed by the node since it started,
+    /// and within the last minute and hour.
+    pub raft_leader_changes: u64,
+    pub raft_leader_changes_last_minute: u64,
+    pub raft_leader_changes_last_hour: u64,
+    /// The smoothed heartbeat round-trip time to each peer, as of when the
+    /// node was last leader.
+    pub raft_peer_rtt: BTreeMap<String, Duration>,
+    /// The range of client protocol versions the node supports, or 0 if the
+    /// node predates protocol versions.
+    pub min_protocol_version: u32,
+    pub max_protocol_version: u32,
+    /// Whether the node is a witness, which forwards all queries to the leader.
+    pub witness: bool,
 }
 
 /// Converts a protobuf error into a node error
 fn error_from_protobuf(err: protobuf::SingularPtrField<proto::Error>) -> Result<(), Error> {
     match err.into_option() {
+        Some(err) if err.read_only => Err(Error::ReadOnly(err.message)),
         Some(err) => Err(Error::Internal(err.message)),
         _ => Ok(()),
     }
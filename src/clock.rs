@@ -0,0 +1,236 @@
+use crate::Error;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A hybrid logical clock timestamp. It consists of a physical wall clock time
+/// in milliseconds since the UNIX epoch, and a logical counter which orders
+/// events within the same millisecond. Timestamps order by wall time first.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Timestamp {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+impl Timestamp {
+    /// Returns the timestamp following this one.
+    fn next(self) -> Self {
+        match self.logical.checked_add(1) {
+            Some(logical) => Self {
+                wall: self.wall,
+                logical,
+            },
+            None => Self {
+                wall: self.wall + 1,
+                logical: 0,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.wall, self.logical)
+    }
+}
+
+/// A hybrid logical clock, as described by Kulkarni et al. in "Logical
+/// Physical Clocks and Consistent Snapshots in Globally Distributed Databases".
+/// Timestamps are close to wall clock time, but are strictly increasing and
+/// respect causality across nodes as long as every message received from
+/// another node is passed to update(), regardless of clock skew.
+#[derive(Debug)]
+pub struct Clock {
+    /// The last timestamp issued or observed.
+    last: Mutex<Timestamp>,
+    /// The physical clock, in milliseconds since the UNIX epoch.
+    physical: fn() -> u64,
+    /// The maximum expected offset between node clocks in milliseconds, or 0
+    /// if unbounded.
+    max_offset: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    /// Creates a new clock using the system wall clock.
+    pub fn new() -> Self {
+        Self::with_physical(system_time)
+    }
+
+    /// Creates a new clock using the given physical clock.
+    pub fn with_physical(physical: fn() -> u64) -> Self {
+        Self {
+            last: Mutex::new(Timestamp::default()),
+            physical,
+            max_offset: 0,
+        }
+    }
+
+    /// Sets the maximum expected clock offset between nodes, in milliseconds.
+    pub fn max_offset(mut self, max_offset: u64) -> Self {
+        self.max_offset = max_offset;
+        self
+    }
+
+    /// Checks that a remote timestamp is not ahead of the local physical clock
+    /// by more than the maximum offset. Since a remote timestamp is never
+    /// behind the remote physical clock, this detects remote clocks that are
+    /// too far ahead, and remote clocks that are too far behind are detected
+    /// by the remote node.
+    pub fn check_offset(&self, remote: Timestamp) -> Result<(), Error> {
+        let offset = remote.wall.saturating_sub((self.physical)());
+        if self.max_offset > 0 && offset > self.max_offset {
+            return Err(Error::Value(format!(
+                "Clock offset {}ms exceeds maximum offset {}ms",
+                offset, self.max_offset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns a new timestamp for a local event, greater than any timestamp
+    /// previously issued or observed.
+    pub fn now(&self) -> Result<Timestamp, Error> {
+        let mut last = self.last.lock()?;
+        let wall = (self.physical)();
+        *last = if wall > last.wall {
+            Timestamp { wall, logical: 0 }
+        } else {
+            last.next()
+        };
+        Ok(*last)
+    }
+
+    /// Observes a timestamp received from another node, such that subsequent
+    /// timestamps are greater than it. Returns the updated clock timestamp.
+    pub fn update(&self, remote: Timestamp) -> Result<Timestamp, Error> {
+        let mut last = self.last.lock()?;
+        let wall = (self.physical)();
+        let max = std::cmp::max(*last, remote);
+        *last = if wall > max.wall {
+            Timestamp { wall, logical: 0 }
+        } else {
+            max.next()
+        };
+        Ok(*last)
+    }
+}
+
+/// Returns the system wall clock time in milliseconds since the UNIX epoch.
+fn system_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn now() {
+        let clock = Clock::with_physical(|| 100);
+        assert_eq!(
+            Timestamp {
+                wall: 100,
+                logical: 0
+            },
+            clock.now().unwrap()
+        );
+        assert_eq!(
+            Timestamp {
+                wall: 100,
+                logical: 1
+            },
+            clock.now().unwrap()
+        );
+
+        // Timestamps are monotonic even with the system clock
+        let clock = Clock::new();
+        let mut last = clock.now().unwrap();
+        for _ in 0..1000 {
+            let ts = clock.now().unwrap();
+            assert!(ts > last);
+            last = ts;
+        }
+    }
+
+    #[test]
+    fn update() {
+        let clock = Clock::with_physical(|| 100);
+
+        // A remote timestamp ahead of us moves the clock past it
+        let remote = Timestamp {
+            wall: 200,
+            logical: 3,
+        };
+        assert_eq!(
+            Timestamp {
+                wall: 200,
+                logical: 4
+            },
+            clock.update(remote).unwrap()
+        );
+        assert_eq!(
+            Timestamp {
+                wall: 200,
+                logical: 5
+            },
+            clock.now().unwrap()
+        );
+
+        // A remote timestamp behind us still advances the logical counter
+        assert_eq!(
+            Timestamp {
+                wall: 200,
+                logical: 6
+            },
+            clock.update(Timestamp::default()).unwrap()
+        );
+
+        // The physical clock takes over once it passes the remote time
+        let clock = Clock::with_physical(|| 300);
+        clock.update(remote).unwrap();
+        assert_eq!(
+            Timestamp {
+                wall: 300,
+                logical: 1
+            },
+            clock.now().unwrap()
+        );
+
+        // The logical counter overflows into the wall time
+        let clock = Clock::with_physical(|| 0);
+        let remote = Timestamp {
+            wall: 1,
+            logical: u32::MAX,
+        };
+        assert_eq!(
+            Timestamp {
+                wall: 2,
+                logical: 0
+            },
+            clock.update(remote).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_offset() {
+        let clock = Clock::with_physical(|| 1000).max_offset(500);
+        let at = |wall| Timestamp { wall, logical: 0 };
+        assert_eq!(Ok(()), clock.check_offset(at(0)));
+        assert_eq!(Ok(()), clock.check_offset(at(1500)));
+        assert_matches!(clock.check_offset(at(1501)), Err(Error::Value(_)));
+
+        let clock = Clock::with_physical(|| 1000);
+        assert_eq!(Ok(()), clock.check_offset(at(u64::MAX)));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::sql::audit::AuditLog;
+use crate::sql::session::Registry;
+use crate::sql::types::Row;
+use crate::sql::{Context, Parser, Plan, Storage};
+use crate::store::{DataDir, KVMemory, Lock, MemoryStats};
+use crate::Error;
+
+/// The client ID of embedded queries, as listed in system.sessions
+const EMBEDDED_CLIENT: &str = "embedded";
+
+/// An embedded, in-process SQL engine, which runs queries directly against a
+/// local store without any networking or Raft replication, like SQLite. Data
+/// directories used by an embedded engine must not be used by a node, since
+/// writes bypass the Raft log.
+pub struct Embedded {
+    storage: Storage,
+    sessions: Registry,
+    /// The in-memory store, if any
+    memory: Option<KVMemory>,
+    /// Held while the data directory is in use
+    _lock: Option<Lock>,
+}
+
+impl std::fmt::Debug for Embedded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Embedded")
+    }
+}
+
+impl Embedded {
+    /// Opens an embedded engine over a data directory, creating it if needed.
+    /// The directory is locked while the engine is in use.
+    pub fn open(data_dir: &DataDir) -> Result<Self, Error> {
+        let lock = data_dir.lock()?;
+        data_dir.check_layout()?;
+        data_dir.init()?;
+        Ok(Self {
+            storage: Storage::new(data_dir.open_state()?),
+            sessions: Registry::new(),
+            memory: None,
+            _lock: Some(lock),
+        })
+    }
+
+    /// Creates an embedded engine with in-memory storage
+    pub fn memory() -> Self {
+        Self::with_memory(KVMemory::new())
+    }
+
+    /// Creates an embedded engine with in-memory storage limited to a memory
+    /// budget in bytes, beyond which writes fail with an error.
+    pub fn memory_limited(max_bytes: usize) -> Self {
+        Self::with_memory(KVMemory::new().max_bytes(max_bytes))
+    }
+
+    /// Creates an embedded engine with the given in-memory store
+    fn with_memory(memory: KVMemory) -> Self {
+        Self {
+            storage: Storage::new(memory.clone()),
+            sessions: Registry::new(),
+            memory: Some(memory),
+            _lock: None,
+        }
+    }
+
+    /// Returns memory usage statistics for in-memory engines
+    pub fn memory_stats(&self) -> Result<Option<MemoryStats>, Error> {
+        self.memory.as_ref().map(|m| m.stats()).transpose()
+    }
+
+    /// Runs a query
+    pub fn query(&self, query: &str) -> Result<impl Iterator<Item = Result<Row, Error>>, Error> {
+        let session = self.sessions.register(EMBEDDED_CLIENT, query)?;
+        let statement = Parser::new(query).parse()?;
+        session.variables().check_role(&statement)?;
+        let result = Plan::build(statement)?.execute(Context {
+            storage: Box::new(self.storage.clone()),
+            sessions: self.sessions.clone(),
+            // Embedded queries are run by the application itself, so aren't audited
+            audit: AuditLog::new(),
+            progress: session.progress().clone(),
+            cancellation: session.cancellation().clone(),
+            variables: session.variables().clone(),
+            temporary: session.temporary().clone(),
+            user: None,
+        })?;
+        Ok(session.guard(result))
+    }
+
+    /// Lists tables in the current database
+    pub fn list_tables(&self) -> Result<Vec<String>, Error> {
+        self.current_storage()?.list_tables()
+    }
+
+    /// Fetches the table schema as SQL
+    pub fn get_table(&self, table: &str) -> Result<String, Error> {
+        Ok(self.current_storage()?.get_table(table)?.to_query())
+    }
+
+    /// Returns the storage of the current database, see USE
+    fn current_storage(&self) -> Result<Storage, Error> {
+        Ok(self
+            .storage
+            .database(&self.sessions.database(EMBEDDED_CLIENT)?))
+    }
+
+    /// Dumps the database schema as CREATE TABLE statements, see
+    /// Client::dump_schema().
+    pub fn dump_schema(&self) -> Result<Vec<String>, Error> {
+        self.list_tables()?
+            .iter()
+            .map(|table| self.get_table(table))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::Value;
+
+    #[test]
+    fn query() -> Result<(), Error> {
+        let engine = Embedded::memory();
+        engine
+            .query("CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL)")?
+            .count();
+        engine
+            .query("INSERT INTO movies VALUES (1, 'Sicario'), (2, 'Stalker')")?
+            .count();
+        let rows = engine
+            .query("SELECT * FROM movies")?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            vec![
+                vec![Value::Integer(1), Value::String("Sicario".into())],
+                vec![Value::Integer(2), Value::String("Stalker".into())]
+            ],
+            rows
+        );
+        assert_eq!(vec!["movies".to_string()], engine.list_tables()?);
+        assert_matches!(engine.query("SELECT * FROM").err(), Some(Error::Parse(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn variables() -> Result<(), Error> {
+        let engine = Embedded::memory();
+        engine
+            .query("CREATE TABLE t (id INTEGER PRIMARY KEY)")?
+            .count();
+        engine.query("INSERT INTO t VALUES (1), (2), (3)")?.count();
+        engine.query("SET max_rows = 2")?.count();
+        assert_eq!(2, engine.query("SELECT * FROM t")?.count());
+        assert_eq!(
+            vec![vec![Value::Integer(2)]],
+            engine
+                .query("SHOW max_rows")?
+                .collect::<Result<Vec<_>, _>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn databases() -> Result<(), Error> {
+        let engine = Embedded::memory();
+        engine
+            .query("CREATE TABLE t (id INTEGER PRIMARY KEY)")?
+            .count();
+        engine.query("INSERT INTO t VALUES (1)")?.count();
+        engine.query("CREATE DATABASE other")?.count();
+        assert_matches!(engine.query("USE missing").err(), Some(Error::Value(_)));
+        engine.query("USE other")?.count();
+        assert!(engine.list_tables()?.is_empty());
+        engine
+            .query("CREATE TABLE t (id INTEGER PRIMARY KEY)")?
+            .count();
+        engine.query("INSERT INTO t VALUES (2), (3)")?.count();
+        assert_eq!(2, engine.query("SELECT * FROM t")?.count());
+        assert_eq!(vec!["t".to_string()], engine.list_tables()?);
+
+        engine.query("USE default")?.count();
+        assert_eq!(1, engine.query("SELECT * FROM t")?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn batch() -> Result<(), Error> {
+        let engine = Embedded::memory();
+        engine
+            .query(
+                "CREATE TABLE a (id INTEGER PRIMARY KEY); CREATE TABLE b (id INTEGER PRIMARY KEY);",
+            )?
+            .count();
+        assert_eq!(
+            vec!["a".to_string(), "b".to_string()],
+            engine.list_tables()?
+        );
+
+        // A failing statement rolls back the whole batch
+        assert_matches!(
+            engine
+                .query("DROP TABLE a; CREATE TABLE c (id INTEGER PRIMARY KEY); CREATE TABLE b (id INTEGER PRIMARY KEY)")
+                .err(),
+            Some(Error::Value(_))
+        );
+        assert_eq!(
+            vec!["a".to_string(), "b".to_string()],
+            engine.list_tables()?
+        );
+        assert_matches!(
+            engine.query("DROP TABLE a; INSERT INTO b VALUES (1)").err(),
+            Some(Error::Value(_))
+        );
+        assert_eq!(
+            vec!["a".to_string(), "b".to_string()],
+            engine.list_tables()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn explain_analyze() -> Result<(), Error> {
+        let engine = Embedded::memory();
+        engine
+            .query("CREATE TABLE t (id INTEGER PRIMARY KEY)")?
+            .count();
+        let explain = |query: &str| -> Result<Vec<String>, Error> {
+            engine
+                .query(query)?
+                .map(|row| match row?.as_slice() {
+                    [Value::String(line)] => Ok(line.clone()),
+                    row => Err(Error::Value(format!("Unexpected row {:?}", row))),
+                })
+                .collect()
+        };
+
+        // Mutations are executed
+        let lines = explain("EXPLAIN ANALYZE INSERT INTO t VALUES (1), (2), (3)")?;
+        assert_eq!(1, lines.len());
+        assert!(lines[0].starts_with("Insert t (rows=0 batches=0 time="));
+        assert_eq!(3, engine.query("SELECT * FROM t")?.count());
+
+        let lines = explain("EXPLAIN ANALYZE SELECT 1 AS one FROM t")?;
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("Projection one (rows=3 batches=1 time="));
+        assert!(lines[1].starts_with("  Scan t (rows=3 batches=1 time="));
+        Ok(())
+    }
+
+    #[test]
+    fn memory_limited() -> Result<(), Error> {
+        let engine = Embedded::memory_limited(1024);
+        engine
+            .query("CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL)")?
+            .count();
+        let used = engine.memory_stats()?.unwrap().bytes;
+        assert!(used > 0);
+        let title = "x".repeat(1024);
+        assert_matches!(
+            engine
+                .query(&format!("INSERT INTO movies VALUES (1, '{}')", title))
+                .err(),
+            Some(Error::ResourceExhausted(_))
+        );
+        assert_eq!(used, engine.memory_stats()?.unwrap().bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn open() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let data_dir = DataDir::new(dir.path());
+        {
+            let engine = Embedded::open(&data_dir)?;
+            assert_matches!(Embedded::open(&data_dir), Err(Error::Value(_)));
+            engine
+                .query("CREATE TABLE movies (id INTEGER PRIMARY KEY)")?
+                .count();
+            engine.query("INSERT INTO movies VALUES (1)")?.count();
+        }
+        let engine = Embedded::open(&data_dir)?;
+        let rows = engine
+            .query("SELECT * FROM movies")?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(vec![vec![Value::Integer(1)]], rows);
+        Ok(())
+    }
+}
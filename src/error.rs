@@ -6,12 +6,30 @@ pub enum Error {
     Internal(String),
     Network(String),
     Parse(String),
+    PermissionDenied(String),
+    ReadOnly(String),
+    ResourceExhausted(String),
+    Unauthenticated(String),
     Value(String),
     NotFound,
 }
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Converts the payload of a caught panic into an internal error.
+    pub fn from_panic(payload: Box<dyn std::any::Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown cause".into(),
+            },
+        };
+        Error::Internal(format!("Panicked: {}", message))
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -20,6 +38,10 @@ impl std::fmt::Display for Error {
             | Error::Internal(s)
             | Error::Network(s)
             | Error::Parse(s)
+            | Error::PermissionDenied(s)
+            | Error::ReadOnly(s)
+            | Error::ResourceExhausted(s)
+            | Error::Unauthenticated(s)
             | Error::Value(s) => write!(f, "{}", s),
             Error::NotFound => write!(f, "not found"),
             Error::RaftBaseNotFound { index, term } => {
@@ -33,18 +55,38 @@ impl std::fmt::Display for Error {
     }
 }
 
+#[cfg(feature = "server")]
 impl From<config::ConfigError> for Error {
     fn from(err: config::ConfigError) -> Self {
         Error::Config(err.to_string())
     }
 }
 
+#[cfg(feature = "server")]
 impl From<grpc::Error> for Error {
     fn from(err: grpc::Error) -> Self {
-        Error::Network(err.to_string())
+        match err {
+            grpc::Error::GrpcMessage(ref msg)
+                if msg.grpc_status == grpc::GrpcStatus::ResourceExhausted as i32 =>
+            {
+                Error::ResourceExhausted(msg.grpc_message.clone())
+            }
+            grpc::Error::GrpcMessage(ref msg)
+                if msg.grpc_status == grpc::GrpcStatus::Unauthenticated as i32 =>
+            {
+                Error::Unauthenticated(msg.grpc_message.clone())
+            }
+            grpc::Error::GrpcMessage(ref msg)
+                if msg.grpc_status == grpc::GrpcStatus::PermissionDenied as i32 =>
+            {
+                Error::PermissionDenied(msg.grpc_message.clone())
+            }
+            _ => Error::Network(err.to_string()),
+        }
     }
 }
 
+#[cfg(feature = "server")]
 impl From<httpbis::Error> for Error {
     fn from(err: httpbis::Error) -> Self {
         Error::Network(err.to_string())
@@ -63,6 +105,12 @@ impl From<log::SetLoggerError> for Error {
     }
 }
 
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
 impl From<rmps::decode::Error> for Error {
     fn from(err: rmps::decode::Error) -> Self {
         Error::IO(err.to_string())
@@ -75,12 +123,19 @@ impl From<rmps::encode::Error> for Error {
     }
 }
 
+#[cfg(feature = "server")]
 impl From<rustyline::error::ReadlineError> for Error {
     fn from(err: rustyline::error::ReadlineError) -> Self {
         Error::Internal(err.to_string())
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Error::IO(err.to_string())
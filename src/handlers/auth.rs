@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::io::{Read as _, Write as _};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// The request metadata keys carrying client credentials: either a user name
+/// and password, or a token.
+pub const USER_METADATA: &str = "user";
+pub const PASSWORD_METADATA: &str = "password";
+pub const TOKEN_METADATA: &str = "token";
+
+/// How long an external authentication command may run before it's killed
+/// and the request fails.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long credentials accepted by an external authentication command are
+/// accepted without running it again.
+const COMMAND_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Credentials presented by a client.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Credentials {
+    Password { user: String, password: String },
+    Token(String),
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Password { user, .. } => write!(f, "Password {{ user: {:?} }}", user),
+            Credentials::Token(_) => write!(f, "Token"),
+        }
+    }
+}
+
+impl Credentials {
+    /// Returns the credentials carried by request metadata, if any.
+    pub fn from_metadata(metadata: &grpc::Metadata) -> Option<Self> {
+        let get = |key| {
+            metadata
+                .get(key)
+                .and_then(|value| std::str::from_utf8(value).ok())
+                .map(|value| value.to_string())
+        };
+        match (
+            get(USER_METADATA),
+            get(PASSWORD_METADATA),
+            get(TOKEN_METADATA),
+        ) {
+            (_, _, Some(token)) => Some(Credentials::Token(token)),
+            (Some(user), Some(password), None) => Some(Credentials::Password { user, password }),
+            _ => None,
+        }
+    }
+
+    /// Adds the credentials to request metadata.
+    pub fn to_metadata(&self, metadata: &mut grpc::Metadata) {
+        let mut add = |key, value: &str| {
+            metadata.add(
+                grpc::MetadataKey::from(key),
+                value.as_bytes().to_vec().into(),
+            )
+        };
+        match self {
+            Credentials::Password { user, password } => {
+                add(USER_METADATA, user);
+                add(PASSWORD_METADATA, password);
+            }
+            Credentials::Token(token) => add(TOKEN_METADATA, token),
+        }
+    }
+}
+
+/// An authentication backend, which verifies client credentials. Nodes
+/// authenticate every client request except status requests when a backend
+/// is configured.
+pub trait Authenticator: Send + Sync {
+    /// Verifies credentials, returning the authenticated user name, or an
+    /// Unauthenticated error if they're invalid or unsupported.
+    fn authenticate(&self, credentials: &Credentials) -> Result<String, Error>;
+}
+
+/// Returns the error for rejected credentials. It deliberately doesn't say
+/// why they were rejected, e.g. whether the user exists.
+fn rejected() -> Error {
+    Error::Unauthenticated("Invalid credentials".into())
+}
+
+/// Compares secrets in constant time, to not leak their contents via timing.
+fn secrets_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Authenticates users against a password file. Each line contains a user
+/// name and password separated by a colon. Blank lines and lines starting
+/// with # are ignored. Passwords are stored in plain text, so the file must
+/// only be readable by the node.
+#[derive(Clone)]
+pub struct PasswordFile {
+    passwords: HashMap<String, String>,
+}
+
+impl std::fmt::Debug for PasswordFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let users: Vec<&String> = self.passwords.keys().collect();
+        write!(f, "PasswordFile {:?}", users)
+    }
+}
+
+impl PasswordFile {
+    /// Loads a password file.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a password file, see PasswordFile for the format.
+    pub fn parse(passwords: &str) -> Result<Self, Error> {
+        let mut file = Self {
+            passwords: HashMap::new(),
+        };
+        for line in passwords.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((user, password)) if !user.is_empty() && !password.is_empty() => {
+                    if file
+                        .passwords
+                        .insert(user.to_string(), password.to_string())
+                        .is_some()
+                    {
+                        return Err(Error::Config(format!("Duplicate user {}", user)));
+                    }
+                }
+                _ => {
+                    return Err(Error::Config(
+                        "Expected user:password in password file".into(),
+                    ))
+                }
+            }
+        }
+        Ok(file)
+    }
+}
+
+impl Authenticator for PasswordFile {
+    fn authenticate(&self, credentials: &Credentials) -> Result<String, Error> {
+        match credentials {
+            Credentials::Password { user, password } => match self.passwords.get(user) {
+                Some(expected) if secrets_equal(password, expected) => Ok(user.clone()),
+                _ => Err(rejected()),
+            },
+            Credentials::Token(_) => Err(rejected()),
+        }
+    }
+}
+
+/// Authenticates clients against a static list of tokens, each belonging to
+/// a user, e.g. for service accounts.
+#[derive(Clone)]
+pub struct StaticTokens {
+    /// Maps tokens to user names.
+    tokens: HashMap<String, String>,
+}
+
+impl std::fmt::Debug for StaticTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let users: Vec<&String> = self.tokens.values().collect();
+        write!(f, "StaticTokens {:?}", users)
+    }
+}
+
+impl StaticTokens {
+    /// Creates a token list from a map of tokens to user names.
+    pub fn new(tokens: HashMap<String, String>) -> Self {
+        Self { tokens }
+    }
+}
+
+impl Authenticator for StaticTokens {
+    fn authenticate(&self, credentials: &Credentials) -> Result<String, Error> {
+        match credentials {
+            Credentials::Token(token) => self
+                .tokens
+                .iter()
+                .find(|(t, _)| secrets_equal(token, t))
+                .map(|(_, user)| user.clone())
+                .ok_or_else(rejected),
+            Credentials::Password { .. } => Err(rejected()),
+        }
+    }
+}
+
+/// Authenticates clients by running an external command, e.g. to check
+/// credentials against LDAP or a hashed credential store. The credentials are
+/// written to the command's stdin as lines, either "password", the user name
+/// and the password, or "token" and the token. The command accepts them by
+/// exiting successfully, and may print the authenticated user name, which is
+/// required for tokens. It's killed if it runs for more than 5 seconds.
+/// Accepted credentials are cached for 30 seconds, so revocations by the
+/// command take effect within that time.
+#[derive(Clone, Debug)]
+pub struct ExternalCommand {
+    program: String,
+    args: Vec<String>,
+    /// Recently accepted credentials, with their user name and expiry time.
+    cache: Arc<Mutex<HashMap<Credentials, (String, Instant)>>>,
+}
+
+impl ExternalCommand {
+    /// Creates a command authenticator from a program and its arguments,
+    /// separated by whitespace.
+    pub fn new(command: &str) -> Result<Self, Error> {
+        let mut parts = command.split_whitespace().map(|s| s.to_string());
+        let program = parts
+            .next()
+            .ok_or_else(|| Error::Config("Authentication command can't be empty".into()))?;
+        Ok(Self {
+            program,
+            args: parts.collect(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Runs the command to verify credentials, returning the user name.
+    fn run(&self, credentials: &Credentials) -> Result<String, Error> {
+        let (lines, user) = match credentials {
+            Credentials::Password { user, password } => {
+                (vec!["password", user, password], Some(user))
+            }
+            Credentials::Token(token) => (vec!["token", token], None),
+        };
+        if lines.iter().any(|line| line.contains(['\n', '\r'])) {
+            return Err(rejected());
+        }
+        let input: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| {
+                Error::Internal(format!(
+                    "Failed to run authentication command {}: {}",
+                    self.program, err
+                ))
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // The command may exit without reading its input.
+            stdin.write_all(input.as_bytes()).ok();
+        }
+        // Stdout is drained on a separate thread, so the command can't block
+        // on a full pipe, until it's closed when the command exits.
+        let started = Instant::now();
+        let (tx, rx) = mpsc::channel();
+        if let Some(mut stdout) = child.stdout.take() {
+            std::thread::spawn(move || {
+                let mut output = Vec::new();
+                stdout.read_to_end(&mut output).ok();
+                tx.send(output).ok();
+            });
+        }
+        let output = match rx.recv_timeout(COMMAND_TIMEOUT) {
+            Ok(output) => output,
+            Err(_) => return Err(self.timed_out(&mut child)),
+        };
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= COMMAND_TIMEOUT {
+                return Err(self.timed_out(&mut child));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Err(rejected());
+        }
+        let stdout = String::from_utf8_lossy(&output);
+        match (stdout.lines().next().map(|l| l.trim()), user) {
+            (Some(name), _) if !name.is_empty() => Ok(name.to_string()),
+            (_, Some(user)) => Ok(user.clone()),
+            (_, None) => Err(rejected()),
+        }
+    }
+
+    /// Kills a command that ran out of time, returning the error.
+    fn timed_out(&self, child: &mut Child) -> Error {
+        child.kill().ok();
+        child.wait().ok();
+        Error::Internal(format!("Authentication command {} timed out", self.program))
+    }
+}
+
+impl Authenticator for ExternalCommand {
+    fn authenticate(&self, credentials: &Credentials) -> Result<String, Error> {
+        let now = Instant::now();
+        if let Some((user, expires)) = self.cache.lock()?.get(credentials) {
+            if *expires > now {
+                return Ok(user.clone());
+            }
+        }
+        let user = self.run(credentials)?;
+        let mut cache = self.cache.lock()?;
+        cache.retain(|_, (_, expires)| *expires > now);
+        cache.insert(credentials.clone(), (user.clone(), now + COMMAND_CACHE_TTL));
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn password(user: &str, password: &str) -> Credentials {
+        Credentials::Password {
+            user: user.into(),
+            password: password.into(),
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        let mut metadata = grpc::Metadata::new();
+        assert_eq!(None, Credentials::from_metadata(&metadata));
+        password("alice", "secret").to_metadata(&mut metadata);
+        assert_eq!(
+            Some(password("alice", "secret")),
+            Credentials::from_metadata(&metadata)
+        );
+
+        let mut metadata = grpc::Metadata::new();
+        Credentials::Token("t0k3n".into()).to_metadata(&mut metadata);
+        assert_eq!(
+            Some(Credentials::Token("t0k3n".into())),
+            Credentials::from_metadata(&metadata)
+        );
+        assert_eq!("Token", format!("{:?}", Credentials::Token("t0k3n".into())));
+    }
+
+    #[test]
+    fn password_file() -> Result<(), Error> {
+        let file = PasswordFile::parse("# Users\nalice:secret\n\nbob:pass:word\n")?;
+        assert_eq!(
+            Ok("alice".into()),
+            file.authenticate(&password("alice", "secret"))
+        );
+        assert_eq!(
+            Ok("bob".into()),
+            file.authenticate(&password("bob", "pass:word"))
+        );
+        for credentials in &[
+            password("alice", "secre"),
+            password("alice", "secrets"),
+            password("carol", "secret"),
+            Credentials::Token("secret".into()),
+        ] {
+            assert_matches!(
+                file.authenticate(credentials),
+                Err(Error::Unauthenticated(_))
+            );
+        }
+
+        for invalid in &["alice", "alice:", ":secret", "alice:a\nalice:b"] {
+            assert_matches!(PasswordFile::parse(invalid), Err(Error::Config(_)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn static_tokens() {
+        let tokens = StaticTokens::new(
+            vec![("t0k3n".to_string(), "svc".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(
+            Ok("svc".into()),
+            tokens.authenticate(&Credentials::Token("t0k3n".into()))
+        );
+        assert_matches!(
+            tokens.authenticate(&Credentials::Token("t0k3".into())),
+            Err(Error::Unauthenticated(_))
+        );
+        assert_matches!(
+            tokens.authenticate(&password("svc", "t0k3n")),
+            Err(Error::Unauthenticated(_))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn external_command() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("auth.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/sh\n\
+             read kind; read a; read b\n\
+             [ \"$kind\" = token ] && [ \"$a\" = t0k3n ] && echo svc && exit 0\n\
+             [ \"$kind\" = password ] && [ \"$b\" = secret ] && exit 0\n\
+             exit 1\n",
+        )?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        let command = ExternalCommand::new(path.to_str().unwrap())?;
+
+        assert_eq!(
+            Ok("alice".into()),
+            command.authenticate(&password("alice", "secret"))
+        );
+        assert_eq!(
+            Ok("svc".into()),
+            command.authenticate(&Credentials::Token("t0k3n".into()))
+        );
+        assert_matches!(
+            command.authenticate(&password("alice", "wrong")),
+            Err(Error::Unauthenticated(_))
+        );
+        assert_matches!(
+            command.authenticate(&password("alice\nsecret", "x")),
+            Err(Error::Unauthenticated(_))
+        );
+
+        // Accepted credentials are cached, rejected ones aren't.
+        std::fs::write(&path, "#!/bin/sh\nexit 1\n")?;
+        assert_eq!(
+            Ok("alice".into()),
+            command.authenticate(&password("alice", "secret"))
+        );
+        assert_matches!(
+            command.authenticate(&password("alice", "wrong")),
+            Err(Error::Unauthenticated(_))
+        );
+
+        // Output larger than the pipe buffer doesn't block the command.
+        std::fs::write(
+            &path,
+            "#!/bin/sh\necho svc\nhead -c 1000000 /dev/zero | tr '\\0' x\nexit 0\n",
+        )?;
+        assert_eq!(
+            Ok("svc".into()),
+            ExternalCommand::new(path.to_str().unwrap())?
+                .authenticate(&Credentials::Token("t0k3n".into()))
+        );
+
+        assert_matches!(ExternalCommand::new(" "), Err(Error::Config(_)));
+        assert_matches!(
+            ExternalCommand::new("/nonexistent/auth")?.authenticate(&password("a", "b")),
+            Err(Error::Internal(_))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::raft::RaftEvent;
+use crate::store::DataDir;
+use crate::Error;
+use crossbeam_channel::Receiver;
+use std::sync::{Arc, RwLock};
+
+/// The cluster ID, which protects against mixing messages between clusters
+/// (e.g. when configs are copy-pasted). It is generated when a node of a new
+/// cluster first becomes leader, including single-node clusters, adopted by
+/// other nodes from leader messages, and persisted in the data directory.
+#[derive(Debug)]
+pub struct ClusterId {
+    id: RwLock<Option<String>>,
+    data_dir: DataDir,
+}
+
+impl ClusterId {
+    /// Loads the cluster ID from a data directory, if any.
+    pub fn load(data_dir: DataDir) -> Result<Self, Error> {
+        Ok(Self {
+            id: RwLock::new(data_dir.cluster_id()?),
+            data_dir,
+        })
+    }
+
+    /// Returns the cluster ID, or None if the node has not joined a cluster yet.
+    pub fn get(&self) -> Result<Option<String>, Error> {
+        Ok(self.id.read()?.clone())
+    }
+
+    /// Bootstraps a new cluster ID, if the node does not already have one.
+    /// Called when the node sends leader messages.
+    pub fn bootstrap(&self) -> Result<String, Error> {
+        if let Some(id) = self.get()? {
+            return Ok(id);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        self.adopt(&id)?;
+        info!("Bootstrapped new cluster with ID {}", id);
+        Ok(id)
+    }
+
+    /// Bootstraps the cluster ID when the local node becomes leader, from an
+    /// event subscription in a separate thread.
+    pub fn track(self: &Arc<Self>, events: Receiver<RaftEvent>) {
+        let cluster = self.clone();
+        std::thread::spawn(move || {
+            for event in events {
+                if let RaftEvent::BecameLeader { .. } = event {
+                    if let Err(err) = cluster.bootstrap() {
+                        error!("Failed to bootstrap cluster ID: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Checks the cluster ID of an inbound message. All messages are accepted
+    /// if this node has not joined a cluster, in which case it adopts the ID of
+    /// leader messages. Once joined, messages must carry the same ID.
+    pub fn check(&self, remote: &str, from_leader: bool) -> Result<(), Error> {
+        match self.get()? {
+            Some(ref local) if local == remote => Ok(()),
+            Some(local) if remote.is_empty() => Err(Error::Value(format!(
+                "Cluster ID missing, expected {}",
+                local
+            ))),
+            Some(local) => Err(Error::Value(format!(
+                "Cluster ID mismatch: local {}, remote {}",
+                local, remote
+            ))),
+            None if from_leader && !remote.is_empty() => {
+                info!("Joined cluster with ID {}", remote);
+                self.adopt(remote)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sets and persists the cluster ID, unless one is already set.
+    fn adopt(&self, id: &str) -> Result<(), Error> {
+        let mut current = self.id.write()?;
+        if current.is_none() {
+            self.data_dir.set_cluster_id(id)?;
+            *current = Some(id.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+    use super::*;
+
+    #[test]
+    fn check() {
+        let dir = tempfile::tempdir().unwrap();
+        let cluster = ClusterId::load(DataDir::new(dir.path())).unwrap();
+        assert_eq!(None, cluster.get().unwrap());
+
+        // Unjoined nodes accept anything, but only adopt IDs from leaders
+        cluster.check("", true).unwrap();
+        cluster.check("a", false).unwrap();
+        assert_eq!(None, cluster.get().unwrap());
+        cluster.check("a", true).unwrap();
+        assert_eq!(Some("a".to_string()), cluster.get().unwrap());
+
+        cluster.check("a", false).unwrap();
+        assert_matches!(cluster.check("", false), Err(Error::Value(_)));
+        assert_matches!(cluster.check("b", true), Err(Error::Value(_)));
+
+        // The ID is persisted
+        let cluster = ClusterId::load(DataDir::new(dir.path())).unwrap();
+        assert_eq!(Some("a".to_string()), cluster.get().unwrap());
+        assert_eq!("a", cluster.bootstrap().unwrap());
+    }
+
+    #[test]
+    fn bootstrap() {
+        let dir = tempfile::tempdir().unwrap();
+        let cluster = ClusterId::load(DataDir::new(dir.path())).unwrap();
+        let id = cluster.bootstrap().unwrap();
+        assert_eq!(id, cluster.bootstrap().unwrap());
+        assert_matches!(cluster.check("b", true), Err(Error::Value(_)));
+
+        let cluster = ClusterId::load(DataDir::new(dir.path())).unwrap();
+        assert_eq!(Some(id), cluster.get().unwrap());
+    }
+
+    #[test]
+    fn track() {
+        let dir = tempfile::tempdir().unwrap();
+        let cluster = Arc::new(ClusterId::load(DataDir::new(dir.path())).unwrap());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        cluster.track(rx);
+        tx.send(RaftEvent::ElectionStarted { term: 1 }).unwrap();
+        tx.send(RaftEvent::BecameLeader { term: 1 }).unwrap();
+        drop(tx);
+        for _ in 0..100 {
+            if cluster.get().unwrap().is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(cluster.get().unwrap().is_some());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::proto;
+use crate::sql::types::{Row, Value};
+use crate::Error;
+
+/// The request metadata key used to negotiate the result encoding
+pub const ENCODING_METADATA: &str = "encoding";
+/// The encoding value requesting columnar results
+pub const COLUMNAR_ENCODING: &str = "columnar";
+/// The maximum number of rows in a columnar batch
+pub const COLUMNAR_BATCH_SIZE: usize = 1024;
+
+const TYPE_NULL: u8 = 0;
+const TYPE_BOOLEAN: u8 = 1;
+const TYPE_INTEGER: u8 = 2;
+const TYPE_FLOAT: u8 = 3;
+const TYPE_STRING: u8 = 4;
+
+/// Returns true if the request metadata asks for columnar results.
+pub fn requested(metadata: &grpc::Metadata) -> bool {
+    metadata.get(ENCODING_METADATA) == Some(COLUMNAR_ENCODING.as_bytes())
+}
+
+/// Encodes a batch of rows as columns. Rows must have equal length.
+pub fn encode(rows: Vec<Row>) -> proto::ColumnBatch {
+    let width = rows.first().map(|r| r.len()).unwrap_or(0);
+    let mut columns = vec![proto::Column::new(); width];
+    for row in rows {
+        for (column, value) in columns.iter_mut().zip(row) {
+            let tag = match value {
+                Value::Null => TYPE_NULL,
+                Value::Boolean(b) => {
+                    column.boolean.push(b);
+                    TYPE_BOOLEAN
+                }
+                Value::Integer(i) => {
+                    column.integer.push(i);
+                    TYPE_INTEGER
+                }
+                Value::Float(f) => {
+                    column.float.push(f);
+                    TYPE_FLOAT
+                }
+                Value::String(s) => {
+                    column.string.push(s);
+                    TYPE_STRING
+                }
+            };
+            column.types.push(tag);
+        }
+    }
+    proto::ColumnBatch {
+        column: columns.into(),
+        ..Default::default()
+    }
+}
+
+/// Decodes a columnar batch into rows.
+pub fn decode(batch: proto::ColumnBatch) -> Result<Vec<Row>, Error> {
+    let length = batch.column.first().map(|c| c.types.len()).unwrap_or(0);
+    let mut rows = vec![Row::with_capacity(batch.column.len()); length];
+    for column in batch.column.into_iter() {
+        if column.types.len() != length {
+            return Err(Error::Internal("Columnar batch has uneven columns".into()));
+        }
+        let mut booleans = column.boolean.into_iter();
+        let mut integers = column.integer.into_iter();
+        let mut floats = column.float.into_iter();
+        let mut strings = column.string.into_iter();
+        for (row, tag) in rows.iter_mut().zip(column.types) {
+            let value = match tag {
+                TYPE_NULL => Some(Value::Null),
+                TYPE_BOOLEAN => booleans.next().map(Value::Boolean),
+                TYPE_INTEGER => integers.next().map(Value::Integer),
+                TYPE_FLOAT => floats.next().map(Value::Float),
+                TYPE_STRING => strings.next().map(Value::String),
+                t => return Err(Error::Internal(format!("Unknown column type {}", t))),
+            };
+            row.push(
+                value.ok_or_else(|| Error::Internal("Columnar batch is missing values".into()))?,
+            );
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let rows = vec![
+            vec![
+                Value::Integer(1),
+                Value::String("a".into()),
+                Value::Null,
+                Value::Boolean(true),
+            ],
+            vec![
+                Value::Integer(2),
+                Value::Null,
+                Value::Float(2.5),
+                Value::Boolean(false),
+            ],
+        ];
+        let batch = encode(rows.clone());
+        assert_eq!(4, batch.column.len());
+        assert_eq!(vec![1, 2], batch.column[0].integer);
+        assert_eq!(vec![TYPE_STRING, TYPE_NULL], batch.column[1].types);
+        assert_eq!(rows, decode(batch).unwrap());
+
+        assert_eq!(Vec::<Row>::new(), decode(encode(Vec::new())).unwrap());
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let mut batch = encode(vec![vec![Value::Integer(1), Value::Integer(2)]]);
+        batch.column[1].integer.clear();
+        assert_matches!(decode(batch), Err(Error::Internal(_)));
+
+        let mut batch = encode(vec![vec![Value::Integer(1), Value::Integer(2)]]);
+        batch.column[1].types.push(TYPE_NULL);
+        assert_matches!(decode(batch), Err(Error::Internal(_)));
+    }
+}
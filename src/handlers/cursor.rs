@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::sql::types::Row;
+use crate::Error;
+
+/// A cursor result row iterator
+pub type Rows = Box<dyn Iterator<Item = Result<Row, Error>> + Send>;
+
+/// The maximum number of open cursors
+pub const MAX_CURSORS: usize = 1024;
+/// How long a cursor can go without fetches before it's closed
+pub const CURSOR_TIMEOUT: Duration = Duration::from_secs(300);
+/// The maximum number of rows returned by a single fetch
+pub const MAX_FETCH_ROWS: usize = 10000;
+
+/// An open cursor
+struct Cursor {
+    /// The client which opened the cursor, i.e. the authenticated user if
+    /// any, see RateLimiter::principal()
+    client: String,
+    /// The remaining result rows
+    rows: Rows,
+    /// When the cursor was last used
+    used: Instant,
+}
+
+/// Server-side cursors, holding paused query results which clients fetch a
+/// batch of rows at a time, under their own flow control. Cursors are closed
+/// once exhausted, when closed by the client, or after going unused for the
+/// timeout.
+pub struct Cursors {
+    inner: Mutex<Inner>,
+    max_cursors: usize,
+    timeout: Duration,
+}
+
+struct Inner {
+    next_id: u64,
+    cursors: HashMap<u64, Cursor>,
+}
+
+impl std::fmt::Debug for Cursors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cursors")
+    }
+}
+
+impl Cursors {
+    /// Creates a new cursor set
+    pub fn new(max_cursors: usize, timeout: Duration) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                next_id: 0,
+                cursors: HashMap::new(),
+            }),
+            max_cursors,
+            timeout,
+        }
+    }
+
+    /// Opens a cursor over result rows for a client, returning its ID
+    pub fn open(&self, client: &str, rows: Rows) -> Result<u64, Error> {
+        let mut inner = self.inner.lock()?;
+        self.expire(&mut inner);
+        if inner.cursors.len() >= self.max_cursors {
+            return Err(Error::ResourceExhausted(format!(
+                "Too many open cursors, limit is {}",
+                self.max_cursors
+            )));
+        }
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.cursors.insert(
+            id,
+            Cursor {
+                client: client.to_string(),
+                rows,
+                used: Instant::now(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Fetches up to count rows from a cursor, returning the rows and whether
+    /// the cursor is exhausted. Exhausted and failed cursors are closed.
+    pub fn fetch(&self, client: &str, id: u64, count: usize) -> Result<(Vec<Row>, bool), Error> {
+        if count > MAX_FETCH_ROWS {
+            return Err(Error::Value(format!(
+                "Can't fetch {} rows, limit is {}",
+                count, MAX_FETCH_ROWS
+            )));
+        }
+        // Take the cursor out while fetching, so other cursors aren't blocked.
+        let mut cursor = self.take(client, id)?;
+        let mut rows = Vec::new();
+        while rows.len() < count {
+            match cursor.rows.next() {
+                Some(row) => rows.push(row?),
+                None => return Ok((rows, true)),
+            }
+        }
+        cursor.used = Instant::now();
+        self.inner.lock()?.cursors.insert(id, cursor);
+        Ok((rows, false))
+    }
+
+    /// Closes a cursor
+    pub fn close(&self, client: &str, id: u64) -> Result<(), Error> {
+        self.take(client, id).map(|_| ())
+    }
+
+    /// Removes a client's cursor from the set
+    fn take(&self, client: &str, id: u64) -> Result<Cursor, Error> {
+        let mut inner = self.inner.lock()?;
+        self.expire(&mut inner);
+        match inner.cursors.remove(&id) {
+            Some(cursor) if cursor.client == client => Ok(cursor),
+            Some(cursor) => {
+                inner.cursors.insert(id, cursor);
+                Err(Error::Value(format!("Cursor {} does not exist", id)))
+            }
+            None => Err(Error::Value(format!("Cursor {} does not exist", id))),
+        }
+    }
+
+    /// Closes cursors which have gone unused for the timeout
+    fn expire(&self, inner: &mut Inner) {
+        let timeout = self.timeout;
+        inner.cursors.retain(|id, cursor| {
+            let expired = cursor.used.elapsed() >= timeout;
+            if expired {
+                debug!("Closing cursor {} after {:?} unused", id, timeout);
+            }
+            !expired
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::Value;
+
+    fn rows(n: i64) -> Rows {
+        Box::new((1..=n).map(|i| Ok(vec![Value::Integer(i)])))
+    }
+
+    #[test]
+    fn fetch() {
+        let cursors = Cursors::new(MAX_CURSORS, CURSOR_TIMEOUT);
+        let id = cursors.open("a", rows(5)).unwrap();
+
+        let (batch, done) = cursors.fetch("a", id, 2).unwrap();
+        assert_eq!(
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]],
+            batch
+        );
+        assert!(!done);
+
+        // Other clients can't access the cursor
+        assert_matches!(cursors.fetch("b", id, 2), Err(Error::Value(_)));
+        assert_matches!(cursors.close("b", id), Err(Error::Value(_)));
+        assert_matches!(
+            cursors.fetch("a", id, MAX_FETCH_ROWS + 1),
+            Err(Error::Value(_))
+        );
+
+        let (batch, done) = cursors.fetch("a", id, 2).unwrap();
+        assert_eq!(2, batch.len());
+        assert!(!done);
+        let (batch, done) = cursors.fetch("a", id, 2).unwrap();
+        assert_eq!(vec![vec![Value::Integer(5)]], batch);
+        assert!(done);
+
+        // Exhausted cursors are closed
+        assert_matches!(cursors.fetch("a", id, 2), Err(Error::Value(_)));
+        assert_eq!(0, cursors.inner.lock().unwrap().cursors.len());
+
+        let id = cursors.open("a", rows(5)).unwrap();
+        cursors.close("a", id).unwrap();
+        assert_matches!(cursors.fetch("a", id, 2), Err(Error::Value(_)));
+
+        // Failed cursors are closed
+        let failing: Rows = Box::new(std::iter::once(Err(Error::Value("boom".into()))));
+        let id = cursors.open("a", failing).unwrap();
+        assert_matches!(cursors.fetch("a", id, 2), Err(Error::Value(_)));
+        assert_eq!(0, cursors.inner.lock().unwrap().cursors.len());
+    }
+
+    #[test]
+    fn limits() {
+        let cursors = Cursors::new(2, CURSOR_TIMEOUT);
+        cursors.open("a", rows(1)).unwrap();
+        cursors.open("a", rows(1)).unwrap();
+        assert_matches!(cursors.open("a", rows(1)), Err(Error::ResourceExhausted(_)));
+
+        let cursors = Cursors::new(2, Duration::from_millis(0));
+        let id = cursors.open("a", rows(1)).unwrap();
+        assert_matches!(cursors.fetch("a", id, 1), Err(Error::Value(_)));
+        assert_eq!(0, cursors.inner.lock().unwrap().cursors.len());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::Error;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often the available disk space is checked.
+pub const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A disk space watchdog, which fences writes submitted through the node while
+/// the space available under its data directory is below a threshold, rather
+/// than letting the stores fail with partial writes once the disk is full.
+/// Writes are accepted again once the available space is 10% above the
+/// threshold, to avoid flapping around it.
+#[derive(Debug, Default)]
+pub struct DiskWatchdog {
+    /// The minimum available space in bytes, or 0 to disable the watchdog.
+    min_free: u64,
+    /// The available space in bytes as of the last check.
+    available: AtomicU64,
+    /// Whether writes are fenced.
+    fenced: AtomicBool,
+}
+
+impl DiskWatchdog {
+    /// Creates a new watchdog with the given minimum available space in
+    /// bytes, or 0 to disable it.
+    pub fn new(min_free: u64) -> Self {
+        Self {
+            min_free,
+            ..Default::default()
+        }
+    }
+
+    /// Checks the space available under a path, and keeps checking it
+    /// periodically in a background thread. Does nothing if disabled.
+    pub fn start(self: &Arc<Self>, path: &str) -> Result<(), Error> {
+        if self.min_free == 0 {
+            return Ok(());
+        }
+        self.update(fs2::available_space(path)?);
+        let watchdog = self.clone();
+        let path = path.to_string();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(DISK_CHECK_INTERVAL);
+            match fs2::available_space(&path) {
+                Ok(available) => watchdog.update(available),
+                Err(err) => error!("Failed to check available disk space in {}: {}", path, err),
+            }
+        });
+        Ok(())
+    }
+
+    /// Records the available space, fencing or unfencing writes as needed.
+    fn update(&self, available: u64) {
+        self.available.store(available, Ordering::SeqCst);
+        let fenced = if self.fenced.load(Ordering::SeqCst) {
+            available < self.min_free + self.min_free / 10
+        } else {
+            available < self.min_free
+        };
+        if self.fenced.swap(fenced, Ordering::SeqCst) != fenced {
+            if fenced {
+                error!(
+                    "Only {} bytes of disk space available, below the minimum of {}, rejecting writes",
+                    available, self.min_free
+                );
+            } else {
+                info!(
+                    "{} bytes of disk space available, accepting writes again",
+                    available
+                );
+            }
+        }
+    }
+
+    /// Returns the available space in bytes as of the last check, or 0 if
+    /// disabled.
+    pub fn available(&self) -> u64 {
+        self.available.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if writes are fenced.
+    pub fn is_fenced(&self) -> bool {
+        self.fenced.load(Ordering::SeqCst)
+    }
+
+    /// Returns an error if writes are fenced.
+    pub fn check_write(&self) -> Result<(), Error> {
+        if self.is_fenced() {
+            return Err(Error::ResourceExhausted(format!(
+                "Disk space is low ({} bytes available, minimum {}), refusing to write",
+                self.available(),
+                self.min_free
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fence() {
+        let watchdog = DiskWatchdog::new(1000);
+        assert!(watchdog.check_write().is_ok());
+
+        watchdog.update(1000);
+        assert!(!watchdog.is_fenced());
+        watchdog.update(999);
+        assert!(watchdog.is_fenced());
+        assert_eq!(999, watchdog.available());
+        assert_matches!(watchdog.check_write(), Err(Error::ResourceExhausted(_)));
+
+        // Writes are only unfenced once 10% above the minimum
+        watchdog.update(1050);
+        assert!(watchdog.is_fenced());
+        watchdog.update(1100);
+        assert!(!watchdog.is_fenced());
+        assert!(watchdog.check_write().is_ok());
+    }
+
+    #[test]
+    fn disabled() -> Result<(), Error> {
+        let watchdog = Arc::new(DiskWatchdog::new(0));
+        watchdog.start("/nonexistent")?;
+        assert!(!watchdog.is_fenced());
+        assert_eq!(0, watchdog.available());
+        Ok(())
+    }
+}
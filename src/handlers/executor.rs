@@ -0,0 +1,150 @@
+use crossbeam_channel::{Sender, TrySendError};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::Error;
+
+/// A job run by the executor
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A query execution thread pool with a bounded queue, such that heavy queries
+/// don't occupy the gRPC threads which also serve e.g. status requests.
+#[derive(Debug)]
+pub struct Executor {
+    jobs: Sender<Job>,
+}
+
+impl Executor {
+    /// Starts a new executor with the given number of threads, and a queue
+    /// holding at most queue_size jobs waiting for a thread.
+    pub fn new(threads: usize, queue_size: usize) -> Result<Self, Error> {
+        let (jobs, rx) = crossbeam_channel::bounded::<Job>(queue_size);
+        for i in 0..threads {
+            let rx = rx.clone();
+            std::thread::Builder::new()
+                .name(format!("query-{}", i))
+                .spawn(move || {
+                    for job in rx {
+                        // Don't let a panicking query take down the thread
+                        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            error!("Query execution panicked");
+                        }
+                    }
+                })?;
+        }
+        Ok(Self { jobs })
+    }
+
+    /// Submits a job for execution, or errors if the queue is full. Panics in
+    /// jobs are caught and logged, but jobs should use catch_panic() to return
+    /// an error to the client instead.
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, job: F) -> Result<(), Error> {
+        match self.jobs.try_send(Box::new(job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(Error::ResourceExhausted(
+                "Query execution queue is full".into(),
+            )),
+            Err(TrySendError::Disconnected(_)) => {
+                Err(Error::Internal("Query executor has shut down".into()))
+            }
+        }
+    }
+}
+
+/// Runs a function, converting a panic into an internal error.
+pub fn catch_panic<T, F: FnOnce() -> Result<T, Error>>(f: F) -> Result<T, Error> {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| Err(Error::from_panic(panic)))
+}
+
+/// A result iterator which converts a panic in the wrapped iterator into an
+/// internal error, ending the iteration, e.g. for plan nodes executed lazily.
+pub struct CatchPanic<I> {
+    inner: Option<I>,
+}
+
+impl<I> CatchPanic<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner: Some(inner) }
+    }
+}
+
+impl<T, I: Iterator<Item = Result<T, Error>>> Iterator for CatchPanic<I> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let inner = self.inner.as_mut()?;
+        match catch_unwind(AssertUnwindSafe(|| inner.next())) {
+            Ok(item) => item,
+            Err(panic) => {
+                self.inner = None;
+                Some(Err(Error::from_panic(panic)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn submit() {
+        let executor = Executor::new(2, 4).unwrap();
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for i in 0..4 {
+            let tx = tx.clone();
+            executor.submit(move || tx.send(i).unwrap()).unwrap();
+        }
+        let mut results: Vec<i32> = (0..4)
+            .map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap())
+            .collect();
+        results.sort();
+        assert_eq!(vec![0, 1, 2, 3], results);
+
+        // Panicking jobs don't take down the pool
+        executor.submit(|| panic!("query panicked")).unwrap();
+        executor.submit(|| panic!("query panicked")).unwrap();
+        executor.submit(move || tx.send(4).unwrap()).unwrap();
+        assert_eq!(Ok(4), rx.recv_timeout(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn catch() {
+        assert_eq!(Ok(1), catch_panic(|| Ok(1)));
+        assert_eq!(
+            Err(Error::Internal("Panicked: boom".into())),
+            catch_panic::<(), _>(|| panic!("boom"))
+        );
+        assert_eq!(
+            Err(Error::Internal("Panicked: row 2".into())),
+            catch_panic::<(), _>(|| panic!("row {}", 2))
+        );
+
+        let rows = (1..).map(|i| match i {
+            3 => panic!("row {}", i),
+            i => Ok(i),
+        });
+        assert_eq!(
+            vec![Ok(1), Ok(2), Err(Error::Internal("Panicked: row 3".into()))],
+            CatchPanic::new(rows).collect::<Vec<Result<i32, Error>>>()
+        );
+    }
+
+    #[test]
+    fn queue_full() {
+        let executor = Executor::new(1, 1).unwrap();
+        let (block_tx, block_rx) = crossbeam_channel::unbounded::<()>();
+        let (started_tx, started_rx) = crossbeam_channel::unbounded();
+        executor
+            .submit(move || {
+                started_tx.send(()).unwrap();
+                block_rx.recv().unwrap();
+            })
+            .unwrap();
+        started_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        executor.submit(|| {}).unwrap();
+        assert_matches!(executor.submit(|| {}), Err(Error::ResourceExhausted(_)));
+        block_tx.send(()).unwrap();
+    }
+}
@@ -3,25 +3,52 @@ use std::{
     time::{SystemTime, SystemTimeError, UNIX_EPOCH},
 };
 
+use futures::{Future, Stream};
+
 use crate::{
     proto,
-    store::{get_obj, set_obj, Store},
+    store::{get_obj, set_obj, Chunked, Store, Upload, DEFAULT_CHUNK_SIZE},
     Error,
 };
 
+/// The maximum size of values written with SetStream
+pub const MAX_VALUE_SIZE: u64 = 64 * 1024 * 1024;
+
 pub struct KvTestServiceImpl {
     id: String,
-    store: Arc<Mutex<Box<dyn Store>>>,
+    store: Arc<Mutex<Chunked<Box<dyn Store>>>>,
+    read_only: bool,
 }
 
 impl KvTestServiceImpl {
+    /// Creates a new service. Large values are chunked in the store, and
+    /// can be transferred in chunks using the streaming RPCs.
     pub fn new<S: Store>(id: String, store: S) -> Self {
+        let store: Box<dyn Store> = Box::new(store);
         KvTestServiceImpl {
             id,
-            store: Arc::new(Mutex::new(Box::new(store))),
+            store: Arc::new(Mutex::new(Chunked::new(store, DEFAULT_CHUNK_SIZE))),
+            read_only: false,
         }
     }
 
+    /// Rejects writes, like the read_only node option does for SQL.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Errors if the service is read-only.
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly(format!(
+                "Node {} is read-only, refusing to write",
+                self.id
+            )));
+        }
+        Ok(())
+    }
+
     fn get_timestamp(&self) -> Result<i64, SystemTimeError> {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -58,7 +85,7 @@ impl proto::KvTestService for KvTestServiceImpl {
         let store_map = self.store.clone();
         let store = store_map.lock().unwrap();
 
-        let value_opt = match get_obj(store.as_ref(), key) {
+        let value_opt = match get_obj(&*store, key) {
             Ok(v) => v,
             Err(e) => return error_response(e.into()),
         };
@@ -81,10 +108,13 @@ impl proto::KvTestService for KvTestServiceImpl {
         _: grpc::RequestOptions,
         req: proto::SetRequest,
     ) -> grpc::SingleResponse<proto::SetResponse> {
+        if let Err(e) = self.check_writable() {
+            return error_response(e.into());
+        }
         let store_map = self.store.clone();
         let mut store = store_map.lock().unwrap();
 
-        if let Err(e) = set_obj(store.as_mut(), req.key.as_str(), req.value.clone()) {
+        if let Err(e) = set_obj(&mut *store, req.key.as_str(), req.value.clone()) {
             return error_response(e.into());
         }
 
@@ -95,4 +125,89 @@ impl proto::KvTestService for KvTestServiceImpl {
         };
         grpc::SingleResponse::completed(response)
     }
+    fn get_stream(
+        &self,
+        _: grpc::RequestOptions,
+        req: proto::GetRequest,
+    ) -> grpc::StreamingResponse<proto::ValueChunk> {
+        let chunks = match self.store.lock().unwrap().get_chunks(&req.key) {
+            Ok(Some(chunks)) => chunks,
+            Ok(None) => {
+                return grpc::StreamingResponse::err(grpc::Error::Panic(
+                    Error::NotFound.to_string(),
+                ))
+            }
+            Err(e) => return grpc::StreamingResponse::err(grpc::Error::Panic(e.to_string())),
+        };
+        let key = req.key;
+        grpc::StreamingResponse::iter(chunks.into_iter().map(move |data| proto::ValueChunk {
+            key: key.clone(),
+            data: data.to_vec(),
+            ..Default::default()
+        }))
+    }
+
+    fn set_stream(
+        &self,
+        _: grpc::RequestOptions,
+        req: grpc::StreamingRequest<proto::ValueChunk>,
+    ) -> grpc::SingleResponse<proto::SetStreamResponse> {
+        if let Err(e) = self.check_writable() {
+            return error_response(e.into());
+        }
+        // Chunks are written to the store as they arrive, and the upload is
+        // shared with the completion handler so it can be aborted on errors.
+        let (store, finish_store) = (self.store.clone(), self.store.clone());
+        let upload: Arc<Mutex<Option<Upload>>> = Arc::new(Mutex::new(None));
+        let finish_upload = upload.clone();
+        let response = req
+            .0
+            .for_each(move |chunk| {
+                let mut store = store.lock().unwrap();
+                let mut upload = upload.lock().unwrap();
+                if upload.is_none() {
+                    if chunk.key.is_empty() {
+                        return Err(grpc::Error::Panic("No key given for streamed value".into()));
+                    }
+                    *upload = Some(store.start_upload(&chunk.key));
+                }
+                let upload = upload.as_mut().unwrap();
+                if upload.size() + chunk.data.len() as u64 > MAX_VALUE_SIZE {
+                    return Err(grpc::Error::Panic(format!(
+                        "Streamed value exceeds the maximum size of {} bytes",
+                        MAX_VALUE_SIZE
+                    )));
+                }
+                store
+                    .write_upload(upload, &chunk.data)
+                    .map_err(|e| grpc::Error::Panic(e.to_string()))
+            })
+            .then(move |result| {
+                let mut store = finish_store.lock().unwrap();
+                let upload = finish_upload.lock().unwrap().take();
+                match (result, upload) {
+                    (Ok(()), Some(upload)) => {
+                        let (key, size) = (upload.key().to_string(), upload.size());
+                        store
+                            .finish_upload(upload)
+                            .map_err(|e| grpc::Error::Panic(e.to_string()))?;
+                        Ok(proto::SetStreamResponse {
+                            key,
+                            size,
+                            ..Default::default()
+                        })
+                    }
+                    (Ok(()), None) => {
+                        Err(grpc::Error::Panic("No key given for streamed value".into()))
+                    }
+                    (Err(e), upload) => {
+                        if let Some(upload) = upload {
+                            store.abort_upload(upload).ok();
+                        }
+                        Err(e)
+                    }
+                }
+            });
+        grpc::SingleResponse::no_metadata(response)
+    }
 }
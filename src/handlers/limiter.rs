@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// The gRPC metadata key carrying the client identity.
+pub const CLIENT_ID_METADATA: &str = "client-id";
+
+/// The client identity used when a request does not carry one.
+const ANONYMOUS_CLIENT: &str = "anonymous";
+
+/// How often idle client states are evicted.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rate limiter configuration. A zero value disables the corresponding limit.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// The maximum sustained number of requests per second per client.
+    pub qps: f64,
+    /// The maximum number of concurrently running queries per client.
+    pub concurrent_queries: usize,
+}
+
+/// A per-client rate limiter, using a token bucket for request rates and a
+/// counter for concurrent queries. Clients are keyed on their principal, i.e.
+/// the authenticated user if authentication is enabled, and otherwise the
+/// self-reported client-id metadata, which a client can rotate to evade its
+/// limits. Clients with a full bucket and no running queries are evicted
+/// periodically.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RwLock<Limits>,
+    clients: Arc<Mutex<HashMap<String, ClientState>>>,
+    /// The last time idle clients were evicted.
+    swept: Mutex<Instant>,
+}
+
+/// The limiter state of a single client.
+#[derive(Debug)]
+struct ClientState {
+    /// The number of available request tokens.
+    tokens: f64,
+    /// The last time tokens were refilled.
+    refilled: Instant,
+    /// The number of currently running queries.
+    running: usize,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter.
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits: RwLock::new(limits),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            swept: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Replaces the limits, e.g. on configuration reload. Running queries keep
+    /// their slots, but new queries are subject to the new limits.
+    pub fn set_limits(&self, limits: Limits) -> Result<(), Error> {
+        *self.limits.write()? = limits;
+        Ok(())
+    }
+
+    /// Returns the client identity of a request.
+    pub fn client_id(metadata: &grpc::Metadata) -> String {
+        metadata
+            .get(CLIENT_ID_METADATA)
+            .and_then(|id| std::str::from_utf8(id).ok())
+            .filter(|id| !id.is_empty())
+            .unwrap_or(ANONYMOUS_CLIENT)
+            .to_string()
+    }
+
+    /// Returns the principal of a request, i.e. the authenticated user if any,
+    /// otherwise the self-reported client identity.
+    pub fn principal(metadata: &grpc::Metadata, user: Option<&str>) -> String {
+        match user {
+            Some(user) => user.to_string(),
+            None => Self::client_id(metadata),
+        }
+    }
+
+    /// Takes a request token for the client, or errors if the client exceeded its rate.
+    pub fn request(&self, client: &str) -> Result<(), Error> {
+        self.request_at(client, Instant::now())
+    }
+
+    /// Takes a request token and a query slot for the client. The returned permit
+    /// releases the query slot when dropped.
+    pub fn query(&self, client: &str) -> Result<Permit, Error> {
+        self.query_at(client, Instant::now())
+    }
+
+    /// Evicts clients whose state is the same as a new client's, i.e. with a
+    /// full bucket and no running queries, at most once per SWEEP_INTERVAL.
+    fn sweep(&self, now: Instant) -> Result<(), Error> {
+        let mut swept = self.swept.lock()?;
+        if now.saturating_duration_since(*swept) < SWEEP_INTERVAL {
+            return Ok(());
+        }
+        *swept = now;
+        let qps = self.limits.read()?.qps;
+        let burst = qps.max(1.0);
+        self.clients.lock()?.retain(|_, state| {
+            let elapsed = now.saturating_duration_since(state.refilled).as_secs_f64();
+            state.running > 0 || (qps > 0.0 && state.tokens + elapsed * qps < burst)
+        });
+        Ok(())
+    }
+
+    fn request_at(&self, client: &str, now: Instant) -> Result<(), Error> {
+        self.sweep(now)?;
+        let qps = self.limits.read()?.qps;
+        if qps <= 0.0 {
+            return Ok(());
+        }
+        let burst = qps.max(1.0);
+        let mut clients = self.clients.lock()?;
+        let state = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientState {
+                tokens: burst,
+                refilled: now,
+                running: 0,
+            });
+        let elapsed = now.saturating_duration_since(state.refilled).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * qps).min(burst);
+        state.refilled = now;
+        if state.tokens < 1.0 {
+            return Err(Error::ResourceExhausted(format!(
+                "Client {} exceeded the rate limit of {} requests per second",
+                client, qps
+            )));
+        }
+        state.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn query_at(&self, client: &str, now: Instant) -> Result<Permit, Error> {
+        self.request_at(client, now)?;
+        let max_queries = self.limits.read()?.concurrent_queries;
+        if max_queries > 0 {
+            let mut clients = self.clients.lock()?;
+            let state = clients
+                .entry(client.to_string())
+                .or_insert_with(|| ClientState {
+                    tokens: 0.0,
+                    refilled: now,
+                    running: 0,
+                });
+            if state.running >= max_queries {
+                return Err(Error::ResourceExhausted(format!(
+                    "Client {} exceeded the limit of {} concurrent queries",
+                    client, max_queries
+                )));
+            }
+            state.running += 1;
+        }
+        Ok(Permit {
+            client: client.to_string(),
+            clients: self.clients.clone(),
+            counted: max_queries > 0,
+        })
+    }
+}
+
+/// A running query slot, released when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    client: String,
+    clients: Arc<Mutex<HashMap<String, ClientState>>>,
+    counted: bool,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.counted {
+            return;
+        }
+        if let Ok(mut clients) = self.clients.lock() {
+            if let Some(state) = clients.get_mut(&self.client) {
+                state.running = state.running.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unlimited() {
+        let limiter = RateLimiter::new(Limits::default());
+        let now = Instant::now();
+        let permits: Vec<Permit> = (0..100)
+            .map(|_| limiter.query_at("a", now).unwrap())
+            .collect();
+        assert_eq!(permits.len(), 100);
+    }
+
+    #[test]
+    fn request_rate() {
+        let limiter = RateLimiter::new(Limits {
+            qps: 2.0,
+            concurrent_queries: 0,
+        });
+        let now = Instant::now();
+        assert_eq!(Ok(()), limiter.request_at("a", now));
+        assert_eq!(Ok(()), limiter.request_at("a", now));
+        assert_matches!(
+            limiter.request_at("a", now),
+            Err(Error::ResourceExhausted(_))
+        );
+
+        // Other clients have their own buckets
+        assert_eq!(Ok(()), limiter.request_at("b", now));
+
+        // Tokens are refilled over time
+        let later = now + Duration::from_millis(500);
+        assert_eq!(Ok(()), limiter.request_at("a", later));
+        assert_matches!(
+            limiter.request_at("a", later),
+            Err(Error::ResourceExhausted(_))
+        );
+    }
+
+    #[test]
+    fn concurrent_queries() {
+        let limiter = RateLimiter::new(Limits {
+            qps: 0.0,
+            concurrent_queries: 2,
+        });
+        let now = Instant::now();
+        let first = limiter.query_at("a", now).unwrap();
+        let _second = limiter.query_at("a", now).unwrap();
+        assert_matches!(limiter.query_at("a", now), Err(Error::ResourceExhausted(_)));
+        assert!(limiter.query_at("b", now).is_ok());
+
+        drop(first);
+        assert!(limiter.query_at("a", now).is_ok());
+    }
+
+    #[test]
+    fn sweep() {
+        let limiter = RateLimiter::new(Limits {
+            qps: 1.0,
+            concurrent_queries: 1,
+        });
+        let now = *limiter.swept.lock().unwrap();
+        limiter.request_at("a", now).unwrap();
+        let _running = limiter.query_at("b", now).unwrap();
+        limiter
+            .request_at("c", now + SWEEP_INTERVAL - Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(3, limiter.clients.lock().unwrap().len());
+
+        // Idle clients are evicted once the interval passes, but not clients
+        // with running queries or tokens still to refill
+        limiter.request_at("d", now + SWEEP_INTERVAL).unwrap();
+        let mut clients: Vec<String> = limiter.clients.lock().unwrap().keys().cloned().collect();
+        clients.sort();
+        assert_eq!(vec!["b", "c", "d"], clients);
+    }
+
+    #[test]
+    fn set_limits() {
+        let limiter = RateLimiter::new(Limits {
+            qps: 0.0,
+            concurrent_queries: 1,
+        });
+        let now = Instant::now();
+        let _first = limiter.query_at("a", now).unwrap();
+        assert_matches!(limiter.query_at("a", now), Err(Error::ResourceExhausted(_)));
+
+        limiter.set_limits(Limits::default()).unwrap();
+        assert!(limiter.query_at("a", now).is_ok());
+    }
+}
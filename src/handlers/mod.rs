@@ -1,68 +1,279 @@
+pub mod auth;
+mod cluster;
+pub mod columnar;
+mod cursor;
+mod disk;
+mod executor;
 pub mod kvtest;
+pub mod limiter;
 pub mod store;
 
 mod raft;
 
-use std::collections::HashMap;
+pub use raft::PeerAddress;
+use raft::RaftStatus;
 
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
 use crate::error::Error;
+use crate::handlers::auth::Authenticator;
+use crate::handlers::cluster::ClusterId;
+use crate::handlers::disk::DiskWatchdog;
+use crate::handlers::executor::Executor;
+use crate::handlers::kvtest::KvTestServiceImpl;
+use crate::handlers::limiter::RateLimiter;
 use crate::handlers::store::StoreServiceImpl;
 use crate::proto;
-use crate::raft::Raft;
+use crate::raft::{EventBus, Raft};
+use crate::sql::audit::AuditLog;
 use crate::sql::Storage;
+use crate::store::{ChecksumStatus, Compression, DataDir, Keyring};
 
 pub struct Node {
     pub id: String,
     pub addr: String,
+    /// The address to serve the peer-facing Raft service on, if separate
+    /// from the client-facing address.
+    pub peer_addr: Option<String>,
     pub threads: usize,
-    pub peers: HashMap<String, std::net::SocketAddr>,
+    pub peers: HashMap<String, PeerAddress>,
     pub data_dir: String,
+    pub read_only: bool,
+    pub witness: bool,
+    pub limiter: Arc<RateLimiter>,
+    /// The backend authenticating client requests, if any.
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    /// The users who are administrators. If empty, all users are.
+    pub admins: HashSet<String>,
+    pub keyring: Option<Arc<Keyring>>,
+    pub compression: Compression,
+    /// The store write-ahead log size in bytes at which stores are flushed.
+    pub store_flush_bytes: u64,
+    pub query_cache_bytes: usize,
+    pub statement_cache_size: usize,
+    pub query_threads: usize,
+    pub query_queue_size: usize,
+    pub max_clock_offset: u64,
+    pub checksum_interval: u64,
+    pub max_message_size: usize,
+    /// The maximum size of Raft commands, i.e. writes, in bytes.
+    pub max_command_size: usize,
+    /// The peer idle timeout in milliseconds, or 0 to disable it.
+    pub peer_timeout: u64,
+    /// The minimum available disk space in bytes below which writes are
+    /// rejected, or 0 to disable the check.
+    pub min_disk_free: u64,
+    /// The file to append audit log entries to, if any.
+    pub audit_log_file: Option<String>,
+    /// Whether to check the data directory for problems before starting.
+    pub startup_check: bool,
 }
 
 impl Node {
     pub fn listen(&self) -> Result<(), Error> {
+        let started = Instant::now();
         info!("Starting node with ID {}", self.id);
         let mut server = grpc::ServerBuilder::new_plain();
         server.http.set_addr(&self.addr)?;
         server.http.set_cpu_pool_threads(self.threads);
+        let mut peer_server = match &self.peer_addr {
+            Some(peer_addr) => {
+                info!("Serving Raft peers on {}", peer_addr);
+                let mut peer_server = grpc::ServerBuilder::new_plain();
+                peer_server.http.set_addr(peer_addr)?;
+                peer_server.http.set_cpu_pool_threads(self.threads);
+                Some(peer_server)
+            }
+            None => None,
+        };
 
-        let data_path = std::path::Path::new(&self.data_dir);
-        std::fs::create_dir_all(data_path)?;
-
-        let raft_transport = raft::GRPC::new(self.peers.clone())?;
-        server.add_service(proto::RaftServer::new_service_def(
-            raft_transport.build_service()?,
-        ));
+        let mut data_dir = DataDir::new(&self.data_dir)
+            .compressed(self.compression)
+            .flush_threshold(self.store_flush_bytes);
+        if let Some(keyring) = &self.keyring {
+            data_dir = data_dir.encrypted(keyring.clone());
+        }
+        let _lock = data_dir.lock()?;
+        data_dir.init()?;
+        data_dir.check_layout()?;
+        if self.startup_check {
+            self.check_data_dir(&data_dir)?;
+        }
+        let audit = match &self.audit_log_file {
+            Some(path) => AuditLog::new().file(path)?,
+            None => AuditLog::new(),
+        };
+        let disk = Arc::new(DiskWatchdog::new(self.min_disk_free));
+        disk.start(&self.data_dir)?;
 
-        let state_file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(data_path.join("statef"))?;
+        let cluster = Arc::new(ClusterId::load(data_dir.clone())?);
+        let clock = Arc::new(Clock::new().max_offset(self.max_clock_offset));
+        let events = Arc::new(EventBus::new());
+        let raft_status = RaftStatus::track(events.subscribe()?);
+        cluster.track(events.subscribe()?);
+        let mut raft_transport = raft::GRPC::new(
+            self.peers.clone(),
+            cluster.clone(),
+            self.compression,
+            clock.clone(),
+            events.clone(),
+        )?
+        .max_message_size(self.max_message_size)
+        .max_command_size(self.max_command_size);
+        if self.peer_timeout > 0 {
+            raft_transport = raft_transport.peer_timeout(Duration::from_millis(self.peer_timeout));
+        }
+        let raft_service = proto::RaftServer::new_service_def(raft_transport.build_service()?);
+        match &mut peer_server {
+            Some(peer_server) => peer_server.add_service(raft_service),
+            None => server.add_service(raft_service),
+        }
+        let raft_store = data_dir.open_raft()?;
+        let state_store = || data_dir.open_state();
 
-        let raft_file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(data_path.join("raft"))?;
+        #[cfg(feature = "chaos")]
+        let faults = Arc::new(crate::chaos::Faults::default());
+        #[cfg(feature = "chaos")]
+        let (raft_store, state_store, raft_transport) = {
+            use crate::chaos::{FaultyStore, FaultyTransport};
+            warn!("Fault injection is enabled, this node is not fit for production");
+            let wrap = |store| -> Box<dyn crate::store::Store> {
+                Box::new(FaultyStore::new(store, faults.clone()))
+            };
+            (
+                wrap(raft_store),
+                move || state_store().map(wrap),
+                FaultyTransport::new(raft_transport, faults.clone()),
+            )
+        };
 
-        let raft = Raft::start(
-            &self.id,
-            self.peers.keys().cloned().collect(),
-            crate::store::Raft::new_state(crate::store::File::new(state_file)?),
-            crate::store::File::new(raft_file)?,
-            raft_transport,
-        )?;
+        // Cached schemas are invalidated as DDL is applied, whichever node ran it.
+        let schemas = Arc::new(crate::sql::schema::SchemaCache::new());
+        let peers = self.peers.keys().cloned().collect();
+        let (raft, checksums) = if self.witness {
+            info!("Running as witness, without a state machine");
+            let raft = Raft::start_witness(&self.id, peers, raft_store, raft_transport, events)?
+                .max_command_size(self.max_command_size);
+            (raft, Arc::new(Mutex::new(ChecksumStatus::default())))
+        } else {
+            let state = crate::store::Raft::new_state(state_store()?)
+                .clock(clock.clone())
+                .on_write(Storage::schema_invalidator(schemas.clone()));
+            let checksums = state.checksums();
+            let raft = Raft::start(&self.id, peers, state, raft_store, raft_transport, events)?
+                .max_command_size(self.max_command_size);
+            self.run_checksums(&raft, raft_status.clone());
+            (raft, checksums)
+        };
 
+        if self.authenticator.is_some() && self.admins.is_empty() {
+            warn!("No auth_admins are configured, so every authenticated user is an administrator");
+        }
         server.add_service(proto::StoreServiceServer::new_service_def(
             StoreServiceImpl {
                 id: self.id.clone(),
+                read_only: self.read_only,
+                witness: self.witness,
+                limiter: self.limiter.clone(),
+                authenticator: self.authenticator.clone(),
+                admins: Arc::new(self.admins.clone()),
+                executor: Arc::new(Executor::new(self.query_threads, self.query_queue_size)?),
+                sessions: crate::sql::session::Registry::new(),
+                audit,
+                cursors: Arc::new(cursor::Cursors::new(
+                    cursor::MAX_CURSORS,
+                    cursor::CURSOR_TIMEOUT,
+                )),
+                cache: Arc::new(crate::sql::cache::QueryCache::new(self.query_cache_bytes)),
+                statements: Arc::new(crate::sql::cache::StatementCache::new(
+                    self.statement_cache_size,
+                )),
+                cluster,
                 raft: raft.clone(),
-                storage: Box::new(Storage::new(crate::store::Raft::new(raft.clone()))),
+                raft_status,
+                checksums,
+                disk,
+                data_dir: data_dir.clone(),
+                started,
+                storage: Box::new(
+                    Storage::new(crate::store::Raft::new(raft.clone()).clock(clock))
+                        .schema_cache(schemas)
+                        .max_write_size(self.max_command_size),
+                ),
+                #[cfg(feature = "chaos")]
+                faults,
             },
         ));
+        // The key/value test service uses a node-local in-memory store, which
+        // is neither replicated nor persisted.
+        server.add_service(proto::KvTestServiceServer::new_service_def(
+            KvTestServiceImpl::new(self.id.clone(), crate::store::KVMemory::new())
+                .read_only(self.read_only),
+        ));
         let _s = server.build()?;
+        let _p = match peer_server {
+            Some(peer_server) => Some(peer_server.build()?),
+            None => None,
+        };
 
         raft.join()
     }
+    /// Checks the Raft log and SQL storage for problems before starting, and
+    /// refuses to start if there are any, rather than failing mysteriously
+    /// later. Orphaned rows are only warned about, since they're harmless.
+    fn check_data_dir(&self, data_dir: &DataDir) -> Result<(), Error> {
+        let log = data_dir.check_raft_log()?;
+        let mut problems: Vec<String> = log
+            .problems
+            .iter()
+            .map(|p| format!("Raft log: {}", p))
+            .collect();
+        if !self.witness {
+            let sql = data_dir.inspect_sql(false)?;
+            if !sql.orphans.is_empty() {
+                warn!(
+                    "Found {} orphaned SQL rows, run inspect-sql --repair to delete them",
+                    sql.orphans.len()
+                );
+            }
+            problems.extend(sql.problems.iter().map(|p| format!("SQL storage: {}", p)));
+        }
+        if problems.is_empty() {
+            info!(
+                "Checked data directory: {} Raft log entries, applied up to {}",
+                log.entries, log.apply_index
+            );
+            return Ok(());
+        }
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        Err(Error::Internal(format!(
+            "Found {} problems in data directory {}. A torn Raft log tail can be removed \
+             with check-raft-log --repair, or the check skipped with startup_check: false",
+            problems.len(),
+            self.data_dir
+        )))
+    }
+
+    /// Periodically runs state machine consistency checks while leader, if enabled.
+    fn run_checksums(&self, raft: &Raft, raft_status: Arc<Mutex<RaftStatus>>) {
+        if self.checksum_interval == 0 {
+            return;
+        }
+        let store = crate::store::Raft::new(raft.clone());
+        let interval = Duration::from_secs(self.checksum_interval);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if !raft_status.lock().map(|s| s.leader).unwrap_or(false) {
+                continue;
+            }
+            if let Err(err) = store.checksum() {
+                error!("Failed to run state machine consistency check: {}", err);
+            }
+        });
+    }
 }
@@ -1,10 +1,115 @@
+use super::cluster::ClusterId;
+use crate::clock::{Clock, Timestamp};
 use crate::proto;
 use crate::proto::Raft;
-use crate::raft::{Entry, Event, Message, Transport};
+use crate::raft::{Entry, Event, EventBus, Message, RaftEvent, Transport};
+use crate::store::Compression;
 use crate::Error;
 use crossbeam_channel::{Receiver, Sender};
+use futures::{Future, Stream};
 use grpc::ClientStubExt;
-use std::collections::HashMap;
+use protobuf::Message as _;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The default Raft peer port.
+const DEFAULT_PORT: u16 = 9605;
+
+/// How often peer hostnames are re-resolved, to pick up address changes.
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often to retry resolving peers which have never been resolved.
+const RESOLVE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The timeout for establishing connections to peers.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default maximum size of Raft messages, in bytes.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// How often to check the applied index while waiting for it.
+const WAIT_APPLIED_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long leader changes are retained, for counting them within windows.
+const LEADER_CHANGES_WINDOW: Duration = Duration::from_secs(3600);
+
+/// The weight of past heartbeat round-trip times in the smoothed average,
+/// as in TCP: each new sample contributes 1/8.
+const RTT_SMOOTHING: u32 = 8;
+
+/// A peer address, given as an IP address or hostname with an optional port.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PeerAddress {
+    host: String,
+    port: u16,
+}
+
+impl std::str::FromStr for PeerAddress {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self, Error> {
+        let invalid = || Error::Config(format!("Invalid peer address {}", address));
+        if let Ok(addr) = address.parse::<SocketAddr>() {
+            return Ok(Self {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            });
+        }
+        if let Ok(ip) = address.parse::<std::net::IpAddr>() {
+            return Ok(Self {
+                host: ip.to_string(),
+                port: DEFAULT_PORT,
+            });
+        }
+        let (host, port) = match address.rfind(':') {
+            Some(i) => (
+                &address[..i],
+                address[i + 1..].parse().map_err(|_| invalid())?,
+            ),
+            None => (address, DEFAULT_PORT),
+        };
+        if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == ':') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl PeerAddress {
+    /// Resolves the address, looking up the hostname via DNS if necessary.
+    pub fn resolve(&self) -> Result<SocketAddr, Error> {
+        (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::Network(format!("Peer address {} did not resolve", self)))
+    }
+}
+
+/// A Raft peer, with a client for its last resolved address.
+struct Peer {
+    address: PeerAddress,
+    client: Option<(SocketAddr, proto::RaftClient)>,
+    resolved_at: Option<Instant>,
+    /// When the current client was connected.
+    connected_at: Option<Instant>,
+    /// Whether the peer was unreachable when last sent a message.
+    unreachable: bool,
+}
 
 /// A gRPC transport.
 pub struct GRPC {
@@ -12,8 +117,27 @@ pub struct GRPC {
     node_rx: Receiver<Message>,
     /// The node channel sender
     node_tx: Sender<Message>,
-    /// A hash map of peer IDs and gRPC clients.
-    peers: HashMap<String, proto::RaftClient>,
+    /// A hash map of peer IDs and peers.
+    peers: Mutex<HashMap<String, Peer>>,
+    /// The cluster ID.
+    cluster: Arc<ClusterId>,
+    /// The compression used for replicated entries.
+    compression: Compression,
+    /// The node's hybrid logical clock, carried in messages.
+    clock: Arc<Clock>,
+    /// The Raft event bus, for unreachable peers.
+    events: Arc<EventBus>,
+    /// The maximum size of sent and received messages, in bytes.
+    max_message_size: usize,
+    /// The maximum size of replicated entry commands, in bytes, if any.
+    max_command_size: Option<usize>,
+    /// How long a peer connection can go without receiving any messages from
+    /// the peer before reconnecting, or None to never reconnect.
+    peer_timeout: Option<Duration>,
+    /// When a message was last received from each peer.
+    last_seen: Arc<Mutex<HashMap<String, Instant>>>,
+    /// When the oldest unconfirmed heartbeat was sent to each peer.
+    heartbeats: Arc<Mutex<HashMap<String, Instant>>>,
 }
 
 impl Transport for GRPC {
@@ -21,11 +145,87 @@ impl Transport for GRPC {
         self.node_rx.clone()
     }
 
-    fn send(&self, msg: Message) -> Result<(), Error> {
-        if let Some(to) = &msg.to {
-            if let Some(client) = self.peers.get(to) {
-                // TODO: FIXME Needs to check the response.
-                client.step(grpc::RequestOptions::new(), message_to_protobuf(msg));
+    fn send(&self, mut msg: Message) -> Result<(), Error> {
+        if let Some(to) = msg.to.clone() {
+            if let Some(max) = self.max_command_size {
+                if let Some((index, size)) = truncate_entries(&mut msg, max) {
+                    error!(
+                        "Not replicating entry {} to Raft peer {}: command of {} bytes exceeds maximum command size {}",
+                        index, to, size, max
+                    );
+                }
+            }
+            let mut peers = self.peers.lock()?;
+            if let Some(peer) = peers.get_mut(&to) {
+                // Only leaders send these, so a leader without a cluster ID
+                // has just bootstrapped a new cluster.
+                let cluster_id = if is_leader_event(&msg.event) {
+                    Some(self.cluster.bootstrap()?)
+                } else {
+                    self.cluster.get()?
+                };
+                if let Some(timeout) = self.peer_timeout {
+                    let last_seen = self.last_seen.lock()?.get(&to).copied();
+                    if is_idle(peer.connected_at, last_seen, timeout) {
+                        warn!(
+                            "No messages from Raft peer {} in {:?}, reconnecting",
+                            to, timeout
+                        );
+                        peer.client = None;
+                        peer.resolved_at = None;
+                    }
+                }
+                match Self::peer_client(peer) {
+                    // TODO: FIXME Needs to check the response.
+                    Ok(client) => {
+                        let heartbeat = matches!(msg.event, Event::Heartbeat { .. });
+                        let mut pb = message_to_protobuf(msg, self.compression)?;
+                        pb.cluster_id = cluster_id.unwrap_or_default();
+                        let timestamp = self.clock.now()?;
+                        pb.timestamp_wall = timestamp.wall;
+                        pb.timestamp_logical = timestamp.logical;
+                        let opts = grpc::RequestOptions::new();
+                        match pb.event {
+                            // Entries are streamed in chunks, to keep messages
+                            // below the maximum size.
+                            Some(proto::Message_oneof_event::replicate_entries(_)) => {
+                                match chunk_entries(pb, self.max_message_size) {
+                                    Ok(chunks) => {
+                                        client.step_stream(opts, grpc::StreamingRequest::iter(chunks));
+                                    }
+                                    Err(err) => error!("Dropping entries to Raft peer {}: {}", to, err),
+                                }
+                            }
+                            _ if pb.compute_size() as usize > self.max_message_size => error!(
+                                "Dropping message to Raft peer {}: size {} exceeds maximum message size {}",
+                                to,
+                                pb.compute_size(),
+                                self.max_message_size
+                            ),
+                            _ => {
+                                client.step(opts, pb);
+                            }
+                        }
+                        peer.unreachable = false;
+                        // Heartbeat round-trip times are measured from the
+                        // oldest unconfirmed heartbeat, see GRPCService.
+                        if heartbeat {
+                            self.heartbeats
+                                .lock()?
+                                .entry(to.clone())
+                                .or_insert_with(Instant::now);
+                        }
+                    }
+                    // Unreachable peers are treated like a lossy network, Raft will retry.
+                    Err(err) => {
+                        warn!("Dropping message to Raft peer {}: {}", to, err);
+                        if !peer.unreachable {
+                            peer.unreachable = true;
+                            self.events
+                                .publish(RaftEvent::PeerUnreachable(to.clone()))?;
+                        }
+                    }
+                }
                 Ok(())
             } else {
                 Err(Error::Network(format!("Unknown Raft peer {}", to)))
@@ -38,26 +238,120 @@ impl Transport for GRPC {
 
 // TODO: revisit this
 impl GRPC {
-    /// Creates a new GRPC transport
-    pub fn new(peers: HashMap<String, std::net::SocketAddr>) -> Result<Self, Error> {
+    /// Creates a new GRPC transport. Peer addresses are resolved lazily, and
+    /// re-resolved periodically such that peers can change IP addresses.
+    pub fn new(
+        peers: HashMap<String, PeerAddress>,
+        cluster: Arc<ClusterId>,
+        compression: Compression,
+        clock: Arc<Clock>,
+        events: Arc<EventBus>,
+    ) -> Result<Self, Error> {
         let (node_tx, node_rx) = crossbeam_channel::unbounded();
-        let mut t = GRPC {
-            peers: HashMap::new(),
+        Ok(GRPC {
+            peers: Mutex::new(
+                peers
+                    .into_iter()
+                    .map(|(id, address)| {
+                        let peer = Peer {
+                            address,
+                            client: None,
+                            resolved_at: None,
+                            connected_at: None,
+                            unreachable: false,
+                        };
+                        (id, peer)
+                    })
+                    .collect(),
+            ),
+            cluster,
+            compression,
+            clock,
+            events,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_command_size: None,
+            peer_timeout: None,
+            last_seen: Arc::new(Mutex::new(HashMap::new())),
+            heartbeats: Arc::new(Mutex::new(HashMap::new())),
             node_tx,
             node_rx,
+        })
+    }
+
+    /// Sets the maximum size of sent and received messages, in bytes.
+    /// Replicated entries are split into chunks below it, but individual
+    /// entries larger than it can't be replicated.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Stops replicating entries at the first entry whose command is larger
+    /// than the given size in bytes. Such entries should have been rejected
+    /// when submitted, see raft::Raft::max_command_size(), but may have been
+    /// appended e.g. before the limit was lowered.
+    pub fn max_command_size(mut self, max_command_size: usize) -> Self {
+        self.max_command_size = Some(max_command_size);
+        self
+    }
+
+    /// Reconnects to peers that haven't sent any messages within the timeout,
+    /// e.g. because the connection was silently dropped by a NAT. Raft
+    /// heartbeats and their responses act as keepalives.
+    pub fn peer_timeout(mut self, timeout: Duration) -> Self {
+        self.peer_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns a gRPC client for a peer, re-resolving its address periodically
+    /// and reconnecting if the address changed.
+    fn peer_client(peer: &mut Peer) -> Result<&proto::RaftClient, Error> {
+        let interval = if peer.client.is_some() {
+            RESOLVE_INTERVAL
+        } else {
+            RESOLVE_RETRY_INTERVAL
+        };
+        let due = match peer.resolved_at {
+            Some(at) => at.elapsed() >= interval,
+            None => true,
         };
-        for (id, addr) in peers.into_iter() {
-            t.peers.insert(id, t.build_client(addr)?);
+        if due {
+            peer.resolved_at = Some(Instant::now());
+            match peer.address.resolve() {
+                Ok(addr) => match &peer.client {
+                    Some((current, _)) if *current == addr => {}
+                    _ => {
+                        info!("Connecting to Raft peer {} at {}", peer.address, addr);
+                        peer.client = Some((addr, Self::build_client(addr)?));
+                        peer.connected_at = Some(Instant::now());
+                    }
+                },
+                Err(err) if peer.client.is_some() => {
+                    warn!("Failed to re-resolve Raft peer {}: {}", peer.address, err)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        match &peer.client {
+            Some((_, client)) => Ok(client),
+            None => Err(Error::Network(format!(
+                "Peer address {} not resolved",
+                peer.address
+            ))),
         }
-        Ok(t)
     }
 
     /// Builds a gRPC client for a peer.
-    pub fn build_client(&self, addr: std::net::SocketAddr) -> Result<proto::RaftClient, Error> {
+    pub fn build_client(addr: SocketAddr) -> Result<proto::RaftClient, Error> {
         Ok(proto::RaftClient::new_plain(
             &addr.ip().to_string(),
             addr.port(),
-            grpc::ClientConf::new(),
+            grpc::ClientConf {
+                http: httpbis::ClientConf {
+                    connection_timeout: Some(CONNECT_TIMEOUT),
+                    ..Default::default()
+                },
+            },
         )?)
     }
 
@@ -65,13 +359,33 @@ impl GRPC {
     pub fn build_service(&self) -> Result<impl proto::Raft, Error> {
         Ok(GRPCService {
             local: self.node_tx.clone(),
+            cluster: self.cluster.clone(),
+            clock: self.clock.clone(),
+            skewed: Arc::new(Mutex::new(HashSet::new())),
+            max_message_size: self.max_message_size,
+            last_seen: self.last_seen.clone(),
+            heartbeats: self.heartbeats.clone(),
+            events: self.events.clone(),
         })
     }
 }
 
 /// A gRPC service for a local server.
+#[derive(Clone)]
 struct GRPCService {
     local: Sender<Message>,
+    cluster: Arc<ClusterId>,
+    clock: Arc<Clock>,
+    /// Peers whose clocks are currently beyond the maximum offset.
+    skewed: Arc<Mutex<HashSet<String>>>,
+    /// The maximum size of received messages, in bytes.
+    max_message_size: usize,
+    /// When a message was last received from each peer.
+    last_seen: Arc<Mutex<HashMap<String, Instant>>>,
+    /// When the oldest unconfirmed heartbeat was sent to each peer.
+    heartbeats: Arc<Mutex<HashMap<String, Instant>>>,
+    /// The Raft event bus, for heartbeat round-trip times.
+    events: Arc<EventBus>,
 }
 
 impl proto::Raft for GRPCService {
@@ -80,9 +394,221 @@ impl proto::Raft for GRPCService {
         _: grpc::RequestOptions,
         pb: proto::Message,
     ) -> grpc::SingleResponse<proto::Success> {
-        self.local.send(message_from_protobuf(pb).unwrap()).unwrap();
-        grpc::SingleResponse::completed(proto::Success::new())
+        match self.receive(pb) {
+            Ok(()) => grpc::SingleResponse::completed(proto::Success::new()),
+            Err(err) => grpc::SingleResponse::err(err),
+        }
+    }
+
+    fn step_stream(
+        &self,
+        _: grpc::RequestOptions,
+        stream: grpc::StreamingRequest<proto::Message>,
+    ) -> grpc::SingleResponse<proto::Success> {
+        let service = self.clone();
+        grpc::SingleResponse::no_metadata(
+            stream
+                .0
+                .for_each(move |pb| service.receive(pb))
+                .map(|_| proto::Success::new()),
+        )
+    }
+}
+
+impl GRPCService {
+    /// Receives a message from a peer, passing it to the local node.
+    fn receive(&self, pb: proto::Message) -> Result<(), grpc::Error> {
+        let size = pb.compute_size() as usize;
+        if size > self.max_message_size {
+            return Err(grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+                grpc_status: grpc::GrpcStatus::ResourceExhausted as i32,
+                grpc_message: format!(
+                    "Message size {} exceeds maximum message size {}",
+                    size, self.max_message_size
+                ),
+            }));
+        }
+        let cluster_id = pb.cluster_id.clone();
+        let timestamp = Timestamp {
+            wall: pb.timestamp_wall,
+            logical: pb.timestamp_logical,
+        };
+        let msg = message_from_protobuf(pb).map_err(|err| {
+            grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+                grpc_status: grpc::GrpcStatus::Argument as i32,
+                grpc_message: err.to_string(),
+            })
+        })?;
+        if let Err(err) = self.cluster.check(&cluster_id, is_leader_event(&msg.event)) {
+            warn!("Rejecting Raft message from {:?}: {}", msg.from, err);
+            return Err(grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+                grpc_status: grpc::GrpcStatus::FailedPrecondition as i32,
+                grpc_message: err.to_string(),
+            }));
+        }
+        if let Some(from) = &msg.from {
+            if let Ok(mut last_seen) = self.last_seen.lock() {
+                last_seen.insert(from.clone(), Instant::now());
+            }
+            if let Event::ConfirmLeader { .. } = msg.event {
+                self.confirm_heartbeat(from);
+            }
+        }
+        if timestamp != Timestamp::default() {
+            self.check_clock(msg.from.as_deref().unwrap_or_default(), timestamp);
+            if let Err(err) = self.clock.update(timestamp) {
+                error!("Failed to update clock: {}", err);
+            }
+        }
+        self.local.send(msg).unwrap();
+        Ok(())
+    }
+
+    /// Publishes the round-trip time of a peer's oldest unconfirmed heartbeat,
+    /// if any. A lost heartbeat makes the next sample larger, since its
+    /// confirmation is attributed to the lost one.
+    fn confirm_heartbeat(&self, peer: &str) {
+        let sent = match self.heartbeats.lock() {
+            Ok(mut heartbeats) => heartbeats.remove(peer),
+            Err(err) => return error!("Failed to track heartbeats: {}", err),
+        };
+        if let Some(sent) = sent {
+            let event = RaftEvent::HeartbeatRtt {
+                peer: peer.to_string(),
+                rtt: sent.elapsed(),
+            };
+            if let Err(err) = self.events.publish(event) {
+                error!("Failed to publish heartbeat round-trip time: {}", err)
+            }
+        }
+    }
+
+    /// Checks the clock offset of a peer, logging a warning when it goes
+    /// beyond the maximum offset and when it recovers.
+    fn check_clock(&self, peer: &str, timestamp: Timestamp) {
+        let mut skewed = match self.skewed.lock() {
+            Ok(skewed) => skewed,
+            Err(err) => return error!("Failed to check clock offset: {}", err),
+        };
+        match self.clock.check_offset(timestamp) {
+            Err(err) if skewed.insert(peer.to_string()) => {
+                warn!("Clock of Raft peer {} is ahead of ours: {}", peer, err)
+            }
+            Ok(()) if skewed.remove(peer) => {
+                info!("Clock of Raft peer {} is back within maximum offset", peer)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The local Raft node status, tracked via Raft events for status requests.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RaftStatus {
+    pub leader: bool,
+    pub commit_index: u64,
+    pub apply_index: u64,
+    /// The number of elections started by the local node.
+    pub elections: u64,
+    /// The number of leader changes observed by the local node.
+    pub leader_changes: u64,
+    /// When leader changes were observed, within LEADER_CHANGES_WINDOW.
+    pub recent_leader_changes: VecDeque<Instant>,
+    /// The smoothed heartbeat round-trip time to each peer, as of when the
+    /// local node was last leader.
+    pub peer_rtt: HashMap<String, Duration>,
+}
+
+impl RaftStatus {
+    /// Tracks the Raft status from an event subscription in a separate thread.
+    pub fn track(events: Receiver<RaftEvent>) -> Arc<Mutex<Self>> {
+        let status = Arc::new(Mutex::new(Self::default()));
+        let tracked = status.clone();
+        std::thread::spawn(move || {
+            for event in events {
+                match tracked.lock() {
+                    Ok(mut status) => status.observe(event),
+                    Err(err) => return error!("Failed to track Raft status: {}", err),
+                }
+            }
+        });
+        status
+    }
+
+    /// Waits until the local applied index reaches the given index, or errors
+    /// on timeout.
+    pub fn wait_applied(status: &Mutex<Self>, index: u64, timeout: Duration) -> Result<(), Error> {
+        let start = Instant::now();
+        loop {
+            let applied = status.lock()?.apply_index;
+            if applied >= index {
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                return Err(Error::Network(format!(
+                    "Timed out waiting for applied index {}, currently at {}",
+                    index, applied
+                )));
+            }
+            std::thread::sleep(WAIT_APPLIED_INTERVAL);
+        }
     }
+
+    /// Returns the number of leader changes observed within the given window,
+    /// which is capped at LEADER_CHANGES_WINDOW.
+    pub fn leader_changes_within(&self, window: Duration) -> u64 {
+        self.recent_leader_changes
+            .iter()
+            .filter(|at| at.elapsed() <= window)
+            .count() as u64
+    }
+
+    /// Updates the status from a Raft event.
+    fn observe(&mut self, event: RaftEvent) {
+        match event {
+            RaftEvent::BecameLeader { .. } => self.leader = true,
+            RaftEvent::SteppedDown { .. } => self.leader = false,
+            RaftEvent::Committed(index) => self.commit_index = index,
+            RaftEvent::Applied(index) => self.apply_index = index,
+            RaftEvent::PeerUnreachable(_) => {}
+            RaftEvent::ElectionStarted { .. } => self.elections += 1,
+            RaftEvent::LeaderChanged { .. } => {
+                self.leader_changes += 1;
+                self.recent_leader_changes.push_back(Instant::now());
+                while let Some(at) = self.recent_leader_changes.front() {
+                    if at.elapsed() <= LEADER_CHANGES_WINDOW {
+                        break;
+                    }
+                    self.recent_leader_changes.pop_front();
+                }
+            }
+            RaftEvent::HeartbeatRtt { peer, rtt } => {
+                let smoothed = self.peer_rtt.entry(peer).or_insert(rtt);
+                *smoothed = (*smoothed * (RTT_SMOOTHING - 1) + rtt) / RTT_SMOOTHING;
+            }
+        }
+    }
+}
+
+/// Returns true if a peer connection is idle, i.e. it was established more
+/// than the timeout ago and nothing has been received from the peer since
+/// the timeout.
+fn is_idle(connected_at: Option<Instant>, last_seen: Option<Instant>, timeout: Duration) -> bool {
+    match connected_at {
+        Some(connected_at) if connected_at.elapsed() >= timeout => match last_seen {
+            Some(last_seen) => last_seen.elapsed() >= timeout,
+            None => true,
+        },
+        _ => false,
+    }
+}
+
+/// Returns true if the event is only sent by leaders.
+fn is_leader_event(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Heartbeat { .. } | Event::ReplicateEntries { .. }
+    )
 }
 
 /// Converts a Protobuf message to a `Message`.
@@ -95,10 +621,12 @@ fn message_from_protobuf(pb: proto::Message) -> Result<Message, Error> {
             Some(proto::Message_oneof_event::heartbeat(e)) => Event::Heartbeat {
                 commit_index: e.commit_index,
                 commit_term: e.commit_term,
+                read_seq: e.read_seq,
             },
             Some(proto::Message_oneof_event::confirm_leader(e)) => Event::ConfirmLeader {
                 commit_index: e.commit_index,
                 has_committed: e.has_committed,
+                read_seq: e.read_seq,
             },
             Some(proto::Message_oneof_event::solicit_vote(e)) => Event::SolicitVote {
                 last_index: e.last_index,
@@ -115,6 +643,7 @@ fn message_from_protobuf(pb: proto::Message) -> Result<Message, Error> {
             },
             Some(proto::Message_oneof_event::respond_state(e)) => Event::RespondState {
                 call_id: e.call_id,
+                index: e.index,
                 response: e.response,
             },
             Some(proto::Message_oneof_event::respond_error(e)) => Event::RespondError {
@@ -128,28 +657,38 @@ fn message_from_protobuf(pb: proto::Message) -> Result<Message, Error> {
                     .entries
                     .to_vec()
                     .into_iter()
-                    .map(|entry| Entry {
-                        term: entry.term,
-                        command: if entry.command.is_empty() {
-                            None
-                        } else {
-                            Some(entry.command)
-                        },
+                    .map(|entry| {
+                        Ok(Entry {
+                            term: entry.term,
+                            command: if entry.command.is_empty() {
+                                None
+                            } else {
+                                Some(
+                                    Compression::from_flag(entry.compression as u8)?
+                                        .decompress(entry.command)?,
+                                )
+                            },
+                        })
                     })
-                    .collect(),
+                    .collect::<Result<_, Error>>()?,
             },
             Some(proto::Message_oneof_event::accept_entries(e)) => Event::AcceptEntries {
                 last_index: e.last_index,
             },
             Some(proto::Message_oneof_event::reject_entries(_)) => Event::RejectEntries,
+            Some(proto::Message_oneof_event::request_entries(e)) => Event::RequestEntries {
+                last_index: e.last_index,
+                last_term: e.last_term,
+            },
             None => return Err(Error::Network("No event found in protobuf message".into())),
         },
     })
 }
 
-/// Converts a `Message` to its Protobuf representation.
-fn message_to_protobuf(msg: Message) -> proto::Message {
-    proto::Message {
+/// Converts a `Message` to its Protobuf representation, compressing large
+/// replicated entries.
+fn message_to_protobuf(msg: Message, compression: Compression) -> Result<proto::Message, Error> {
+    Ok(proto::Message {
         term: msg.term,
         from: msg.from.unwrap(),
         to: msg.to.unwrap(),
@@ -157,17 +696,21 @@ fn message_to_protobuf(msg: Message) -> proto::Message {
             Event::Heartbeat {
                 commit_index,
                 commit_term,
+                read_seq,
             } => proto::Message_oneof_event::heartbeat(proto::Heartbeat {
                 commit_index,
                 commit_term,
+                read_seq,
                 ..Default::default()
             }),
             Event::ConfirmLeader {
                 commit_index,
                 has_committed,
+                read_seq,
             } => proto::Message_oneof_event::confirm_leader(proto::ConfirmLeader {
                 commit_index,
                 has_committed,
+                read_seq,
                 ..Default::default()
             }),
             Event::SolicitVote {
@@ -193,13 +736,16 @@ fn message_to_protobuf(msg: Message) -> proto::Message {
                     ..Default::default()
                 })
             }
-            Event::RespondState { call_id, response } => {
-                proto::Message_oneof_event::respond_state(proto::RespondState {
-                    call_id,
-                    response,
-                    ..Default::default()
-                })
-            }
+            Event::RespondState {
+                call_id,
+                index,
+                response,
+            } => proto::Message_oneof_event::respond_state(proto::RespondState {
+                call_id,
+                index,
+                response,
+                ..Default::default()
+            }),
             Event::RespondError { call_id, error } => {
                 proto::Message_oneof_event::respond_error(proto::RespondError {
                     call_id,
@@ -217,16 +763,17 @@ fn message_to_protobuf(msg: Message) -> proto::Message {
                 entries: protobuf::RepeatedField::from_vec(
                     entries
                         .into_iter()
-                        .map(|entry| proto::Entry {
-                            term: entry.term,
-                            command: if let Some(bytes) = entry.command {
-                                bytes
-                            } else {
-                                vec![]
-                            },
-                            ..Default::default()
+                        .map(|entry| {
+                            let (compression, command) =
+                                compression.compress(entry.command.unwrap_or_default())?;
+                            Ok(proto::Entry {
+                                term: entry.term,
+                                command,
+                                compression: compression as u32,
+                                ..Default::default()
+                            })
                         })
-                        .collect(),
+                        .collect::<Result<_, Error>>()?,
                 ),
                 ..Default::default()
             }),
@@ -239,7 +786,436 @@ fn message_to_protobuf(msg: Message) -> proto::Message {
             Event::RejectEntries => {
                 proto::Message_oneof_event::reject_entries(proto::RejectEntries::new())
             }
+            Event::RequestEntries {
+                last_index,
+                last_term,
+            } => proto::Message_oneof_event::request_entries(proto::RequestEntries {
+                last_index,
+                last_term,
+                ..Default::default()
+            }),
         }),
         ..Default::default()
+    })
+}
+
+/// Splits a Protobuf ReplicateEntries message into chunks below the maximum
+/// message size, each replicating consecutive entries on top of the previous
+/// chunk. Errors if a single entry exceeds the maximum size.
+fn chunk_entries(mut pb: proto::Message, max_size: usize) -> Result<Vec<proto::Message>, Error> {
+    let replicate = match pb.event.take() {
+        Some(proto::Message_oneof_event::replicate_entries(replicate)) => replicate,
+        event => {
+            pb.event = event;
+            return Ok(vec![pb]);
+        }
+    };
+    let chunk = |base_index, base_term, entries| {
+        let mut chunk = pb.clone();
+        chunk.event = Some(proto::Message_oneof_event::replicate_entries(
+            proto::ReplicateEntries {
+                base_index,
+                base_term,
+                entries: protobuf::RepeatedField::from_vec(entries),
+                ..Default::default()
+            },
+        ));
+        chunk
+    };
+    // Allow for the base index and term, and length prefixes.
+    let overhead = chunk(u64::MAX, u64::MAX, vec![]).compute_size() as usize + 8;
+    let mut chunks = Vec::new();
+    let (mut base_index, mut base_term) = (replicate.base_index, replicate.base_term);
+    let (mut entries, mut size) = (Vec::new(), overhead);
+    for entry in replicate.entries.into_iter() {
+        let entry_size = entry.compute_size() as usize + 8;
+        if overhead + entry_size > max_size {
+            return Err(Error::ResourceExhausted(format!(
+                "Raft entry of {} bytes exceeds maximum message size {}",
+                entry_size, max_size
+            )));
+        }
+        if size + entry_size > max_size {
+            let (last_index, last_term) = (base_index + entries.len() as u64, entry_term(&entries));
+            chunks.push(chunk(base_index, base_term, std::mem::take(&mut entries)));
+            base_index = last_index;
+            base_term = last_term;
+            size = overhead;
+        }
+        size += entry_size;
+        entries.push(entry);
+    }
+    chunks.push(chunk(base_index, base_term, entries));
+    Ok(chunks)
+}
+
+/// Truncates replicated entries before the first entry whose command is larger
+/// than the maximum size, such that the entries before it are still
+/// replicated. Returns the index and command size of that entry, if any.
+fn truncate_entries(msg: &mut Message, max_command_size: usize) -> Option<(u64, usize)> {
+    let (base_index, entries) = match &mut msg.event {
+        Event::ReplicateEntries {
+            base_index,
+            entries,
+            ..
+        } => (*base_index, entries),
+        _ => return None,
+    };
+    let size = |entry: &Entry| entry.command.as_ref().map_or(0, Vec::len);
+    let position = entries
+        .iter()
+        .position(|entry| size(entry) > max_command_size)?;
+    let oversized = (base_index + position as u64 + 1, size(&entries[position]));
+    entries.truncate(position);
+    Some(oversized)
+}
+
+/// Returns the term of the last entry, or 0 if none.
+fn entry_term(entries: &[proto::Entry]) -> u64 {
+    entries.last().map(|e| e.term).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(host: &str, port: u16) -> PeerAddress {
+        PeerAddress {
+            host: host.into(),
+            port,
+        }
+    }
+
+    #[test]
+    fn raft_status() {
+        let bus = EventBus::new();
+        let status = RaftStatus::track(bus.subscribe().unwrap());
+        for event in &[
+            RaftEvent::BecameLeader { term: 1 },
+            RaftEvent::Committed(2),
+            RaftEvent::Applied(1),
+            RaftEvent::PeerUnreachable("b".into()),
+        ] {
+            bus.publish(event.clone()).unwrap();
+        }
+        drop(bus);
+        let expect = RaftStatus {
+            leader: true,
+            commit_index: 2,
+            apply_index: 1,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            if *status.lock().unwrap() == expect {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(expect, *status.lock().unwrap());
+
+        let timeout = Duration::from_millis(50);
+        assert_eq!(Ok(()), RaftStatus::wait_applied(&status, 1, timeout));
+        assert_matches!(
+            RaftStatus::wait_applied(&status, 2, timeout),
+            Err(Error::Network(_))
+        );
+    }
+
+    #[test]
+    fn raft_status_metrics() {
+        let mut status = RaftStatus::default();
+        let rtt = |peer: &str, ms| RaftEvent::HeartbeatRtt {
+            peer: peer.into(),
+            rtt: Duration::from_millis(ms),
+        };
+        for event in &[
+            RaftEvent::ElectionStarted { term: 2 },
+            RaftEvent::ElectionStarted { term: 3 },
+            RaftEvent::LeaderChanged {
+                term: 3,
+                leader: "a".into(),
+            },
+            rtt("b", 8),
+            rtt("b", 16),
+            rtt("c", 4),
+        ] {
+            status.observe(event.clone());
+        }
+        assert_eq!(2, status.elections);
+        assert_eq!(1, status.leader_changes);
+        assert_eq!(1, status.leader_changes_within(Duration::from_secs(60)));
+        assert_eq!(Some(&Duration::from_millis(9)), status.peer_rtt.get("b"));
+        assert_eq!(Some(&Duration::from_millis(4)), status.peer_rtt.get("c"));
+    }
+
+    #[test]
+    fn peer_address() {
+        assert_eq!(Ok(addr("10.0.0.1", 9605)), "10.0.0.1".parse());
+        assert_eq!(Ok(addr("10.0.0.1", 9000)), "10.0.0.1:9000".parse());
+        assert_eq!(Ok(addr("::1", 9605)), "::1".parse());
+        assert_eq!(Ok(addr("::1", 9000)), "[::1]:9000".parse());
+        assert_eq!(Ok(addr("node-a", 9605)), "node-a".parse());
+        assert_eq!(
+            Ok(addr("node-a.nodes.svc", 9000)),
+            "node-a.nodes.svc:9000".parse()
+        );
+        assert_matches!("".parse::<PeerAddress>(), Err(Error::Config(_)));
+        assert_matches!("node-a:port".parse::<PeerAddress>(), Err(Error::Config(_)));
+        assert_matches!(":9000".parse::<PeerAddress>(), Err(Error::Config(_)));
+
+        assert_eq!("[::1]:9000", addr("::1", 9000).to_string());
+        assert_eq!(
+            Ok("127.0.0.1:9000".parse().unwrap()),
+            addr("127.0.0.1", 9000).resolve()
+        );
+    }
+
+    /// Returns one event of every kind. The match makes sure new variants
+    /// are added here, and thus covered by the conversion tests.
+    fn events() -> Vec<Event> {
+        let events = vec![
+            Event::Heartbeat {
+                commit_index: 1,
+                commit_term: 2,
+                read_seq: 0,
+            },
+            Event::ConfirmLeader {
+                commit_index: 1,
+                has_committed: true,
+                read_seq: 0,
+            },
+            Event::SolicitVote {
+                last_index: 1,
+                last_term: 2,
+            },
+            Event::GrantVote,
+            Event::ReplicateEntries {
+                base_index: 1,
+                base_term: 2,
+                entries: vec![
+                    Entry {
+                        term: 2,
+                        command: None,
+                    },
+                    Entry {
+                        term: 2,
+                        command: Some(vec![0x01]),
+                    },
+                ],
+            },
+            Event::AcceptEntries { last_index: 3 },
+            Event::RejectEntries,
+            Event::RequestEntries {
+                last_index: 3,
+                last_term: 2,
+            },
+            Event::ReadState {
+                call_id: vec![0x01],
+                command: vec![0x02],
+            },
+            Event::MutateState {
+                call_id: vec![0x01],
+                command: vec![0x02],
+            },
+            Event::RespondState {
+                call_id: vec![0x01],
+                index: 3,
+                response: vec![0x02],
+            },
+            Event::RespondError {
+                call_id: vec![0x01],
+                error: "error".into(),
+            },
+        ];
+        let mut covered = std::collections::HashSet::new();
+        for event in &events {
+            covered.insert(match event {
+                Event::Heartbeat { .. } => "heartbeat",
+                Event::ConfirmLeader { .. } => "confirm_leader",
+                Event::SolicitVote { .. } => "solicit_vote",
+                Event::GrantVote => "grant_vote",
+                Event::ReplicateEntries { .. } => "replicate_entries",
+                Event::AcceptEntries { .. } => "accept_entries",
+                Event::RejectEntries => "reject_entries",
+                Event::RequestEntries { .. } => "request_entries",
+                Event::ReadState { .. } => "read_state",
+                Event::MutateState { .. } => "mutate_state",
+                Event::RespondState { .. } => "respond_state",
+                Event::RespondError { .. } => "respond_error",
+            });
+        }
+        assert_eq!(events.len(), covered.len(), "Duplicate event kinds");
+        events
+    }
+
+    #[test]
+    fn message_conversion() {
+        for event in events() {
+            let msg = || Message {
+                term: 3,
+                from: Some("a".into()),
+                to: Some("b".into()),
+                event: event.clone(),
+            };
+            let pb = message_to_protobuf(msg(), Compression::None).unwrap();
+            // The Protobuf oneof field must match the event name
+            let name = format!("{:?}", pb.event.as_ref().unwrap());
+            let kind = format!("{:?}", event);
+            assert_eq!(
+                kind.split(|c: char| !c.is_alphanumeric()).next(),
+                name.split('(')
+                    .next()
+                    .map(|n| n
+                        .split('_')
+                        .map(|w| w[..1].to_uppercase() + &w[1..])
+                        .collect::<String>())
+                    .as_deref(),
+            );
+            assert_eq!(msg(), message_from_protobuf(pb).unwrap());
+
+            let serialized = crate::serializer::wire::serialize(msg()).unwrap();
+            assert_eq!(
+                msg(),
+                crate::serializer::wire::deserialize::<Message>(&serialized).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn is_idle() {
+        let timeout = Duration::from_millis(100);
+        let now = Instant::now();
+        let old = now - Duration::from_millis(200);
+        assert!(!super::is_idle(None, None, timeout));
+        assert!(!super::is_idle(Some(now), None, timeout));
+        assert!(super::is_idle(Some(old), None, timeout));
+        assert!(super::is_idle(Some(old), Some(old), timeout));
+        assert!(!super::is_idle(Some(old), Some(now), timeout));
+    }
+
+    #[test]
+    fn chunk_entries() {
+        let entries: Vec<Entry> = (1..=10)
+            .map(|i| Entry {
+                term: i / 4 + 1,
+                command: Some(vec![i as u8; 100]),
+            })
+            .collect();
+        let msg = || Message {
+            term: 3,
+            from: Some("a".into()),
+            to: Some("b".into()),
+            event: Event::ReplicateEntries {
+                base_index: 2,
+                base_term: 1,
+                entries: entries.clone(),
+            },
+        };
+        let pb = message_to_protobuf(msg(), Compression::None).unwrap();
+
+        // Chunks are below the limit, and replicate consecutive entries.
+        let chunks = super::chunk_entries(pb.clone(), 400).unwrap();
+        assert_eq!(4, chunks.len());
+        let (mut base_index, mut base_term, mut replicated) = (2, 1, Vec::new());
+        for chunk in chunks {
+            assert!(chunk.compute_size() <= 400);
+            match message_from_protobuf(chunk).unwrap().event {
+                Event::ReplicateEntries {
+                    base_index: index,
+                    base_term: term,
+                    entries,
+                } => {
+                    assert_eq!((base_index, base_term), (index, term));
+                    base_index += entries.len() as u64;
+                    base_term = entries.last().unwrap().term;
+                    replicated.extend(entries);
+                }
+                event => panic!("Unexpected event {:?}", event),
+            }
+        }
+        assert_eq!(entries, replicated);
+
+        // Small messages aren't split.
+        let chunks = super::chunk_entries(pb.clone(), DEFAULT_MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!(
+            vec![msg()],
+            chunks
+                .into_iter()
+                .map(|c| message_from_protobuf(c).unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        // Entries larger than the limit error.
+        assert_matches!(
+            super::chunk_entries(pb, 100),
+            Err(Error::ResourceExhausted(_))
+        );
+    }
+
+    #[test]
+    fn truncate_entries() {
+        let entry = |size| Entry {
+            term: 1,
+            command: Some(vec![0x01; size]),
+        };
+        let mut msg = Message {
+            term: 1,
+            from: Some("a".into()),
+            to: Some("b".into()),
+            event: Event::ReplicateEntries {
+                base_index: 2,
+                base_term: 1,
+                entries: vec![
+                    entry(10),
+                    Entry {
+                        term: 1,
+                        command: None,
+                    },
+                    entry(11),
+                    entry(10),
+                ],
+            },
+        };
+        assert_eq!(None, super::truncate_entries(&mut msg, 11));
+        assert_eq!(Some((5, 11)), super::truncate_entries(&mut msg, 10));
+        assert_matches!(msg.event, Event::ReplicateEntries { entries, .. } if entries.len() == 2);
+    }
+
+    #[test]
+    fn replicate_entries_compression() {
+        let msg = || Message {
+            term: 1,
+            from: Some("a".into()),
+            to: Some("b".into()),
+            event: Event::ReplicateEntries {
+                base_index: 1,
+                base_term: 1,
+                entries: vec![
+                    Entry {
+                        term: 1,
+                        command: None,
+                    },
+                    Entry {
+                        term: 1,
+                        command: Some(vec![0x01]),
+                    },
+                    Entry {
+                        term: 1,
+                        command: Some(vec![0x01; 4096]),
+                    },
+                ],
+            },
+        };
+        for &compression in &[Compression::None, Compression::Lz4, Compression::Zstd] {
+            let pb = message_to_protobuf(msg(), compression).unwrap();
+            match &pb.event {
+                Some(proto::Message_oneof_event::replicate_entries(e)) => {
+                    assert_eq!(0, e.entries[1].compression);
+                    assert_eq!(compression as u32, e.entries[2].compression);
+                }
+                event => panic!("Unexpected event {:?}", event),
+            }
+            assert_eq!(msg(), message_from_protobuf(pb).unwrap());
+        }
     }
 }
@@ -1,18 +1,163 @@
-use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, SystemTimeError, UNIX_EPOCH};
 
+use futures::{Future, Sink, Stream};
 use grpc::{RequestOptions, StreamingResponse};
 
+use crate::handlers::auth::{Authenticator, Credentials};
+use crate::handlers::cluster::ClusterId;
+use crate::handlers::columnar;
+use crate::handlers::cursor::Cursors;
+use crate::handlers::disk::DiskWatchdog;
+use crate::handlers::executor::{catch_panic, CatchPanic, Executor};
+use crate::handlers::limiter::RateLimiter;
+use crate::handlers::raft::RaftStatus;
 use crate::proto::QueryRequest;
-use crate::raft::Raft;
-use crate::serializer::serialize;
+use crate::raft::{Raft, ReadConsistency};
+use crate::serializer::wire::serialize;
 use crate::sql;
+use crate::sql::session::User;
 use crate::sql::types::{Row, Value};
+use crate::store::DataDir;
 use crate::{proto, Error};
 
+/// A query result row iterator
+type Rows = Box<dyn Iterator<Item = Result<Row, Error>> + Send>;
+
+/// The number of result rows buffered between the executor and the client
+const ROW_BUFFER: usize = 64;
+
+/// The version of the client protocol, i.e. the store service messages and
+/// metadata. It must be bumped on changes that older clients or servers can't
+/// handle, e.g. new value or result encodings.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest client protocol version the server still supports.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The request metadata key carrying a read-your-writes token, i.e. a Raft log
+/// index which the local state machine must have applied before the query runs.
+pub const MIN_APPLIED_INDEX_METADATA: &str = "min-applied-index";
+
+/// How long to wait for the local state machine to catch up with a
+/// read-your-writes token.
+const MIN_APPLIED_INDEX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The request metadata key requesting periodic query progress updates.
+pub const PROGRESS_METADATA: &str = "progress";
+
+/// The request metadata key carrying a query timeout in milliseconds, after
+/// which the query is aborted. It's ignored when opening cursors, which are
+/// closed after going unused instead.
+pub const TIMEOUT_METADATA: &str = "timeout-ms";
+
+/// The request metadata key carrying the read consistency of a query, see
+/// ReadConsistency. It overrides the read_consistency session variable.
+pub const READ_CONSISTENCY_METADATA: &str = "read-consistency";
+
+/// How often query progress is sent to clients which requested it.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Returns the query timeout from request metadata, if any.
+fn timeout(metadata: &grpc::Metadata) -> Result<Option<Duration>, Error> {
+    match metadata.get(TIMEOUT_METADATA) {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .ok_or_else(|| Error::Value(format!("Invalid {} metadata", TIMEOUT_METADATA))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the read consistency from request metadata, if any.
+fn read_consistency(metadata: &grpc::Metadata) -> Result<Option<ReadConsistency>, Error> {
+    match metadata.get(READ_CONSISTENCY_METADATA) {
+        Some(value) => std::str::from_utf8(value)
+            .map_err(|_| Error::Value(format!("Invalid {} metadata", READ_CONSISTENCY_METADATA)))?
+            .parse()
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Returns the read-your-writes token from request metadata, or 0 if none.
+fn min_applied_index(metadata: &grpc::Metadata) -> Result<u64, Error> {
+    match metadata.get(MIN_APPLIED_INDEX_METADATA) {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| {
+                Error::Value(format!("Invalid {} metadata", MIN_APPLIED_INDEX_METADATA))
+            }),
+        None => Ok(0),
+    }
+}
+
+#[derive(Clone)]
 pub struct StoreServiceImpl {
     pub id: String,
+    pub read_only: bool,
+    pub witness: bool,
+    pub limiter: Arc<RateLimiter>,
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    pub admins: Arc<HashSet<String>>,
+    pub executor: Arc<Executor>,
+    pub sessions: sql::session::Registry,
+    pub audit: sql::audit::AuditLog,
+    pub cursors: Arc<Cursors>,
+    pub cache: Arc<sql::cache::QueryCache>,
+    pub statements: Arc<sql::cache::StatementCache>,
+    pub cluster: Arc<ClusterId>,
     pub raft: Raft,
+    pub raft_status: Arc<Mutex<RaftStatus>>,
+    pub checksums: Arc<Mutex<crate::store::ChecksumStatus>>,
+    pub disk: Arc<DiskWatchdog>,
+    pub data_dir: DataDir,
+    /// When the node started, for reporting its uptime.
+    pub started: Instant,
     pub storage: Box<sql::Storage>,
+    #[cfg(feature = "chaos")]
+    pub faults: Arc<crate::chaos::Faults>,
+}
+
+/// Returns the compiled-in Cargo features.
+fn features() -> Vec<String> {
+    let mut features = vec!["server".to_string()];
+    if cfg!(feature = "chaos") {
+        features.push("chaos".into());
+    }
+    features
+}
+
+/// Returns the build time as an RFC 3339 timestamp.
+fn build_time() -> String {
+    let secs = env!("MYNODE_BUILD_TIME").parse().unwrap_or_default();
+    crate::logging::format_timestamp(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Converts a rate limiter rejection into a retryable gRPC error
+fn limited_error(err: Error) -> grpc::Error {
+    grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+        grpc_status: grpc::GrpcStatus::ResourceExhausted as i32,
+        grpc_message: err.to_string(),
+    })
+}
+
+/// Converts an authentication failure into a gRPC error, with the
+/// Unauthenticated status if the credentials were rejected, or the
+/// PermissionDenied status if the user may not make the request.
+fn auth_error(err: Error) -> grpc::Error {
+    let grpc_status = match err {
+        Error::Unauthenticated(_) => grpc::GrpcStatus::Unauthenticated,
+        Error::PermissionDenied(_) => grpc::GrpcStatus::PermissionDenied,
+        _ => grpc::GrpcStatus::Internal,
+    };
+    grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+        grpc_status: grpc_status as i32,
+        grpc_message: err.to_string(),
+    })
 }
 
 fn error_response<T: Send>(error: Box<dyn std::error::Error>) -> grpc::SingleResponse<T> {
@@ -26,17 +171,105 @@ impl proto::StoreService for StoreServiceImpl {
         _: grpc::RequestOptions,
         _: proto::StatusRequest,
     ) -> grpc::SingleResponse<proto::StatusResponse> {
+        let (query_cache_hits, query_cache_misses) = self.cache.stats();
+        let (statement_cache_hits, statement_cache_misses) = self.statements.stats();
+        let raft_status = match self.raft_status.lock() {
+            Ok(status) => status.clone(),
+            Err(err) => return error_response(Box::new(Error::from(err))),
+        };
+        let checksums = match self.checksums.lock() {
+            Ok(checksums) => checksums.clone(),
+            Err(err) => return error_response(Box::new(Error::from(err))),
+        };
+        let (state_store_bytes, raft_store_bytes) = match self.data_dir.store_sizes() {
+            Ok(sizes) => sizes,
+            Err(err) => return error_response(Box::new(err)),
+        };
         let response = proto::StatusResponse {
             id: self.id.clone(),
             version: env!("CARGO_PKG_VERSION").into(),
+            cluster_id: self.cluster.get().ok().flatten().unwrap_or_default(),
+            query_cache_hits,
+            query_cache_misses,
+            statement_cache_hits,
+            statement_cache_misses,
+            raft_leader: raft_status.leader,
+            raft_commit_index: raft_status.commit_index,
+            raft_apply_index: raft_status.apply_index,
+            checksums_verified: checksums.verified,
+            checksum_mismatches: checksums.mismatches,
+            disk_available_bytes: self.disk.available(),
+            disk_fenced: self.disk.is_fenced(),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            git_commit: env!("MYNODE_GIT_COMMIT").into(),
+            build_time: build_time(),
+            features: features().into(),
+            state_store_bytes,
+            raft_store_bytes,
+            raft_elections: raft_status.elections,
+            raft_leader_changes: raft_status.leader_changes,
+            raft_leader_changes_last_minute: raft_status
+                .leader_changes_within(Duration::from_secs(60)),
+            raft_leader_changes_last_hour: raft_status
+                .leader_changes_within(Duration::from_secs(3600)),
+            raft_peer_rtt_micros: raft_status
+                .peer_rtt
+                .iter()
+                .map(|(peer, rtt)| (peer.clone(), rtt.as_micros() as u64))
+                .collect(),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+            witness: self.witness,
             ..Default::default()
         };
         grpc::SingleResponse::completed(response)
     }
 
-    fn query(&self, _: RequestOptions, req: QueryRequest) -> StreamingResponse<proto::Row> {
-        let result = match self.execute(&req.query) {
-            Ok(result) => result,
+    fn query(&self, opts: RequestOptions, req: QueryRequest) -> StreamingResponse<proto::Row> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::StreamingResponse::err(auth_error(err)),
+        };
+        let client = RateLimiter::client_id(&opts.metadata);
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        let columnar = columnar::requested(&opts.metadata);
+        let progress = opts.metadata.get(PROGRESS_METADATA).is_some();
+        let (min_index, timeout, consistency) =
+            match min_applied_index(&opts.metadata).and_then(|index| {
+                Ok((
+                    index,
+                    timeout(&opts.metadata)?,
+                    read_consistency(&opts.metadata)?,
+                ))
+            }) {
+                Ok(result) => result,
+                Err(err) => {
+                    return grpc::StreamingResponse::completed(vec![proto::Row {
+                        error: Self::error_to_protobuf(err),
+                        ..Default::default()
+                    }])
+                }
+            };
+        let permit = match self.limiter.query(&principal) {
+            Ok(permit) => permit,
+            Err(err) => return grpc::StreamingResponse::err(limited_error(err)),
+        };
+        let session = match self.sessions.register(&client, &req.query) {
+            Ok(session) => {
+                let session = match timeout {
+                    Some(timeout) => session.timeout(timeout),
+                    None => session,
+                };
+                let session = match consistency {
+                    Some(consistency) => session.read_consistency(consistency),
+                    None => session,
+                };
+                match user {
+                    Some(user) => session.user(user),
+                    None => session,
+                }
+            }
             Err(err) => {
                 return grpc::StreamingResponse::completed(vec![proto::Row {
                     error: Self::error_to_protobuf(err),
@@ -44,6 +277,61 @@ impl proto::StoreService for StoreServiceImpl {
                 }])
             }
         };
+
+        // The query runs on the executor, which streams rows back through a
+        // bounded channel, blocking if the client falls behind.
+        let (tx, rx) = futures::sync::mpsc::channel(ROW_BUFFER);
+        let service = self.clone();
+        let job = move || {
+            // Hold the query slot until the query completes
+            let _permit = permit;
+            let _log_scope = crate::logging::query_scope(session.id());
+            // Report progress until the query completes
+            let _reporter = if progress {
+                Some(Self::report_progress(
+                    tx.clone(),
+                    session.progress().clone(),
+                ))
+            } else {
+                None
+            };
+            let rows: Rows = match RaftStatus::wait_applied(
+                &service.raft_status,
+                min_index,
+                MIN_APPLIED_INDEX_TIMEOUT,
+            )
+            .and_then(|_| catch_panic(|| service.execute(&req.query, &session)))
+            {
+                Ok(rows) => Box::new(CatchPanic::new(session.guard(rows))),
+                Err(err) => Box::new(std::iter::once(Err(err))),
+            };
+            let messages: Box<dyn Iterator<Item = proto::Row>> = if columnar {
+                Box::new(Self::batches_to_protobuf(rows))
+            } else {
+                Box::new(rows.map(Self::result_to_protobuf))
+            };
+            let mut tx = tx;
+            for message in messages {
+                tx = match tx.send(message).wait() {
+                    Ok(tx) => tx,
+                    // The client went away
+                    Err(_) => return,
+                };
+            }
+            // Finish with a read-your-writes token, once all rows are read.
+            let applied_index = service.raft.observed_index();
+            if applied_index > 0 {
+                let token = proto::Row {
+                    applied_index,
+                    ..Default::default()
+                };
+                tx.send(token).wait().ok();
+            }
+        };
+        if let Err(err) = self.executor.submit(job) {
+            return grpc::StreamingResponse::err(limited_error(err));
+        }
+
         let mut metadata = grpc::Metadata::new();
         // TODO: FIXME, retrieve columns
         // metadata.add(grpc::MetadataKey::from("columns"), serialize(&plan.columns).unwrap().into());
@@ -51,25 +339,34 @@ impl proto::StoreService for StoreServiceImpl {
             grpc::MetadataKey::from("columns"),
             serialize(Vec::<String>::new()).unwrap().into(),
         );
-        grpc::StreamingResponse::iter_with_metadata(
+        grpc::StreamingResponse::metadata_and_stream(
             metadata,
-            result.map(|r| match r {
-                Ok(row) => Self::row_to_protobuf(row),
-                Err(err) => proto::Row {
-                    error: Self::error_to_protobuf(err),
-                    ..Default::default()
-                },
-            }),
+            rx.map_err(|_| grpc::Error::Other("Query result stream failed")),
         )
     }
 
     fn get_table(
         &self,
-        _: grpc::RequestOptions,
+        opts: grpc::RequestOptions,
         req: proto::GetTableRequest,
     ) -> grpc::SingleResponse<proto::GetTableResponse> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::SingleResponse::err(auth_error(err)),
+        };
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        if let Err(err) = self.limiter.request(&principal) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        let client = RateLimiter::client_id(&opts.metadata);
         let mut resp = proto::GetTableResponse::new();
-        match self.storage.get_table(&req.name) {
+        match self
+            .sessions
+            .database(&client)
+            .and_then(|database| self.storage.database(&database).get_table(&req.name))
+            .and_then(|schema| Self::authorize_select(user.as_ref(), schema))
+        {
             Ok(schema) => resp.sql = schema.to_query(),
             Err(err) => resp.error = Self::error_to_protobuf(err),
         };
@@ -78,29 +375,400 @@ impl proto::StoreService for StoreServiceImpl {
 
     fn list_tables(
         &self,
-        _: grpc::RequestOptions,
+        opts: grpc::RequestOptions,
         _: proto::Empty,
     ) -> grpc::SingleResponse<proto::ListTablesResponse> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::SingleResponse::err(auth_error(err)),
+        };
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        if let Err(err) = self.limiter.request(&principal) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        let client = RateLimiter::client_id(&opts.metadata);
         let mut resp = proto::ListTablesResponse::new();
-        match self.storage.list_tables() {
+        // Only tables the user may read are listed.
+        match self.sessions.database(&client).and_then(|database| {
+            let storage = self.storage.database(&database);
+            let mut tables = Vec::new();
+            for name in storage.list_tables()? {
+                let schema = storage.get_table(&name)?;
+                if Self::authorize_select(user.as_ref(), schema).is_ok() {
+                    tables.push(name);
+                }
+            }
+            Ok(tables)
+        }) {
             Ok(tables) => resp.name = protobuf::RepeatedField::from_vec(tables),
             Err(err) => resp.error = Self::error_to_protobuf(err),
         }
         grpc::SingleResponse::completed(resp)
     }
+
+    fn set_faults(
+        &self,
+        opts: grpc::RequestOptions,
+        req: proto::SetFaultsRequest,
+    ) -> grpc::SingleResponse<proto::SetFaultsResponse> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::SingleResponse::err(auth_error(err)),
+        };
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        if let Err(err) = self.limiter.request(&principal) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        let action = format!(
+            "SetFaults target={} fail_rate={} delay_rate={} delay_ms={}",
+            req.target, req.fail_rate, req.delay_rate, req.delay_ms
+        );
+        // Faults can take down the cluster, so only administrators may set
+        // them. Denied attempts are audited too.
+        if let Some(user) = user.as_ref().filter(|user| !user.admin) {
+            let err = Error::PermissionDenied(format!(
+                "Permission denied: user {} can't set faults, only administrators can",
+                user.name
+            ));
+            self.audit.record(&principal, &action, Some(&err));
+            return grpc::SingleResponse::err(auth_error(err));
+        }
+        let fault = crate::chaos::Fault {
+            fail_rate: req.fail_rate,
+            delay_rate: req.delay_rate,
+            delay: std::time::Duration::from_millis(req.delay_ms),
+        };
+        let mut resp = proto::SetFaultsResponse::new();
+        let result = self.set_faults(&req.target, fault);
+        self.audit
+            .record(&principal, &action, result.as_ref().err());
+        if let Err(err) = result {
+            resp.error = Self::error_to_protobuf(err);
+        }
+        grpc::SingleResponse::completed(resp)
+    }
+
+    fn open_cursor(
+        &self,
+        opts: grpc::RequestOptions,
+        req: QueryRequest,
+    ) -> grpc::SingleResponse<proto::OpenCursorResponse> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::SingleResponse::err(auth_error(err)),
+        };
+        let client = RateLimiter::client_id(&opts.metadata);
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        let permit = match self.limiter.query(&principal) {
+            Ok(permit) => permit,
+            Err(err) => return grpc::SingleResponse::err(limited_error(err)),
+        };
+        let (tx, rx) = futures::sync::oneshot::channel();
+        let service = self.clone();
+        let job = move || {
+            // Hold the query slot while the query is planned, but not while
+            // the cursor is open.
+            let _permit = permit;
+            let mut resp = proto::OpenCursorResponse::new();
+            let result = min_applied_index(&opts.metadata)
+                .and_then(|index| {
+                    RaftStatus::wait_applied(&service.raft_status, index, MIN_APPLIED_INDEX_TIMEOUT)
+                })
+                .and_then(|_| service.sessions.register(&client, &req.query))
+                .and_then(|session| match read_consistency(&opts.metadata)? {
+                    Some(consistency) => Ok(session.read_consistency(consistency)),
+                    None => Ok(session),
+                })
+                .map(|session| match user {
+                    Some(user) => session.user(user),
+                    None => session,
+                })
+                .and_then(|session| {
+                    let rows = catch_panic(|| service.execute(&req.query, &session))?;
+                    let rows = CatchPanic::new(session.guard(rows));
+                    service.cursors.open(&principal, Box::new(rows))
+                });
+            match result {
+                Ok(id) => resp.id = id,
+                Err(err) => resp.error = Self::error_to_protobuf(err),
+            }
+            tx.send(resp).ok();
+        };
+        if let Err(err) = self.executor.submit(job) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        grpc::SingleResponse::no_metadata(rx.map_err(|_| grpc::Error::Other("Cursor open failed")))
+    }
+
+    fn fetch(
+        &self,
+        opts: grpc::RequestOptions,
+        req: proto::FetchRequest,
+    ) -> grpc::SingleResponse<proto::FetchResponse> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::SingleResponse::err(auth_error(err)),
+        };
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        if let Err(err) = self.limiter.request(&principal) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        // Fetching runs the query, so it's done on the executor.
+        let (tx, rx) = futures::sync::oneshot::channel();
+        let cursors = self.cursors.clone();
+        let job = move || {
+            let mut resp = proto::FetchResponse::new();
+            match cursors.fetch(&principal, req.id, req.count as usize) {
+                Ok((rows, done)) => {
+                    resp.row = rows.into_iter().map(Self::row_to_protobuf).collect();
+                    resp.done = done;
+                }
+                Err(err) => resp.error = Self::error_to_protobuf(err),
+            }
+            tx.send(resp).ok();
+        };
+        if let Err(err) = self.executor.submit(job) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        grpc::SingleResponse::no_metadata(rx.map_err(|_| grpc::Error::Other("Cursor fetch failed")))
+    }
+
+    fn close_cursor(
+        &self,
+        opts: grpc::RequestOptions,
+        req: proto::CloseCursorRequest,
+    ) -> grpc::SingleResponse<proto::CloseCursorResponse> {
+        let user = match self.authenticate(&opts.metadata) {
+            Ok(user) => user,
+            Err(err) => return grpc::SingleResponse::err(auth_error(err)),
+        };
+        let principal =
+            RateLimiter::principal(&opts.metadata, user.as_ref().map(|u| u.name.as_str()));
+        if let Err(err) = self.limiter.request(&principal) {
+            return grpc::SingleResponse::err(limited_error(err));
+        }
+        let mut resp = proto::CloseCursorResponse::new();
+        if let Err(err) = self.cursors.close(&principal, req.id) {
+            resp.error = Self::error_to_protobuf(err);
+        }
+        grpc::SingleResponse::completed(resp)
+    }
 }
 
 impl StoreServiceImpl {
-    /// Executes an SQL statement
-    fn execute(&self, query: &str) -> Result<sql::ResultSet, Error> {
-        sql::Plan::build(sql::Parser::new(query).parse()?)?.execute(sql::Context {
-            storage: self.storage.clone(),
-        })
+    /// Authenticates a request if an authenticator is configured, returning
+    /// the authenticated user.
+    fn authenticate(&self, metadata: &grpc::Metadata) -> Result<Option<User>, Error> {
+        let authenticator = match &self.authenticator {
+            Some(authenticator) => authenticator,
+            None => return Ok(None),
+        };
+        let result = match Credentials::from_metadata(metadata) {
+            Some(credentials) => authenticator.authenticate(&credentials),
+            None => Err(Error::Unauthenticated("Authentication required".into())),
+        };
+        match result {
+            Ok(name) => Ok(Some(User {
+                admin: self.admins.is_empty() || self.admins.contains(&name),
+                name,
+            })),
+            Err(err) => {
+                warn!(
+                    "Rejected request from client {}: {}",
+                    RateLimiter::client_id(metadata),
+                    err
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns a table schema if the user may read it, i.e. is an
+    /// administrator or holds the SELECT privilege, see Context::authorize().
+    fn authorize_select(
+        user: Option<&User>,
+        schema: sql::schema::Table,
+    ) -> Result<sql::schema::Table, Error> {
+        let user = match user {
+            Some(user) if !user.admin => user,
+            _ => return Ok(schema),
+        };
+        let columns: Vec<&str> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+        if !schema.is_granted(&user.name, sql::schema::Privilege::Select, &columns) {
+            return Err(Error::PermissionDenied(format!(
+                "Permission denied: user {} lacks {} privilege on table {}",
+                user.name,
+                sql::schema::Privilege::Select,
+                schema.name
+            )));
+        }
+        Ok(schema)
+    }
+
+    /// Sets injected faults, if built with the chaos feature.
+    #[cfg(feature = "chaos")]
+    fn set_faults(&self, target: &str, fault: crate::chaos::Fault) -> Result<(), Error> {
+        self.faults.set(target, fault)
+    }
+
+    /// Sets injected faults, if built with the chaos feature.
+    #[cfg(not(feature = "chaos"))]
+    fn set_faults(&self, _: &str, _: crate::chaos::Fault) -> Result<(), Error> {
+        Err(Error::Config(
+            "Fault injection requires a node built with the chaos feature".into(),
+        ))
+    }
+
+    /// Executes an SQL statement, recording administrative statements in the
+    /// audit log along with their outcome.
+    fn execute(&self, query: &str, session: &sql::session::Query) -> Result<Rows, Error> {
+        let statement = self.statements.parse(query)?;
+        if !statement.is_administrative() {
+            return self.execute_statement(query, statement, session);
+        }
+        let result = self.execute_statement(query, statement, session);
+        self.audit
+            .record(session.principal(), query, result.as_ref().err());
+        result
+    }
+
+    /// Executes a parsed SQL statement. Results of read-only queries are served
+    /// from the query cache if possible, which is invalidated by local writes
+    /// and by any change to the state machine version. Read-only queries read
+    /// at the session's read consistency, bypassing the cache unless strong.
+    fn execute_statement(
+        &self,
+        query: &str,
+        statement: sql::ast::Statement,
+        session: &sql::session::Query,
+    ) -> Result<Rows, Error> {
+        if self.read_only && statement.is_mutation() {
+            return Err(Error::ReadOnly(format!(
+                "Node {} is read-only, refusing to execute mutating statement",
+                self.id
+            )));
+        }
+        session.variables().check_role(&statement)?;
+        if statement.is_mutation() {
+            self.disk.check_write()?;
+        }
+        let consistency = if statement.is_mutation() {
+            ReadConsistency::Strong
+        } else {
+            session.consistency()
+        };
+        let database = session.variables().database()?;
+        let written: Vec<String> = sql::cache::written_tables(&statement)
+            .into_iter()
+            .map(|t| sql::cache::qualify(&database, t))
+            .collect();
+        let schema_change = statement.is_schema_change();
+        // Temporary tables are private to the session, and their writes don't
+        // change the state machine version, so their results aren't cached.
+        let temporary = session.temporary();
+        // Cached results skip privilege checks, so only administrators use them.
+        let admin = !matches!(session.get_user(), Some(user) if !user.admin);
+        let cached = match sql::cache::cacheable_tables(&statement) {
+            Some(tables)
+                if self.cache.is_enabled()
+                    && admin
+                    && consistency == ReadConsistency::Strong
+                    && !tables
+                        .iter()
+                        .any(|t| temporary.table_exists(t).unwrap_or(true)) =>
+            {
+                Some((
+                    sql::cache::qualify(&database, &sql::cache::normalize(query)?),
+                    crate::store::Raft::new(self.raft.clone()).version()?,
+                    tables
+                        .iter()
+                        .map(|t| sql::cache::qualify(&database, t))
+                        .collect(),
+                ))
+            }
+            _ => None,
+        };
+        if let Some((key, version, _)) = &cached {
+            if let Some(rows) = self.cache.get(key, version)? {
+                return Ok(Box::new(rows.into_iter().map(Ok)));
+            }
+        }
+
+        let storage =
+            match consistency {
+                ReadConsistency::Strong => self.storage.clone(),
+                consistency => Box::new(self.storage.with_store(
+                    crate::store::Raft::new(self.raft.clone()).consistency(consistency),
+                )),
+            };
+        let result: sql::ResultSet = sql::Plan::build(statement)?.execute(sql::Context {
+            storage,
+            sessions: self.sessions.clone(),
+            audit: self.audit.clone(),
+            progress: session.progress().clone(),
+            cancellation: session.cancellation().clone(),
+            variables: session.variables().clone(),
+            temporary: session.temporary().clone(),
+            user: session.get_user().cloned(),
+        })?;
+        for table in written {
+            self.cache.invalidate_table(&table)?;
+        }
+        if schema_change {
+            self.statements.invalidate()?;
+        }
+        match cached {
+            Some((key, version, tables)) => {
+                let mut rows = Vec::new();
+                for row in result {
+                    session.check()?;
+                    rows.push(row?);
+                }
+                self.cache.put(key, version, tables, rows.clone())?;
+                Ok(Box::new(rows.into_iter().map(Ok)))
+            }
+            None => Ok(Box::new(result)),
+        }
+    }
+
+    /// Periodically sends query progress to the client from a background
+    /// thread, until the returned sender is dropped.
+    fn report_progress(
+        tx: futures::sync::mpsc::Sender<proto::Row>,
+        progress: sql::session::Progress,
+    ) -> crossbeam_channel::Sender<()> {
+        let (done_tx, done_rx) = crossbeam_channel::bounded::<()>(0);
+        std::thread::spawn(move || {
+            let mut tx = tx;
+            while let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+                done_rx.recv_timeout(PROGRESS_INTERVAL)
+            {
+                let row = proto::Row {
+                    progress: Some(proto::QueryProgress {
+                        rows_scanned: progress.scanned(),
+                        rows_returned: progress.returned(),
+                        ..Default::default()
+                    })
+                    .into(),
+                    ..Default::default()
+                };
+                tx = match tx.send(row).wait() {
+                    Ok(tx) => tx,
+                    // The client went away
+                    Err(_) => return,
+                };
+            }
+        });
+        done_tx
     }
 
-    /// Converts an error into a protobuf object
     fn error_to_protobuf(err: Error) -> protobuf::SingularPtrField<proto::Error> {
         protobuf::SingularPtrField::from(Some(proto::Error {
+            read_only: matches!(err, Error::ReadOnly(_)),
             message: err.to_string(),
             ..Default::default()
         }))
@@ -112,6 +780,48 @@ impl StoreServiceImpl {
             .map(|t| t.as_secs() as i64)
     }
 
+    /// Converts a row result into a protobuf row
+    fn result_to_protobuf(result: Result<Row, Error>) -> proto::Row {
+        match result {
+            Ok(row) => Self::row_to_protobuf(row),
+            Err(err) => proto::Row {
+                error: Self::error_to_protobuf(err),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Converts row results into protobuf rows holding columnar batches.
+    /// Errors are sent as separate rows, after any rows preceding them.
+    fn batches_to_protobuf(mut rows: Rows) -> impl Iterator<Item = proto::Row> {
+        let mut error = None;
+        std::iter::from_fn(move || {
+            if let Some(err) = error.take() {
+                return Some(Self::result_to_protobuf(Err(err)));
+            }
+            let mut batch = Vec::new();
+            for result in &mut rows {
+                match result {
+                    Ok(row) => batch.push(row),
+                    Err(err) => {
+                        error = Some(err);
+                        break;
+                    }
+                }
+                if batch.len() >= columnar::COLUMNAR_BATCH_SIZE {
+                    break;
+                }
+            }
+            if batch.is_empty() {
+                return error.take().map(|err| Self::result_to_protobuf(Err(err)));
+            }
+            Some(proto::Row {
+                batch: Some(columnar::encode(batch)).into(),
+                ..Default::default()
+            })
+        })
+    }
+
     /// Converts a row into a protobuf row
     fn row_to_protobuf(row: Row) -> proto::Row {
         proto::Row {
@@ -1,6 +1,7 @@
 #[cfg(test)]
 #[macro_use]
 extern crate assert_matches;
+#[cfg(feature = "server")]
 extern crate config;
 #[macro_use(select)]
 extern crate crossbeam_channel;
@@ -8,22 +9,63 @@ extern crate crossbeam_channel;
 extern crate derivative;
 #[cfg(test)]
 extern crate goldenfile;
+#[cfg(feature = "server")]
 extern crate httpbis;
 #[macro_use]
 extern crate log;
 extern crate rmp_serde as rmps;
+#[cfg(feature = "server")]
 extern crate rustyline;
 extern crate serde;
 
+mod chaos;
+#[cfg(feature = "server")]
 mod client;
+#[cfg(feature = "server")]
+mod clock;
+mod embedded;
 mod error;
+#[cfg(feature = "server")]
 mod handlers;
+mod logging;
+#[cfg(feature = "server")]
+mod migrate;
+#[cfg(feature = "server")]
 mod proto;
-mod raft;
+pub mod raft;
 mod serializer;
 mod sql;
 mod store;
 
-pub use client::Client;
+pub use chaos::Fault;
+#[cfg(feature = "server")]
+pub use client::{Client, Cursor, QueryProgress};
+pub use embedded::Embedded;
 pub use error::Error;
-pub use handlers::Node;
+#[cfg(feature = "server")]
+pub use handlers::auth::{Authenticator, Credentials, ExternalCommand, PasswordFile, StaticTokens};
+#[cfg(feature = "server")]
+pub use handlers::limiter::{Limits, RateLimiter};
+#[cfg(feature = "server")]
+pub use handlers::{Node, PeerAddress};
+pub use logging::JsonLogger;
+#[cfg(feature = "server")]
+pub use migrate::{Migration, DEFAULT_BATCH_SIZE};
+pub use raft::LogReport;
+pub use sql::types::{Row, Value};
+pub use sql::{format_query, is_incomplete_query, PreparedStatement, StorageReport};
+pub use store::{
+    Bytes, Chunked, Compression, DataDir, Direction, File, KVMemory, Keyring, MemoryStats, Range,
+    Store, DEFAULT_CHUNK_SIZE, DEFAULT_FLUSH_THRESHOLD, LAYOUT_VERSION,
+};
+
+/// Internals exposed for the benchmarks in benches/ and the fuzz targets in fuzz/,
+/// not part of the public API.
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::embedded::Embedded;
+    pub use crate::raft::{Entry, Log};
+    pub use crate::sql::types::Value;
+    pub use crate::sql::{Expression, Parser};
+    pub use crate::store::{File, KVMemory, Store};
+}
@@ -0,0 +1,193 @@
+//! Structured JSON logging. Log records are written to stdout as one JSON
+//! object per line, with the node ID and any context of the logging thread:
+//! the Raft term on the Raft thread, and the query ID while executing a query.
+
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// The log context of a thread.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Context {
+    term: Option<u64>,
+    query_id: Option<u64>,
+}
+
+/// Sets the Raft term of log records on this thread.
+pub fn set_term(term: u64) {
+    CONTEXT.with(|c| c.borrow_mut().term = Some(term))
+}
+
+/// Sets the query ID of log records on this thread, until the returned guard
+/// is dropped.
+#[cfg(feature = "server")]
+pub fn query_scope(id: u64) -> QueryScope {
+    let previous = CONTEXT.with(|c| c.borrow_mut().query_id.replace(id));
+    QueryScope { previous }
+}
+
+/// A guard which resets the query ID of log records when dropped, see
+/// query_scope().
+#[cfg(feature = "server")]
+pub struct QueryScope {
+    previous: Option<u64>,
+}
+
+#[cfg(feature = "server")]
+impl Drop for QueryScope {
+    fn drop(&mut self) {
+        CONTEXT.with(|c| c.borrow_mut().query_id = self.previous)
+    }
+}
+
+/// A logger writing JSON records. Records are filtered by the global max
+/// level, such that it can be changed at runtime.
+pub struct JsonLogger {
+    node_id: String,
+    /// Whether to log records of other crates than this one.
+    all_modules: bool,
+}
+
+impl JsonLogger {
+    /// Creates a new JSON logger for a node.
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.into(),
+            all_modules: false,
+        }
+    }
+
+    /// Also logs records of other crates, e.g. the gRPC library.
+    pub fn all_modules(mut self, all_modules: bool) -> Self {
+        self.all_modules = all_modules;
+        self
+    }
+
+    /// Formats a record as a JSON object.
+    fn format(&self, record: &log::Record, time: SystemTime) -> String {
+        let mut object = Map::new();
+        object.insert("timestamp".into(), json!(format_timestamp(time)));
+        object.insert("level".into(), json!(record.level().to_string()));
+        object.insert(
+            "module".into(),
+            json!(record.module_path().unwrap_or_else(|| record.target())),
+        );
+        object.insert("node_id".into(), json!(self.node_id));
+        CONTEXT.with(|c| {
+            let context = c.borrow();
+            if let Some(term) = context.term {
+                object.insert("term".into(), json!(term));
+            }
+            if let Some(query_id) = context.query_id {
+                object.insert("query_id".into(), json!(query_id));
+            }
+        });
+        object.insert("message".into(), json!(record.args().to_string()));
+        Value::Object(object).to_string()
+    }
+}
+
+impl log::Log for JsonLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.all_modules || metadata.target().starts_with("mynode")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let line = self.format(record, SystemTime::now());
+            writeln!(std::io::stdout().lock(), "{}", line).ok();
+        }
+    }
+
+    fn flush(&self) {
+        std::io::stdout().flush().ok();
+    }
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with milliseconds, using the
+/// civil_from_days algorithm from
+/// http://howardhinnant.github.io/date_algorithms.html
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, secs) = (since.as_secs() / 86400, since.as_secs() % 86400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamp() {
+        assert_eq!("1970-01-01T00:00:00.000Z", format_timestamp(UNIX_EPOCH));
+        assert_eq!(
+            "2000-02-29T12:34:56.789Z",
+            format_timestamp(UNIX_EPOCH + Duration::from_millis(951_827_696_789))
+        );
+        assert_eq!(
+            "2024-12-31T23:59:59.000Z",
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1_735_689_599))
+        );
+    }
+
+    #[test]
+    fn format() {
+        let logger = JsonLogger::new("a");
+        let time = UNIX_EPOCH + Duration::from_secs(1);
+        let log = |message: &str| {
+            let line = logger.format(
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(log::Level::Warn)
+                    .target("mynode::raft")
+                    .module_path(Some("mynode::raft::node"))
+                    .build(),
+                time,
+            );
+            serde_json::from_str::<Value>(&line).unwrap()
+        };
+        assert_eq!(
+            json!({
+                "timestamp": "1970-01-01T00:00:01.000Z",
+                "level": "WARN",
+                "module": "mynode::raft::node",
+                "node_id": "a",
+                "message": "Log \"quoted\"",
+            }),
+            log("Log \"quoted\"")
+        );
+
+        // Context fields are included while set
+        set_term(3);
+        let scope = query_scope(7);
+        let record = log("In query");
+        assert_eq!(json!(3), record["term"]);
+        assert_eq!(json!(7), record["query_id"]);
+        drop(scope);
+        assert_eq!(None, log("After query").get("query_id"));
+    }
+}
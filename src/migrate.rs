@@ -0,0 +1,163 @@
+use crate::client::Client;
+use crate::sql::ast;
+use crate::sql::types::Row;
+use crate::Error;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The default number of rows per insert batch
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Copies tables from one cluster to another, by scanning source tables with
+/// a server-side cursor and inserting rows into the target in batches. The
+/// number of rows copied per table is recorded in an optional checkpoint
+/// file, such that an interrupted migration resumes where it left off. Rows
+/// copied after the last checkpoint are copied again, skipping the ones which
+/// already exist with ON CONFLICT DO NOTHING.
+pub struct Migration<'a> {
+    from: &'a Client,
+    to: &'a Client,
+    batch_size: usize,
+    checkpoint: Option<PathBuf>,
+}
+
+impl<'a> Migration<'a> {
+    /// Creates a new migration between two clusters
+    pub fn new(from: &'a Client, to: &'a Client) -> Self {
+        Self {
+            from,
+            to,
+            batch_size: DEFAULT_BATCH_SIZE,
+            checkpoint: None,
+        }
+    }
+
+    /// Sets the number of rows per insert batch
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Records progress in a checkpoint file, resuming from it if it exists
+    pub fn checkpoint(mut self, path: &Path) -> Self {
+        self.checkpoint = Some(path.to_path_buf());
+        self
+    }
+
+    /// Copies a table, creating it in the target cluster if it doesn't exist.
+    /// Returns the total number of rows copied, including previous runs.
+    pub fn copy_table(&self, table: &str) -> Result<u64, Error> {
+        if !self.to.list_tables()?.iter().any(|t| t == table) {
+            info!("Creating table {}", table);
+            self.to.query(&self.from.get_table(table)?)?.count();
+        }
+
+        // Skip rows copied by previous runs. This relies on the source table
+        // not changing, since scans are ordered by key.
+        let mut checkpoints = self.load_checkpoints()?;
+        let mut copied = checkpoints.get(table).copied().unwrap_or(0);
+        let mut cursor = self.from.cursor(&format!("SELECT * FROM {}", table))?;
+        let mut skip = copied;
+        while skip > 0 {
+            let count = std::cmp::min(skip, self.batch_size as u64) as usize;
+            let rows = cursor.fetch(count)?;
+            if rows.len() < count {
+                return Err(Error::Value(format!(
+                    "Table {} has fewer rows than the checkpoint, it was changed since",
+                    table
+                )));
+            }
+            skip -= count as u64;
+        }
+        if copied > 0 {
+            info!("Resuming copy of table {} after {} rows", table, copied);
+        }
+
+        loop {
+            let rows = cursor.fetch(self.batch_size)?;
+            if rows.is_empty() {
+                break;
+            }
+            let count = rows.len() as u64;
+            self.to.query(&insert_statement(table, rows))?.count();
+            copied += count;
+            checkpoints.insert(table.to_string(), copied);
+            self.save_checkpoints(&checkpoints)?;
+        }
+        info!("Copied {} rows of table {}", copied, table);
+        Ok(copied)
+    }
+
+    /// Loads table checkpoints, as lines of table name and rows copied
+    fn load_checkpoints(&self) -> Result<BTreeMap<String, u64>, Error> {
+        let path = match &self.checkpoint {
+            Some(path) if path.exists() => path,
+            _ => return Ok(BTreeMap::new()),
+        };
+        let mut checkpoints = BTreeMap::new();
+        for line in std::fs::read_to_string(path)?.lines() {
+            match line.split_once(' ') {
+                Some((table, rows)) => checkpoints.insert(table.to_string(), rows.parse()?),
+                None => {
+                    return Err(Error::Parse(format!(
+                        "Invalid checkpoint line {}, expected table and rows",
+                        line
+                    )))
+                }
+            };
+        }
+        Ok(checkpoints)
+    }
+
+    /// Saves table checkpoints, atomically replacing the checkpoint file
+    fn save_checkpoints(&self, checkpoints: &BTreeMap<String, u64>) -> Result<(), Error> {
+        if let Some(path) = &self.checkpoint {
+            let tmp = path.with_extension("tmp");
+            let content: String = checkpoints
+                .iter()
+                .map(|(table, rows)| format!("{} {}\n", table, rows))
+                .collect();
+            std::fs::write(&tmp, content)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds an INSERT statement for a batch of rows
+fn insert_statement(table: &str, rows: Vec<Row>) -> String {
+    ast::Statement::Insert {
+        table: table.to_string(),
+        columns: None,
+        values: rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|v| ast::Literal::from(v).into())
+                    .collect()
+            })
+            .collect(),
+        ignore_conflicts: true,
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::types::Value;
+    use crate::sql::Parser;
+
+    #[test]
+    fn insert_statement() {
+        let rows = vec![
+            vec![Value::Integer(1), Value::String("it's".into())],
+            vec![Value::Null, Value::Float(1.0)],
+        ];
+        let sql = super::insert_statement("movies", rows);
+        assert_eq!(
+            "INSERT INTO movies VALUES (1, 'it''s'), (NULL, 1.0) ON CONFLICT DO NOTHING",
+            sql
+        );
+        assert_eq!(sql, Parser::new(&sql).parse().unwrap().to_string());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::Error;
+use std::time::Duration;
+
+/// The staleness bound of bounded-staleness reads given without one.
+pub const DEFAULT_MAX_STALENESS: Duration = Duration::from_secs(1);
+
+/// The consistency of a state machine read, see Raft::read_with(). Weaker
+/// consistencies let reads skip the quorum round trip of strong reads, at the
+/// risk of stale results. Reads which can't be served at the requested
+/// consistency by the local node fall back to strong reads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadConsistency {
+    /// Linearizable reads, served by the leader once a quorum has confirmed
+    /// its leadership.
+    Strong,
+    /// Reads served by the leader without a round trip while it holds a lease,
+    /// i.e. a quorum has confirmed its leadership recently. They may be stale
+    /// if a new leader has been elected within the lease duration.
+    LeaderLease,
+    /// Reads served by any node which has heard from the leader within the
+    /// given duration, and has applied all entries committed as of then.
+    BoundedStaleness(Duration),
+    /// Reads served by any node from its local state machine, however stale.
+    Eventual,
+}
+
+impl ReadConsistency {
+    /// Returns true if reads can be served by followers, not just the leader.
+    pub fn allows_followers(&self) -> bool {
+        matches!(
+            self,
+            ReadConsistency::BoundedStaleness(_) | ReadConsistency::Eventual
+        )
+    }
+}
+
+impl std::fmt::Display for ReadConsistency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReadConsistency::Strong => write!(f, "strong"),
+            ReadConsistency::LeaderLease => write!(f, "leader-lease"),
+            ReadConsistency::BoundedStaleness(bound) => {
+                write!(f, "bounded-staleness:{}", bound.as_millis())
+            }
+            ReadConsistency::Eventual => write!(f, "eventual"),
+        }
+    }
+}
+
+/// Parses a read consistency, as written by Display. The staleness bound is
+/// given in milliseconds, e.g. bounded-staleness:500, and defaults to
+/// DEFAULT_MAX_STALENESS.
+impl std::str::FromStr for ReadConsistency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::Value(format!("Invalid read consistency {}", s));
+        let (name, bound) = match s.split_once(':') {
+            Some((name, bound)) => (name, Some(bound)),
+            None => (s, None),
+        };
+        Ok(match (name.to_lowercase().as_str(), bound) {
+            ("strong", None) => ReadConsistency::Strong,
+            ("leader-lease", None) => ReadConsistency::LeaderLease,
+            ("bounded-staleness", None) => ReadConsistency::BoundedStaleness(DEFAULT_MAX_STALENESS),
+            ("bounded-staleness", Some(ms)) => ReadConsistency::BoundedStaleness(
+                Duration::from_millis(ms.parse().map_err(|_| invalid())?),
+            ),
+            ("eventual", None) => ReadConsistency::Eventual,
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<(), Error> {
+        for consistency in &[
+            ReadConsistency::Strong,
+            ReadConsistency::LeaderLease,
+            ReadConsistency::BoundedStaleness(Duration::from_millis(250)),
+            ReadConsistency::Eventual,
+        ] {
+            assert_eq!(*consistency, consistency.to_string().parse()?);
+        }
+        assert_eq!(
+            ReadConsistency::BoundedStaleness(DEFAULT_MAX_STALENESS),
+            "Bounded-Staleness".parse()?
+        );
+        for invalid in &["", "linearizable", "strong:1", "bounded-staleness:x"] {
+            assert_matches!(invalid.parse::<ReadConsistency>(), Err(Error::Value(_)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn allows_followers() {
+        assert!(!ReadConsistency::Strong.allows_followers());
+        assert!(!ReadConsistency::LeaderLease.allows_followers());
+        assert!(ReadConsistency::BoundedStaleness(DEFAULT_MAX_STALENESS).allows_followers());
+        assert!(ReadConsistency::Eventual.allows_followers());
+    }
+}
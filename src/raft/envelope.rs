@@ -0,0 +1,69 @@
+use crate::serializer::{deserialize, serialize};
+use crate::Error;
+use serde_derive::{Deserialize, Serialize};
+
+/// A versioned envelope for state machine commands, identifying the kind of
+/// command and the schema version of its payload. This allows several state
+/// machines to share a log, tools to decode commands, and nodes to skip
+/// command kinds or versions they don't know rather than failing to apply
+/// them, e.g. during upgrades.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The command kind, naming the state machine or subsystem handling it.
+    pub kind: String,
+    /// The schema version of the payload.
+    pub version: u32,
+    /// The encoded command.
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Creates a new envelope.
+    pub fn new(kind: &str, version: u32, payload: Vec<u8>) -> Self {
+        Self {
+            kind: kind.into(),
+            version,
+            payload,
+        }
+    }
+
+    /// Encodes the envelope, for use as a command.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        serialize(self)
+    }
+
+    /// Decodes an envelope, or returns None if the command isn't one, e.g.
+    /// commands written before envelopes were introduced.
+    pub fn decode(command: &[u8]) -> Option<Self> {
+        deserialize(command).ok()
+    }
+
+    /// Returns true if a handler of the given kind, supporting payload
+    /// versions up to max_version, can handle the envelope.
+    pub fn is_supported(&self, kind: &str, max_version: u32) -> bool {
+        self.kind == kind && self.version <= max_version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope() -> Result<(), Error> {
+        let envelope = Envelope::new("kv", 2, vec![0x01, 0x02]);
+        assert_eq!(
+            Some(envelope.clone()),
+            Envelope::decode(&envelope.encode()?)
+        );
+        assert!(envelope.is_supported("kv", 2));
+        assert!(envelope.is_supported("kv", 3));
+        assert!(!envelope.is_supported("kv", 1));
+        assert!(!envelope.is_supported("sql", 2));
+
+        // Other values aren't envelopes
+        assert_eq!(None, Envelope::decode(&serialize(("kv", 2))?));
+        assert_eq!(None, Envelope::decode(&[0xff]));
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use super::node::Status;
+use super::ApplyHooks;
+use crate::Error;
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A Raft event, published on the event bus when the local node changes state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RaftEvent {
+    /// The local node became leader for the given term.
+    BecameLeader { term: u64 },
+    /// The local node stepped down as leader in the given term.
+    SteppedDown { term: u64 },
+    /// The local node started an election for the given term.
+    ElectionStarted { term: u64 },
+    /// The local node learned of a new leader, possibly itself, or of a new
+    /// term with a known leader.
+    LeaderChanged { term: u64, leader: String },
+    /// The commit index advanced to the given index. Several entries may be
+    /// committed at once, so indexes may be skipped.
+    Committed(u64),
+    /// The apply index advanced to the given index, possibly skipping indexes.
+    Applied(u64),
+    /// A message could not be sent to the given peer, since its address
+    /// could not be resolved. Only published once until the peer is reachable.
+    PeerUnreachable(String),
+    /// A peer confirmed a heartbeat after the given round-trip time.
+    HeartbeatRtt { peer: String, rtt: Duration },
+}
+
+impl RaftEvent {
+    /// Returns the events describing a transition between two node statuses.
+    pub(super) fn changes(old: &Status, new: &Status) -> Vec<Self> {
+        let mut events = Vec::new();
+        if old.leader && (!new.leader || new.term != old.term) {
+            events.push(RaftEvent::SteppedDown { term: new.term });
+        }
+        if new.leader && (!old.leader || new.term != old.term) {
+            events.push(RaftEvent::BecameLeader { term: new.term });
+        }
+        if new.candidate && (!old.candidate || new.term != old.term) {
+            events.push(RaftEvent::ElectionStarted { term: new.term });
+        }
+        if let Some(leader) = &new.leader_id {
+            if new.leader_id != old.leader_id || new.term != old.term {
+                events.push(RaftEvent::LeaderChanged {
+                    term: new.term,
+                    leader: leader.clone(),
+                });
+            }
+        }
+        if new.commit_index > old.commit_index {
+            events.push(RaftEvent::Committed(new.commit_index));
+        }
+        if new.apply_index > old.apply_index {
+            events.push(RaftEvent::Applied(new.apply_index));
+        }
+        events
+    }
+}
+
+/// An event bus, which broadcasts Raft events to all subscribers. This allows
+/// e.g. admin APIs and metrics to observe the Raft node without accessing its
+/// internals. It also carries the node's post-apply hooks, for observing the
+/// applied entries themselves.
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<RaftEvent>>>,
+    hooks: ApplyHooks,
+}
+
+impl EventBus {
+    /// Creates a new event bus.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes to events published after this call. The receiver must be
+    /// drained, or dropped to unsubscribe.
+    pub fn subscribe(&self) -> Result<Receiver<RaftEvent>, Error> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.subscribers.lock()?.push(tx);
+        Ok(rx)
+    }
+
+    /// Returns the post-apply hooks of the node, see ApplyHooks.
+    pub fn hooks(&self) -> &ApplyHooks {
+        &self.hooks
+    }
+
+    /// Publishes an event to all subscribers, removing any that have gone away.
+    pub fn publish(&self, event: RaftEvent) -> Result<(), Error> {
+        debug!("Publishing Raft event {:?}", event);
+        self.subscribers
+            .lock()?
+            .retain(|tx| tx.send(event.clone()).is_ok());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes() {
+        let status = |leader, term, commit_index, apply_index| Status {
+            leader,
+            candidate: false,
+            leader_id: None,
+            term,
+            commit_index,
+            apply_index,
+        };
+        assert_eq!(
+            Vec::<RaftEvent>::new(),
+            RaftEvent::changes(&status(false, 1, 2, 2), &status(false, 2, 2, 2))
+        );
+        assert_eq!(
+            vec![
+                RaftEvent::BecameLeader { term: 2 },
+                RaftEvent::Committed(3),
+                RaftEvent::Applied(3),
+            ],
+            RaftEvent::changes(&status(false, 1, 2, 2), &status(true, 2, 3, 3))
+        );
+        assert_eq!(
+            vec![RaftEvent::SteppedDown { term: 3 }],
+            RaftEvent::changes(&status(true, 2, 3, 3), &status(false, 3, 3, 3))
+        );
+        assert_eq!(
+            vec![
+                RaftEvent::SteppedDown { term: 3 },
+                RaftEvent::BecameLeader { term: 3 }
+            ],
+            RaftEvent::changes(&status(true, 2, 3, 3), &status(true, 3, 3, 3))
+        );
+
+        // Elections and leader changes.
+        let role = |term, candidate, leader_id: Option<&str>| Status {
+            candidate,
+            leader_id: leader_id.map(String::from),
+            ..status(false, term, 3, 3)
+        };
+        let changed = |term, leader: &str| RaftEvent::LeaderChanged {
+            term,
+            leader: leader.into(),
+        };
+        assert_eq!(
+            vec![RaftEvent::ElectionStarted { term: 3 }],
+            RaftEvent::changes(&role(2, false, Some("a")), &role(3, true, None))
+        );
+        assert_eq!(
+            vec![RaftEvent::ElectionStarted { term: 4 }],
+            RaftEvent::changes(&role(3, true, None), &role(4, true, None))
+        );
+        assert_eq!(
+            vec![changed(4, "b")],
+            RaftEvent::changes(&role(4, true, None), &role(4, false, Some("b")))
+        );
+        assert_eq!(
+            vec![changed(5, "b")],
+            RaftEvent::changes(&role(4, false, Some("b")), &role(5, false, Some("b")))
+        );
+        assert_eq!(
+            Vec::<RaftEvent>::new(),
+            RaftEvent::changes(&role(5, false, Some("b")), &role(5, false, Some("b")))
+        );
+        assert_eq!(
+            Vec::<RaftEvent>::new(),
+            RaftEvent::changes(&role(5, false, Some("b")), &role(6, false, None))
+        );
+    }
+
+    #[test]
+    fn bus() {
+        let bus = EventBus::new();
+        bus.publish(RaftEvent::Committed(1)).unwrap();
+        let a = bus.subscribe().unwrap();
+        let b = bus.subscribe().unwrap();
+        bus.publish(RaftEvent::Committed(2)).unwrap();
+        assert_eq!(Ok(RaftEvent::Committed(2)), a.try_recv());
+        assert_eq!(Ok(RaftEvent::Committed(2)), b.try_recv());
+        assert!(a.try_recv().is_err());
+
+        drop(b);
+        bus.publish(RaftEvent::Applied(2)).unwrap();
+        assert_eq!(1, bus.subscribers.lock().unwrap().len());
+        assert_eq!(Ok(RaftEvent::Applied(2)), a.try_recv());
+    }
+}
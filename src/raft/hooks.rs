@@ -0,0 +1,115 @@
+use super::log::Entry;
+use crate::Error;
+use std::sync::{Arc, RwLock};
+
+/// The result of applying a log entry to the state machine.
+#[derive(Debug)]
+pub struct ApplyResult {
+    /// The index of the applied entry.
+    pub index: u64,
+    /// The state machine output, empty for noop entries.
+    pub output: Result<Vec<u8>, Error>,
+}
+
+/// A post-apply hook, called with each entry applied to the state machine.
+pub type ApplyHook = Box<dyn Fn(&Entry, &ApplyResult) + Send + Sync>;
+
+/// A registry of post-apply hooks, which lets embedders observe entries as
+/// they're applied, e.g. for change data capture, metrics or cache
+/// invalidation. Hooks are called synchronously on the Raft thread, in log
+/// order, so they must be fast and must not call back into the Raft node.
+/// A panicking hook stops the node. The registry can be cloned and shared,
+/// and hooks can be registered before or after the node is started.
+#[derive(Clone, Default)]
+pub struct ApplyHooks {
+    hooks: Arc<RwLock<Vec<(String, ApplyHook)>>>,
+}
+
+impl std::fmt::Debug for ApplyHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ApplyHooks")
+    }
+}
+
+impl ApplyHooks {
+    /// Creates a new, empty hook registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a hook under a name, replacing any hook with the same name.
+    pub fn register(&self, name: &str, hook: ApplyHook) -> Result<(), Error> {
+        let mut hooks = self.hooks.write()?;
+        hooks.retain(|(n, _)| n != name);
+        hooks.push((name.into(), hook));
+        Ok(())
+    }
+
+    /// Removes a hook, returning true if it was registered.
+    pub fn unregister(&self, name: &str) -> Result<bool, Error> {
+        let mut hooks = self.hooks.write()?;
+        let len = hooks.len();
+        hooks.retain(|(n, _)| n != name);
+        Ok(hooks.len() < len)
+    }
+
+    /// Calls all hooks with an applied entry, in registration order.
+    pub(super) fn call(&self, entry: &Entry, result: &ApplyResult) -> Result<(), Error> {
+        for (_, hook) in self.hooks.read()?.iter() {
+            hook(entry, result)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn hooks() -> Result<(), Error> {
+        let hooks = ApplyHooks::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook = |name: &'static str| -> ApplyHook {
+            let seen = seen.clone();
+            Box::new(move |entry, result| {
+                seen.lock()
+                    .unwrap()
+                    .push((name, entry.term, result.index, result.output.is_ok()))
+            })
+        };
+        hooks.register("a", hook("a"))?;
+        hooks.register("b", hook("b"))?;
+        hooks.register("a", hook("c"))?;
+
+        let entry = Entry {
+            term: 2,
+            command: Some(vec![0x01]),
+        };
+        hooks.call(
+            &entry,
+            &ApplyResult {
+                index: 3,
+                output: Ok(vec![]),
+            },
+        )?;
+        assert_eq!(
+            vec![("b", 2, 3, true), ("c", 2, 3, true)],
+            *seen.lock().unwrap()
+        );
+
+        assert!(hooks.unregister("b")?);
+        assert!(!hooks.unregister("b")?);
+        seen.lock().unwrap().clear();
+        hooks.call(
+            &entry,
+            &ApplyResult {
+                index: 4,
+                output: Err(Error::Internal("boom".into())),
+            },
+        )?;
+        assert_eq!(vec![("c", 2, 4, false)], *seen.lock().unwrap());
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use crate::{
     Error,
 };
 
-use super::State;
+use super::{ApplyHooks, ApplyResult, State};
 
 /// A replicated log entry
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -17,6 +17,52 @@ pub struct Entry {
     pub command: Option<Vec<u8>>,
 }
 
+/// The result of an offline Raft log check, see Log::check().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogReport {
+    /// The persisted current term.
+    pub term: u64,
+    /// The persisted vote in the current term.
+    pub voted_for: Option<String>,
+    /// The persisted index of the last applied entry.
+    pub apply_index: u64,
+    /// The number of stored entries.
+    pub entries: u64,
+    /// The index of the last contiguous entry, as seen by the node.
+    pub last_index: u64,
+    /// The term of the last contiguous entry.
+    pub last_term: u64,
+    /// Any problems found.
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for LogReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Term:        {}", self.term)?;
+        writeln!(
+            f,
+            "Voted for:   {}",
+            self.voted_for.as_deref().unwrap_or("none")
+        )?;
+        writeln!(f, "Applied:     {}", self.apply_index)?;
+        writeln!(f, "Entries:     {}", self.entries)?;
+        writeln!(
+            f,
+            "Last entry:  {} (term {})",
+            self.last_index, self.last_term
+        )?;
+        if self.problems.is_empty() {
+            write!(f, "No problems found")
+        } else {
+            write!(f, "Found {} problems:", self.problems.len())?;
+            for problem in &self.problems {
+                write!(f, "\n- {}", problem)?;
+            }
+            Ok(())
+        }
+    }
+}
+
 /// The replicated Raft Log
 #[derive(Debug)]
 pub struct Log {
@@ -36,17 +82,19 @@ pub struct Log {
     apply_index: u64,
     /// The term of the last applied entry.
     apply_term: u64,
+    /// Hooks called with each applied entry.
+    hooks: ApplyHooks,
 }
 
 impl Log {
     pub fn new<S: Store>(store: S) -> Result<Self, Error> {
         let apply_index = match store.get("apply_index")? {
-            Some(raw_apply_index) => deserialize(raw_apply_index)?,
+            Some(raw_apply_index) => deserialize(&raw_apply_index)?,
             None => 0,
         };
 
         let (commit_index, commit_term) = match store.get(&apply_index.to_string())? {
-            Some(raw_entry) => (apply_index, deserialize::<Entry>(raw_entry)?.term),
+            Some(raw_entry) => (apply_index, deserialize::<Entry>(&raw_entry)?.term),
             None if apply_index == 0 => (0, 0),
             None => {
                 return Err(Error::Internal(format!(
@@ -67,9 +115,16 @@ impl Log {
             commit_term,
             apply_index,
             apply_term,
+            hooks: ApplyHooks::new(),
         })
     }
 
+    /// Calls the given hooks with each entry applied from now on.
+    pub fn hooks(mut self, hooks: ApplyHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
     /// Appends an entry in the log
     pub fn append(&mut self, entry: Entry) -> Result<u64, Error> {
         debug!("Appending log entry: {}: {:?}", self.last_index + 1, entry);
@@ -111,9 +166,15 @@ impl Log {
         let mut output = vec![];
         if let Some(entry) = self.get(self.apply_index + 1)? {
             debug!("Applying log entry: {}: {:?}", self.apply_index + 1, entry);
-            if let Some(command) = entry.command {
-                output = state.mutate(command)?;
-            }
+            let result = ApplyResult {
+                index: self.apply_index + 1,
+                output: match &entry.command {
+                    Some(command) => state.mutate(command.clone()),
+                    None => Ok(vec![]),
+                },
+            };
+            self.hooks.call(&entry, &result)?;
+            output = result.output?;
             self.apply_index += 1;
             self.apply_term = entry.term;
         }
@@ -129,9 +190,26 @@ impl Log {
     /// * If no existing entry exists at an index, append it
     /// * If the existing entry has a different term, replace it and following entries
     /// * If the existing entry has the same term, assume entry is equal and skip it
+    /// * If entry terms decrease or the indexes overflow, raise Error::Value
     //
     // TODO: FIXME Needs to be transactional
     pub fn splice(&mut self, base: u64, base_term: u64, entries: Vec<Entry>) -> Result<u64, Error> {
+        let mut term = base_term;
+        for entry in &entries {
+            if entry.term < term {
+                return Err(Error::Value(format!(
+                    "Entry term {} is below preceding term {}",
+                    entry.term, term
+                )));
+            }
+            term = entry.term;
+        }
+        if base.checked_add(entries.len() as u64).is_none() {
+            return Err(Error::Value(format!(
+                "Entry indexes overflow base {}",
+                base
+            )));
+        }
         if !self.has(base, base_term)? {
             return Err(Error::RaftBaseNotFound {
                 index: base,
@@ -183,12 +261,12 @@ impl Log {
     /// in current term (if any).
     pub fn load_term(&self) -> Result<(u64, Option<String>), Error> {
         let term = if let Some(value) = self.kv.get("term")? {
-            deserialize(value)?
+            deserialize(&value)?
         } else {
             0
         };
         let voted_for = if let Some(value) = self.kv.get("voted_for")? {
-            Some(deserialize(value)?)
+            Some(deserialize(&value)?)
         } else {
             None
         };
@@ -219,7 +297,7 @@ impl Log {
     /// Fetches an entry at an index
     pub fn get(&self, index: u64) -> Result<Option<Entry>, Error> {
         if let Some(value) = self.kv.get(&index.to_string())? {
-            Ok(Some(deserialize(value)?))
+            Ok(Some(deserialize(&value)?))
         } else {
             Ok(None)
         }
@@ -264,13 +342,113 @@ impl Log {
         Ok(entries)
     }
 
+    /// Checks a Raft log store for consistency, without modifying it. Unlike
+    /// Log::new() this reads every record, and reports problems instead of
+    /// failing on the first one.
+    pub fn check<S: Store>(store: &S) -> LogReport {
+        let mut report = LogReport::default();
+        let mut entries = Vec::new();
+        for item in store.iter_prefix("") {
+            let (key, value) = match item {
+                Ok(pair) => pair,
+                Err(err) => {
+                    report.problems.push(format!("Unreadable record: {}", err));
+                    continue;
+                }
+            };
+            let result = match key.as_str() {
+                "term" => deserialize(&value).map(|term| report.term = term),
+                "voted_for" => deserialize(&value).map(|v| report.voted_for = Some(v)),
+                "apply_index" => deserialize(&value).map(|i| report.apply_index = i),
+                _ => match key.parse::<u64>() {
+                    Ok(index) if index > 0 && key == index.to_string() => {
+                        deserialize::<Entry>(&value).map(|e| entries.push((index, e.term)))
+                    }
+                    _ => {
+                        report.problems.push(format!("Orphaned key {:?}", key));
+                        Ok(())
+                    }
+                },
+            };
+            if let Err(err) = result {
+                report
+                    .problems
+                    .push(format!("Invalid record {:?}: {}", key, err));
+            }
+        }
+
+        // Store keys are ordered as strings, so sort entries numerically.
+        entries.sort_unstable();
+        report.entries = entries.len() as u64;
+        let mut prev = (0, 0);
+        for &(index, term) in &entries {
+            if index > prev.0 + 1 {
+                report.problems.push(match index - prev.0 {
+                    2 => format!("Missing entry {}", prev.0 + 1),
+                    _ => format!("Missing entries {} to {}", prev.0 + 1, index - 1),
+                });
+            } else if report.last_index == prev.0 {
+                report.last_index = index;
+                report.last_term = term;
+            }
+            if term < prev.1 {
+                report.problems.push(format!(
+                    "Entry {} has term {} below term {} of entry {}",
+                    index, term, prev.1, prev.0
+                ));
+            }
+            if term > report.term {
+                report.problems.push(format!(
+                    "Entry {} has term {} above current term {}",
+                    index, term, report.term
+                ));
+            }
+            prev = (index, term);
+        }
+        if report.apply_index > report.last_index {
+            report.problems.push(format!(
+                "Applied index {} is beyond last entry {}",
+                report.apply_index, report.last_index
+            ));
+        }
+        report
+    }
+
+    /// Repairs a torn tail of a Raft log store, e.g. left behind by a partial
+    /// write, by removing all entries after the last contiguous valid entry.
+    /// Applied entries are never removed. Returns the removed entry indexes.
+    pub fn repair<S: Store>(store: &mut S) -> Result<Vec<u64>, Error> {
+        let report = Self::check(store);
+        if report.apply_index > report.last_index {
+            return Err(Error::Value(format!(
+                "Can't repair Raft log, applied entries {} to {} are missing or invalid",
+                report.last_index + 1,
+                report.apply_index
+            )));
+        }
+        let mut tail: Vec<u64> = store
+            .iter_prefix("")
+            .filter_map(|item| item.ok())
+            .filter_map(|(key, _)| match key.parse::<u64>() {
+                Ok(index) if index > report.last_index && key == index.to_string() => Some(index),
+                _ => None,
+            })
+            .collect();
+        tail.sort_unstable();
+        for index in &tail {
+            info!("Removing Raft log entry {} beyond last valid entry", index);
+            store.delete(&index.to_string())?;
+        }
+        Ok(tail)
+    }
+
     fn get_last_index_and_term<S: Store>(store: &S) -> Result<(u64, u64), Error> {
         let mut last_index = 0;
         let mut last_term = 0;
 
         for i in 1..std::u64::MAX {
             if let Some(raw_entry) = store.get(&i.to_string())? {
-                let entry = deserialize::<Entry>(raw_entry)?;
+                let entry = deserialize::<Entry>(&raw_entry)?;
                 last_index = i;
                 last_term = entry.term;
             } else {
@@ -509,6 +687,40 @@ mod tests {
         assert_eq!((3, 2), l.get_applied());
     }
 
+    #[test]
+    fn apply_hooks() {
+        let (l, _) = setup();
+        let hooks = ApplyHooks::new();
+        let mut l = l.hooks(hooks.clone());
+        setup_appends(&mut l);
+        l.commit(3).unwrap();
+
+        let applied = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let a = applied.clone();
+        hooks
+            .register(
+                "test",
+                Box::new(move |entry, result| {
+                    a.lock().unwrap().push((
+                        result.index,
+                        entry.command.clone(),
+                        result.output.clone().unwrap(),
+                    ))
+                }),
+            )
+            .unwrap();
+        let state = TestState::new();
+        while l.apply(&mut state.boxed()).unwrap().is_some() {}
+        assert_eq!(
+            vec![
+                (1, Some(vec![0x01]), vec![0xff, 0x01]),
+                (2, None, vec![]),
+                (3, Some(vec![0x03]), vec![0xff, 0x03]),
+            ],
+            *applied.lock().unwrap()
+        );
+    }
+
     #[test]
     fn apply_committed_only() {
         let (mut l, store) = setup();
@@ -819,6 +1031,26 @@ mod tests {
         assert_eq!((2, 2), l.get_last());
     }
 
+    #[test]
+    fn splice_invalid() {
+        let (mut l, _) = setup();
+        let entry = |term| Entry {
+            term,
+            command: Some(vec![term as u8]),
+        };
+        l.append(entry(1)).unwrap();
+        l.append(entry(2)).unwrap();
+
+        assert_matches!(
+            l.splice(2, 2, vec![entry(3), entry(2)]),
+            Err(Error::Value(_))
+        );
+        assert_matches!(l.splice(2, 2, vec![entry(1)]), Err(Error::Value(_)));
+        assert_matches!(l.splice(u64::MAX, 2, vec![entry(3)]), Err(Error::Value(_)));
+        assert_eq!((2, 2), l.get_last());
+        assert_eq!(Ok(3), l.splice(2, 2, vec![entry(2)]));
+    }
+
     #[test]
     fn splice_base_term_conflict() {
         let (mut l, _) = setup();
@@ -1042,4 +1274,81 @@ mod tests {
         assert_eq!(Ok(None), l.get(3));
         assert_eq!((0, 0), l.get_last());
     }
+
+    #[test]
+    fn check() {
+        let (mut l, mut store) = setup();
+        setup_appends(&mut l);
+        l.save_term(2, Some("b")).unwrap();
+        l.commit(2).unwrap();
+        l.apply(&mut TestState::new().boxed()).unwrap();
+        assert_eq!(
+            LogReport {
+                term: 2,
+                voted_for: Some("b".into()),
+                apply_index: 1,
+                entries: 3,
+                last_index: 3,
+                last_term: 2,
+                problems: vec![],
+            },
+            Log::check(&store)
+        );
+
+        // Introduce some corruption
+        store.delete("2").unwrap();
+        store
+            .set(
+                "5",
+                serialize(Entry {
+                    term: 1,
+                    command: None,
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        store
+            .set(
+                "6",
+                serialize(Entry {
+                    term: 3,
+                    command: None,
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        store.set("7", vec![0xc1]).unwrap();
+        store.set("apply_index", serialize(4).unwrap()).unwrap();
+        store.set("x", vec![]).unwrap();
+        let report = Log::check(&store);
+        assert_eq!(
+            (4, 1, 1),
+            (report.entries, report.last_index, report.last_term)
+        );
+        assert_eq!(
+            vec![
+                "Invalid record \"7\": wrong msgpack marker Reserved",
+                "Orphaned key \"x\"",
+                "Missing entry 2",
+                "Missing entry 4",
+                "Entry 5 has term 1 below term 2 of entry 3",
+                "Entry 6 has term 3 above current term 2",
+                "Applied index 4 is beyond last entry 1",
+            ],
+            report.problems
+        );
+
+        // Applied entries can't be repaired, but a torn tail can
+        assert_matches!(Log::repair(&mut store), Err(Error::Value(_)));
+        store.set("apply_index", serialize(1).unwrap()).unwrap();
+        store.delete("x").unwrap();
+        assert_eq!(Ok(vec![3, 5, 6, 7]), Log::repair(&mut store));
+        let report = Log::check(&store);
+        assert_eq!(
+            (1, 1, 1),
+            (report.entries, report.last_index, report.last_term)
+        );
+        assert_eq!(Vec::<String>::new(), report.problems);
+        assert_eq!(Ok(vec![]), Log::repair(&mut store));
+    }
 }
@@ -1,64 +1,170 @@
+//! The Raft consensus core, which replicates commands to a State machine
+//! across a cluster of nodes. It can be embedded with a custom state machine
+//! by implementing State, and a Transport for exchanging messages with peers,
+//! and passing them to Raft::start() along with a Store for the Raft log. See
+//! examples/counter.rs for a minimal example. Applied entries can be observed
+//! by registering ApplyHooks on the EventBus.
+
+mod consistency;
+mod envelope;
+mod events;
+mod hooks;
 mod log;
 mod node;
 mod state;
 mod transport;
 
-pub use self::log::Entry;
-pub use self::state::State;
+pub use self::consistency::{ReadConsistency, DEFAULT_MAX_STALENESS};
+pub use self::envelope::Envelope;
+pub use self::events::{EventBus, RaftEvent};
+pub use self::hooks::{ApplyHook, ApplyHooks, ApplyResult};
+pub use self::log::{Entry, Log, LogReport};
+pub use self::state::{State, Witness};
 pub use self::transport::{Event, Message, Transport};
 
 use crate::{store, Error};
 use crossbeam_channel::{Receiver, Sender};
 use node::Node;
 use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use uuid::Uuid;
 
 /// The duration of a Raft tick, which is the unit of time for e.g.
 /// heartbeat intervals and election timeouts.
 const TICK: std::time::Duration = std::time::Duration::from_millis(100);
 
+/// A local method call: an event, the consistency of reads, and a channel for
+/// the response
+type Call = (Event, ReadConsistency, Sender<Event>);
+
+/// A handle to a running Raft node, used to submit commands to the cluster.
+/// Handles can be cloned and shared between threads.
 #[derive(Clone)]
 pub struct Raft {
-    call_tx: Sender<(Event, Sender<Event>)>,
+    call_tx: Sender<Call>,
     join_rx: Receiver<Result<(), Error>>,
+    /// The highest log index of any call response, see observed_index().
+    observed_index: Arc<AtomicU64>,
+    /// The maximum size of mutation commands, if any.
+    max_command_size: Option<usize>,
 }
 
 impl Raft {
-    /// Starts a new Raft state machine in a separate thread.
+    /// Starts a new Raft state machine in a separate thread, publishing
+    /// state changes on the given event bus. The node is identified by id
+    /// among its peers, which the transport must be able to reach. The log
+    /// is persisted in the given store, and committed commands are applied
+    /// to the state machine.
     pub fn start<S, L, T>(
         id: &str,
         peers: Vec<String>,
         state: S,
         store: L,
         transport: T,
+        events: Arc<EventBus>,
     ) -> Result<Raft, Error>
     where
         S: State,
         L: store::Store,
         T: Transport,
     {
+        let (outbound_tx, outbound_rx) = crossbeam_channel::unbounded();
+        let node = Node::new(
+            id,
+            peers,
+            false,
+            store,
+            state,
+            events.hooks().clone(),
+            outbound_tx,
+        )?;
+        Ok(Self::run(node, outbound_rx, transport, events))
+    }
+
+    /// Starts a new Raft witness in a separate thread, which only takes part
+    /// in elections and log replication, see Witness.
+    pub fn start_witness<L, T>(
+        id: &str,
+        peers: Vec<String>,
+        store: L,
+        transport: T,
+        events: Arc<EventBus>,
+    ) -> Result<Raft, Error>
+    where
+        L: store::Store,
+        T: Transport,
+    {
+        let (outbound_tx, outbound_rx) = crossbeam_channel::unbounded();
+        let node = Node::new(
+            id,
+            peers,
+            true,
+            store,
+            Witness,
+            events.hooks().clone(),
+            outbound_tx,
+        )?;
+        Ok(Self::run(node, outbound_rx, transport, events))
+    }
+
+    /// Runs a Raft node in a separate thread.
+    fn run<T: Transport>(
+        mut node: Node,
+        outbound_rx: Receiver<Message>,
+        transport: T,
+        events: Arc<EventBus>,
+    ) -> Raft {
         let ticker = crossbeam_channel::tick(TICK);
 
         let inbound_rx = transport.receiver();
-        let (outbound_tx, outbound_rx) = crossbeam_channel::unbounded();
-        let (call_tx, call_rx) = crossbeam_channel::unbounded::<(Event, Sender<Event>)>();
+        let (call_tx, call_rx) = crossbeam_channel::unbounded::<Call>();
         let (join_tx, join_rx) = crossbeam_channel::unbounded();
         let mut response_txs: HashMap<Vec<u8>, Sender<Event>> = HashMap::new();
-        let mut node = Node::new(id, peers, store, state, outbound_tx)?;
+        // Start out as a non-leader without a known leader, such that an
+        // initial leader is published.
+        let mut status = node::Status {
+            leader: false,
+            leader_id: None,
+            ..node.status()
+        };
 
         // TODO: revisit this
         std::thread::spawn(move || {
             // Ugly workaround to use ?, while waiting for try_blocks:
             // https://doc.rust-lang.org/unstable-book/language-features/try-blocks.html
-            let result = (move || loop {
+            crate::logging::set_term(status.term);
+            // A panic leaves the node in an unknown state, so rather than
+            // resuming it's converted into an error returned by join(), and
+            // pending calls fail as their response channels are dropped.
+            let result = catch_unwind(AssertUnwindSafe(move || loop {
                 select! {
                     // Handle ticks
                     recv(ticker) -> _ => node = node.tick()?,
 
                     // Handle local method calls
                     recv(call_rx) -> recv => {
-                        let (event, response_tx) = recv?;
-                        if let Some(call_id) = event.call_id() {
+                        let (event, consistency, response_tx) = recv?;
+                        // Reads are served locally if the node can do so at
+                        // the requested consistency, otherwise they're strong.
+                        let local = match &event {
+                            Event::ReadState { call_id, command } => node
+                                .read_local(command, consistency)
+                                .map(|r| r.map(|(index, response)| Event::RespondState {
+                                    call_id: call_id.clone(),
+                                    index,
+                                    response,
+                                }))
+                                .unwrap_or_else(|err| Some(Event::RespondError {
+                                    call_id: call_id.clone(),
+                                    error: err.to_string(),
+                                })),
+                            _ => None,
+                        };
+                        if let Some(response) = local {
+                            response_tx.send(response)?;
+                        } else if let Some(call_id) = event.call_id() {
                             response_txs.insert(call_id, response_tx);
                             node = node.step(Message{from: None, to: None, term: 0, event})?;
                         } else {
@@ -84,11 +190,36 @@ impl Raft {
                         }
                     },
                 }
-            })();
+                let next = node.status();
+                for event in RaftEvent::changes(&status, &next) {
+                    events.publish(event)?;
+                }
+                if next.term != status.term {
+                    crate::logging::set_term(next.term);
+                }
+                status = next;
+            }))
+            .unwrap_or_else(|panic| Err(Error::from_panic(panic)));
+            if let Err(err) = &result {
+                error!("Raft node stopped: {}", err);
+            }
             join_tx.send(result).unwrap()
         });
 
-        Ok(Raft { call_tx, join_rx })
+        Raft {
+            call_tx,
+            join_rx,
+            observed_index: Arc::new(AtomicU64::new(0)),
+            max_command_size: None,
+        }
+    }
+
+    /// Rejects mutation commands larger than the given size in bytes when
+    /// they're submitted via this handle, rather than appending entries that
+    /// the transport may be unable to replicate.
+    pub fn max_command_size(mut self, max_command_size: usize) -> Self {
+        self.max_command_size = Some(max_command_size);
+        self
     }
 
     /// Waits for the Raft node to complete
@@ -97,26 +228,51 @@ impl Raft {
     }
 
     /// Runs a synchronous client call on the Raft cluster
-    fn call(&self, event: Event) -> Result<Event, Error> {
+    fn call(&self, event: Event, consistency: ReadConsistency) -> Result<Event, Error> {
         let (response_tx, response_rx) = crossbeam_channel::unbounded();
-        self.call_tx.send((event, response_tx))?;
-        match response_rx.recv()? {
+        self.call_tx.send((event, consistency, response_tx))?;
+        let response = response_rx.recv()?;
+        if let Event::RespondState { index, .. } = response {
+            self.observed_index.fetch_max(index, Ordering::SeqCst);
+        }
+        match response {
             Event::RespondError { error, .. } => Err(Error::Network(error)),
             e => Ok(e),
         }
     }
 
+    /// Returns the highest log index at which a call made via this handle (or
+    /// its clones) was served. A node whose applied index has reached it
+    /// reflects all mutations made via the handle.
+    pub fn observed_index(&self) -> u64 {
+        self.observed_index.load(Ordering::SeqCst)
+    }
+
     /// Generates a call ID
     fn call_id() -> Vec<u8> {
         Uuid::new_v4().as_bytes().to_vec()
     }
 
-    /// Mutates the Raft state machine.
+    /// Mutates the Raft state machine, returning the state machine's response
+    /// once the command is committed and applied. Errors if there is no leader.
     pub fn mutate(&self, command: Vec<u8>) -> Result<Vec<u8>, Error> {
-        match self.call(Event::MutateState {
-            call_id: Self::call_id(),
-            command,
-        })? {
+        match self.max_command_size {
+            Some(max) if command.len() > max => {
+                return Err(Error::Value(format!(
+                    "Command of {} bytes exceeds the maximum command size of {} bytes",
+                    command.len(),
+                    max
+                )))
+            }
+            _ => {}
+        }
+        match self.call(
+            Event::MutateState {
+                call_id: Self::call_id(),
+                command,
+            },
+            ReadConsistency::Strong,
+        )? {
             Event::RespondState { response, .. } => Ok(response),
             event => Err(Error::Internal(format!(
                 "Unexpected Raft mutate response {:?}",
@@ -125,12 +281,24 @@ impl Raft {
         }
     }
 
-    /// Reads from the Raft state machine.
+    /// Reads from the Raft state machine, once the leader has confirmed its
+    /// leadership with a quorum.
     pub fn read(&self, command: Vec<u8>) -> Result<Vec<u8>, Error> {
-        match self.call(Event::ReadState {
+        self.read_with(command, ReadConsistency::Strong)
+    }
+
+    /// Reads from the Raft state machine at the given consistency. Reads the
+    /// local node can't serve at that consistency are strong reads.
+    pub fn read_with(
+        &self,
+        command: Vec<u8>,
+        consistency: ReadConsistency,
+    ) -> Result<Vec<u8>, Error> {
+        let event = Event::ReadState {
             call_id: Self::call_id(),
             command,
-        })? {
+        };
+        match self.call(event, consistency)? {
             Event::RespondState { response, .. } => Ok(response),
             event => Err(Error::Internal(format!(
                 "Unexpected Raft read response {:?}",
@@ -146,7 +314,7 @@ pub mod tests {
     use crossbeam_channel::Receiver;
     use std::sync::{Arc, Mutex};
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, Default)]
     pub struct TestState {
         commands: Arc<Mutex<Vec<Vec<u8>>>>,
     }
@@ -204,4 +372,23 @@ pub mod tests {
         }
         assert_eq!(msgs, actual);
     }
+
+    #[test]
+    fn max_command_size() {
+        // Without a node to receive calls, submitted commands fail with a
+        // network error.
+        let (call_tx, _) = crossbeam_channel::unbounded();
+        let (_, join_rx) = crossbeam_channel::unbounded();
+        let raft = Raft {
+            call_tx,
+            join_rx,
+            observed_index: Arc::new(AtomicU64::new(0)),
+            max_command_size: None,
+        };
+        assert_matches!(raft.mutate(vec![0x00; 3]), Err(Error::Network(_)));
+
+        let raft = raft.max_command_size(2);
+        assert_matches!(raft.mutate(vec![0x00; 3]), Err(Error::Value(_)));
+        assert_matches!(raft.mutate(vec![0x00; 2]), Err(Error::Network(_)));
+    }
 }
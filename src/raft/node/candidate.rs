@@ -38,14 +38,8 @@ impl RoleNode<Candidate> {
         info!("Won election for term {}, becoming leader", self.term);
         let peers = self.peers.clone();
         let (last_index, _) = self.log.get_last();
-        let (commit_index, commit_term) = self.log.get_committed();
-        let mut node = self.become_role(Leader::new(peers, last_index))?;
-        node.broadcast(Event::Heartbeat {
-            commit_index,
-            commit_term,
-        })?;
-        node.append(None)?;
-        Ok(node)
+        self.become_role(Leader::new(peers, last_index))?
+            .establish()
     }
 
     /// Processes a logical clock tick.
@@ -113,6 +107,7 @@ impl RoleNode<Candidate> {
             Event::ReplicateEntries { .. } => {}
             Event::AcceptEntries { .. } => {}
             Event::RejectEntries { .. } => {}
+            Event::RequestEntries { .. } => {}
             // TODO: FIXME These should be queued or something
             Event::ReadState { .. } => {}
             Event::MutateState { .. } => {}
@@ -156,6 +151,7 @@ mod tests {
         let mut node = RoleNode {
             id: "a".into(),
             peers: vec!["b".into(), "c".into(), "d".into(), "e".into()],
+            witness: false,
             term: 3,
             log,
             state,
@@ -178,6 +174,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -191,6 +188,7 @@ mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 1,
                     has_committed: true,
+                    read_seq: 0,
                 },
             }],
         );
@@ -208,6 +206,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -221,6 +220,7 @@ mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 1,
                     has_committed: true,
+                    read_seq: 0,
                 },
             }],
         );
@@ -238,6 +238,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -284,7 +285,8 @@ mod tests {
                     term: 3,
                     event: Event::Heartbeat {
                         commit_index: 2,
-                        commit_term: 1
+                        commit_term: 1,
+                        read_seq: 0
                     },
                 }
             )
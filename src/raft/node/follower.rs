@@ -13,6 +13,10 @@ pub struct Follower {
     leader_seen_ticks: u64,
     /// The timeout before triggering an election.
     leader_seen_timeout: u64,
+    /// The commit index of the last heartbeat from the leader.
+    leader_commit_index: u64,
+    /// Whether we've requested missing entries from the leader.
+    requested_entries: bool,
     /// The node we voted for in the current term, if any.
     voted_for: Option<String>,
     /// Keeps track of any proxied calls to the leader (call ID to message sender).
@@ -24,6 +28,8 @@ impl Follower {
         Self {
             leader,
             leader_seen_ticks: 0,
+            leader_commit_index: 0,
+            requested_entries: false,
             leader_seen_timeout: rand::thread_rng()
                 .gen_range(ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX),
             voted_for,
@@ -41,6 +47,11 @@ impl RoleNode<Follower> {
         Ok(node)
     }
 
+    /// Returns the current leader, if known.
+    pub(super) fn leader(&self) -> Option<String> {
+        self.role.leader.clone()
+    }
+
     /// Checks if the message sender is the current leader
     fn is_message_sent_from_leader(&self, from: Option<&str>) -> bool {
         if let Some(leader) = self.role.leader.as_deref() {
@@ -94,14 +105,32 @@ impl RoleNode<Follower> {
             Event::Heartbeat {
                 commit_index,
                 commit_term,
+                read_seq,
             } => {
                 if self.is_message_sent_from_leader(msg.from.as_deref()) {
+                    self.role.leader_commit_index = commit_index;
                     let has_committed = self.log.has(commit_index, commit_term)?;
+                    // If we're behind a new leader, e.g. after a restart, have
+                    // it replicate from our last entry rather than probing
+                    // backwards from its own one entry per round trip.
+                    if !has_committed && !self.role.requested_entries {
+                        let (last_index, last_term) = self.log.get_last();
+                        debug!("Requesting log entries after index {}", last_index);
+                        self.send(
+                            msg.from.as_deref(),
+                            Event::RequestEntries {
+                                last_index,
+                                last_term,
+                            },
+                        )?;
+                        self.role.requested_entries = true;
+                    }
                     self.send(
                         msg.from.as_deref(),
                         Event::ConfirmLeader {
                             commit_index,
                             has_committed,
+                            read_seq,
                         },
                     )?;
                     if has_committed {
@@ -148,6 +177,14 @@ impl RoleNode<Follower> {
                             debug!("Rejecting log entries at base {}", base_index);
                             self.send(msg.from.as_deref(), Event::RejectEntries)?
                         }
+                        // E.g. decreasing terms, or truncating committed
+                        // entries, which a correct leader never sends.
+                        Err(Error::Value(err)) => {
+                            warn!(
+                                "Ignoring invalid log entries at base {}: {}",
+                                base_index, err
+                            )
+                        }
                         Err(err) => return Err(err),
                     }
                 }
@@ -166,17 +203,46 @@ impl RoleNode<Follower> {
             Event::ConfirmLeader { .. }
             | Event::GrantVote
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries => {}
+            | Event::RejectEntries
+            | Event::RequestEntries { .. } => {}
         }
 
         Ok(self.into())
     }
 
+    /// Serves a read locally if possible, see Node::read_local(). Bounded
+    /// staleness reads require having heard from the leader within the bound,
+    /// and having applied the entries committed as of then. Witnesses have no
+    /// state machine, so their reads are always forwarded to the leader.
+    pub(super) fn read_local(
+        &mut self,
+        command: &[u8],
+        consistency: ReadConsistency,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        if self.witness {
+            return Ok(None);
+        }
+        self.apply_committed()?;
+        let readable = match consistency {
+            ReadConsistency::Eventual => true,
+            ReadConsistency::BoundedStaleness(bound) => {
+                self.role.leader.is_some()
+                    && self.role.leader_seen_ticks <= ticks(bound)
+                    && self.log.get_applied().0 >= self.role.leader_commit_index
+            }
+            ReadConsistency::Strong | ReadConsistency::LeaderLease => false,
+        };
+        if !readable {
+            return Ok(None);
+        }
+        self.read_state(command).map(Some)
+    }
+
     /// Processes a logical clock tick
     pub fn tick(mut self) -> Result<Node, Error> {
-        while self.log.apply(&mut self.state)?.is_some() {}
+        self.apply_committed()?;
         self.role.leader_seen_ticks += 1;
-        if self.role.leader_seen_ticks >= self.role.leader_seen_timeout {
+        if self.role.leader_seen_ticks >= self.role.leader_seen_timeout && !self.witness {
             Ok(self.become_candidate()?.into())
         } else {
             Ok(self.into())
@@ -225,6 +291,7 @@ pub mod tests {
         let mut node = RoleNode {
             id: "a".into(),
             peers: vec!["b".into(), "c".into(), "d".into(), "e".into()],
+            witness: false,
             term: 3,
             log,
             state,
@@ -247,6 +314,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 3,
                     commit_term: 2,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -266,6 +334,7 @@ pub mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 3,
                     has_committed: true,
+                    read_seq: 0,
                 },
             }],
         );
@@ -283,6 +352,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 3,
                     commit_term: 3,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -295,15 +365,27 @@ pub mod tests {
             .applied(1);
         assert_messages(
             &rx,
-            vec![Message {
-                from: Some("a".into()),
-                to: Some("b".into()),
-                term: 3,
-                event: Event::ConfirmLeader {
-                    commit_index: 3,
-                    has_committed: false,
+            vec![
+                Message {
+                    from: Some("a".into()),
+                    to: Some("b".into()),
+                    term: 3,
+                    event: Event::RequestEntries {
+                        last_index: 3,
+                        last_term: 2,
+                    },
                 },
-            }],
+                Message {
+                    from: Some("a".into()),
+                    to: Some("b".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader {
+                        commit_index: 3,
+                        has_committed: false,
+                        read_seq: 0,
+                    },
+                },
+            ],
         );
     }
 
@@ -319,6 +401,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 5,
                     commit_term: 3,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -331,15 +414,27 @@ pub mod tests {
             .applied(1);
         assert_messages(
             &rx,
-            vec![Message {
-                from: Some("a".into()),
-                to: Some("b".into()),
-                term: 3,
-                event: Event::ConfirmLeader {
-                    commit_index: 5,
-                    has_committed: false,
+            vec![
+                Message {
+                    from: Some("a".into()),
+                    to: Some("b".into()),
+                    term: 3,
+                    event: Event::RequestEntries {
+                        last_index: 3,
+                        last_term: 2,
+                    },
                 },
-            }],
+                Message {
+                    from: Some("a".into()),
+                    to: Some("b".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader {
+                        commit_index: 5,
+                        has_committed: false,
+                        read_seq: 0,
+                    },
+                },
+            ],
         );
     }
 
@@ -355,6 +450,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 5,
                     commit_term: 3,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -381,6 +477,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 3,
                     commit_term: 2,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -400,6 +497,7 @@ pub mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 3,
                     has_committed: true,
+                    read_seq: 0,
                 },
             }],
         );
@@ -417,6 +515,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -436,6 +535,7 @@ pub mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 1,
                     has_committed: true,
+                    read_seq: 0,
                 },
             }],
         );
@@ -453,6 +553,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 3,
                     commit_term: 2,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -470,6 +571,7 @@ pub mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 3,
                     has_committed: true,
+                    read_seq: 0,
                 },
             }],
         );
@@ -487,6 +589,7 @@ pub mod tests {
                 event: Event::Heartbeat {
                     commit_index: 3,
                     commit_term: 2,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -959,6 +1062,29 @@ pub mod tests {
         );
     }
 
+    #[test]
+    // ReplicateEntries ignores entries that would truncate committed entries
+    fn step_replicateentries_ignore_invalid() {
+        let (follower, rx) = setup();
+        let node = follower
+            .step(Message {
+                from: Some("b".into()),
+                to: Some("a".into()),
+                term: 3,
+                event: Event::ReplicateEntries {
+                    base_index: 1,
+                    base_term: 1,
+                    entries: vec![Entry {
+                        term: 3,
+                        command: Some(vec![0x04]),
+                    }],
+                },
+            })
+            .unwrap();
+        assert_node(&node).is_follower().term(3).last(3);
+        assert_messages(&rx, vec![]);
+    }
+
     #[test]
     // ReplicateEntries rejects conflicting base term
     fn step_replicateentries_reject_missing_base_term() {
@@ -1023,6 +1149,7 @@ pub mod tests {
             },
             Event::RespondState {
                 call_id: vec![],
+                index: 1,
                 response: vec![0xaf],
             },
         ];
@@ -1106,6 +1233,7 @@ pub mod tests {
                     event: Event::Heartbeat {
                         commit_index: 2,
                         commit_term: 1,
+                        read_seq: 0,
                     },
                 })
                 .unwrap();
@@ -1118,6 +1246,7 @@ pub mod tests {
                     event: Event::ConfirmLeader {
                         commit_index: 2,
                         has_committed: true,
+                        read_seq: 0,
                     },
                 }],
             )
@@ -1145,4 +1274,40 @@ pub mod tests {
             )
         }
     }
+
+    #[test]
+    // Witnesses never campaign, even without a leader
+    fn tick_witness() {
+        let (mut follower, rx) = setup();
+        follower.witness = true;
+        let timeout = follower.role.leader_seen_timeout;
+        let mut node = Node::Follower(follower);
+
+        for _ in 0..(3 * timeout) {
+            node = node.tick().unwrap();
+        }
+        assert_node(&node).is_follower().term(3).leader(Some("b"));
+        assert_messages(&rx, vec![]);
+    }
+
+    #[test]
+    // Witnesses don't serve reads, even eventually consistent ones
+    fn read_local_witness() {
+        let (mut follower, _) = setup();
+        assert_eq!(
+            Some((2, vec![0xbb, 0x01])),
+            follower
+                .read_local(&[0x01], ReadConsistency::Eventual)
+                .unwrap()
+        );
+        follower.witness = true;
+        let mut node = Node::Follower(follower);
+        for consistency in [
+            ReadConsistency::Eventual,
+            ReadConsistency::BoundedStaleness(TICK),
+            ReadConsistency::Strong,
+        ] {
+            assert_eq!(None, node.read_local(&[0x01], consistency).unwrap());
+        }
+    }
 }
@@ -7,28 +7,133 @@ use super::*;
 pub struct Leader {
     /// Number of ticks since last heartbeat.
     heartbeat_ticks: u64,
-    /// The next index to replicate to a peer.
-    peer_next_index: HashMap<String, u64>,
-    /// The last index known to be replicated on a peer.
-    peer_last_index: HashMap<String, u64>,
+    /// Number of ticks since becoming leader.
+    ticks: u64,
+    /// The replication progress of each peer.
+    progress: HashMap<String, Progress>,
     /// Any client calls being processed.
     calls: Calls,
+    /// The heartbeat round confirming leadership for reads, if any.
+    read_round: Option<ReadRound>,
+    /// The sequence number of the latest read round, sent with heartbeats.
+    read_seq: u64,
 }
 
 impl Leader {
     /// Creates a new leader role.
     pub fn new(peers: Vec<String>, last_index: u64) -> Self {
-        let mut leader = Self {
+        Self {
             heartbeat_ticks: 0,
-            peer_next_index: HashMap::new(),
-            peer_last_index: HashMap::new(),
+            ticks: 0,
+            progress: peers
+                .into_iter()
+                .map(|peer| (peer, Progress::new(last_index)))
+                .collect(),
             calls: Calls::new(),
-        };
-        for peer in peers {
-            leader.peer_next_index.insert(peer.clone(), last_index + 1);
-            leader.peer_last_index.insert(peer.clone(), 0);
+            read_round: None,
+            read_seq: 0,
         }
-        leader
+    }
+}
+
+/// A round of heartbeats broadcast to confirm leadership for reads. Reads
+/// arriving while a round is in flight don't broadcast heartbeats of their
+/// own, but are batched into the next round, which starts once a quorum has
+/// confirmed the current one. A single quorum confirmation thus releases all
+/// queued reads at or below the confirmed commit index.
+///
+/// Each round has a sequence number, sent with heartbeats and echoed by
+/// ConfirmLeader. Reads record the sequence number as of their arrival, and
+/// only count confirmations with a later one: a confirmation of a heartbeat
+/// sent before the read arrived doesn't prove we were still leader after.
+#[derive(Clone, Debug, PartialEq)]
+struct ReadRound {
+    /// The sequence number of the round.
+    seq: u64,
+    /// The commit index broadcast in the round.
+    commit_index: u64,
+    /// The peers which have confirmed the round.
+    confirmed: HashSet<String>,
+    /// Whether reads arrived during the round, awaiting the next one.
+    deferred: bool,
+}
+
+/// The number of consecutive rejections after which a peer is considered to
+/// be lagging, and replication falls back to the last known replicated index.
+const LAGGING_REJECTIONS: u64 = 16;
+
+/// The replication progress of a peer.
+#[derive(Clone, Debug, PartialEq)]
+struct Progress {
+    /// The next index to replicate to the peer.
+    next: u64,
+    /// The last index known to be replicated on the peer.
+    last: u64,
+    /// The number of consecutive rejections by the peer.
+    rejections: u64,
+    /// The tick at which the peer last responded to us, if ever.
+    contact: Option<u64>,
+}
+
+impl Progress {
+    /// Creates a new progress for a leader with the given last index.
+    fn new(last_index: u64) -> Self {
+        Self {
+            next: last_index + 1,
+            last: 0,
+            rejections: 0,
+            contact: None,
+        }
+    }
+
+    /// Records entries accepted by the peer up to the given index. Stale
+    /// (reordered) acceptances are ignored. Returns true if progress was made.
+    fn accept(&mut self, last_index: u64) -> bool {
+        if last_index <= self.last {
+            return false;
+        }
+        self.last = last_index;
+        self.next = self.next.max(last_index + 1);
+        self.rejections = 0;
+        true
+    }
+
+    /// Records entries rejected by the peer, backing off the next index
+    /// to replicate. It never goes below the last replicated index, unless the
+    /// peer rejects entries following it, in which case the peer has lost its
+    /// log and the last replicated index is reset. Returns true if the peer is
+    /// lagging, in which case the next index falls back to the last replicated
+    /// index rather than backing off one entry per round trip.
+    fn reject(&mut self) -> bool {
+        if self.next <= self.last + 1 {
+            self.last = 0;
+        }
+        self.rejections += 1;
+        if self.rejections >= LAGGING_REJECTIONS {
+            self.rejections = 0;
+            self.next = self.last + 1;
+            return true;
+        }
+        if self.next > self.last + 1 {
+            self.next -= 1;
+        }
+        false
+    }
+
+    /// Records a peer's request for the entries following its last entry. If
+    /// we have the entry, the peer's log matches ours up to it and
+    /// replication continues from there. Otherwise, replication backs off from
+    /// the peer's last index as usual. Returns true if progress was made.
+    fn fetch(&mut self, last_index: u64, matches: bool) -> bool {
+        self.rejections = 0;
+        if !matches {
+            self.next = self.next.min(last_index + 1).max(self.last + 1);
+            return false;
+        }
+        let progressed = last_index > self.last;
+        self.last = last_index;
+        self.next = last_index + 1;
+        progressed
     }
 }
 
@@ -43,6 +148,25 @@ impl RoleNode<Leader> {
         self.become_role(Follower::new(Some(leader.to_string()), None))
     }
 
+    /// Establishes leadership of a new term, by broadcasting heartbeats and
+    /// appending a no-op entry for the term. Entries from previous terms can
+    /// only be committed once an entry from our own term is (see section 5.4.2
+    /// in the Raft paper), and reads are not served until then.
+    pub(super) fn establish(mut self) -> Result<Self, Error> {
+        let (commit_index, commit_term) = self.log.get_committed();
+        self.broadcast(Event::Heartbeat {
+            commit_index,
+            commit_term,
+            read_seq: self.role.read_seq,
+        })?;
+        self.append(None)?;
+        if self.peers.is_empty() {
+            self.commit()?;
+            self.apply()?;
+        }
+        Ok(self)
+    }
+
     /// Appends an entry to the log and replicates it to peers.
     pub fn append(&mut self, command: Option<Vec<u8>>) -> Result<u64, Error> {
         let index = self.log.append(Entry {
@@ -65,6 +189,7 @@ impl RoleNode<Leader> {
                     call.from.as_deref(),
                     Event::RespondState {
                         call_id: call.id,
+                        index,
                         response: output,
                     },
                 )?
@@ -77,9 +202,9 @@ impl RoleNode<Leader> {
     fn replicate(&self, peer: &str) -> Result<(), Error> {
         let peer_next = self
             .role
-            .peer_next_index
+            .progress
             .get(peer)
-            .cloned()
+            .map(|p| p.next)
             .ok_or_else(|| Error::Internal(format!("Unknown peer {}", peer)))?;
         let base_index = if peer_next > 0 { peer_next - 1 } else { 0 };
         let base_term = match self.log.get(base_index)? {
@@ -115,7 +240,7 @@ impl RoleNode<Leader> {
         let (last_index, _) = self.log.get_last();
         let (commit_index, _) = self.log.get_committed();
         let mut last_indexes = vec![last_index];
-        last_indexes.extend(self.role.peer_last_index.values());
+        last_indexes.extend(self.role.progress.values().map(|p| p.last));
         last_indexes.sort();
         last_indexes.reverse();
         let quorum_index = last_indexes[self.quorum() as usize - 1];
@@ -130,14 +255,72 @@ impl RoleNode<Leader> {
         }
     }
 
-    /// Registers a vote for quorum-based client calls.
-    fn vote_call(&mut self, from: &str, commit_index: u64) -> Result<(), Error> {
-        for call in self.role.calls.quorum_vote(from, commit_index) {
+    /// Commits and applies entries after a peer has made progress.
+    fn commit_progress(&mut self) -> Result<(), Error> {
+        let (_, prev_term) = self.log.get_committed();
+        self.commit()?;
+        self.apply()?;
+        let (commit_index, commit_term) = self.log.get_committed();
+        // Serve any reads held back awaiting our first commit.
+        if prev_term != self.term && commit_term == self.term {
+            self.vote_call(self.id.clone().as_ref(), commit_index, u64::MAX)?;
+        }
+        Ok(())
+    }
+
+    /// Checks if we hold a read lease: a quorum (including us) has responded
+    /// within the lease duration, and an entry from our term is committed, so
+    /// the state machine reflects all committed entries once applied.
+    fn has_lease(&self) -> bool {
+        let (_, commit_term) = self.log.get_committed();
+        let ticks = self.role.ticks;
+        let recent = self
+            .role
+            .progress
+            .values()
+            .filter(|p| matches!(p.contact, Some(contact) if ticks - contact < LEASE_TICKS))
+            .count() as u64;
+        commit_term == self.term && recent + 1 >= self.quorum()
+    }
+
+    /// Serves a read locally if possible, see Node::read_local(). Leader lease
+    /// and bounded staleness reads require a lease.
+    pub(super) fn read_local(
+        &mut self,
+        command: &[u8],
+        consistency: ReadConsistency,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        let readable = match consistency {
+            ReadConsistency::Eventual => true,
+            ReadConsistency::LeaderLease | ReadConsistency::BoundedStaleness(_) => self.has_lease(),
+            ReadConsistency::Strong => false,
+        };
+        if !readable {
+            return Ok(None);
+        }
+        self.apply()?;
+        self.read_state(command).map(Some)
+    }
+
+    /// Registers a vote for quorum-based client calls, confirming the read
+    /// round with the given sequence number. Our own votes use u64::MAX, since
+    /// they're cast while we know we're the leader.
+    fn vote_call(&mut self, from: &str, commit_index: u64, read_seq: u64) -> Result<(), Error> {
+        let (_, commit_term) = self.log.get_committed();
+        for call in self.role.calls.quorum_vote(from, commit_index, read_seq) {
+            // Until the no-op entry of our term is committed we may not have
+            // applied all committed entries, so keep the call (and its votes)
+            // around until then.
+            if commit_term != self.term {
+                self.role.calls.register(call);
+                continue;
+            }
             match call.operation {
                 Operation::ReadState { command, .. } => self.send(
                     call.from.as_deref(),
                     Event::RespondState {
                         call_id: call.id,
+                        index: self.log.get_applied().0,
                         response: self.state.read(command)?,
                     },
                 )?,
@@ -147,6 +330,49 @@ impl RoleNode<Leader> {
         Ok(())
     }
 
+    /// Starts a read round by broadcasting heartbeats, see ReadRound.
+    fn start_read_round(&mut self) -> Result<(), Error> {
+        let (commit_index, commit_term) = self.log.get_committed();
+        self.role.read_seq += 1;
+        self.role.read_round = Some(ReadRound {
+            seq: self.role.read_seq,
+            commit_index,
+            confirmed: HashSet::new(),
+            deferred: false,
+        });
+        self.broadcast(Event::Heartbeat {
+            commit_index,
+            commit_term,
+            read_seq: self.role.read_seq,
+        })
+    }
+
+    /// Records a peer's leadership confirmation for the current read round,
+    /// starting the next round once a quorum has confirmed it if reads are
+    /// still waiting for one.
+    fn confirm_read_round(
+        &mut self,
+        from: &str,
+        commit_index: u64,
+        read_seq: u64,
+    ) -> Result<(), Error> {
+        let quorum = self.quorum();
+        let round = match &mut self.role.read_round {
+            Some(round) if commit_index >= round.commit_index && read_seq >= round.seq => round,
+            _ => return Ok(()),
+        };
+        round.confirmed.insert(from.to_owned());
+        if (round.confirmed.len() as u64) + 1 < quorum {
+            return Ok(());
+        }
+        let deferred = round.deferred;
+        self.role.read_round = None;
+        if deferred && self.role.calls.has_reads() {
+            self.start_read_round()?;
+        }
+        Ok(())
+    }
+
     pub fn step(self, mut msg: Message) -> Result<Node, Error> {
         if !self.normalize_message(&mut msg) {
             return Ok(self.into());
@@ -165,59 +391,106 @@ impl RoleNode<Leader> {
     }
 
     fn process_event(mut self, msg: Message) -> Result<Node, Error> {
+        // Responses from peers renew our read lease.
+        if let Event::ConfirmLeader { .. }
+        | Event::AcceptEntries { .. }
+        | Event::RejectEntries
+        | Event::RequestEntries { .. } = msg.event
+        {
+            let ticks = self.role.ticks;
+            if let Some(progress) = msg
+                .from
+                .as_ref()
+                .and_then(|f| self.role.progress.get_mut(f))
+            {
+                progress.contact = Some(ticks);
+            }
+        }
         match msg.event {
             Event::ConfirmLeader {
                 commit_index,
                 has_committed,
+                read_seq,
             } => {
                 if let Some(from) = &msg.from {
-                    self.vote_call(from, commit_index)?;
+                    self.vote_call(from, commit_index, read_seq)?;
+                    self.confirm_read_round(from, commit_index, read_seq)?;
                     if !has_committed {
                         self.replicate(from)?;
                     }
                 }
             }
             Event::AcceptEntries { last_index } => {
-                if let Some(from) = msg.from {
-                    self.role.peer_last_index.insert(from.clone(), last_index);
-                    self.role
-                        .peer_next_index
-                        .insert(from.clone(), last_index + 1);
+                // Peers can't have entries beyond our own log in our term.
+                let (our_last, _) = self.log.get_last();
+                let accepted = match msg.from.and_then(|f| self.role.progress.get_mut(&f)) {
+                    Some(progress) => progress.accept(last_index.min(our_last)),
+                    None => false,
+                };
+                if accepted {
+                    self.commit_progress()?;
+                }
+            }
+            Event::RequestEntries {
+                last_index,
+                last_term,
+            } => {
+                // The peer's ConfirmLeader for the heartbeat that prompted the
+                // request triggers replication from the updated progress.
+                let matches = self.log.has(last_index, last_term)?;
+                let fetched = match msg.from.and_then(|f| self.role.progress.get_mut(&f)) {
+                    Some(progress) => {
+                        debug!("Peer requested entries after index {}", last_index);
+                        progress.fetch(last_index, matches)
+                    }
+                    None => false,
+                };
+                if fetched {
+                    self.commit_progress()?;
                 }
-                self.commit()?;
-                self.apply()?;
             }
             Event::RejectEntries => {
                 if let Some(from) = msg.from {
-                    self.role
-                        .peer_next_index
-                        .entry(from.clone())
-                        .and_modify(|i| {
-                            if *i > 1 {
-                                *i -= 1
-                            }
-                        });
-                    self.replicate(&from)?;
+                    if let Some(progress) = self.role.progress.get_mut(&from) {
+                        if progress.last > 0 && progress.next <= progress.last + 1 {
+                            warn!(
+                                "Peer {} rejected entries after replicated index {}, it may have lost its log",
+                                from, progress.last
+                            );
+                        }
+                        if progress.reject() {
+                            // Without log compaction, the full log is retained
+                            // and the peer can always catch up by replication.
+                            warn!(
+                                "Peer {} is lagging after {} rejections, replicating from index {}",
+                                from, LAGGING_REJECTIONS, progress.next
+                            );
+                        }
+                        self.replicate(&from)?;
+                    }
                 }
             }
             Event::ReadState { call_id, command } => {
-                let (commit_index, commit_term) = self.log.get_committed();
+                let (commit_index, _) = self.log.get_committed();
                 self.role.calls.register(Call {
                     id: call_id,
                     from: msg.from,
                     operation: Operation::ReadState {
                         command,
                         commit_index,
+                        read_seq: self.role.read_seq,
                         quorum: self.quorum(),
                         votes: HashSet::new(),
                     },
                 });
-                self.vote_call(self.id.clone().as_ref(), commit_index)?;
-                // Send heartbeats immediately, so we don't have to wait for the next tick
-                self.broadcast(Event::Heartbeat {
-                    commit_index,
-                    commit_term,
-                })?;
+                self.vote_call(self.id.clone().as_ref(), commit_index, u64::MAX)?;
+                // Send heartbeats immediately, so we don't have to wait for
+                // the next tick, unless a round is already in flight.
+                match &mut self.role.read_round {
+                    Some(round) => round.deferred = true,
+                    None if self.peers.is_empty() => {}
+                    None => self.start_read_round()?,
+                }
             }
             Event::MutateState { call_id, command } => {
                 let index = self.append(Some(command))?;
@@ -245,6 +518,7 @@ impl RoleNode<Leader> {
 
     pub fn tick(mut self) -> Result<Node, Error> {
         self.apply()?;
+        self.role.ticks += 1;
         self.role.heartbeat_ticks += 1;
         if self.role.heartbeat_ticks >= HEARTBEAT_INTERVAL {
             self.role.heartbeat_ticks = 0;
@@ -252,6 +526,7 @@ impl RoleNode<Leader> {
             self.broadcast(Event::Heartbeat {
                 commit_index,
                 commit_term,
+                read_seq: self.role.read_seq,
             })?;
         }
         Ok(self.into())
@@ -275,6 +550,8 @@ enum Operation {
     ReadState {
         command: Vec<u8>,
         commit_index: u64,
+        /// The read round sequence number when the read arrived.
+        read_seq: u64,
         quorum: u64,
         votes: HashSet<String>,
     },
@@ -297,6 +574,13 @@ impl Calls {
         self.calls.push(call);
     }
 
+    /// Returns true if any reads are awaiting a quorum.
+    fn has_reads(&self) -> bool {
+        self.calls
+            .iter()
+            .any(|call| matches!(call.operation, Operation::ReadState { .. }))
+    }
+
     /// Signals application of the log entry with the given index, removes and
     /// returns the call tracking the entry (if any).
     fn log_applied(&mut self, index: u64) -> Option<Call> {
@@ -309,20 +593,22 @@ impl Calls {
             .map(|i| self.calls.remove(i))
     }
 
-    /// Signals a leadership vote by a peer for a given commit index, removes and
-    /// returns any calls which have received votes from a quorum.
-    fn quorum_vote(&mut self, voter: &str, index: u64) -> Vec<Call> {
+    /// Signals a leadership vote by a peer for a given commit index and read
+    /// round, removes and returns any calls which have received votes from a
+    /// quorum. Votes for rounds from before a read arrived don't count.
+    fn quorum_vote(&mut self, voter: &str, index: u64, seq: u64) -> Vec<Call> {
         let mut indexes = Vec::new();
         for (i, call) in self.calls.iter_mut().enumerate() {
             match call.operation {
                 Operation::MutateState { .. } => {}
                 Operation::ReadState {
                     commit_index,
+                    read_seq,
                     ref mut votes,
                     quorum,
                     ..
                 } => {
-                    if index >= commit_index {
+                    if index >= commit_index && seq > read_seq {
                         votes.insert(voter.to_owned());
                         if votes.len() as u64 >= quorum {
                             indexes.push(i)
@@ -385,6 +671,7 @@ mod tests {
         let mut node = RoleNode {
             id: "a".into(),
             peers: peers.clone(),
+            witness: false,
             term: 3,
             log,
             state,
@@ -409,6 +696,7 @@ mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 2,
                     has_committed: true,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -434,6 +722,7 @@ mod tests {
                 event: Event::ConfirmLeader {
                     commit_index: 2,
                     has_committed: false,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -471,6 +760,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 5,
                     commit_term: 3,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -496,6 +786,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 7,
                     commit_term: 4,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -507,15 +798,27 @@ mod tests {
             .applied(1);
         assert_messages(
             &rx,
-            vec![Message {
-                from: Some("a".into()),
-                to: Some("b".into()),
-                term: 4,
-                event: Event::ConfirmLeader {
-                    commit_index: 7,
-                    has_committed: false,
+            vec![
+                Message {
+                    from: Some("a".into()),
+                    to: Some("b".into()),
+                    term: 4,
+                    event: Event::RequestEntries {
+                        last_index: 5,
+                        last_term: 3,
+                    },
                 },
-            }],
+                Message {
+                    from: Some("a".into()),
+                    to: Some("b".into()),
+                    term: 4,
+                    event: Event::ConfirmLeader {
+                        commit_index: 7,
+                        has_committed: false,
+                        read_seq: 0,
+                    },
+                },
+            ],
         );
     }
 
@@ -533,6 +836,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 3,
                     commit_term: 2,
+                    read_seq: 0,
                 },
             })
             .unwrap();
@@ -664,6 +968,316 @@ mod tests {
         }
     }
 
+    #[test]
+    // Stale AcceptEntries must not regress peer progress, and AcceptEntries
+    // from unknown peers are ignored.
+    fn step_acceptentries_stale() {
+        let (leader, rx) = setup();
+        let mut node: Node = leader.into();
+
+        for &(from, last_index) in &[("b", 5), ("b", 3), ("x", 5), ("x", 5)] {
+            node = node
+                .step(Message {
+                    from: Some(from.into()),
+                    to: Some("a".into()),
+                    term: 3,
+                    event: Event::AcceptEntries { last_index },
+                })
+                .unwrap();
+            assert_node(&node).is_leader().committed(2).applied(2);
+            assert_messages(&rx, vec![]);
+        }
+        match node {
+            Node::Leader(ref n) => assert_eq!(
+                Some(&Progress {
+                    next: 6,
+                    last: 5,
+                    rejections: 0,
+                    contact: Some(0),
+                }),
+                n.role.progress.get("b")
+            ),
+            _ => panic!("Expected leader"),
+        }
+
+        node = node
+            .step(Message {
+                from: Some("c".into()),
+                to: Some("a".into()),
+                term: 3,
+                event: Event::AcceptEntries { last_index: 4 },
+            })
+            .unwrap();
+        assert_node(&node).is_leader().committed(4).applied(4);
+        assert_messages(&rx, vec![]);
+    }
+
+    #[test]
+    // Reads are held back until an entry from the leader's term is committed
+    fn step_readstate_before_commit() {
+        let (leader, rx) = setup();
+        let mut node: Node = leader.into();
+
+        node = node
+            .step(Message {
+                from: None,
+                to: None,
+                term: 0,
+                event: Event::ReadState {
+                    call_id: vec![0x01],
+                    command: vec![0x02],
+                },
+            })
+            .unwrap();
+        while rx.try_recv().is_ok() {}
+
+        for peer in &["b", "c", "d"] {
+            node = node
+                .step(Message {
+                    from: Some(peer.to_string()),
+                    to: Some("a".into()),
+                    term: 3,
+                    event: Event::ConfirmLeader {
+                        commit_index: 2,
+                        has_committed: true,
+                        read_seq: 1,
+                    },
+                })
+                .unwrap();
+        }
+        assert_node(&node).committed(2).applied(1);
+        assert_messages(&rx, vec![]);
+
+        for peer in &["b", "c"] {
+            node = node
+                .step(Message {
+                    from: Some(peer.to_string()),
+                    to: Some("a".into()),
+                    term: 3,
+                    event: Event::AcceptEntries { last_index: 5 },
+                })
+                .unwrap();
+        }
+        assert_node(&node).committed(5).applied(5);
+        assert_messages(
+            &rx,
+            vec![Message {
+                from: Some("a".into()),
+                to: None,
+                term: 3,
+                event: Event::RespondState {
+                    call_id: vec![0x01],
+                    index: 5,
+                    response: vec![0xbb, 0x02],
+                },
+            }],
+        );
+    }
+
+    #[test]
+    // Reads arriving during a heartbeat round are batched into the next one
+    fn step_readstate_batched() {
+        let (leader, rx) = setup();
+        let peers = leader.peers.clone();
+        let mut node: Node = leader.into();
+        for peer in &["b", "c"] {
+            node = node
+                .step(Message {
+                    from: Some(peer.to_string()),
+                    to: Some("a".into()),
+                    term: 3,
+                    event: Event::AcceptEntries { last_index: 5 },
+                })
+                .unwrap();
+        }
+        assert_node(&node).committed(5).applied(5);
+        while rx.try_recv().is_ok() {}
+
+        let heartbeats = |read_seq| -> Vec<Message> {
+            peers
+                .iter()
+                .map(|peer| Message {
+                    from: Some("a".into()),
+                    to: Some(peer.clone()),
+                    term: 3,
+                    event: Event::Heartbeat {
+                        commit_index: 5,
+                        commit_term: 3,
+                        read_seq,
+                    },
+                })
+                .collect()
+        };
+        let respond = |id: u8| Message {
+            from: Some("a".into()),
+            to: None,
+            term: 3,
+            event: Event::RespondState {
+                call_id: vec![id],
+                index: 5,
+                response: vec![0xbb, id],
+            },
+        };
+        let read = |node: Node, id: u8| {
+            node.step(Message {
+                from: None,
+                to: None,
+                term: 0,
+                event: Event::ReadState {
+                    call_id: vec![id],
+                    command: vec![id],
+                },
+            })
+            .unwrap()
+        };
+        let confirm = |node: Node, peer: &str, read_seq| {
+            node.step(Message {
+                from: Some(peer.into()),
+                to: Some("a".into()),
+                term: 3,
+                event: Event::ConfirmLeader {
+                    commit_index: 5,
+                    has_committed: true,
+                    read_seq,
+                },
+            })
+            .unwrap()
+        };
+
+        // Only the first read broadcasts heartbeats
+        node = read(node, 1);
+        assert_messages(&rx, heartbeats(1));
+        node = confirm(node, "b", 1);
+        node = read(node, 2);
+        node = read(node, 3);
+        assert_messages(&rx, vec![]);
+
+        // Once the round is confirmed by a quorum, the next one starts for
+        // the deferred reads. Confirmations of the first round, whose
+        // heartbeats were sent before the reads arrived, don't count for them.
+        node = confirm(node, "c", 1);
+        let mut expect = vec![respond(1)];
+        expect.extend(heartbeats(2));
+        assert_messages(&rx, expect);
+        node = confirm(node, "d", 1);
+        node = confirm(node, "e", 1);
+        assert_messages(&rx, vec![]);
+
+        // A quorum confirmation of the next round releases them all.
+        node = confirm(node, "d", 2);
+        assert_messages(&rx, vec![]);
+        node = confirm(node, "e", 2);
+        assert_messages(&rx, vec![respond(2), respond(3)]);
+        node = confirm(node, "b", 2);
+        assert_messages(&rx, vec![]);
+        assert_node(&node).is_leader().term(3);
+    }
+
+    #[test]
+    fn progress() {
+        let at = |next, last, rejections| Progress {
+            next,
+            last,
+            rejections,
+            contact: None,
+        };
+        let mut progress = Progress::new(5);
+        assert_eq!(at(6, 0, 0), progress);
+        assert!(!progress.reject());
+        assert_eq!(at(5, 0, 1), progress);
+        assert!(progress.accept(3));
+        assert_eq!(at(5, 3, 0), progress);
+        assert!(!progress.accept(2));
+        assert!(!progress.accept(3));
+        assert!(progress.accept(7));
+        assert_eq!(at(8, 7, 0), progress);
+
+        // Rejecting entries after the last replicated index resets it.
+        assert!(!progress.reject());
+        assert_eq!(at(7, 0, 1), progress);
+
+        // A lagging peer falls back to the last replicated index.
+        let mut progress = Progress::new(100);
+        assert!(progress.accept(10));
+        progress.next = 50;
+        for i in 1..LAGGING_REJECTIONS {
+            assert!(!progress.reject());
+            assert_eq!(at(50 - i, 10, i), progress);
+        }
+        assert!(progress.reject());
+        assert_eq!(at(11, 10, 0), progress);
+
+        // Fetches jump to the peer's last entry if it matches ours, and
+        // otherwise back off to it, but not below the last replicated index.
+        let mut progress = Progress::new(100);
+        assert!(!progress.reject());
+        assert!(!progress.fetch(20, false));
+        assert_eq!(at(21, 0, 0), progress);
+        assert!(progress.fetch(10, true));
+        assert_eq!(at(11, 10, 0), progress);
+        assert!(!progress.fetch(30, false));
+        assert_eq!(at(11, 10, 0), progress);
+        assert!(!progress.fetch(5, true));
+        assert_eq!(at(6, 5, 0), progress);
+    }
+
+    #[test]
+    // RequestEntries moves replication to the peer's last entry, and the
+    // subsequent ConfirmLeader replicates from there.
+    fn step_requestentries() {
+        let (leader, rx) = setup();
+        let entries = leader.log.range(0..).unwrap();
+        let mut node: Node = leader.into();
+
+        // The matching fetch from b commits and applies the entry at 2.
+        for &(peer, last_index, last_term, base_index) in
+            &[("b", 3, 2, 3), ("c", 4, 2, 4), ("d", 0, 0, 0)]
+        {
+            for event in &[
+                Event::RequestEntries {
+                    last_index,
+                    last_term,
+                },
+                Event::ConfirmLeader {
+                    commit_index: 2,
+                    has_committed: false,
+                    read_seq: 0,
+                },
+            ] {
+                node = node
+                    .step(Message {
+                        from: Some(peer.into()),
+                        to: Some("a".into()),
+                        term: 3,
+                        event: event.clone(),
+                    })
+                    .unwrap();
+            }
+            assert_node(&node)
+                .is_leader()
+                .term(3)
+                .committed(2)
+                .applied(2);
+            assert_messages(
+                &rx,
+                vec![Message {
+                    from: Some("a".into()),
+                    to: Some(peer.into()),
+                    term: 3,
+                    event: Event::ReplicateEntries {
+                        base_index,
+                        base_term: if base_index > 0 {
+                            entries[base_index as usize - 1].term
+                        } else {
+                            0
+                        },
+                        entries: entries[base_index as usize..].to_vec(),
+                    },
+                }],
+            );
+        }
+    }
+
     #[test]
     fn step_rejectentries() {
         let (leader, rx) = setup();
@@ -779,6 +1393,7 @@ mod tests {
                     event: Event::ConfirmLeader {
                         commit_index: 6,
                         has_committed: true,
+                        read_seq: 0,
                     },
                 })
                 .unwrap();
@@ -813,6 +1428,7 @@ mod tests {
                 term: 3,
                 event: Event::RespondState {
                     call_id: vec![0x01],
+                    index: 6,
                     response: vec![0xff, 0xaf],
                 },
             }],
@@ -842,7 +1458,8 @@ mod tests {
                     term: 3,
                     event: Event::Heartbeat {
                         commit_index: 6,
-                        commit_term: 3
+                        commit_term: 3,
+                        read_seq: 1
                     },
                 }
             )
@@ -861,6 +1478,7 @@ mod tests {
                     event: Event::ConfirmLeader {
                         commit_index: 5,
                         has_committed: true,
+                        read_seq: 1,
                     },
                 })
                 .unwrap();
@@ -886,6 +1504,7 @@ mod tests {
                     event: Event::ConfirmLeader {
                         commit_index: 6,
                         has_committed: true,
+                        read_seq: 1,
                     },
                 })
                 .unwrap();
@@ -909,6 +1528,7 @@ mod tests {
                 term: 3,
                 event: Event::RespondState {
                     call_id: vec![0x02],
+                    index: 6,
                     response: vec![0xbb, 0xaf],
                 },
             }],
@@ -924,6 +1544,7 @@ mod tests {
                     event: Event::ConfirmLeader {
                         commit_index: 6,
                         has_committed: true,
+                        read_seq: 0,
                     },
                 })
                 .unwrap();
@@ -962,7 +1583,8 @@ mod tests {
                         term: 3,
                         event: Event::Heartbeat {
                             commit_index: 2,
-                            commit_term: 1
+                            commit_term: 1,
+                            read_seq: 0
                         },
                     }
                 );
@@ -994,6 +1616,7 @@ mod tests {
             operation: Operation::ReadState {
                 command: vec![0x01],
                 commit_index: 1,
+                read_seq: 0,
                 quorum: 3,
                 votes: HashSet::new(),
             },
@@ -1004,6 +1627,7 @@ mod tests {
             operation: Operation::ReadState {
                 command: vec![0x02],
                 commit_index: 2,
+                read_seq: 0,
                 quorum: 3,
                 votes: HashSet::new(),
             },
@@ -1014,6 +1638,7 @@ mod tests {
             operation: Operation::ReadState {
                 command: vec![0x02],
                 commit_index: 3,
+                read_seq: 0,
                 quorum: 3,
                 votes: HashSet::new(),
             },
@@ -1033,13 +1658,13 @@ mod tests {
     fn calls_quorum_vote() {
         let mut calls = setup_calls();
         // 0xb0=1 0xb1=1 0xb2=1
-        assert_eq!(calls.quorum_vote("a", 3), vec![]);
+        assert_eq!(calls.quorum_vote("a", 3, 1), vec![]);
         // 0xb0=2 0xb1=1 0xb2=1
-        assert_eq!(calls.quorum_vote("b", 1), vec![]);
+        assert_eq!(calls.quorum_vote("b", 1, 1), vec![]);
         // 0xb0=3 0xb1=2 0xb2=1
         assert_eq!(
             calls
-                .quorum_vote("c", 2)
+                .quorum_vote("c", 2, 1)
                 .into_iter()
                 .map(|c| c.id)
                 .collect::<Vec<Vec<u8>>>(),
@@ -1048,7 +1673,7 @@ mod tests {
         // 0xb1=3 0xb2=2
         assert_eq!(
             calls
-                .quorum_vote("d", 4)
+                .quorum_vote("d", 4, 1)
                 .into_iter()
                 .map(|c| c.id)
                 .collect::<Vec<Vec<u8>>>(),
@@ -1057,7 +1682,7 @@ mod tests {
         // 0xb2=3
         assert_eq!(
             calls
-                .quorum_vote("e", 3)
+                .quorum_vote("e", 3, 1)
                 .into_iter()
                 .map(|c| c.id)
                 .collect::<Vec<Vec<u8>>>(),
@@ -1068,11 +1693,11 @@ mod tests {
     #[test]
     fn calls_quorum_vote_multiple() {
         let mut calls = setup_calls();
-        assert_eq!(calls.quorum_vote("a", 3), vec![]);
-        assert_eq!(calls.quorum_vote("b", 3), vec![]);
+        assert_eq!(calls.quorum_vote("a", 3, 1), vec![]);
+        assert_eq!(calls.quorum_vote("b", 3, 1), vec![]);
         assert_eq!(
             calls
-                .quorum_vote("c", 3)
+                .quorum_vote("c", 3, 1)
                 .into_iter()
                 .map(|c| c.id)
                 .collect::<Vec<Vec<u8>>>(),
@@ -1083,15 +1708,15 @@ mod tests {
     #[test]
     fn calls_quorum_vote_same_voter_ignored() {
         let mut calls = setup_calls();
-        assert_eq!(calls.quorum_vote("a", 1), vec![]);
-        assert_eq!(calls.quorum_vote("a", 1), vec![]);
-        assert_eq!(calls.quorum_vote("a", 1), vec![]);
-        assert_eq!(calls.quorum_vote("b", 1), vec![]);
-        assert_eq!(calls.quorum_vote("b", 1), vec![]);
-        assert_eq!(calls.quorum_vote("b", 1), vec![]);
+        assert_eq!(calls.quorum_vote("a", 1, 1), vec![]);
+        assert_eq!(calls.quorum_vote("a", 1, 1), vec![]);
+        assert_eq!(calls.quorum_vote("a", 1, 1), vec![]);
+        assert_eq!(calls.quorum_vote("b", 1, 1), vec![]);
+        assert_eq!(calls.quorum_vote("b", 1, 1), vec![]);
+        assert_eq!(calls.quorum_vote("b", 1, 1), vec![]);
         assert_eq!(
             calls
-                .quorum_vote("c", 1)
+                .quorum_vote("c", 1, 1)
                 .into_iter()
                 .map(|c| c.id)
                 .collect::<Vec<Vec<u8>>>(),
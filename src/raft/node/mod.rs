@@ -5,7 +5,7 @@ use crate::{store::Store, Error};
 use super::{
     log::{Entry, Log},
     transport::{Event, Message},
-    State,
+    ApplyHooks, ReadConsistency, State, TICK,
 };
 
 mod candidate;
@@ -25,6 +25,16 @@ const ELECTION_TIMEOUT_MIN: u64 = 8 * HEARTBEAT_INTERVAL;
 /// The maximum election timeout, in ticks.
 const ELECTION_TIMEOUT_MAX: u64 = 15 * HEARTBEAT_INTERVAL;
 
+/// The duration of a leader's read lease after a quorum has confirmed its
+/// leadership, in ticks. It's well below the minimum election timeout, since
+/// other nodes only campaign after not hearing from the leader for that long.
+const LEASE_TICKS: u64 = ELECTION_TIMEOUT_MIN / 2;
+
+/// Converts a duration to whole ticks, rounding down.
+fn ticks(duration: std::time::Duration) -> u64 {
+    (duration.as_millis() / TICK.as_millis()) as u64
+}
+
 /// The local Raft node state machine.
 #[derive(Debug)]
 pub enum Node {
@@ -35,18 +45,26 @@ pub enum Node {
 
 impl Node {
     /// Creates a new Raft node, starting as a follower, or leader if no peers.
+    /// Witness nodes never campaign for leadership. The hooks are called with
+    /// each applied entry.
     pub fn new<L: Store, S: State>(
         id: &str,
         peers: Vec<String>,
+        witness: bool,
         log_store: L,
         state: S,
+        hooks: ApplyHooks,
         sender: Sender<Message>,
     ) -> Result<Node, Error> {
-        let log = Log::new(log_store)?;
+        if witness && peers.is_empty() {
+            return Err(Error::Config("A witness node requires peers".into()));
+        }
+        let log = Log::new(log_store)?.hooks(hooks);
         let (term, voted_for) = log.load_term()?;
         let node = RoleNode {
             id: id.into(),
             peers,
+            witness,
             term,
             log,
             state: Box::new(state),
@@ -55,8 +73,13 @@ impl Node {
         };
         if node.peers.is_empty() {
             info!("No peers specified, starting as leader");
+            let mut node = node;
+            node.save_term(term + 1, None)?;
             let (last_index, _) = node.log.get_last();
-            Ok(node.become_role(Leader::new(vec![], last_index))?.into())
+            Ok(node
+                .become_role(Leader::new(vec![], last_index))?
+                .establish()?
+                .into())
         } else {
             Ok(node.into())
         }
@@ -80,6 +103,51 @@ impl Node {
             Node::Leader(n) => n.tick(),
         }
     }
+
+    /// Serves a read from the local state machine if the node can do so at the
+    /// given consistency, returning the applied index and the response. If
+    /// not, the read must be stepped as a strong ReadState call.
+    pub fn read_local(
+        &mut self,
+        command: &[u8],
+        consistency: ReadConsistency,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        match (self, consistency) {
+            (_, ReadConsistency::Strong) => Ok(None),
+            (Node::Candidate(n), ReadConsistency::Eventual) if !n.witness => {
+                n.apply_committed()?;
+                n.read_state(command).map(Some)
+            }
+            (Node::Candidate(_), _) => Ok(None),
+            (Node::Follower(n), consistency) => n.read_local(command, consistency),
+            (Node::Leader(n), consistency) => n.read_local(command, consistency),
+        }
+    }
+
+    /// Returns the current node status.
+    pub fn status(&self) -> Status {
+        match self {
+            Node::Candidate(n) => Status {
+                candidate: true,
+                ..n.status(false, None)
+            },
+            Node::Follower(n) => n.status(false, n.leader()),
+            Node::Leader(n) => n.status(true, Some(n.id.clone())),
+        }
+    }
+}
+
+/// A summary of the node status, used to detect state changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    pub leader: bool,
+    /// Whether the node is campaigning for leadership.
+    pub candidate: bool,
+    /// The leader of the current term, if known (possibly ourself).
+    pub leader_id: Option<String>,
+    pub term: u64,
+    pub commit_index: u64,
+    pub apply_index: u64,
 }
 
 impl From<RoleNode<Candidate>> for Node {
@@ -105,6 +173,8 @@ impl From<RoleNode<Leader>> for Node {
 pub struct RoleNode<R> {
     id: String,
     peers: Vec<String>,
+    /// Witness nodes vote and replicate the log, but never become leader.
+    witness: bool,
     term: u64,
     log: Log,
     state: Box<dyn State>,
@@ -118,6 +188,7 @@ impl<R> RoleNode<R> {
         Ok(RoleNode {
             id: self.id,
             peers: self.peers,
+            witness: self.witness,
             term: self.term,
             log: self.log,
             state: self.state,
@@ -135,11 +206,15 @@ impl<R> RoleNode<R> {
     }
 
     /// Normalizes and validates a message, ensuring it is addressed
-    /// to the local node and term. On any errors it emits a warning and
-    /// returns false.
+    /// to the local node and term, and has sane terms and indexes. On any
+    /// errors it emits a warning and returns false.
     fn normalize_message(&self, msg: &mut Message) -> bool {
         msg.normalize(&self.id, self.term);
-        if let Err(err) = msg.validate(&self.id, self.term) {
+        let (last_index, _) = self.log.get_last();
+        if let Err(err) = msg
+            .validate(&self.id, self.term)
+            .and_then(|_| msg.check_bounds(self.term, last_index))
+        {
             warn!("{}", err);
             false
         } else {
@@ -164,6 +239,31 @@ impl<R> RoleNode<R> {
         (self.peers.len() as u64 + 1) / 2 + 1
     }
 
+    /// Returns the node status.
+    fn status(&self, leader: bool, leader_id: Option<String>) -> Status {
+        Status {
+            leader,
+            candidate: false,
+            leader_id,
+            term: self.term,
+            commit_index: self.log.get_committed().0,
+            apply_index: self.log.get_applied().0,
+        }
+    }
+
+    /// Applies any committed log entries which haven't been applied yet.
+    fn apply_committed(&mut self) -> Result<(), Error> {
+        while self.log.apply(&mut self.state)?.is_some() {}
+        Ok(())
+    }
+
+    /// Reads from the local state machine, returning the applied index and
+    /// the response.
+    fn read_state(&mut self, command: &[u8]) -> Result<(u64, Vec<u8>), Error> {
+        let (index, _) = self.log.get_applied();
+        Ok((index, self.state.read(command.to_vec())?))
+    }
+
     /// Updates the current term and stores it in the log
     fn save_term(&mut self, term: u64, voted_for: Option<&str>) -> Result<(), Error> {
         self.log.save_term(term, voted_for)?;
@@ -326,6 +426,7 @@ mod tests {
             role: (),
             id: "a".into(),
             peers,
+            witness: false,
             term: 1,
             log: Log::new(KVMemory::new()).unwrap(),
             state: TestState::new().boxed(),
@@ -340,8 +441,10 @@ mod tests {
         let node = Node::new(
             "a",
             vec!["b".into(), "c".into()],
+            false,
             KVMemory::new(),
             TestState::new(),
+            ApplyHooks::new(),
             sender,
         )
         .unwrap();
@@ -366,8 +469,10 @@ mod tests {
         let node = Node::new(
             "a",
             vec!["b".into(), "c".into()],
+            false,
             store,
             TestState::new(),
+            ApplyHooks::new(),
             sender,
         )
         .unwrap();
@@ -380,12 +485,30 @@ mod tests {
     #[test]
     fn new_single() {
         let (sender, _) = crossbeam_channel::unbounded();
-        let node = Node::new("a", vec![], KVMemory::new(), TestState::new(), sender).unwrap();
+        let node = Node::new(
+            "a",
+            vec![],
+            false,
+            KVMemory::new(),
+            TestState::new(),
+            ApplyHooks::new(),
+            sender,
+        )
+        .unwrap();
         match node {
             Node::Leader(rolenode) => {
                 assert_eq!(rolenode.id, "a".to_owned());
-                assert_eq!(rolenode.term, 0);
+                assert_eq!(rolenode.term, 1);
                 assert!(rolenode.peers.is_empty());
+                // The no-op entry for the new term is committed immediately
+                assert_eq!(rolenode.log.get_committed(), (1, 1));
+                assert_eq!(
+                    rolenode.log.get(1).unwrap(),
+                    Some(Entry {
+                        term: 1,
+                        command: None
+                    })
+                );
             }
             _ => panic!("Expected leader"),
         }
@@ -407,6 +530,7 @@ mod tests {
         node.broadcast(Event::Heartbeat {
             commit_index: 1,
             commit_term: 1,
+            read_seq: 0,
         })
         .unwrap();
 
@@ -420,7 +544,8 @@ mod tests {
                     term: 1,
                     event: Event::Heartbeat {
                         commit_index: 1,
-                        commit_term: 1
+                        commit_term: 1,
+                        read_seq: 0
                     },
                 },
             )
@@ -488,6 +613,7 @@ mod tests {
             Event::Heartbeat {
                 commit_index: 1,
                 commit_term: 1,
+                read_seq: 0,
             },
         )
         .unwrap();
@@ -500,6 +626,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0,
                 },
             }],
         );
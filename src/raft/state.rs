@@ -1,6 +1,8 @@
 use crate::Error;
 
-/// A Raft-managed state machine.
+/// A Raft-managed state machine. Mutations are applied in log order on every
+/// node, so they must be deterministic, while reads are only served by the
+/// leader. Commands and responses are opaque bytes, encoded by the user.
 pub trait State: 'static + Sync + Send + std::fmt::Debug {
     /// Reads from the state machine.
     fn read(&self, command: Vec<u8>) -> Result<Vec<u8>, Error>;
@@ -8,3 +10,19 @@ pub trait State: 'static + Sync + Send + std::fmt::Debug {
     /// Mutates the state machine.
     fn mutate(&mut self, command: Vec<u8>) -> Result<Vec<u8>, Error>;
 }
+
+/// The state machine of a witness node, which takes part in elections and log
+/// replication to make up a quorum, but doesn't keep any state. Witnesses
+/// never become leader, so reads are never served.
+#[derive(Debug)]
+pub struct Witness;
+
+impl State for Witness {
+    fn read(&self, _: Vec<u8>) -> Result<Vec<u8>, Error> {
+        Err(Error::Internal("Witness nodes have no state".into()))
+    }
+
+    fn mutate(&mut self, _: Vec<u8>) -> Result<Vec<u8>, Error> {
+        Ok(vec![])
+    }
+}
@@ -1,4 +1,5 @@
 use crossbeam_channel::Receiver;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::Error;
 
@@ -6,7 +7,7 @@ use super::log::Entry;
 
 /// A transport for communication between a Raft node and its peers.
 pub trait Transport: 'static + Sync + Send {
-    /// Returns a channel for receiving inbound messages
+    /// Returns a channel for receiving inbound messages from peers
     fn receiver(&self) -> Receiver<Message>;
 
     /// Sends a message to a peer.
@@ -14,7 +15,7 @@ pub trait Transport: 'static + Sync + Send {
 }
 
 /// A message passed between Raft nodes.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     /// The current term of the sender.
     pub term: u64,
@@ -29,6 +30,17 @@ pub struct Message {
     pub event: Event,
 }
 
+/// The largest term increase accepted from a peer. Terms increase by one per
+/// election, so this allows for a peer that has campaigned continually while
+/// partitioned for weeks, while rejecting absurd terms from buggy or malicious
+/// peers, which would otherwise be persisted and disrupt the cluster.
+pub const MAX_TERM_JUMP: u64 = 1_000_000;
+
+/// The largest index beyond our last log index that a peer may claim to have
+/// in our own term. Such claims are harmless since commits are capped to our
+/// log, but absurd ones are rejected, e.g. to avoid next index overflow.
+pub const MAX_INDEX_JUMP: u64 = 1_000_000;
+
 impl Message {
     /// Normalizes a message by setting to and term for local messages
     pub fn normalize(&mut self, node_id: &str, term: u64) {
@@ -72,13 +84,68 @@ impl Message {
 
         Ok(())
     }
+
+    /// Checks a peer message for absurd terms and indexes, given the receiving
+    /// node's term and last log index. Peers can't refer to terms beyond the
+    /// message term, nor to indexes far beyond our log in our own term, where
+    /// we are the leader (or candidate).
+    pub fn check_bounds(&self, term: u64, last_index: u64) -> Result<(), Error> {
+        if self.from.is_none() {
+            return Ok(());
+        }
+        if self.term > term.saturating_add(MAX_TERM_JUMP) {
+            return Err(Error::Network(format!(
+                "Ignoring message with term {} far beyond current term {}",
+                self.term, term
+            )));
+        }
+        let invalid = match &self.event {
+            Event::Heartbeat { commit_term, .. } => *commit_term > self.term,
+            Event::SolicitVote { last_term, .. } => *last_term > self.term,
+            Event::ReplicateEntries {
+                base_index,
+                base_term,
+                entries,
+            } => {
+                *base_term > self.term
+                    || entries.iter().any(|e| e.term > self.term)
+                    || base_index.checked_add(entries.len() as u64).is_none()
+            }
+            Event::AcceptEntries { last_index: index }
+            | Event::ConfirmLeader {
+                commit_index: index,
+                ..
+            } => self.term == term && *index > last_index.saturating_add(MAX_INDEX_JUMP),
+            Event::RequestEntries {
+                last_index: index,
+                last_term,
+            } => {
+                *last_term > self.term
+                    || self.term == term && *index > last_index.saturating_add(MAX_INDEX_JUMP)
+            }
+            _ => false,
+        };
+        if invalid {
+            return Err(Error::Network(format!(
+                "Ignoring message with invalid term or index: {:?}",
+                self
+            )));
+        }
+        Ok(())
+    }
 }
 
-/// An Event contained within messages.
-#[derive(Clone, Debug, PartialEq)]
+/// An Event contained within messages. Each variant corresponds to a
+/// Protobuf message of the same name in protobuf/raft.proto.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Event {
     /// Leaders send periodic heartbeats to its followers.
-    Heartbeat { commit_index: u64, commit_term: u64 },
+    Heartbeat {
+        commit_index: u64,
+        commit_term: u64,
+        /// The sequence number of the leader's latest read round.
+        read_seq: u64,
+    },
     /// Followers confirm loyalty to leader after heartbeats
     ConfirmLeader {
         /// The commit_index of the original leader heartbeat, to confirm
@@ -87,6 +154,9 @@ pub enum Event {
         /// If false the follower does not have the entry at commit_index
         /// and wants the leader to replicate it.
         has_committed: bool,
+        /// The read_seq of the original leader heartbeat. Only confirmations
+        /// of heartbeats sent after a read arrived count towards it.
+        read_seq: u64,
     },
     /// Candidates solicit votes from all other peers
     SolicitVote {
@@ -113,6 +183,16 @@ pub enum Event {
     },
     /// Followers may also reject a set of log entries from a leader
     RejectEntries,
+    /// Followers that are behind request the entries following their last
+    /// entry from a new leader, which then replicates from there instead of
+    /// probing backwards from its own last entry. Replication is triggered by
+    /// the ConfirmLeader sent right after it.
+    RequestEntries {
+        /// The index of the follower's last log entry
+        last_index: u64,
+        /// The term of the follower's last log entry
+        last_term: u64,
+    },
     /// Reads from the state machine
     ReadState {
         /// The call ID
@@ -131,10 +211,12 @@ pub enum Event {
     RespondState {
         /// The call ID
         call_id: Vec<u8>,
+        /// The log index of the state the response was computed at
+        index: u64,
         /// The command response
         response: Vec<u8>,
     },
-    /// Reads from the state machine
+    /// The error response of a state machine command
     RespondError {
         /// The call ID
         call_id: Vec<u8>,
@@ -168,6 +250,7 @@ mod tests {
             event: Event::Heartbeat {
                 commit_index: 1,
                 commit_term: 1,
+                read_seq: 0,
             },
         };
 
@@ -182,6 +265,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0
                 }
             }
         )
@@ -196,6 +280,7 @@ mod tests {
             event: Event::Heartbeat {
                 commit_index: 1,
                 commit_term: 1,
+                read_seq: 0,
             },
         };
 
@@ -210,6 +295,7 @@ mod tests {
                 event: Event::Heartbeat {
                     commit_index: 1,
                     commit_term: 1,
+                    read_seq: 0
                 }
             }
         )
@@ -220,6 +306,7 @@ mod tests {
         let event = Event::Heartbeat {
             commit_index: 1,
             commit_term: 1,
+            read_seq: 0,
         };
 
         // Errors on stale term
@@ -252,4 +339,92 @@ mod tests {
         .validate("alice", 3)
         .is_err());
     }
+
+    #[test]
+    fn check_bounds() {
+        let msg = |term, event| Message {
+            from: Some("bob".into()),
+            to: Some("alice".into()),
+            term,
+            event,
+        };
+        let entry = |term| Entry {
+            term,
+            command: None,
+        };
+        let replicate = |base_index, base_term, entries| Event::ReplicateEntries {
+            base_index,
+            base_term,
+            entries,
+        };
+
+        let valid = vec![
+            msg(3 + MAX_TERM_JUMP, Event::GrantVote),
+            msg(3, replicate(10, 2, vec![entry(2), entry(3)])),
+            msg(
+                3,
+                Event::AcceptEntries {
+                    last_index: 5 + MAX_INDEX_JUMP,
+                },
+            ),
+            msg(4, Event::AcceptEntries { last_index: 9 }),
+            msg(
+                3,
+                Event::Heartbeat {
+                    commit_index: 9,
+                    commit_term: 3,
+                    read_seq: 0,
+                },
+            ),
+        ];
+        for msg in valid {
+            assert_eq!(Ok(()), msg.check_bounds(3, 5), "{:?}", msg);
+        }
+
+        let invalid = vec![
+            msg(4 + MAX_TERM_JUMP, Event::GrantVote),
+            msg(3, replicate(10, 4, vec![])),
+            msg(3, replicate(10, 2, vec![entry(4)])),
+            msg(3, replicate(u64::MAX, 2, vec![entry(3)])),
+            msg(
+                3,
+                Event::AcceptEntries {
+                    last_index: u64::MAX,
+                },
+            ),
+            msg(
+                3,
+                Event::RequestEntries {
+                    last_index: 1,
+                    last_term: 4,
+                },
+            ),
+            msg(
+                3,
+                Event::ConfirmLeader {
+                    commit_index: 6 + MAX_INDEX_JUMP,
+                    has_committed: true,
+                    read_seq: 0,
+                },
+            ),
+            msg(
+                3,
+                Event::Heartbeat {
+                    commit_index: 1,
+                    commit_term: 4,
+                    read_seq: 0,
+                },
+            ),
+            msg(
+                3,
+                Event::SolicitVote {
+                    last_index: 1,
+                    last_term: 4,
+                },
+            ),
+        ];
+        for msg in invalid {
+            assert!(msg.check_bounds(3, 5).is_err(), "{:?}", msg);
+        }
+    }
 }
@@ -1,14 +1,201 @@
 use crate::Error;
 
-/// Serializes a value into a byte buffer
-pub fn serialize<V: serde::Serialize>(value: V) -> Result<Vec<u8>, Error> {
-    let mut buffer = Vec::new();
-    value.serialize(&mut rmps::Serializer::new(&mut buffer))?;
+/// The first byte of a versioned header. 0xc1 is never used in MessagePack, so
+/// values with a header can be told apart from headerless MessagePack.
+pub const HEADER_MAGIC: u8 = 0xc1;
+/// The versioned header format written by this binary
+pub const HEADER_VERSION: u8 = 1;
+/// The codec ID of SQL rows, which are encoded against their table schema
+/// rather than via serde, see sql::row.
+pub const ROW_CODEC_ID: u8 = 3;
+
+/// The codec for values persisted in stores, e.g. SQL rows and Raft log metadata
+pub type StorageCodec = MessagePack;
+
+/// A serialization format
+pub trait Codec {
+    /// The codec ID, as written in versioned headers
+    const ID: u8;
+
+    /// Serializes a value, without a header
+    fn serialize<V: serde::Serialize>(value: &V) -> Result<Vec<u8>, Error>;
+
+    /// Deserializes a value, without a header
+    fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error>;
+}
+
+/// MessagePack, a compact binary format
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    const ID: u8 = 1;
+
+    fn serialize<V: serde::Serialize>(value: &V) -> Result<Vec<u8>, Error> {
+        let mut buffer = Vec::new();
+        value.serialize(&mut rmps::Serializer::new(&mut buffer))?;
+        Ok(buffer)
+    }
+
+    fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error> {
+        let mut deserializer = rmps::Deserializer::from_read_ref(bytes);
+        Ok(serde::Deserialize::deserialize(&mut deserializer)?)
+    }
+}
+
+/// Bincode, a fast binary format. Unlike MessagePack, it isn't
+/// self-describing, so values must be read as the type they were written as.
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const ID: u8 = 4;
+
+    fn serialize<V: serde::Serialize>(value: &V) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// JSON, a human-readable format for debugging
+pub struct Json;
+
+impl Codec for Json {
+    const ID: u8 = 2;
+
+    fn serialize<V: serde::Serialize>(value: &V) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Serializes a value with a versioned header identifying the codec, which
+/// decode() uses to pick the codec to deserialize with.
+pub fn encode<C: Codec, V: serde::Serialize>(value: &V) -> Result<Vec<u8>, Error> {
+    let mut buffer = vec![HEADER_MAGIC, HEADER_VERSION, C::ID];
+    buffer.extend(C::serialize(value)?);
     Ok(buffer)
 }
 
-/// Deserializes a value from a byte buffer
-pub fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: Vec<u8>) -> Result<V, Error> {
-    let mut deserializer = rmps::Deserializer::new(&bytes[..]);
-    Ok(serde::Deserialize::deserialize(&mut deserializer)?)
+/// Deserializes a value written by encode(), or headerless MessagePack
+pub fn decode<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error> {
+    match bytes {
+        [HEADER_MAGIC, HEADER_VERSION, id, payload @ ..] => match *id {
+            MessagePack::ID => MessagePack::deserialize(payload),
+            Json::ID => Json::deserialize(payload),
+            Bincode::ID => Bincode::deserialize(payload),
+            ROW_CODEC_ID => Err(Error::Value(
+                "SQL rows must be decoded with their table schema".into(),
+            )),
+            id => Err(Error::Value(format!("Unknown serialization codec {}", id))),
+        },
+        [HEADER_MAGIC, version, ..] if *version != HEADER_VERSION => Err(Error::Value(format!(
+            "Unsupported serialization header version {}",
+            version
+        ))),
+        _ => MessagePack::deserialize(bytes),
+    }
+}
+
+/// Serializes a value with the given codec. MessagePack is written without a
+/// header, for compatibility with existing data and nodes, while other codecs
+/// get a versioned header such that data written before and after a codec
+/// change can both be read.
+fn serialize_with<C: Codec, V: serde::Serialize>(value: &V) -> Result<Vec<u8>, Error> {
+    if C::ID == MessagePack::ID {
+        C::serialize(value)
+    } else {
+        encode::<C, V>(value)
+    }
+}
+
+/// Serializes a value for storage
+pub fn serialize<V: serde::Serialize>(value: V) -> Result<Vec<u8>, Error> {
+    serialize_with::<StorageCodec, V>(&value)
+}
+
+/// Deserializes a value from storage, borrowing from the buffer where possible
+pub fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error> {
+    decode(bytes)
+}
+
+/// Serialization of Raft commands and their results, which are replicated
+/// between nodes and sent to clients.
+#[cfg(feature = "server")]
+pub mod wire {
+    use super::{decode, serialize_with, MessagePack};
+    use crate::Error;
+
+    /// The codec for Raft commands and their results. Raft entries are on the
+    /// hot path, so it should be fast and compact.
+    pub type WireCodec = MessagePack;
+
+    /// Serializes a value for the wire
+    pub fn serialize<V: serde::Serialize>(value: V) -> Result<Vec<u8>, Error> {
+        serialize_with::<WireCodec, V>(&value)
+    }
+
+    /// Deserializes a value from the wire, borrowing from the buffer where possible
+    pub fn deserialize<'de, V: serde::Deserialize<'de>>(bytes: &'de [u8]) -> Result<V, Error> {
+        decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn codecs() -> Result<(), Error> {
+        let value: BTreeMap<String, Option<Vec<u8>>> = vec![
+            ("a".to_string(), Some(vec![0x01, 0x02])),
+            ("b".to_string(), None),
+        ]
+        .into_iter()
+        .collect();
+
+        let bytes = MessagePack::serialize(&value)?;
+        assert_eq!(value, MessagePack::deserialize::<BTreeMap<_, _>>(&bytes)?);
+        assert_eq!(bytes, serialize(&value)?);
+        assert_eq!(value, decode::<BTreeMap<_, _>>(&bytes)?);
+
+        let bytes = Json::serialize(&value)?;
+        assert_eq!(r#"{"a":[1,2],"b":null}"#.as_bytes(), &bytes[..]);
+        assert_eq!(value, Json::deserialize::<BTreeMap<_, _>>(&bytes)?);
+
+        let bytes = Bincode::serialize(&value)?;
+        assert_eq!(value, Bincode::deserialize::<BTreeMap<_, _>>(&bytes)?);
+
+        for bytes in [
+            encode::<MessagePack, _>(&value)?,
+            encode::<Json, _>(&value)?,
+            encode::<Bincode, _>(&value)?,
+        ] {
+            assert_eq!(HEADER_MAGIC, bytes[0]);
+            assert_eq!(value, decode::<BTreeMap<_, _>>(&bytes)?);
+        }
+        assert_eq!(value, deserialize(&serialize_with::<Json, _>(&value)?)?);
+        assert_eq!(value, deserialize(&serialize_with::<Bincode, _>(&value)?)?);
+
+        // Codec IDs are unique, including the one of SQL rows
+        let mut ids = vec![MessagePack::ID, Json::ID, Bincode::ID, ROW_CODEC_ID];
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(4, ids.len());
+
+        assert_matches!(
+            decode::<BTreeMap<String, Option<Vec<u8>>>>(&[HEADER_MAGIC, HEADER_VERSION, 9]),
+            Err(Error::Value(_))
+        );
+        assert_matches!(
+            decode::<BTreeMap<String, Option<Vec<u8>>>>(&[HEADER_MAGIC, 9, Json::ID]),
+            Err(Error::Value(_))
+        );
+        Ok(())
+    }
 }
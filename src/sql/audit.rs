@@ -0,0 +1,183 @@
+use super::types::{Row, Value};
+use crate::Error;
+use serde_derive::Serialize;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the virtual table listing recent audit log entries
+pub const AUDIT_TABLE: &str = "system.audit";
+
+/// The number of recent entries kept in memory, for the audit table
+pub const AUDIT_MAX_ENTRIES: usize = 1000;
+
+/// An audit log of administrative actions, i.e. DDL statements, KILL and
+/// admin RPCs, with the client which ran them and their outcome. Recent
+/// entries are kept in memory, and all entries can also be appended to a file
+/// as JSON lines.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    entries: VecDeque<Entry>,
+    file: Option<std::fs::File>,
+}
+
+/// An audit log entry
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Entry {
+    pub id: u64,
+    /// The time of the action, in seconds since the Unix epoch
+    pub time: u64,
+    /// The client ID which performed the action
+    pub client: String,
+    /// The action, e.g. an SQL statement
+    pub action: String,
+    /// The outcome, either ok or the error
+    pub outcome: String,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AuditLog")
+    }
+}
+
+impl AuditLog {
+    /// Creates a new, empty audit log, kept in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also appends entries to a file, which is created if it doesn't exist.
+    pub fn file(self, path: &str) -> Result<Self, Error> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| Error::IO(format!("Failed to open audit log {}: {}", path, err)))?;
+        self.inner.lock()?.file = Some(file);
+        Ok(self)
+    }
+
+    /// Records an action by a client, with its error if any. Entries are
+    /// synced to the file before returning. Failing to write them is logged,
+    /// but doesn't fail the action, which has already been performed.
+    pub fn record(&self, client: &str, action: &str, error: Option<&Error>) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(err) => {
+                error!("Failed to record audit log entry: {}", err);
+                return;
+            }
+        };
+        inner.next_id += 1;
+        let entry = Entry {
+            id: inner.next_id,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            client: client.into(),
+            action: action.into(),
+            outcome: match error {
+                Some(err) => format!("error: {}", err),
+                None => "ok".into(),
+            },
+        };
+        if let Some(file) = &mut inner.file {
+            let result = serde_json::to_string(&entry)
+                .map_err(|err| Error::Internal(err.to_string()))
+                .and_then(|line| Ok(writeln!(file, "{}", line)?))
+                .and_then(|_| Ok(file.sync_data()?));
+            if let Err(err) = result {
+                error!("Failed to write audit log entry {:?}: {}", entry, err);
+            }
+        }
+        if inner.entries.len() >= AUDIT_MAX_ENTRIES {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry);
+    }
+
+    /// Lists the recent entries as rows, oldest first
+    pub fn list(&self) -> Result<Vec<Row>, Error> {
+        Ok(self
+            .inner
+            .lock()?
+            .entries
+            .iter()
+            .map(|entry| {
+                vec![
+                    Value::Integer(entry.id as i64),
+                    Value::Integer(entry.time as i64),
+                    Value::String(entry.client.clone()),
+                    Value::String(entry.action.clone()),
+                    Value::String(entry.outcome.clone()),
+                ]
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("audit.log");
+        let audit = AuditLog::new().file(path.to_str().unwrap())?;
+        audit.record("a", "CREATE DATABASE x", None);
+        audit.record(
+            "b",
+            "DROP TABLE y",
+            Some(&Error::Value("Table y does not exist".into())),
+        );
+
+        let rows = audit.list()?;
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            vec![
+                Value::Integer(2),
+                rows[1][1].clone(),
+                Value::String("b".into()),
+                Value::String("DROP TABLE y".into()),
+                Value::String("error: Table y does not exist".into()),
+            ],
+            rows[1]
+        );
+
+        // Entries are appended to the file as JSON lines, also when reopened
+        let audit = AuditLog::new().file(path.to_str().unwrap())?;
+        audit.record("c", "KILL 1", None);
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("a", lines[0]["client"]);
+        assert_eq!("ok", lines[0]["outcome"]);
+        assert_eq!("KILL 1", lines[2]["action"]);
+        assert_eq!(1, audit.list()?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn bounded() -> Result<(), Error> {
+        let audit = AuditLog::new();
+        for i in 0..AUDIT_MAX_ENTRIES + 5 {
+            audit.record("a", &format!("KILL {}", i), None);
+        }
+        let rows = audit.list()?;
+        assert_eq!(AUDIT_MAX_ENTRIES, rows.len());
+        assert_eq!(Value::Integer(6), rows[0][0]);
+        Ok(())
+    }
+}
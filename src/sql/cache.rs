@@ -0,0 +1,438 @@
+use super::ast::Statement;
+use super::audit::AUDIT_TABLE;
+use super::lexer::Lexer;
+use super::session::SESSIONS_TABLE;
+use super::storage::DEFAULT_DATABASE;
+use super::types::{Row, Value};
+use super::Parser;
+use crate::store::Version;
+use crate::Error;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// A cache of read-only query results, keyed on the normalized query text and
+/// the state machine version the result was read at. Results are evicted in
+/// least-recently-used order to stay within a memory budget.
+#[derive(Debug)]
+pub struct QueryCache {
+    /// The memory budget in bytes, 0 disables the cache
+    budget: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// The estimated size of all cached results
+    size: usize,
+    /// A logical clock for least-recently-used eviction
+    clock: u64,
+}
+
+/// A cached query result
+#[derive(Debug)]
+struct Entry {
+    version: Version,
+    tables: Vec<String>,
+    rows: Vec<Row>,
+    size: usize,
+    used_at: u64,
+}
+
+impl QueryCache {
+    /// Creates a new query cache with the given memory budget in bytes.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            inner: Mutex::new(Inner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns true if the cache is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    /// Returns the number of cache hits and misses
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Fetches a cached result for a query key at a state version. Results
+    /// cached at other versions are stale, and are evicted.
+    pub fn get(&self, key: &str, version: &Version) -> Result<Option<Vec<Row>>, Error> {
+        let mut inner = self.inner.lock()?;
+        inner.clock += 1;
+        let clock = inner.clock;
+        let rows = match inner.entries.get_mut(key) {
+            Some(entry) if &entry.version == version => {
+                entry.used_at = clock;
+                Some(entry.rows.clone())
+            }
+            Some(_) => {
+                inner.remove(key);
+                None
+            }
+            None => None,
+        };
+        match rows {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        Ok(rows)
+    }
+
+    /// Caches a query result read at a state version, from the given tables.
+    /// Results larger than the budget are not cached.
+    pub fn put(
+        &self,
+        key: String,
+        version: Version,
+        tables: Vec<String>,
+        rows: Vec<Row>,
+    ) -> Result<(), Error> {
+        let size = key.len() + rows.iter().map(|row| row_size(row)).sum::<usize>();
+        if size > self.budget {
+            return Ok(());
+        }
+        let mut inner = self.inner.lock()?;
+        inner.remove(&key);
+        while inner.size + size > self.budget {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used_at)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => inner.remove(&oldest),
+                None => break,
+            }
+        }
+        inner.clock += 1;
+        let entry = Entry {
+            version,
+            tables,
+            rows,
+            size,
+            used_at: inner.clock,
+        };
+        inner.size += size;
+        inner.entries.insert(key, entry);
+        Ok(())
+    }
+
+    /// Evicts all cached results which read from a table, e.g. after a write.
+    pub fn invalidate_table(&self, table: &str) -> Result<(), Error> {
+        let mut inner = self.inner.lock()?;
+        let keys: Vec<String> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.tables.iter().any(|t| t == table))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            inner.remove(&key);
+        }
+        Ok(())
+    }
+}
+
+/// A cache of parsed statements, keyed on the exact query text, such that
+/// repeated queries skip lexing and parsing. Plans are still built per
+/// execution, since plan nodes hold execution state. Holds at most a fixed
+/// number of statements, evicted in least-recently-used order.
+#[derive(Debug)]
+pub struct StatementCache {
+    /// The maximum number of statements, 0 disables the cache
+    capacity: usize,
+    inner: Mutex<StatementInner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct StatementInner {
+    /// Statements and the logical time they were last used at
+    statements: HashMap<String, (Statement, u64)>,
+    clock: u64,
+}
+
+impl StatementCache {
+    /// Creates a new statement cache holding at most capacity statements.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(StatementInner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of cache hits and misses
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Parses a query, using a cached statement if possible.
+    pub fn parse(&self, query: &str) -> Result<Statement, Error> {
+        if self.capacity == 0 {
+            return Parser::new(query).parse();
+        }
+        {
+            let mut inner = self.inner.lock()?;
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some((statement, used_at)) = inner.statements.get_mut(query) {
+                *used_at = clock;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(statement.clone());
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let statement = Parser::new(query).parse()?;
+
+        let mut inner = self.inner.lock()?;
+        if inner.statements.len() >= self.capacity {
+            let oldest = inner
+                .statements
+                .iter()
+                .min_by_key(|(_, (_, used_at))| *used_at)
+                .map(|(query, _)| query.clone());
+            if let Some(oldest) = oldest {
+                inner.statements.remove(&oldest);
+            }
+        }
+        let clock = inner.clock;
+        inner
+            .statements
+            .insert(query.to_string(), (statement.clone(), clock));
+        Ok(statement)
+    }
+
+    /// Removes all cached statements, e.g. after a schema change.
+    pub fn invalidate(&self) -> Result<(), Error> {
+        self.inner.lock()?.statements.clear();
+        Ok(())
+    }
+}
+
+impl Inner {
+    /// Removes an entry, if it exists
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.size;
+        }
+    }
+}
+
+/// Normalizes a query for use as a cache key, such that queries differing
+/// only in whitespace or keyword case share cache entries.
+pub fn normalize(query: &str) -> Result<String, Error> {
+    let tokens = Lexer::new(query).collect::<Result<Vec<_>, Error>>()?;
+    Ok(format!("{:?}", tokens))
+}
+
+/// Qualifies a table name with its database, such that cached results of
+/// tables with the same name in different databases are kept apart.
+pub fn qualify(database: &str, table: &str) -> String {
+    if database == DEFAULT_DATABASE {
+        table.to_string()
+    } else {
+        format!("{}.{}", database, table)
+    }
+}
+
+/// Returns the tables read by a statement if its result can be cached, i.e.
+/// if it is a read-only query over regular tables.
+pub fn cacheable_tables(statement: &Statement) -> Option<Vec<String>> {
+    match statement {
+        Statement::Select { from, .. } => {
+            let tables = from.as_ref().map(|f| f.tables.clone()).unwrap_or_default();
+            if tables
+                .iter()
+                .any(|t| t == SESSIONS_TABLE || t == AUDIT_TABLE)
+            {
+                None
+            } else {
+                Some(tables)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the tables written by a statement
+pub fn written_tables(statement: &Statement) -> Vec<&str> {
+    match statement {
+        Statement::Batch(statements) => statements.iter().flat_map(written_tables).collect(),
+        Statement::CreateTable { name, .. } => vec![name],
+        Statement::DropTable(name) => vec![name],
+        Statement::Grant { table, .. } | Statement::Revoke { table, .. } => vec![table],
+        Statement::Insert { table, .. } => vec![table],
+        Statement::Explain {
+            statement,
+            analyze: true,
+        } => written_tables(statement),
+        Statement::CreateDatabase(_)
+        | Statement::Explain { .. }
+        | Statement::Kill(_)
+        | Statement::Select { .. }
+        | Statement::Set { .. }
+        | Statement::Show(_)
+        | Statement::Use(_) => Vec::new(),
+    }
+}
+
+/// Estimates the memory size of a row
+fn row_size(row: &[Value]) -> usize {
+    row.iter()
+        .map(|value| match value {
+            Value::String(s) => std::mem::size_of::<Value>() + s.len(),
+            _ => std::mem::size_of::<Value>(),
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(s: &str) -> Row {
+        vec![Value::String(s.into())]
+    }
+
+    #[test]
+    fn get_put() {
+        let cache = QueryCache::new(1024);
+        let (v1, v2) = (Version::new("a", 1), Version::new("a", 2));
+        assert_eq!(None, cache.get("q", &v1).unwrap());
+        cache
+            .put("q".into(), v1.clone(), vec!["t".into()], vec![row("a")])
+            .unwrap();
+        assert_eq!(Some(vec![row("a")]), cache.get("q", &v1).unwrap());
+        assert_eq!((1, 1), cache.stats());
+
+        // Results from other versions are stale
+        assert_eq!(None, cache.get("q", &v2).unwrap());
+        assert_eq!(None, cache.get("q", &v1).unwrap());
+        assert_eq!((1, 3), cache.stats());
+    }
+
+    #[test]
+    fn evict() {
+        let size = row_size(&row("a")) + 1;
+        let cache = QueryCache::new(size * 2);
+        let v = Version::new("a", 1);
+        cache
+            .put("a".into(), v.clone(), vec![], vec![row("a")])
+            .unwrap();
+        cache
+            .put("b".into(), v.clone(), vec![], vec![row("b")])
+            .unwrap();
+        cache.get("a", &v).unwrap();
+        cache
+            .put("c".into(), v.clone(), vec![], vec![row("c")])
+            .unwrap();
+        assert_eq!(Some(vec![row("a")]), cache.get("a", &v).unwrap());
+        assert_eq!(None, cache.get("b", &v).unwrap());
+        assert_eq!(Some(vec![row("c")]), cache.get("c", &v).unwrap());
+
+        // Results larger than the budget are not cached
+        cache
+            .put("d".into(), v.clone(), vec![], vec![row("d"); 3])
+            .unwrap();
+        assert_eq!(None, cache.get("d", &v).unwrap());
+        assert_eq!(Some(vec![row("a")]), cache.get("a", &v).unwrap());
+    }
+
+    #[test]
+    fn invalidate_table() {
+        let cache = QueryCache::new(1024);
+        let v = Version::new("a", 1);
+        cache
+            .put("a".into(), v.clone(), vec!["x".into()], vec![])
+            .unwrap();
+        cache
+            .put("b".into(), v.clone(), vec!["y".into()], vec![])
+            .unwrap();
+        cache.invalidate_table("x").unwrap();
+        assert_eq!(None, cache.get("a", &v).unwrap());
+        assert_eq!(Some(vec![]), cache.get("b", &v).unwrap());
+    }
+
+    #[test]
+    fn statements() {
+        assert_eq!(
+            normalize("SELECT * FROM movie").unwrap(),
+            normalize("select  *\nfrom movie").unwrap()
+        );
+        assert_ne!(
+            normalize("SELECT * FROM movie").unwrap(),
+            normalize("SELECT * FROM Movie").unwrap()
+        );
+
+        let parse = |query| Parser::new(query).parse().unwrap();
+        assert_eq!(
+            Some(vec!["movie".to_string()]),
+            cacheable_tables(&parse("SELECT * FROM movie"))
+        );
+        assert_eq!(Some(vec![]), cacheable_tables(&parse("SELECT 1")));
+        assert_eq!(
+            None,
+            cacheable_tables(&parse("SELECT * FROM system.sessions"))
+        );
+        assert_eq!(None, cacheable_tables(&parse("SELECT * FROM system.audit")));
+        assert_eq!(
+            None,
+            cacheable_tables(&parse("INSERT INTO movie VALUES (1)"))
+        );
+        assert_eq!(
+            vec!["movie"],
+            written_tables(&parse("INSERT INTO movie VALUES (1)"))
+        );
+        assert!(written_tables(&parse("SELECT * FROM movie")).is_empty());
+        assert_eq!(
+            vec!["a", "b"],
+            written_tables(&parse("DROP TABLE a; DROP TABLE b"))
+        );
+    }
+
+    #[test]
+    fn statement_cache() {
+        let cache = StatementCache::new(2);
+        let a = cache.parse("SELECT 1").unwrap();
+        assert_eq!(a, cache.parse("SELECT 1").unwrap());
+        assert_eq!((1, 1), cache.stats());
+        assert_matches!(cache.parse("SELECT"), Err(Error::Parse(_)));
+
+        // The least recently used statement is evicted
+        cache.parse("SELECT 2").unwrap();
+        cache.parse("SELECT 1").unwrap();
+        cache.parse("SELECT 3").unwrap();
+        assert_eq!((2, 4), cache.stats());
+        cache.parse("SELECT 1").unwrap();
+        cache.parse("SELECT 2").unwrap();
+        assert_eq!((3, 5), cache.stats());
+
+        cache.invalidate().unwrap();
+        cache.parse("SELECT 1").unwrap();
+        assert_eq!((3, 6), cache.stats());
+
+        // A zero capacity disables the cache
+        let cache = StatementCache::new(0);
+        cache.parse("SELECT 1").unwrap();
+        cache.parse("SELECT 1").unwrap();
+        assert_eq!((0, 0), cache.stats());
+    }
+}
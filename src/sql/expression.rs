@@ -1,5 +1,7 @@
 use super::types::Value;
 use crate::Error;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 
 /// An expression
 #[derive(Debug)]
@@ -32,6 +34,25 @@ pub enum Expression {
 
 pub type Expressions = Vec<Expression>;
 
+/// Compares two values with Value::compare(), returning whether the ordering
+/// matches. Comparisons with NULL are NULL.
+fn compare(
+    lhs: &Expression,
+    rhs: &Expression,
+    matches: fn(Ordering) -> bool,
+) -> Result<Value, Error> {
+    Ok(match lhs.evaluate()?.compare(&rhs.evaluate()?)? {
+        Some(ordering) => Value::Boolean(matches(ordering)),
+        None => Value::Null,
+    })
+}
+
+/// Returns an integer overflow error. INTEGER arithmetic which overflows 64
+/// bits errors rather than wrapping around.
+fn overflow() -> Error {
+    Error::Value("Integer overflow".into())
+}
+
 impl Expression {
     /// Evaluates an expression to a value
     pub fn evaluate(&self) -> Result<Value, Error> {
@@ -40,98 +61,67 @@ impl Expression {
             // Logical operations
             Expression::And(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs && rhs),
+                (Boolean(false), Null) | (Null, Boolean(false)) => Boolean(false),
+                (Boolean(true), Null) | (Null, Boolean(true)) | (Null, Null) => Null,
                 (lhs, rhs) => return Err(Error::Value(format!("Can't and {} and {}", lhs, rhs))),
             },
             Expression::Not(expr) => match expr.evaluate()? {
                 Boolean(b) => Boolean(!b),
+                Null => Null,
                 value => return Err(Error::Value(format!("Can't negate {}", value))),
             },
             Expression::Or(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs || rhs),
+                (Boolean(true), Null) | (Null, Boolean(true)) => Boolean(true),
+                (Boolean(false), Null) | (Null, Boolean(false)) | (Null, Null) => Null,
                 (lhs, rhs) => return Err(Error::Value(format!("Can't or {} and {}", lhs, rhs))),
             },
 
             // Comparison operations
-            #[allow(clippy::float_cmp)] // Up to the user if they want to compare or not
-            Expression::CompareEQ(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs == rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 == rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs == rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs == rhs),
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            Expression::CompareGT(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs > rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 > rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs > rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs > rhs),
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            Expression::CompareGTE(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs >= rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 >= rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs >= rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs >= rhs),
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            Expression::CompareLT(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs < rhs),
-                (Integer(lhs), Float(rhs)) => Boolean((lhs as f64) < rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs < rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs < rhs),
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            Expression::CompareLTE(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs <= rhs),
-                (Integer(lhs), Float(rhs)) => Boolean((lhs as f64) <= rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs <= rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs <= rhs),
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            #[allow(clippy::float_cmp)] // Up to the user if they want to compare or not
-            Expression::CompareNE(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs != rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 != rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs != rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs != rhs),
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
+            Expression::CompareEQ(lhs, rhs) => compare(lhs, rhs, |o| o == Ordering::Equal)?,
+            Expression::CompareGT(lhs, rhs) => compare(lhs, rhs, |o| o == Ordering::Greater)?,
+            Expression::CompareGTE(lhs, rhs) => compare(lhs, rhs, |o| o != Ordering::Less)?,
+            Expression::CompareLT(lhs, rhs) => compare(lhs, rhs, |o| o == Ordering::Less)?,
+            Expression::CompareLTE(lhs, rhs) => compare(lhs, rhs, |o| o != Ordering::Greater)?,
+            Expression::CompareNE(lhs, rhs) => compare(lhs, rhs, |o| o != Ordering::Equal)?,
 
             // Mathematical operations
             Expression::Add(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Integer(lhs + rhs),
+                (Integer(lhs), Integer(rhs)) => Integer(lhs.checked_add(rhs).ok_or_else(overflow)?),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 + rhs),
                 (Float(lhs), Integer(rhs)) => Float(lhs + rhs as f64),
                 (Float(lhs), Float(rhs)) => Float(lhs + rhs),
+                (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => return Err(Error::Value(format!("Can't add {} and {}", lhs, rhs))),
             },
             Expression::Divide(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Integer(lhs / rhs),
+                (Integer(_), Integer(0)) => {
+                    return Err(Error::Value("Can't divide by zero".into()))
+                }
+                (Integer(lhs), Integer(rhs)) => Integer(lhs.checked_div(rhs).ok_or_else(overflow)?),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 / rhs),
                 (Float(lhs), Integer(rhs)) => Float(lhs / rhs as f64),
                 (Float(lhs), Float(rhs)) => Float(lhs / rhs),
+                (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!("Can't divide {} and {}", lhs, rhs)))
                 }
             },
             Expression::Exponentiate(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                // FIXME Handle overflow
-                (Integer(lhs), Integer(rhs)) => Integer(lhs.pow(rhs as u32)),
-                (Integer(lhs), Float(rhs)) => Float((lhs as f64).powi(rhs as i32)),
-                (Float(lhs), Integer(rhs)) => Float((lhs).powi(rhs as i32)),
+                (Integer(lhs), Integer(rhs)) if rhs < 0 => Float((lhs as f64).powf(rhs as f64)),
+                (Integer(lhs), Integer(rhs)) => Integer(
+                    u32::try_from(rhs)
+                        .ok()
+                        .and_then(|rhs| lhs.checked_pow(rhs))
+                        .ok_or_else(overflow)?,
+                ),
+                (Integer(lhs), Float(rhs)) => Float((lhs as f64).powf(rhs)),
+                (Float(lhs), Integer(rhs)) => Float(match i32::try_from(rhs) {
+                    Ok(rhs) => lhs.powi(rhs),
+                    Err(_) => lhs.powf(rhs as f64),
+                }),
                 (Float(lhs), Float(rhs)) => Float((lhs).powf(rhs)),
+                (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!(
                         "Can't exponentiate {} and {}",
@@ -140,16 +130,32 @@ impl Expression {
                 }
             },
             Expression::Factorial(expr) => match expr.evaluate()? {
-                Integer(i) => Integer((1..=i).fold(1, |a, b| a * b as i64)),
+                Integer(i) if i < 0 => {
+                    return Err(Error::Value(format!("Can't take factorial of {}", i)))
+                }
+                Integer(i) => Integer(
+                    (1..=i)
+                        .try_fold(1_i64, |a, b| a.checked_mul(b))
+                        .ok_or_else(overflow)?,
+                ),
+                Null => Null,
                 value => return Err(Error::Value(format!("Can't take factorial of {}", value))),
             },
             Expression::Modulo(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
                 // The % operator in Rust is remainder, not modulo, so we have to do a bit of
                 // acrobatics to make it work right
-                (Integer(lhs), Integer(rhs)) => Integer(((lhs % rhs) + rhs) % rhs),
+                (Integer(_), Integer(0)) => {
+                    return Err(Error::Value("Can't take modulo by zero".into()))
+                }
+                // The remainder only overflows for i64::MIN % -1, which is 0
+                (Integer(lhs), Integer(rhs)) => match lhs.checked_rem(rhs).unwrap_or(0) {
+                    r if r != 0 && (r < 0) != (rhs < 0) => Integer(r + rhs),
+                    r => Integer(r),
+                },
                 (Integer(lhs), Float(rhs)) => Float(((lhs as f64 % rhs) + rhs) % rhs),
                 (Float(lhs), Integer(rhs)) => Float(((lhs % rhs as f64) + rhs as f64) % rhs as f64),
                 (Float(lhs), Float(rhs)) => Float(((lhs % rhs) + rhs) % rhs),
+                (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!(
                         "Can't take modulo of {} and {}",
@@ -158,24 +164,27 @@ impl Expression {
                 }
             },
             Expression::Multiply(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Integer(lhs * rhs),
+                (Integer(lhs), Integer(rhs)) => Integer(lhs.checked_mul(rhs).ok_or_else(overflow)?),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 * rhs),
                 (Float(lhs), Integer(rhs)) => Float(lhs * rhs as f64),
                 (Float(lhs), Float(rhs)) => Float(lhs * rhs),
+                (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!("Can't multiply {} and {}", lhs, rhs)))
                 }
             },
             Expression::Negate(expr) => match expr.evaluate()? {
-                Integer(i) => Integer(-i),
+                Integer(i) => Integer(i.checked_neg().ok_or_else(overflow)?),
                 Float(f) => Float(-f),
+                Null => Null,
                 value => return Err(Error::Value(format!("Can't negate {}", value))),
             },
             Expression::Subtract(lhs, rhs) => match (lhs.evaluate()?, rhs.evaluate()?) {
-                (Integer(lhs), Integer(rhs)) => Integer(lhs - rhs),
+                (Integer(lhs), Integer(rhs)) => Integer(lhs.checked_sub(rhs).ok_or_else(overflow)?),
                 (Integer(lhs), Float(rhs)) => Float(lhs as f64 - rhs),
                 (Float(lhs), Integer(rhs)) => Float(lhs - rhs as f64),
                 (Float(lhs), Float(rhs)) => Float(lhs - rhs),
+                (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => {
                     return Err(Error::Value(format!("Can't subtract {} and {}", lhs, rhs)))
                 }
@@ -185,3 +194,146 @@ impl Expression {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Generates values, with finite floats
+    fn value() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Boolean),
+            any::<i64>().prop_map(Value::Integer),
+            (-1e300..1e300).prop_map(Value::Float),
+            "[a-z]{0,4}".prop_map(Value::String),
+        ]
+    }
+
+    /// A binary expression constructor
+    type BinaryOp = fn(Box<Expression>, Box<Expression>) -> Expression;
+
+    fn constant(value: Value) -> Box<Expression> {
+        Box::new(Expression::Constant(value))
+    }
+
+    proptest! {
+        #[test]
+        fn commutative(a in value(), b in value()) {
+            let ops: &[BinaryOp] = &[
+                Expression::Add,
+                Expression::Multiply,
+                Expression::And,
+                Expression::Or,
+                Expression::CompareEQ,
+                Expression::CompareNE,
+            ];
+            for op in ops {
+                let lhs = op(constant(a.clone()), constant(b.clone())).evaluate();
+                let rhs = op(constant(b.clone()), constant(a.clone())).evaluate();
+                match (lhs, rhs) {
+                    (Ok(lhs), Ok(rhs)) => prop_assert_eq!(lhs, rhs),
+                    (Err(_), Err(_)) => {}
+                    (lhs, rhs) => prop_assert!(false, "{:?} != {:?}", lhs, rhs),
+                }
+            }
+        }
+
+        #[test]
+        fn null_propagation(v in value()) {
+            let binary: &[BinaryOp] = &[
+                Expression::Add,
+                Expression::Divide,
+                Expression::Exponentiate,
+                Expression::Modulo,
+                Expression::Multiply,
+                Expression::Subtract,
+                Expression::CompareEQ,
+                Expression::CompareGT,
+                Expression::CompareGTE,
+                Expression::CompareLT,
+                Expression::CompareLTE,
+                Expression::CompareNE,
+            ];
+            for op in binary {
+                prop_assert_eq!(Ok(Value::Null), op(constant(Value::Null), constant(v.clone())).evaluate());
+                prop_assert_eq!(Ok(Value::Null), op(constant(v.clone()), constant(Value::Null)).evaluate());
+            }
+            let unary: &[fn(Box<Expression>) -> Expression] =
+                &[Expression::Not, Expression::Negate, Expression::Factorial];
+            for op in unary {
+                prop_assert_eq!(Ok(Value::Null), op(constant(Value::Null)).evaluate());
+            }
+        }
+
+        #[test]
+        fn integer_arithmetic(a in any::<i64>(), b in any::<i64>()) {
+            // Integer operations never panic, and match checked arithmetic
+            let (ia, ib) = (Value::Integer(a), Value::Integer(b));
+            let add = Expression::Add(constant(ia.clone()), constant(ib.clone())).evaluate();
+            prop_assert_eq!(a.checked_add(b).map(Value::Integer).ok_or_else(overflow), add);
+            let sub = Expression::Subtract(constant(ia.clone()), constant(ib.clone())).evaluate();
+            prop_assert_eq!(a.checked_sub(b).map(Value::Integer).ok_or_else(overflow), sub);
+            let mul = Expression::Multiply(constant(ia.clone()), constant(ib.clone())).evaluate();
+            prop_assert_eq!(a.checked_mul(b).map(Value::Integer).ok_or_else(overflow), mul);
+            let div = Expression::Divide(constant(ia.clone()), constant(ib.clone())).evaluate();
+            prop_assert_eq!(b == 0 || (a == i64::MIN && b == -1), div.is_err());
+            let rem = Expression::Modulo(constant(ia.clone()), constant(ib.clone())).evaluate();
+            prop_assert_eq!(b == 0, rem.is_err());
+            let _ = Expression::Exponentiate(constant(ia.clone()), constant(ib)).evaluate();
+            let neg = Expression::Negate(constant(ia.clone())).evaluate();
+            prop_assert_eq!(a.checked_neg().map(Value::Integer).ok_or_else(overflow), neg);
+            let fact = Expression::Factorial(constant(ia)).evaluate();
+            prop_assert_eq!(!(0..=20).contains(&a), fact.is_err());
+        }
+    }
+
+    #[test]
+    fn null_logic() {
+        use Value::*;
+        let and = |a, b| {
+            Expression::And(constant(a), constant(b))
+                .evaluate()
+                .unwrap()
+        };
+        let or = |a, b| Expression::Or(constant(a), constant(b)).evaluate().unwrap();
+        assert_eq!(Boolean(false), and(Null, Boolean(false)));
+        assert_eq!(Null, and(Null, Boolean(true)));
+        assert_eq!(Null, and(Null, Null));
+        assert_eq!(Boolean(true), or(Null, Boolean(true)));
+        assert_eq!(Null, or(Null, Boolean(false)));
+        assert_eq!(Null, or(Null, Null));
+    }
+
+    #[test]
+    fn modulo() {
+        use Value::*;
+        let modulo = |a, b| {
+            Expression::Modulo(constant(Integer(a)), constant(Integer(b)))
+                .evaluate()
+                .unwrap()
+        };
+        assert_eq!(Integer(1), modulo(7, 3));
+        assert_eq!(Integer(2), modulo(-7, 3));
+        assert_eq!(Integer(-2), modulo(7, -3));
+        assert_eq!(Integer(-1), modulo(-7, -3));
+        assert_eq!(Integer(0), modulo(6, -3));
+        assert_eq!(Integer(0), modulo(i64::MIN, -1));
+    }
+
+    #[test]
+    fn exponentiate() {
+        use Value::*;
+        let pow = |a, b| {
+            Expression::Exponentiate(constant(a), constant(b))
+                .evaluate()
+                .unwrap()
+        };
+        assert_eq!(Integer(8), pow(Integer(2), Integer(3)));
+        assert_eq!(Float(0.5), pow(Integer(2), Integer(-1)));
+        assert_eq!(Float(2.0), pow(Integer(4), Float(0.5)));
+        assert_eq!(Float(0.25), pow(Float(0.5), Integer(2)));
+        assert_eq!(Float(1.0), pow(Float(-1.0), Integer(1 << 40)));
+    }
+}
@@ -1,13 +1,40 @@
+pub mod audit;
+#[cfg(feature = "server")]
+pub mod cache;
 mod expression;
 mod parser;
 mod plan;
+mod prepared;
+mod row;
 pub mod schema;
+pub mod session;
 mod storage;
 #[cfg(test)]
 mod tests;
 pub mod types;
 
 pub use expression::Expression;
-pub use parser::{ast, lexer, Parser};
-pub use plan::{Context, Plan, ResultSet};
-pub use storage::Storage;
+#[cfg(any(test, feature = "server"))]
+pub use parser::lexer;
+pub use parser::{ast, Parser};
+#[cfg(feature = "server")]
+pub use plan::ResultSet;
+pub use plan::{Context, Plan};
+pub use prepared::PreparedStatement;
+pub use storage::{Storage, StorageReport};
+
+use crate::Error;
+
+/// Formats an SQL statement as canonical SQL.
+pub fn format_query(query: &str) -> Result<String, Error> {
+    Ok(Parser::new(query).parse()?.to_string())
+}
+
+/// Returns true if a query is incomplete, i.e. it ends before the statement
+/// does, such that more input is needed to parse it.
+pub fn is_incomplete_query(query: &str) -> bool {
+    matches!(
+        Parser::new(query).parse(),
+        Err(Error::Parse(message)) if message.starts_with("Unexpected end of")
+    )
+}
@@ -1,21 +1,51 @@
+use super::super::schema::Privilege;
 use super::super::types;
 
 /// Statements
 #[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
+    /// Several statements separated by semicolons, applied atomically
+    Batch(Vec<Statement>),
+    /// A CREATE DATABASE statement
+    CreateDatabase(String),
     /// A CREATE TABLE statement
     CreateTable {
         name: String,
         columns: Vec<ColumnSpec>,
+        /// Whether the table only lives in the session, see CREATE TEMPORARY TABLE
+        temporary: bool,
     },
     /// An INSERT statement
     Insert {
         table: String,
         columns: Option<Vec<String>>,
         values: Vec<Expressions>,
+        /// Whether to skip rows with existing primary keys, see ON CONFLICT DO NOTHING
+        ignore_conflicts: bool,
     },
     /// A DROP TABLE statement
     DropTable(String),
+    /// An EXPLAIN statement, showing the plan of a statement. With ANALYZE,
+    /// the statement is executed and the plan annotated with row counts and
+    /// timings.
+    Explain {
+        statement: Box<Statement>,
+        analyze: bool,
+    },
+    /// A GRANT statement, granting table privileges to a user
+    Grant {
+        privileges: Vec<PrivilegeSpec>,
+        table: String,
+        user: String,
+    },
+    /// A KILL statement, terminating a running query
+    Kill(u64),
+    /// A REVOKE statement, revoking table privileges from a user
+    Revoke {
+        privileges: Vec<PrivilegeSpec>,
+        table: String,
+        user: String,
+    },
     /// A SELECT statement
     Select {
         /// The select clause
@@ -23,6 +53,83 @@ pub enum Statement {
         /// The from clause,
         from: Option<FromClause>,
     },
+    /// A SET statement, setting a session variable
+    Set { variable: String, value: Expression },
+    /// A SHOW statement, showing a session variable
+    Show(String),
+    /// A USE statement, changing the session's current database
+    Use(String),
+}
+
+impl Statement {
+    /// Returns true if the statement mutates the database
+    pub fn is_mutation(&self) -> bool {
+        match self {
+            Statement::Batch(statements) => statements.iter().any(|s| s.is_mutation()),
+            Statement::CreateDatabase(_)
+            | Statement::CreateTable { .. }
+            | Statement::DropTable(_)
+            | Statement::Grant { .. }
+            | Statement::Insert { .. }
+            | Statement::Revoke { .. } => true,
+            Statement::Explain { statement, analyze } => *analyze && statement.is_mutation(),
+            Statement::Kill(_)
+            | Statement::Select { .. }
+            | Statement::Set { .. }
+            | Statement::Show(_)
+            | Statement::Use(_) => false,
+        }
+    }
+
+    /// Returns true if the statement is an administrative action, which is
+    /// recorded in the audit log: DDL, GRANT, REVOKE and KILL
+    pub fn is_administrative(&self) -> bool {
+        match self {
+            Statement::CreateDatabase(_)
+            | Statement::CreateTable { .. }
+            | Statement::DropTable(_)
+            | Statement::Grant { .. }
+            | Statement::Kill(_)
+            | Statement::Revoke { .. } => true,
+            Statement::Batch(statements) => statements.iter().any(|s| s.is_administrative()),
+            Statement::Explain { statement, analyze } => *analyze && statement.is_administrative(),
+            _ => false,
+        }
+    }
+
+    /// Calls the closure on every expression in the statement, including
+    /// nested expressions, parents before their operands.
+    pub fn walk_expressions(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        match self {
+            Statement::Batch(statements) => {
+                statements.iter_mut().for_each(|s| s.walk_expressions(f))
+            }
+            Statement::Explain { statement, .. } => statement.walk_expressions(f),
+            Statement::Insert { values, .. } => values.iter_mut().flatten().for_each(|e| e.walk(f)),
+            Statement::Select { select, .. } => {
+                select.expressions.iter_mut().for_each(|e| e.walk(f))
+            }
+            Statement::Set { value, .. } => value.walk(f),
+            Statement::CreateDatabase(_)
+            | Statement::CreateTable { .. }
+            | Statement::DropTable(_)
+            | Statement::Grant { .. }
+            | Statement::Kill(_)
+            | Statement::Revoke { .. }
+            | Statement::Show(_)
+            | Statement::Use(_) => {}
+        }
+    }
+
+    /// Returns true if the statement changes the schema
+    pub fn is_schema_change(&self) -> bool {
+        match self {
+            Statement::CreateTable { .. } | Statement::DropTable(_) => true,
+            Statement::Batch(statements) => statements.iter().any(|s| s.is_schema_change()),
+            Statement::Explain { statement, analyze } => *analyze && statement.is_schema_change(),
+            _ => false,
+        }
+    }
 }
 
 /// A column specification
@@ -34,6 +141,23 @@ pub struct ColumnSpec {
     pub nullable: Option<bool>,
 }
 
+/// A privilege specification in GRANT or REVOKE
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrivilegeSpec {
+    pub privilege: Privilege,
+    /// The columns the privilege applies to, or None for the whole table
+    pub columns: Option<Vec<String>>,
+}
+
+impl From<Privilege> for PrivilegeSpec {
+    fn from(privilege: Privilege) -> Self {
+        Self {
+            privilege,
+            columns: None,
+        }
+    }
+}
+
 /// A SELECT clause
 #[derive(Clone, Debug, PartialEq)]
 pub struct SelectClause {
@@ -54,6 +178,9 @@ pub struct FromClause {
 pub enum Expression {
     Literal(Literal),
     Operation(Operation),
+    /// A query parameter ?, numbered from 0 in order of appearance. They
+    /// must be bound to values before planning, see PreparedStatement.
+    Parameter(usize),
 }
 
 impl From<Literal> for Expression {
@@ -106,3 +233,252 @@ pub enum Operation {
     Negate(Box<Expression>),
     Subtract(Box<Expression>, Box<Expression>),
 }
+
+/// Formats a list of items separated by commas
+fn join<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats statements as SQL, which parses back into the same statement.
+impl std::fmt::Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Statement::Batch(statements) => {
+                let statements = statements.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", statements.join("; "))
+            }
+            Statement::CreateDatabase(name) => write!(f, "CREATE DATABASE {}", name),
+            Statement::CreateTable {
+                name,
+                columns,
+                temporary,
+            } => {
+                let temporary = if *temporary { "TEMPORARY " } else { "" };
+                write!(f, "CREATE {}TABLE {} ({})", temporary, name, join(columns))
+            }
+            Statement::Insert {
+                table,
+                columns,
+                values,
+                ignore_conflicts,
+            } => {
+                write!(f, "INSERT INTO {} ", table)?;
+                if let Some(columns) = columns {
+                    write!(f, "({}) ", columns.join(", "))?;
+                }
+                let values = values
+                    .iter()
+                    .map(|exprs| format!("({})", join(exprs)))
+                    .collect::<Vec<_>>();
+                write!(f, "VALUES {}", values.join(", "))?;
+                if *ignore_conflicts {
+                    write!(f, " ON CONFLICT DO NOTHING")?;
+                }
+                Ok(())
+            }
+            Statement::DropTable(name) => write!(f, "DROP TABLE {}", name),
+            Statement::Explain { statement, analyze } => {
+                let analyze = if *analyze { "ANALYZE " } else { "" };
+                write!(f, "EXPLAIN {}{}", analyze, statement)
+            }
+            Statement::Grant {
+                privileges,
+                table,
+                user,
+            } => write!(f, "GRANT {} ON {} TO {}", join(privileges), table, user),
+            Statement::Kill(id) => write!(f, "KILL {}", id),
+            Statement::Revoke {
+                privileges,
+                table,
+                user,
+            } => write!(f, "REVOKE {} ON {} FROM {}", join(privileges), table, user),
+            Statement::Select { select, from } => {
+                write!(f, "SELECT ")?;
+                if select.expressions.is_empty() {
+                    write!(f, "*")?;
+                }
+                let selected = select
+                    .expressions
+                    .iter()
+                    .zip(&select.labels)
+                    .map(|(expr, label)| match label {
+                        Some(label) => format!("{} AS {}", expr, label),
+                        None => expr.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", selected.join(", "))?;
+                if let Some(from) = from {
+                    write!(f, " FROM {}", from.tables.join(", "))?;
+                }
+                Ok(())
+            }
+            Statement::Set { variable, value } => write!(f, "SET {} = {}", variable, value),
+            Statement::Show(variable) => write!(f, "SHOW {}", variable),
+            Statement::Use(database) => write!(f, "USE {}", database),
+        }
+    }
+}
+
+impl std::fmt::Display for ColumnSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.datatype)?;
+        if self.primary_key {
+            write!(f, " PRIMARY KEY")?;
+        }
+        match self.nullable {
+            Some(true) => write!(f, " NULL"),
+            Some(false) => write!(f, " NOT NULL"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for PrivilegeSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.columns {
+            Some(columns) => write!(f, "{} ({})", self.privilege, columns.join(", ")),
+            None => write!(f, "{}", self.privilege),
+        }
+    }
+}
+
+/// Formats expressions as canonical SQL, with parentheses only where needed.
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Literal(literal) => literal.fmt(f),
+            Expression::Operation(op) => op.fmt(f),
+            Expression::Parameter(_) => write!(f, "?"),
+        }
+    }
+}
+
+impl Expression {
+    /// Calls the closure on the expression and its operands, recursively.
+    fn walk(&mut self, f: &mut dyn FnMut(&mut Expression)) {
+        f(self);
+        if let Expression::Operation(op) = self {
+            for operand in op.operands_mut() {
+                operand.walk(f)
+            }
+        }
+    }
+
+    /// Returns the precedence of the expression's operator. Literals bind
+    /// tightest.
+    fn prec(&self) -> u8 {
+        match self {
+            Expression::Literal(_) | Expression::Parameter(_) => 10,
+            Expression::Operation(op) => op.prec(),
+        }
+    }
+
+    /// Formats the expression as an operand, parenthesized if it binds
+    /// looser than the given precedence.
+    fn operand(&self, min_prec: u8) -> String {
+        if self.prec() < min_prec {
+            format!("({})", self)
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Literal::Null => write!(f, "NULL"),
+            Literal::Boolean(true) => write!(f, "TRUE"),
+            Literal::Boolean(false) => write!(f, "FALSE"),
+            Literal::Integer(i) => write!(f, "{}", i),
+            // The Debug format always includes a decimal point or exponent
+            Literal::Float(n) => write!(f, "{:?}", n),
+            Literal::String(s) => write!(f, "'{}'", s.replace('\'', "''")),
+        }
+    }
+}
+
+impl Operation {
+    /// Returns the operation's operands.
+    fn operands_mut(&mut self) -> Vec<&mut Expression> {
+        use Operation::*;
+        match self {
+            Not(expr) | Negate(expr) | Factorial(expr) => vec![expr.as_mut()],
+            And(lhs, rhs)
+            | Or(lhs, rhs)
+            | CompareEQ(lhs, rhs)
+            | CompareGT(lhs, rhs)
+            | CompareGTE(lhs, rhs)
+            | CompareLT(lhs, rhs)
+            | CompareLTE(lhs, rhs)
+            | CompareNE(lhs, rhs)
+            | Add(lhs, rhs)
+            | Divide(lhs, rhs)
+            | Exponentiate(lhs, rhs)
+            | Modulo(lhs, rhs)
+            | Multiply(lhs, rhs)
+            | Subtract(lhs, rhs) => vec![lhs.as_mut(), rhs.as_mut()],
+        }
+    }
+
+    /// Returns the operator precedence, matching the parser
+    fn prec(&self) -> u8 {
+        use Operation::*;
+        match self {
+            Or(..) => 1,
+            And(..) => 2,
+            CompareEQ(..) | CompareNE(..) => 3,
+            CompareGT(..) | CompareGTE(..) | CompareLT(..) | CompareLTE(..) => 4,
+            Add(..) | Subtract(..) => 5,
+            Multiply(..) | Divide(..) | Modulo(..) => 6,
+            Exponentiate(..) => 7,
+            Factorial(..) => 8,
+            Not(..) | Negate(..) => 9,
+        }
+    }
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Operation::*;
+        let (lhs, op, rhs) = match self {
+            Not(expr) => return write!(f, "NOT {}", expr.operand(9)),
+            // Nested negations are parenthesized, since -- could be taken for a comment
+            Negate(expr) if matches!(**expr, Expression::Operation(Negate(_))) => {
+                return write!(f, "-({})", expr)
+            }
+            Negate(expr) => return write!(f, "-{}", expr.operand(9)),
+            Factorial(expr) => return write!(f, "{}!", expr.operand(8)),
+            And(lhs, rhs) => (lhs, "AND", rhs),
+            Or(lhs, rhs) => (lhs, "OR", rhs),
+            CompareEQ(lhs, rhs) => (lhs, "=", rhs),
+            CompareGT(lhs, rhs) => (lhs, ">", rhs),
+            CompareGTE(lhs, rhs) => (lhs, ">=", rhs),
+            CompareLT(lhs, rhs) => (lhs, "<", rhs),
+            CompareLTE(lhs, rhs) => (lhs, "<=", rhs),
+            CompareNE(lhs, rhs) => (lhs, "!=", rhs),
+            Add(lhs, rhs) => (lhs, "+", rhs),
+            Divide(lhs, rhs) => (lhs, "/", rhs),
+            Exponentiate(lhs, rhs) => (lhs, "^", rhs),
+            Modulo(lhs, rhs) => (lhs, "%", rhs),
+            Multiply(lhs, rhs) => (lhs, "*", rhs),
+            Subtract(lhs, rhs) => (lhs, "-", rhs),
+        };
+        // Exponentiation is right-associative, all other operators are left-associative
+        let (lhs_prec, rhs_prec) = match self {
+            Exponentiate(..) => (self.prec() + 1, self.prec()),
+            _ => (self.prec(), self.prec() + 1),
+        };
+        write!(
+            f,
+            "{} {} {}",
+            lhs.operand(lhs_prec),
+            op,
+            rhs.operand(rhs_prec)
+        )
+    }
+}
@@ -51,6 +51,8 @@ pub enum Token {
     CloseParen,
     /// An expression separator ,
     Comma,
+    /// A statement separator ;
+    Semicolon,
 }
 
 impl std::fmt::Display for Token {
@@ -79,6 +81,7 @@ impl std::fmt::Display for Token {
             Token::OpenParen => "(",
             Token::CloseParen => ")",
             Token::Comma => ",",
+            Token::Semicolon => ";",
         })
     }
 }
@@ -92,25 +95,44 @@ impl From<Keyword> for Token {
 /// Lexer keywords
 #[derive(Clone, Debug, PartialEq)]
 pub enum Keyword {
+    All,
+    Analyze,
     And,
     As,
     Boolean,
+    Conflict,
     Create,
+    Database,
+    Delete,
+    Do,
     Drop,
+    Explain,
     False,
     Float,
     From,
+    Grant,
     Insert,
     Integer,
     Into,
     Key,
+    Kill,
     Not,
+    Nothing,
     Null,
+    On,
     Or,
     Primary,
+    Privileges,
+    Revoke,
     Select,
+    Set,
+    Show,
     Table,
+    Temporary,
+    To,
     True,
+    Update,
+    Use,
     Values,
     Varchar,
 }
@@ -118,25 +140,44 @@ pub enum Keyword {
 impl Keyword {
     fn from_str(ident: &str) -> Option<Self> {
         Some(match ident.to_uppercase().as_ref() {
+            "ALL" => Self::All,
+            "ANALYZE" => Self::Analyze,
             "AS" => Self::As,
             "AND" => Self::And,
             "BOOLEAN" => Self::Boolean,
+            "CONFLICT" => Self::Conflict,
             "CREATE" => Self::Create,
+            "DATABASE" => Self::Database,
+            "DELETE" => Self::Delete,
+            "DO" => Self::Do,
             "DROP" => Self::Drop,
+            "EXPLAIN" => Self::Explain,
             "FALSE" => Self::False,
             "FLOAT" => Self::Float,
             "FROM" => Self::From,
+            "GRANT" => Self::Grant,
             "INSERT" => Self::Insert,
             "INTO" => Self::Into,
             "INTEGER" => Self::Integer,
             "KEY" => Self::Key,
+            "KILL" => Self::Kill,
             "NOT" => Self::Not,
+            "NOTHING" => Self::Nothing,
             "NULL" => Self::Null,
+            "ON" => Self::On,
             "OR" => Self::Or,
             "PRIMARY" => Self::Primary,
+            "PRIVILEGES" => Self::Privileges,
+            "REVOKE" => Self::Revoke,
             "SELECT" => Self::Select,
+            "SET" => Self::Set,
+            "SHOW" => Self::Show,
             "TABLE" => Self::Table,
+            "TEMPORARY" => Self::Temporary,
+            "TO" => Self::To,
             "TRUE" => Self::True,
+            "UPDATE" => Self::Update,
+            "USE" => Self::Use,
             "VALUES" => Self::Values,
             "VARCHAR" => Self::Varchar,
             _ => return None,
@@ -145,25 +186,44 @@ impl Keyword {
 
     fn to_str(&self) -> &str {
         match self {
+            Self::All => "ALL",
+            Self::Analyze => "ANALYZE",
             Self::As => "AS",
             Self::And => "AND",
             Self::Boolean => "BOOLEAN",
+            Self::Conflict => "CONFLICT",
             Self::Create => "CREATE",
+            Self::Database => "DATABASE",
+            Self::Delete => "DELETE",
+            Self::Do => "DO",
             Self::Drop => "DROP",
+            Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
             Self::Float => "FLOAT",
             Self::From => "FROM",
+            Self::Grant => "GRANT",
             Self::Insert => "INSERT",
             Self::Integer => "INTEGER",
             Self::Into => "INTO",
             Self::Key => "KEY",
+            Self::Kill => "KILL",
             Self::Not => "NOT",
+            Self::Nothing => "NOTHING",
             Self::Null => "NULL",
+            Self::On => "ON",
             Self::Or => "OR",
             Self::Primary => "PRIMARY",
+            Self::Privileges => "PRIVILEGES",
+            Self::Revoke => "REVOKE",
             Self::Select => "SELECT",
+            Self::Set => "SET",
+            Self::Show => "SHOW",
             Self::Table => "TABLE",
+            Self::Temporary => "TEMPORARY",
+            Self::To => "TO",
             Self::True => "TRUE",
+            Self::Update => "UPDATE",
+            Self::Use => "USE",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
         }
@@ -317,6 +377,7 @@ impl<'a> Lexer<'a> {
             '(' => Some(Token::OpenParen),
             ')' => Some(Token::CloseParen),
             ',' => Some(Token::Comma),
+            ';' => Some(Token::Semicolon),
             _ => None,
         })
         .map(|token| match token {
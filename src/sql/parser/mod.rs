@@ -1,13 +1,24 @@
 pub mod ast;
 pub mod lexer;
 
+use super::schema::Privilege;
 use super::types::DataType;
 use crate::Error;
 use lexer::{Keyword, Lexer, Token};
 
+/// The maximum number of atoms and operators in an expression. This bounds
+/// the recursion depth when parsing and evaluating, to avoid stack overflows.
+const MAX_EXPRESSION_NODES: usize = 256;
+
 /// An SQL parser
 pub struct Parser<'a> {
     lexer: std::iter::Peekable<Lexer<'a>>,
+    /// The current expression nesting depth
+    depth: usize,
+    /// The number of nodes in the current top-level expression
+    nodes: usize,
+    /// The number of query parameters parsed so far
+    parameters: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -15,14 +26,33 @@ impl<'a> Parser<'a> {
     pub fn new(query: &str) -> Parser {
         Parser {
             lexer: Lexer::new(query).peekable(),
+            depth: 0,
+            nodes: 0,
+            parameters: 0,
         }
     }
 
-    /// Parses the input string into an AST statement
+    /// Returns the number of query parameters ? parsed so far.
+    pub fn parameters(&self) -> usize {
+        self.parameters
+    }
+
+    /// Parses the input string into an AST statement. Several statements
+    /// separated by semicolons are parsed as a batch.
     pub fn parse(&mut self) -> Result<ast::Statement, Error> {
-        let statement = self.parse_statement()?;
+        let mut statements = vec![self.parse_statement()?];
+        while self.next_if_token(Token::Semicolon).is_some() {
+            if self.peek()?.is_none() {
+                break;
+            }
+            statements.push(self.parse_statement()?);
+        }
         self.next_expect(None)?;
-        Ok(statement)
+        if statements.len() == 1 {
+            Ok(statements.remove(0))
+        } else {
+            Ok(ast::Statement::Batch(statements))
+        }
     }
 
     /// Grabs the next lexer token, or throws an error if none is found.
@@ -102,8 +132,15 @@ impl<'a> Parser<'a> {
         match self.peek()? {
             Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Explain)) => self.parse_statement_explain(),
+            Some(Token::Keyword(Keyword::Grant)) => self.parse_statement_grant(),
             Some(Token::Keyword(Keyword::Insert)) => self.parse_statement_insert(),
+            Some(Token::Keyword(Keyword::Kill)) => self.parse_statement_kill(),
+            Some(Token::Keyword(Keyword::Revoke)) => self.parse_statement_grant(),
             Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select(),
+            Some(Token::Keyword(Keyword::Set)) => self.parse_statement_set(),
+            Some(Token::Keyword(Keyword::Show)) => self.parse_statement_show(),
+            Some(Token::Keyword(Keyword::Use)) => self.parse_statement_use(),
             Some(token) => Err(Error::Parse(format!("Unexpected token {}", token))),
             None => Err(Error::Parse("Unexpected end of input".into())),
         }
@@ -113,7 +150,14 @@ impl<'a> Parser<'a> {
     fn parse_ddl(&mut self) -> Result<ast::Statement, Error> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
-                Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Database) => {
+                    Ok(ast::Statement::CreateDatabase(self.next_ident()?))
+                }
+                Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(false),
+                Token::Keyword(Keyword::Temporary) => {
+                    self.next_expect(Some(Keyword::Table.into()))?;
+                    self.parse_ddl_create_table(true)
+                }
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             Token::Keyword(Keyword::Drop) => match self.next()? {
@@ -124,9 +168,9 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Parses a CREATE TABLE DDL statement. The CREATE TABLE prefix has
-    /// already been consumed.
-    fn parse_ddl_create_table(&mut self) -> Result<ast::Statement, Error> {
+    /// Parses a CREATE [TEMPORARY] TABLE DDL statement. The CREATE TABLE
+    /// prefix has already been consumed.
+    fn parse_ddl_create_table(&mut self, temporary: bool) -> Result<ast::Statement, Error> {
         let name = self.next_ident()?;
         self.next_expect(Some(Token::OpenParen))?;
 
@@ -138,7 +182,11 @@ impl<'a> Parser<'a> {
             }
         }
         self.next_expect(Some(Token::CloseParen))?;
-        Ok(ast::Statement::CreateTable { name, columns })
+        Ok(ast::Statement::CreateTable {
+            name,
+            columns,
+            temporary,
+        })
     }
 
     /// Parses a DROP TABLE DDL statement. The DROP TABLE prefix has
@@ -232,13 +280,158 @@ impl<'a> Parser<'a> {
             }
         }
 
+        let ignore_conflicts = self.next_if_token(Keyword::On.into()).is_some();
+        if ignore_conflicts {
+            self.next_expect(Some(Keyword::Conflict.into()))?;
+            self.next_expect(Some(Keyword::Do.into()))?;
+            self.next_expect(Some(Keyword::Nothing.into()))?;
+        }
+
         Ok(ast::Statement::Insert {
             table,
             columns,
             values,
+            ignore_conflicts,
         })
     }
 
+    /// Parses a kill statement
+    fn parse_statement_kill(&mut self) -> Result<ast::Statement, Error> {
+        self.next_expect(Some(Keyword::Kill.into()))?;
+        match self.next()? {
+            Token::Number(n) => Ok(ast::Statement::Kill(n.parse()?)),
+            token => Err(Error::Parse(format!("Expected query ID, found {}", token))),
+        }
+    }
+
+    /// Parses a GRANT or REVOKE statement, i.e. GRANT privilege [(column
+    /// [, ...])] [, ...] ON [TABLE] table TO user, or REVOKE ... FROM user
+    fn parse_statement_grant(&mut self) -> Result<ast::Statement, Error> {
+        let grant = match self.next()? {
+            Token::Keyword(Keyword::Grant) => true,
+            Token::Keyword(Keyword::Revoke) => false,
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        };
+        let privileges = if self.next_if_token(Keyword::All.into()).is_some() {
+            self.next_if_token(Keyword::Privileges.into());
+            let privileges: &[Privilege] = if grant {
+                &Privilege::GRANTABLE
+            } else {
+                &Privilege::ALL
+            };
+            privileges.iter().map(|&p| p.into()).collect()
+        } else {
+            let mut privileges = Vec::new();
+            loop {
+                let privilege = match self.next()? {
+                    Token::Keyword(Keyword::Select) => Privilege::Select,
+                    Token::Keyword(Keyword::Insert) => Privilege::Insert,
+                    Token::Keyword(Keyword::Update) => Privilege::Update,
+                    Token::Keyword(Keyword::Delete) => Privilege::Delete,
+                    token => {
+                        return Err(Error::Parse(format!("Expected privilege, found {}", token)))
+                    }
+                };
+                if grant && !Privilege::GRANTABLE.contains(&privilege) {
+                    return Err(Error::Parse(format!(
+                        "Can't grant {} privileges, since there are no {} statements yet",
+                        privilege, privilege
+                    )));
+                }
+                let columns = if self.next_if_token(Token::OpenParen).is_some() {
+                    if privilege == Privilege::Delete {
+                        return Err(Error::Parse(
+                            "DELETE privileges apply to whole rows, not columns".into(),
+                        ));
+                    }
+                    let mut columns = Vec::new();
+                    loop {
+                        columns.push(self.next_ident()?);
+                        match self.next()? {
+                            Token::CloseParen => break,
+                            Token::Comma => {}
+                            token => {
+                                return Err(Error::Parse(format!("Unexpected token {}", token)))
+                            }
+                        }
+                    }
+                    Some(columns)
+                } else {
+                    None
+                };
+                privileges.push(ast::PrivilegeSpec { privilege, columns });
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+            privileges
+        };
+        self.next_expect(Some(Keyword::On.into()))?;
+        self.next_if_token(Keyword::Table.into());
+        let table = self.next_ident()?;
+        if grant {
+            self.next_expect(Some(Keyword::To.into()))?;
+            Ok(ast::Statement::Grant {
+                privileges,
+                table,
+                user: self.next_ident()?,
+            })
+        } else {
+            self.next_expect(Some(Keyword::From.into()))?;
+            Ok(ast::Statement::Revoke {
+                privileges,
+                table,
+                user: self.next_ident()?,
+            })
+        }
+    }
+
+    /// Parses an explain statement
+    fn parse_statement_explain(&mut self) -> Result<ast::Statement, Error> {
+        self.next_expect(Some(Keyword::Explain.into()))?;
+        let analyze = self.next_if_token(Keyword::Analyze.into()).is_some();
+        if let Some(Token::Keyword(Keyword::Explain)) = self.peek()? {
+            return Err(Error::Parse("Can't explain an EXPLAIN statement".into()));
+        }
+        Ok(ast::Statement::Explain {
+            statement: Box::new(self.parse_statement()?),
+            analyze,
+        })
+    }
+
+    /// Parses a set statement
+    fn parse_statement_set(&mut self) -> Result<ast::Statement, Error> {
+        self.next_expect(Some(Keyword::Set.into()))?;
+        let variable = self.next_ident()?;
+        // SET ROLE READONLY is shorthand for SET role = 'readonly'
+        if variable.eq_ignore_ascii_case("role") {
+            if let Some(Token::Ident(role)) = self.peek()? {
+                self.next()?;
+                return Ok(ast::Statement::Set {
+                    variable,
+                    value: ast::Literal::String(role.to_lowercase()).into(),
+                });
+            }
+        }
+        self.next_expect(Some(Token::Equals))?;
+        Ok(ast::Statement::Set {
+            variable,
+            value: self.parse_expression(0)?,
+        })
+    }
+
+    /// Parses a show statement
+    fn parse_statement_show(&mut self) -> Result<ast::Statement, Error> {
+        self.next_expect(Some(Keyword::Show.into()))?;
+        Ok(ast::Statement::Show(self.next_ident()?))
+    }
+
+    /// Parses a use statement
+    fn parse_statement_use(&mut self) -> Result<ast::Statement, Error> {
+        self.next_expect(Some(Keyword::Use.into()))?;
+        Ok(ast::Statement::Use(self.next_ident()?))
+    }
+
     /// Parses a select statement
     fn parse_statement_select(&mut self) -> Result<ast::Statement, Error> {
         Ok(ast::Statement::Select {
@@ -282,33 +475,68 @@ impl<'a> Parser<'a> {
             return Ok(None);
         }
         let mut clause = ast::FromClause { tables: Vec::new() };
-        clause.tables.push(self.next_ident()?);
+        let mut table = self.next_ident()?;
+        while self.next_if_token(Token::Period).is_some() {
+            table = format!("{}.{}", table, self.next_ident()?);
+        }
+        clause.tables.push(table);
         Ok(Some(clause))
     }
 
     /// Parses an expression consisting of at least one atom operated on by any
     /// number of operators, using the precedence climbing algorithm.
     fn parse_expression(&mut self, min_prec: u8) -> Result<ast::Expression, Error> {
+        if self.depth == 0 {
+            self.nodes = 0;
+        }
+        self.depth += 1;
+        let result = self.parse_expression_operators(min_prec);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses the operators and atoms of an expression
+    fn parse_expression_operators(&mut self, min_prec: u8) -> Result<ast::Expression, Error> {
         let mut lhs = if let Some(prefix) = self.next_if_operator::<PrefixOperator>(min_prec) {
+            self.add_expression_node()?;
             prefix.build(self.parse_expression(prefix.prec() + prefix.assoc())?)
         } else {
             self.parse_expression_atom()?
         };
         while let Some(postfix) = self.next_if_operator::<PostfixOperator>(min_prec) {
+            self.add_expression_node()?;
             lhs = postfix.build(lhs)
         }
         while let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec) {
+            self.add_expression_node()?;
             lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?)
         }
         Ok(lhs)
     }
 
+    /// Counts a node of the current expression, erroring if it's too large
+    fn add_expression_node(&mut self) -> Result<(), Error> {
+        self.nodes += 1;
+        if self.nodes > MAX_EXPRESSION_NODES {
+            return Err(Error::Parse(format!(
+                "Expression too large, limit is {} operators and values",
+                MAX_EXPRESSION_NODES
+            )));
+        }
+        Ok(())
+    }
+
     /// Parses an expression atom
     fn parse_expression_atom(&mut self) -> Result<ast::Expression, Error> {
+        self.add_expression_node()?;
         Ok(match self.next()? {
             Token::Number(n) => {
                 if n.chars().all(|c| c.is_digit(10)) {
-                    ast::Literal::Integer(n.parse()?).into()
+                    ast::Literal::Integer(
+                        n.parse()
+                            .map_err(|_| Error::Parse(format!("Integer {} is out of range", n)))?,
+                    )
+                    .into()
                 } else {
                     ast::Literal::Float(n.parse()?).into()
                 }
@@ -317,6 +545,15 @@ impl<'a> Parser<'a> {
             Token::Keyword(Keyword::False) => ast::Literal::Boolean(false).into(),
             Token::Keyword(Keyword::Null) => ast::Literal::Null.into(),
             Token::Keyword(Keyword::True) => ast::Literal::Boolean(true).into(),
+            Token::Question => {
+                self.parameters += 1;
+                ast::Expression::Parameter(self.parameters - 1)
+            }
+            Token::OpenParen => {
+                let expr = self.parse_expression(0)?;
+                self.next_expect(Some(Token::CloseParen))?;
+                expr
+            }
             t => {
                 return Err(Error::Parse(format!(
                     "Expected expression atom, found {}",
@@ -422,6 +659,7 @@ impl Operator for InfixOperator {
         Some(match token {
             Token::Asterisk => Self::Multiply,
             Token::Caret => Self::Exponentiate,
+            Token::Equals => Self::CompareEQ,
             Token::GreaterThan => Self::CompareGT,
             Token::GreaterThanOrEqual => Self::CompareGTE,
             Token::Keyword(Keyword::And) => Self::And,
@@ -487,3 +725,301 @@ impl Operator for PostfixOperator {
         8
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Generates identifiers, excluding keywords
+    fn ident() -> impl Strategy<Value = String> {
+        "[a-zA-Z][a-zA-Z0-9_]{0,10}".prop_filter("keyword", |s| {
+            Lexer::new(s).next() == Some(Ok(Token::Ident(s.clone())))
+        })
+    }
+
+    /// Generates literals. Numbers are non-negative, since the parser produces
+    /// negative numbers as negation operations.
+    fn literal() -> impl Strategy<Value = ast::Literal> {
+        prop_oneof![
+            Just(ast::Literal::Null),
+            any::<bool>().prop_map(ast::Literal::Boolean),
+            (0..=i64::MAX).prop_map(ast::Literal::Integer),
+            (0.0..f64::MAX).prop_map(ast::Literal::Float),
+            ".*".prop_map(ast::Literal::String),
+        ]
+    }
+
+    /// Generates expression trees
+    fn expression() -> impl Strategy<Value = ast::Expression> {
+        use ast::Operation::*;
+        literal()
+            .prop_map(ast::Expression::Literal)
+            .prop_recursive(4, 32, 2, |inner| {
+                let unary = inner.clone().prop_map(Box::new);
+                let binary = (inner.clone(), inner).prop_map(|(l, r)| (Box::new(l), Box::new(r)));
+                prop_oneof![
+                    unary.clone().prop_map(Not),
+                    unary.clone().prop_map(Negate),
+                    unary.prop_map(Factorial),
+                    binary.clone().prop_map(|(l, r)| And(l, r)),
+                    binary.clone().prop_map(|(l, r)| Or(l, r)),
+                    binary.clone().prop_map(|(l, r)| CompareEQ(l, r)),
+                    binary.clone().prop_map(|(l, r)| CompareGT(l, r)),
+                    binary.clone().prop_map(|(l, r)| CompareGTE(l, r)),
+                    binary.clone().prop_map(|(l, r)| CompareLT(l, r)),
+                    binary.clone().prop_map(|(l, r)| CompareLTE(l, r)),
+                    binary.clone().prop_map(|(l, r)| CompareNE(l, r)),
+                    binary.clone().prop_map(|(l, r)| Add(l, r)),
+                    binary.clone().prop_map(|(l, r)| Divide(l, r)),
+                    binary.clone().prop_map(|(l, r)| Exponentiate(l, r)),
+                    binary.clone().prop_map(|(l, r)| Modulo(l, r)),
+                    binary.clone().prop_map(|(l, r)| Multiply(l, r)),
+                    binary.prop_map(|(l, r)| Subtract(l, r)),
+                ]
+                .prop_map(ast::Expression::Operation)
+            })
+    }
+
+    /// Generates column specifications
+    fn columnspec() -> impl Strategy<Value = ast::ColumnSpec> {
+        (
+            ident(),
+            prop_oneof![
+                Just(DataType::Boolean),
+                Just(DataType::Integer),
+                Just(DataType::Float),
+                Just(DataType::String),
+            ],
+            any::<bool>(),
+            proptest::option::of(any::<bool>()),
+        )
+            .prop_map(|(name, datatype, primary_key, nullable)| ast::ColumnSpec {
+                name,
+                datatype,
+                primary_key,
+                nullable,
+            })
+    }
+
+    /// Generates statements
+    fn statement() -> impl Strategy<Value = ast::Statement> {
+        let select = proptest::collection::vec((expression(), proptest::option::of(ident())), 0..4)
+            .prop_map(|exprs| {
+                let (expressions, labels) = exprs.into_iter().unzip();
+                ast::SelectClause {
+                    expressions,
+                    labels,
+                }
+            });
+        let from = proptest::collection::vec(ident(), 1..3).prop_map(|parts| ast::FromClause {
+            tables: vec![parts.join(".")],
+        });
+        let statement = prop_oneof![
+            (
+                ident(),
+                proptest::collection::vec(columnspec(), 1..4),
+                any::<bool>()
+            )
+                .prop_map(|(name, columns, temporary)| ast::Statement::CreateTable {
+                    name,
+                    columns,
+                    temporary
+                }),
+            ident().prop_map(ast::Statement::DropTable),
+            any::<u64>().prop_map(ast::Statement::Kill),
+            (
+                ident(),
+                proptest::option::of(proptest::collection::vec(ident(), 1..4)),
+                proptest::collection::vec(proptest::collection::vec(expression(), 1..4), 1..3),
+                any::<bool>(),
+            )
+                .prop_map(|(table, columns, values, ignore_conflicts)| {
+                    ast::Statement::Insert {
+                        table,
+                        columns,
+                        values,
+                        ignore_conflicts,
+                    }
+                }),
+            (select, proptest::option::of(from))
+                .prop_map(|(select, from)| ast::Statement::Select { select, from }),
+            (ident(), expression())
+                .prop_map(|(variable, value)| ast::Statement::Set { variable, value }),
+            ident().prop_map(ast::Statement::Show),
+            ident().prop_map(ast::Statement::CreateDatabase),
+            ident().prop_map(ast::Statement::Use),
+            (
+                proptest::collection::vec(
+                    (
+                        proptest::sample::select(&Privilege::ALL[..]),
+                        proptest::option::of(proptest::collection::vec(ident(), 1..3)),
+                    ),
+                    1..4,
+                ),
+                ident(),
+                ident(),
+                any::<bool>(),
+            )
+                .prop_map(|(privileges, table, user, grant)| {
+                    // Only SELECT and INSERT can be granted, and DELETE
+                    // only on whole tables.
+                    let privileges = privileges
+                        .into_iter()
+                        .map(|(privilege, columns)| {
+                            let privilege = match privilege {
+                                Privilege::Update if grant => Privilege::Select,
+                                Privilege::Delete if grant => Privilege::Insert,
+                                privilege => privilege,
+                            };
+                            ast::PrivilegeSpec {
+                                privilege,
+                                columns: columns.filter(|_| privilege != Privilege::Delete),
+                            }
+                        })
+                        .collect();
+                    if grant {
+                        ast::Statement::Grant {
+                            privileges,
+                            table,
+                            user,
+                        }
+                    } else {
+                        ast::Statement::Revoke {
+                            privileges,
+                            table,
+                            user,
+                        }
+                    }
+                }),
+        ];
+        (statement, proptest::option::of(any::<bool>())).prop_map(|(statement, explain)| {
+            match explain {
+                Some(analyze) => ast::Statement::Explain {
+                    statement: Box::new(statement),
+                    analyze,
+                },
+                None => statement,
+            }
+        })
+    }
+
+    #[test]
+    fn format() {
+        let cases = &[
+            ("select 1+2*3", "SELECT 1 + 2 * 3"),
+            ("SELECT (1 + 2) * 3", "SELECT (1 + 2) * 3"),
+            (
+                "SELECT 1 - (2 - 3), (1 - 2) - 3",
+                "SELECT 1 - (2 - 3), 1 - 2 - 3",
+            ),
+            (
+                "SELECT 2 ^ (3 ^ 4), (2 ^ 3) ^ 4",
+                "SELECT 2 ^ 3 ^ 4, (2 ^ 3) ^ 4",
+            ),
+            (
+                "SELECT - - 1, -(1 + 2), (-1)!, -(1!)",
+                "SELECT -(-1), -(1 + 2), -1!, -(1!)",
+            ),
+            (
+                "SELECT !TRUE OR (FALSE AND TRUE)",
+                "SELECT NOT TRUE OR FALSE AND TRUE",
+            ),
+            (
+                "SELECT 'it''s' a, 1.0 AS b, 3.14e3",
+                "SELECT 'it''s' AS a, 1.0 AS b, 3140.0",
+            ),
+            (
+                "SELECT * FROM system.sessions",
+                "SELECT * FROM system.sessions",
+            ),
+            (
+                "create table t (id integer primary key, v varchar not null)",
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, v VARCHAR NOT NULL)",
+            ),
+            (
+                "insert into t (id, v) values (1, 'a'), (2, NULL)",
+                "INSERT INTO t (id, v) VALUES (1, 'a'), (2, NULL)",
+            ),
+            (
+                "insert into t values (1) on conflict do nothing",
+                "INSERT INTO t VALUES (1) ON CONFLICT DO NOTHING",
+            ),
+            ("set max_rows = 10", "SET max_rows = 10"),
+            ("set role ReadOnly", "SET role = 'readonly'"),
+            ("show TimeZone", "SHOW TimeZone"),
+            ("create database db", "CREATE DATABASE db"),
+            (
+                "create temporary table t (id integer primary key)",
+                "CREATE TEMPORARY TABLE t (id INTEGER PRIMARY KEY)",
+            ),
+            ("use db", "USE db"),
+            ("drop table t;", "DROP TABLE t"),
+            (
+                "create database db; drop table t;",
+                "CREATE DATABASE db; DROP TABLE t",
+            ),
+            (
+                "explain analyze select * from t",
+                "EXPLAIN ANALYZE SELECT * FROM t",
+            ),
+            (
+                "grant select, insert on table t to alice",
+                "GRANT SELECT, INSERT ON t TO alice",
+            ),
+            (
+                "revoke all privileges on t from alice",
+                "REVOKE SELECT, INSERT, UPDATE, DELETE ON t FROM alice",
+            ),
+            (
+                "grant all on t to alice",
+                "GRANT SELECT, INSERT ON t TO alice",
+            ),
+            (
+                "grant select (id,title), insert on t to alice",
+                "GRANT SELECT (id, title), INSERT ON t TO alice",
+            ),
+        ];
+        for (query, expect) in cases {
+            assert_eq!(*expect, Parser::new(query).parse().unwrap().to_string());
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_expression(expr in expression()) {
+            let query = format!("SELECT {}", expr);
+            match Parser::new(&query).parse() {
+                Ok(ast::Statement::Select { mut select, .. }) => {
+                    prop_assert_eq!(vec![expr], std::mem::take(&mut select.expressions))
+                }
+                result => prop_assert!(false, "{} parsed as {:?}", query, result),
+            }
+        }
+
+        #[test]
+        fn roundtrip_statement(statement in statement()) {
+            let query = statement.to_string();
+            let parsed = Parser::new(&query).parse();
+            prop_assert_eq!(Ok(statement), parsed, "{}", query);
+        }
+
+        #[test]
+        fn parse_no_panic(query in "\\PC{0,64}") {
+            let _ = Parser::new(&query).parse();
+        }
+
+        #[test]
+        fn parse_nested(depth in 0..512_usize) {
+            // Deeply nested expressions fail with an error rather than overflowing the stack
+            let result = Parser::new(&format!("SELECT {}1{}", "(".repeat(depth), ")".repeat(depth))).parse();
+            prop_assert_eq!(depth < MAX_EXPRESSION_NODES, result.is_ok());
+        }
+
+        #[test]
+        fn parse_numbers(n in "[0-9]{1,25}(\\.[0-9]{0,5})?([eE][+-]?[0-9]{0,3})?") {
+            // Numbers either parse as a literal or fail with an error
+            let _ = Parser::new(&format!("SELECT {}", n)).parse();
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use super::super::types::Row;
+use super::{Batch, Context, Node, BATCH_SIZE};
+use crate::Error;
+
+/// A scan node for the system.audit virtual table, listing recent audit log
+/// entries
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Audit {
+    #[derivative(Debug = "ignore")]
+    rows: Option<std::vec::IntoIter<Row>>,
+}
+
+impl Audit {
+    pub fn new() -> Self {
+        Self { rows: None }
+    }
+}
+
+impl Node for Audit {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.authorize_admin("read the audit log")?;
+        self.rows = Some(ctx.audit.list()?.into_iter());
+        Ok(())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        let batch: Batch = match self.rows {
+            Some(ref mut rows) => rows.take(BATCH_SIZE).collect(),
+            None => return Ok(None),
+        };
+        if batch.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(batch))
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A statement batch node, which executes DDL statements against a buffered
+/// storage and commits their writes as a single store write batch, i.e. a
+/// single Raft command. If a statement fails, nothing is written.
+#[derive(Debug)]
+pub struct StatementBatch {
+    nodes: Vec<Box<dyn Node>>,
+}
+
+impl StatementBatch {
+    pub fn new(nodes: Vec<Box<dyn Node>>) -> Self {
+        Self { nodes }
+    }
+}
+
+impl Node for StatementBatch {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let batch = ctx.storage.batch();
+        let storage = std::mem::replace(&mut *ctx.storage, batch);
+        let result: Result<(), Error> = self.nodes.iter_mut().try_for_each(|node| {
+            node.execute(ctx)?;
+            while node.next_batch()?.is_some() {}
+            Ok(())
+        });
+        let batch = std::mem::replace(&mut *ctx.storage, storage);
+        result?;
+        batch.commit()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
+
+    fn children(&self) -> Vec<&dyn Node> {
+        self.nodes.iter().map(|node| &**node).collect()
+    }
+}
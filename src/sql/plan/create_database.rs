@@ -0,0 +1,25 @@
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A CREATE DATABASE node
+#[derive(Debug)]
+pub struct CreateDatabase {
+    name: String,
+}
+
+impl CreateDatabase {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+impl Node for CreateDatabase {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.authorize_admin("create databases")?;
+        ctx.storage.create_database(&self.name)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
+}
@@ -1,30 +1,35 @@
 use super::super::schema;
-use super::super::types::Row;
-use super::{Context, Node};
+use super::{Batch, Context, Node};
 use crate::Error;
 
 /// A CREATE TABLE node
 #[derive(Debug)]
 pub struct CreateTable {
     schema: schema::Table,
+    temporary: bool,
 }
 
 impl CreateTable {
-    pub fn new(schema: schema::Table) -> Self {
-        Self { schema }
+    pub fn new(schema: schema::Table, temporary: bool) -> Self {
+        Self { schema, temporary }
     }
 }
 
 impl Node for CreateTable {
     fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
-        ctx.storage.create_table(&self.schema)
+        if self.temporary {
+            ctx.temporary.create_table(&self.schema)
+        } else {
+            ctx.authorize_admin("create tables")?;
+            ctx.storage.create_table(&self.schema)
+        }
     }
-}
 
-impl Iterator for CreateTable {
-    type Item = Result<Row, Error>;
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        None
+    fn describe(&self) -> String {
+        format!("CreateTable {}", self.schema.name)
     }
 }
@@ -1,5 +1,4 @@
-use super::super::types::Row;
-use super::{Context, Node};
+use super::{Batch, Context, Node};
 use crate::Error;
 
 /// A CREATE TABLE node
@@ -16,14 +15,17 @@ impl DropTable {
 
 impl Node for DropTable {
     fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
-        ctx.storage.drop_table(&self.table)
+        if !ctx.temporary.table_exists(&self.table)? {
+            ctx.authorize_admin("drop tables")?;
+        }
+        ctx.table_storage(&self.table)?.drop_table(&self.table)
     }
-}
 
-impl Iterator for DropTable {
-    type Item = Result<Row, Error>;
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        None
+    fn describe(&self) -> String {
+        format!("DropTable {}", self.table)
     }
 }
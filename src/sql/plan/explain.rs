@@ -0,0 +1,111 @@
+use super::super::types::Value;
+use super::{Batch, Context, Node};
+use crate::Error;
+use std::time::{Duration, Instant};
+
+/// An EXPLAIN node, producing the plan tree of its source node with one row
+/// per node. With ANALYZE, the source is executed to completion first, and
+/// each node is annotated with the rows and batches it produced and the time
+/// spent in it, including its children.
+#[derive(Debug)]
+pub struct Explain {
+    source: Box<dyn Node>,
+    analyze: bool,
+    lines: Option<Vec<String>>,
+}
+
+impl Explain {
+    pub fn new(source: Box<dyn Node>, analyze: bool) -> Self {
+        Self {
+            source,
+            analyze,
+            lines: None,
+        }
+    }
+}
+
+impl Node for Explain {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        if self.analyze {
+            self.source.execute(ctx)?;
+            while self.source.next_batch()?.is_some() {}
+        }
+        let mut lines = Vec::new();
+        format_tree(&*self.source, 0, &mut lines);
+        self.lines = Some(lines);
+        Ok(())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(self.lines.take().map(|lines| {
+            lines
+                .into_iter()
+                .map(|line| vec![Value::String(line)])
+                .collect()
+        }))
+    }
+}
+
+/// Formats a plan tree as indented lines, one per node
+fn format_tree(node: &dyn Node, depth: usize, lines: &mut Vec<String>) {
+    lines.push(format!("{}{}", "  ".repeat(depth), node.describe()));
+    for child in node.children() {
+        format_tree(child, depth + 1, lines);
+    }
+}
+
+/// A node wrapper for EXPLAIN ANALYZE, which records the rows and batches
+/// produced by a node and the time spent executing it. It is transparent to
+/// the plan tree, i.e. it describes itself as the wrapped node.
+#[derive(Debug)]
+pub struct Analyze {
+    source: Box<dyn Node>,
+    rows: u64,
+    batches: u64,
+    time: Duration,
+}
+
+impl Analyze {
+    pub fn new(source: Box<dyn Node>) -> Self {
+        Self {
+            source,
+            rows: 0,
+            batches: 0,
+            time: Duration::default(),
+        }
+    }
+}
+
+impl Node for Analyze {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        let start = Instant::now();
+        let result = self.source.execute(ctx);
+        self.time += start.elapsed();
+        result
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        let start = Instant::now();
+        let result = self.source.next_batch();
+        self.time += start.elapsed();
+        if let Ok(Some(ref batch)) = result {
+            self.rows += batch.len() as u64;
+            self.batches += 1;
+        }
+        result
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} (rows={} batches={} time={:.3}ms)",
+            self.source.describe(),
+            self.rows,
+            self.batches,
+            self.time.as_secs_f64() * 1000.0
+        )
+    }
+
+    fn children(&self) -> Vec<&dyn Node> {
+        self.source.children()
+    }
+}
@@ -0,0 +1,50 @@
+use super::super::ast::PrivilegeSpec;
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A GRANT or REVOKE node, changing a user's privileges on a table
+#[derive(Debug)]
+pub struct Grant {
+    privileges: Vec<PrivilegeSpec>,
+    table: String,
+    user: String,
+    revoke: bool,
+}
+
+impl Grant {
+    pub fn new(privileges: Vec<PrivilegeSpec>, table: String, user: String, revoke: bool) -> Self {
+        Self {
+            privileges,
+            table,
+            user,
+            revoke,
+        }
+    }
+}
+
+impl Node for Grant {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.authorize_admin("change privileges")?;
+        if ctx.temporary.table_exists(&self.table)? {
+            return Err(Error::Value(format!(
+                "Can't change privileges on temporary table {}",
+                self.table
+            )));
+        }
+        if self.revoke {
+            ctx.storage
+                .revoke(&self.table, &self.user, &self.privileges)
+        } else {
+            ctx.storage.grant(&self.table, &self.user, &self.privileges)
+        }
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
+
+    fn describe(&self) -> String {
+        let action = if self.revoke { "Revoke" } else { "Grant" };
+        format!("{} {}", action, self.table)
+    }
+}
@@ -1,5 +1,6 @@
+use super::super::schema::Privilege;
 use super::super::types::Row;
-use super::{Context, Node};
+use super::{Batch, Context, Node};
 use crate::sql::expression::Expressions;
 use crate::Error;
 
@@ -8,31 +9,40 @@ use crate::Error;
 pub struct Insert {
     table: String,
     expressions: Vec<Expressions>,
+    ignore_conflicts: bool,
 }
 
 impl Insert {
-    pub fn new(table: String, expressions: Vec<Expressions>) -> Self {
-        Self { table, expressions }
+    pub fn new(table: String, expressions: Vec<Expressions>, ignore_conflicts: bool) -> Self {
+        Self {
+            table,
+            expressions,
+            ignore_conflicts,
+        }
     }
 }
 
 impl Node for Insert {
     fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.authorize(&self.table, Privilege::Insert)?;
+        let mut rows = Vec::with_capacity(self.expressions.len());
         for exprs in &self.expressions {
+            ctx.cancellation.check()?;
             let mut row = Row::new();
             for expr in exprs {
                 row.push(expr.evaluate()?);
             }
-            ctx.storage.create_row(&self.table, row)?;
+            rows.push(row);
         }
-        Ok(())
+        ctx.table_storage(&self.table)?
+            .create_rows(&self.table, rows, self.ignore_conflicts)
     }
-}
 
-impl Iterator for Insert {
-    type Item = Result<Row, Error>;
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        None
+    fn describe(&self) -> String {
+        format!("Insert {}", self.table)
     }
 }
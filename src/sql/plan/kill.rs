@@ -0,0 +1,25 @@
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A KILL node
+#[derive(Debug)]
+pub struct Kill {
+    query_id: u64,
+}
+
+impl Kill {
+    pub fn new(query_id: u64) -> Self {
+        Self { query_id }
+    }
+}
+
+impl Node for Kill {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.authorize_admin("kill queries")?;
+        ctx.sessions.kill(self.query_id)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
+}
@@ -1,22 +1,44 @@
+mod audit;
+mod batch;
+mod create_database;
 mod create_table;
 mod drop_table;
+mod explain;
+mod grant;
 mod insert;
+mod kill;
 mod nothing;
 mod projection;
 mod scan;
+mod sessions;
+mod set;
+mod show;
+mod use_database;
 
+use self::audit::Audit;
 use self::nothing::Nothing;
 use self::projection::Projection;
 use self::scan::Scan;
+use self::sessions::Sessions;
+use self::set::Set;
+use self::show::Show;
 use super::ast::{self, ColumnSpec, Statement};
+use super::audit::{AuditLog, AUDIT_TABLE};
 use super::expression::Expression;
-use super::schema::{Column, Table};
+use super::schema::{Column, Privilege, Table};
+use super::session::{Cancellation, Progress, Registry, User, Variables, SESSIONS_TABLE};
 use super::storage::Storage;
 use super::types::{Row, Value};
 use crate::Error;
+use batch::StatementBatch;
+use create_database::CreateDatabase;
 use create_table::CreateTable;
 use drop_table::DropTable;
+use explain::{Analyze, Explain};
+use grant::Grant;
 use insert::Insert;
+use kill::Kill;
+use use_database::UseDatabase;
 
 /// A plan
 #[derive(Debug)]
@@ -30,9 +52,16 @@ impl Plan {
         Planner::new().build(statement)
     }
 
+    /// Executes the plan in the session's current database
     pub fn execute(mut self, mut context: Context) -> Result<ResultSet, Error> {
+        let database = context.variables.database()?;
+        *context.storage = context.storage.database(&database);
         self.root.execute(&mut context)?;
-        Ok(ResultSet { root: self.root })
+        Ok(ResultSet {
+            root: self.root,
+            batch: Vec::new().into_iter(),
+            done: false,
+        })
     }
 }
 
@@ -40,27 +69,142 @@ impl Plan {
 pub struct Context {
     /// The underlying storage
     pub storage: Box<Storage>,
+    /// The registry of running queries
+    pub sessions: Registry,
+    /// The audit log of administrative actions
+    pub audit: AuditLog,
+    /// The progress counters of the running query
+    pub progress: Progress,
+    /// The cancellation token of the running query
+    pub cancellation: Cancellation,
+    /// The session variables of the query's client
+    pub variables: Variables,
+    /// The temporary tables of the query's client
+    pub temporary: Storage,
+    /// The authenticated user running the query, or None if authentication
+    /// is disabled, in which case privileges aren't checked
+    pub user: Option<User>,
+}
+
+impl Context {
+    /// Returns the storage holding a table. Temporary tables shadow regular
+    /// tables with the same name.
+    pub fn table_storage(&mut self, table: &str) -> Result<&mut Storage, Error> {
+        if self.temporary.table_exists(table)? {
+            Ok(&mut self.temporary)
+        } else {
+            Ok(&mut self.storage)
+        }
+    }
+
+    /// Errors unless the user holds a privilege on a table, or on all of its
+    /// columns, since statements read and write whole rows. Temporary tables
+    /// are private to the session, so need no privileges.
+    pub fn authorize(&self, table: &str, privilege: Privilege) -> Result<(), Error> {
+        let user = match &self.user {
+            Some(user) if !user.admin => user,
+            _ => return Ok(()),
+        };
+        if self.temporary.table_exists(table)? {
+            return Ok(());
+        }
+        let schema = self.storage.get_table(table)?;
+        let columns: Vec<&str> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+        if schema.is_granted(&user.name, privilege, &columns) {
+            Ok(())
+        } else {
+            Err(Error::Value(format!(
+                "Permission denied: user {} lacks {} privilege on table {}",
+                user.name, privilege, table
+            )))
+        }
+    }
+
+    /// Errors unless the user is an administrator
+    pub fn authorize_admin(&self, action: &str) -> Result<(), Error> {
+        match &self.user {
+            Some(user) if !user.admin => Err(Error::Value(format!(
+                "Permission denied: user {} can't {}, only administrators can",
+                user.name, action
+            ))),
+            _ => Ok(()),
+        }
+    }
 }
 
-/// A plan execution result
+/// The maximum number of rows in a batch
+pub const BATCH_SIZE: usize = 1024;
+
+/// A batch of rows, passed between plan nodes
+pub type Batch = Vec<Row>;
+
+/// A plan execution result, which adapts the plan's row batches into a row
+/// iterator. Iteration stops after the first error.
 pub struct ResultSet {
     root: Box<dyn Node>,
+    batch: std::vec::IntoIter<Row>,
+    done: bool,
+}
+
+impl ResultSet {
+    /// Returns the next batch of rows, or None when exhausted.
+    pub fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        if self.batch.len() > 0 {
+            return Ok(Some(std::mem::take(&mut self.batch).collect()));
+        }
+        if self.done {
+            return Ok(None);
+        }
+        match self.root.next_batch() {
+            Ok(None) => {
+                self.done = true;
+                Ok(None)
+            }
+            Err(err) => {
+                self.done = true;
+                Err(err)
+            }
+            batch => batch,
+        }
+    }
 }
 
 impl Iterator for ResultSet {
     type Item = Result<Row, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.root.next()
+        loop {
+            if let Some(row) = self.batch.next() {
+                return Some(Ok(row));
+            }
+            match self.next_batch() {
+                Ok(Some(batch)) => self.batch = batch.into_iter(),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
     }
 }
 
-/// A plan node
-pub trait Node:
-    Iterator<Item = Result<Row, Error>> + std::fmt::Debug + Send + Sync + 'static
-{
+/// A plan node, which produces rows in batches
+pub trait Node: std::fmt::Debug + Send + Sync + 'static {
     /// Execute starts execution of the plan
     fn execute(&mut self, ctx: &mut Context) -> Result<(), Error>;
+
+    /// Returns the next batch of at most BATCH_SIZE rows, or None when
+    /// exhausted. Batches are never empty.
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error>;
+
+    /// Returns a one-line description of the node, for EXPLAIN
+    fn describe(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+
+    /// Returns the node's child nodes, for EXPLAIN
+    fn children(&self) -> Vec<&dyn Node> {
+        Vec::new()
+    }
 }
 
 impl<N: Node> From<N> for Box<dyn Node> {
@@ -69,16 +213,27 @@ impl<N: Node> From<N> for Box<dyn Node> {
     }
 }
 /// The plan builder
-struct Planner {}
+struct Planner {
+    /// Whether to instrument nodes for EXPLAIN ANALYZE
+    analyze: bool,
+}
 
 impl Planner {
     /// Creates a new planner
     pub fn new() -> Self {
-        Self {}
+        Self { analyze: false }
     }
 
     /// Builds a plan tree for an AST statement
-    pub fn build(&self, statement: Statement) -> Result<Plan, Error> {
+    pub fn build(&self, mut statement: Statement) -> Result<Plan, Error> {
+        let mut unbound = false;
+        statement
+            .walk_expressions(&mut |expr| unbound |= matches!(expr, ast::Expression::Parameter(_)));
+        if unbound {
+            return Err(Error::Value(
+                "Query parameters must be bound to values before execution".into(),
+            ));
+        }
         Ok(Plan {
             root: self.build_statement(statement)?,
         })
@@ -86,12 +241,52 @@ impl Planner {
 
     /// Builds a plan node for a statement
     fn build_statement(&self, statement: Statement) -> Result<Box<dyn Node>, Error> {
-        Ok(match statement {
-            Statement::CreateTable { name, columns } => {
-                CreateTable::new(self.build_schema_table(name, columns)?).into()
-            }
+        Ok(self.node(match statement {
+            Statement::Batch(statements) => StatementBatch::new(
+                statements
+                    .into_iter()
+                    .map(|statement| match statement {
+                        Statement::CreateDatabase(_)
+                        | Statement::CreateTable {
+                            temporary: false, ..
+                        }
+                        | Statement::DropTable(_) => self.build_statement(statement),
+                        statement => Err(Error::Value(format!(
+                            "Only CREATE DATABASE, CREATE TABLE and DROP TABLE can be batched, not {}",
+                            statement
+                        ))),
+                    })
+                    .collect::<Result<_, Error>>()?,
+            )
+            .into(),
+            Statement::CreateDatabase(name) => CreateDatabase::new(name).into(),
+            Statement::CreateTable {
+                name,
+                columns,
+                temporary,
+            } => CreateTable::new(self.build_schema_table(name, columns)?, temporary).into(),
             Statement::DropTable(name) => DropTable::new(name).into(),
-            Statement::Insert { table, values, .. } => {
+            Statement::Explain { statement, analyze } => {
+                let planner = Planner { analyze };
+                return Ok(Explain::new(planner.build_statement(*statement)?, analyze).into());
+            }
+            Statement::Grant {
+                privileges,
+                table,
+                user,
+            } => Grant::new(privileges, table, user, false).into(),
+            Statement::Kill(query_id) => Kill::new(query_id).into(),
+            Statement::Revoke {
+                privileges,
+                table,
+                user,
+            } => Grant::new(privileges, table, user, true).into(),
+            Statement::Insert {
+                table,
+                values,
+                ignore_conflicts,
+                ..
+            } => {
                 // FIXME Needs to handle columns
                 Insert::new(
                     table,
@@ -99,33 +294,52 @@ impl Planner {
                         .into_iter()
                         .map(|exprs| exprs.into_iter().map(|expr| expr.into()).collect())
                         .collect(),
+                    ignore_conflicts,
                 )
                 .into()
             }
             Statement::Select { select, from } => {
-                let mut n: Box<dyn Node> = match from {
+                let mut n = self.node(match from {
                     // FIXME Handle multiple FROM tables
+                    Some(ref from) if from.tables[0] == SESSIONS_TABLE => Sessions::new().into(),
+                    Some(ref from) if from.tables[0] == AUDIT_TABLE => Audit::new().into(),
                     Some(from) => Scan::new(from.tables[0].clone()).into(),
                     None if select.expressions.is_empty() => {
                         return Err(Error::Value("Can't select * without a table".into()))
                     }
                     None => Nothing::new().into(),
-                };
+                });
                 if !select.expressions.is_empty() {
-                    n = Projection::new(
-                        n,
-                        select
-                            .labels
-                            .into_iter()
-                            .map(|l| l.unwrap_or_else(|| "?".into()))
-                            .collect(),
-                        self.build_expressions(select.expressions)?,
-                    )
-                    .into();
+                    n = self.node(
+                        Projection::new(
+                            n,
+                            select
+                                .labels
+                                .into_iter()
+                                .map(|l| l.unwrap_or_else(|| "?".into()))
+                                .collect(),
+                            self.build_expressions(select.expressions)?,
+                        )
+                        .into(),
+                    );
                 };
-                n
+                return Ok(n);
             }
-        })
+            Statement::Set { variable, value } => {
+                Set::new(variable, self.build_expression(value)?).into()
+            }
+            Statement::Show(variable) => Show::new(variable).into(),
+            Statement::Use(name) => UseDatabase::new(name).into(),
+        }))
+    }
+
+    /// Prepares a built plan node, instrumenting it for EXPLAIN ANALYZE
+    fn node(&self, node: Box<dyn Node>) -> Box<dyn Node> {
+        if self.analyze {
+            Analyze::new(node).into()
+        } else {
+            node
+        }
     }
 
     /// Builds a plan expression from an AST expression
@@ -176,6 +390,9 @@ impl Planner {
                     nullable: spec.nullable.unwrap_or(!spec.primary_key),
                 })
                 .collect(),
+            version: 0,
+            grants: Default::default(),
+            column_grants: Default::default(),
         })
     }
 }
@@ -185,6 +402,8 @@ impl From<ast::Expression> for Expression {
     fn from(expr: ast::Expression) -> Self {
         match expr {
             ast::Expression::Literal(l) => Expression::Constant(l.into()),
+            // Rejected by Planner::build().
+            ast::Expression::Parameter(_) => Expression::Constant(Value::Null),
             ast::Expression::Operation(op) => match op {
                 // Logical operators
                 ast::Operation::And(lhs, rhs) => Self::And(lhs.into(), rhs.into()),
@@ -232,3 +451,89 @@ impl From<ast::Literal> for Value {
         }
     }
 }
+
+impl From<Value> for ast::Literal {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => ast::Literal::Null,
+            Value::Boolean(b) => ast::Literal::Boolean(b),
+            Value::Integer(i) => ast::Literal::Integer(i),
+            Value::Float(f) => ast::Literal::Float(f),
+            Value::String(s) => ast::Literal::String(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A test node producing a fixed sequence of batches
+    #[derive(Debug)]
+    struct Batches(Vec<Result<Batch, String>>);
+
+    impl Node for Batches {
+        fn execute(&mut self, _: &mut Context) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+            if self.0.is_empty() {
+                return Ok(None);
+            }
+            self.0.remove(0).map(Some).map_err(Error::Value)
+        }
+    }
+
+    fn result_set(batches: Vec<Result<Batch, String>>) -> ResultSet {
+        ResultSet {
+            root: Box::new(Batches(batches)),
+            batch: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    #[test]
+    fn result_set_rows() {
+        let rows = result_set(vec![
+            Ok(vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]),
+            Ok(vec![vec![Value::Integer(3)]]),
+        ])
+        .collect::<Result<Vec<Row>, Error>>()
+        .unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(3)]
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn result_set_error() {
+        let mut rs = result_set(vec![
+            Ok(vec![vec![Value::Integer(1)]]),
+            Err("failed".into()),
+            Ok(vec![vec![Value::Integer(2)]]),
+        ]);
+        assert_eq!(Some(Ok(vec![Value::Integer(1)])), rs.next());
+        assert_eq!(Some(Err(Error::Value("failed".into()))), rs.next());
+        assert_eq!(None, rs.next());
+    }
+
+    #[test]
+    fn result_set_batches() {
+        let mut rs = result_set(vec![Ok(vec![
+            vec![Value::Integer(1)],
+            vec![Value::Integer(2)],
+        ])]);
+        assert_eq!(Some(Ok(vec![Value::Integer(1)])), rs.next());
+        assert_eq!(
+            Some(vec![vec![Value::Integer(2)]]),
+            rs.next_batch().unwrap()
+        );
+        assert_eq!(None, rs.next_batch().unwrap());
+    }
+}
@@ -1,5 +1,5 @@
 use super::super::types::Row;
-use super::{Context, Node};
+use super::{Batch, Context, Node};
 use crate::Error;
 
 /// A source node which produces a single empty row
@@ -20,17 +20,13 @@ impl Node for Nothing {
     fn execute(&mut self, _: &mut Context) -> Result<(), Error> {
         Ok(())
     }
-}
-
-impl Iterator for Nothing {
-    type Item = Result<Row, Error>;
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
         if self.done {
-            None
+            Ok(None)
         } else {
             self.done = true;
-            Some(Ok(Row::new()))
+            Ok(Some(vec![Row::new()]))
         }
     }
 }
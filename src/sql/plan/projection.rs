@@ -1,14 +1,17 @@
-use super::super::types::Row;
-use super::{Context, Node};
+use super::super::session::Cancellation;
+use super::{Batch, Context, Node};
 use crate::sql::expression::Expressions;
 use crate::Error;
 
 /// A projection node
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct Projection {
     source: Box<dyn Node>,
     labels: Vec<String>,
     expressions: Expressions,
+    #[derivative(Debug = "ignore")]
+    cancellation: Cancellation,
 }
 
 impl Projection {
@@ -17,23 +20,36 @@ impl Projection {
             source,
             labels,
             expressions,
+            cancellation: Cancellation::default(),
         }
     }
 }
 
 impl Node for Projection {
     fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        self.cancellation = ctx.cancellation.clone();
         self.source.execute(ctx)
     }
-}
-
-impl Iterator for Projection {
-    type Item = Result<Row, Error>;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.source.next()? {
-            Err(err) => Some(Err(err)),
-            _ => Some(self.expressions.iter().map(|e| e.evaluate()).collect()),
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        match self.source.next_batch()? {
+            Some(batch) => batch
+                .iter()
+                .map(|_| {
+                    self.cancellation.check()?;
+                    self.expressions.iter().map(|e| e.evaluate()).collect()
+                })
+                .collect::<Result<_, Error>>()
+                .map(Some),
+            None => Ok(None),
         }
     }
+
+    fn describe(&self) -> String {
+        format!("Projection {}", self.labels.join(", "))
+    }
+
+    fn children(&self) -> Vec<&dyn Node> {
+        vec![&*self.source]
+    }
 }
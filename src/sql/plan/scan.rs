@@ -1,5 +1,7 @@
+use super::super::schema::Privilege;
+use super::super::session::{Cancellation, Progress};
 use super::super::types::Row;
-use super::{Context, Node};
+use super::{Batch, Context, Node, BATCH_SIZE};
 use crate::Error;
 
 /// A table scan node
@@ -9,28 +11,52 @@ pub struct Scan {
     table: String,
     #[derivative(Debug = "ignore")]
     range: Option<Box<dyn Iterator<Item = Result<Row, Error>> + Sync + Send + 'static>>,
+    #[derivative(Debug = "ignore")]
+    progress: Progress,
+    #[derivative(Debug = "ignore")]
+    cancellation: Cancellation,
 }
 
 impl Scan {
     pub fn new(table: String) -> Self {
-        Self { table, range: None }
+        Self {
+            table,
+            range: None,
+            progress: Progress::default(),
+            cancellation: Cancellation::default(),
+        }
     }
 }
 
 impl Node for Scan {
     fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
-        self.range = Some(ctx.storage.scan_rows(&self.table));
+        ctx.authorize(&self.table, Privilege::Select)?;
+        let cancellation = ctx.cancellation.clone();
+        self.range = Some(
+            ctx.table_storage(&self.table)?
+                .scan_rows(&self.table, cancellation),
+        );
+        self.progress = ctx.progress.clone();
+        self.cancellation = ctx.cancellation.clone();
         Ok(())
     }
-}
 
-impl Iterator for Scan {
-    type Item = Result<Row, Error>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.range {
-            Some(ref mut iter) => iter.next(),
-            None => None,
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        let range = match self.range {
+            Some(ref mut range) => range,
+            None => return Ok(None),
+        };
+        self.cancellation.check()?;
+        let batch = range.take(BATCH_SIZE).collect::<Result<Batch, Error>>()?;
+        self.progress.add_scanned(batch.len() as u64);
+        if batch.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(batch))
         }
     }
+
+    fn describe(&self) -> String {
+        format!("Scan {}", self.table)
+    }
 }
@@ -0,0 +1,37 @@
+use super::super::types::Row;
+use super::{Batch, Context, Node, BATCH_SIZE};
+use crate::Error;
+
+/// A scan node for the system.sessions virtual table, listing running queries
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Sessions {
+    #[derivative(Debug = "ignore")]
+    rows: Option<std::vec::IntoIter<Row>>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self { rows: None }
+    }
+}
+
+impl Node for Sessions {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.authorize_admin("list sessions")?;
+        self.rows = Some(ctx.sessions.list()?.into_iter());
+        Ok(())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        let batch: Batch = match self.rows {
+            Some(ref mut rows) => rows.take(BATCH_SIZE).collect(),
+            None => return Ok(None),
+        };
+        if batch.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(batch))
+        }
+    }
+}
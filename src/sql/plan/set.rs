@@ -0,0 +1,26 @@
+use super::super::expression::Expression;
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A SET node, setting a session variable
+#[derive(Debug)]
+pub struct Set {
+    variable: String,
+    value: Expression,
+}
+
+impl Set {
+    pub fn new(variable: String, value: Expression) -> Self {
+        Self { variable, value }
+    }
+}
+
+impl Node for Set {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        ctx.variables.set(&self.variable, self.value.evaluate()?)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,30 @@
+use super::super::types::Value;
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A SHOW node, producing a single row with the value of a session variable
+#[derive(Debug)]
+pub struct Show {
+    variable: String,
+    value: Option<Value>,
+}
+
+impl Show {
+    pub fn new(variable: String) -> Self {
+        Self {
+            variable,
+            value: None,
+        }
+    }
+}
+
+impl Node for Show {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        self.value = Some(ctx.variables.get(&self.variable)?);
+        Ok(())
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(self.value.take().map(|value| vec![vec![value]]))
+    }
+}
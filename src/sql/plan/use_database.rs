@@ -0,0 +1,30 @@
+use super::{Batch, Context, Node};
+use crate::Error;
+
+/// A USE node, changing the session's current database
+#[derive(Debug)]
+pub struct UseDatabase {
+    name: String,
+}
+
+impl UseDatabase {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+impl Node for UseDatabase {
+    fn execute(&mut self, ctx: &mut Context) -> Result<(), Error> {
+        if !ctx.storage.database_exists(&self.name)? {
+            return Err(Error::Value(format!(
+                "Database {} does not exist",
+                self.name
+            )));
+        }
+        ctx.variables.use_database(&self.name)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, Error> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,126 @@
+use super::ast;
+use super::types::Value;
+use super::{Expression, Parser};
+use crate::Error;
+
+/// A prepared statement: a parsed SQL statement with query parameters ?,
+/// which are bound to values each time it's run. Values are bound as quoted
+/// literals in canonical SQL, so they can't alter the statement, and the
+/// bound query runs like any other query, e.g. via Client::query().
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreparedStatement {
+    statement: ast::Statement,
+    parameters: usize,
+}
+
+impl PreparedStatement {
+    /// Parses a statement, which may contain query parameters.
+    pub fn new(query: &str) -> Result<Self, Error> {
+        let mut parser = Parser::new(query);
+        let statement = parser.parse()?;
+        Ok(Self {
+            statement,
+            parameters: parser.parameters(),
+        })
+    }
+
+    /// Returns the number of query parameters.
+    pub fn parameters(&self) -> usize {
+        self.parameters
+    }
+
+    /// Binds the query parameters to the given values, in order, returning
+    /// the query as canonical SQL.
+    pub fn bind(&self, values: &[Value]) -> Result<String, Error> {
+        if values.len() != self.parameters {
+            return Err(Error::Value(format!(
+                "Expected {} parameters, got {}",
+                self.parameters,
+                values.len()
+            )));
+        }
+        let mut statement = self.statement.clone();
+        statement.walk_expressions(&mut |expr| {
+            if let ast::Expression::Parameter(index) = expr {
+                *expr = ast::Expression::Literal(values[*index].clone().into())
+            }
+        });
+        Ok(statement.to_string())
+    }
+
+    /// Parses comma-separated parameter values given as constant SQL
+    /// expressions, e.g. 1, 'a', -2.5, NULL.
+    pub fn parse_values(input: &str) -> Result<Vec<Value>, Error> {
+        if input.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let invalid = || Error::Value(format!("Invalid parameter values {}", input));
+        let query = format!("SELECT {}", input);
+        let mut parser = Parser::new(&query);
+        match parser.parse().map_err(|_| invalid())? {
+            ast::Statement::Select { select, from: None }
+                if parser.parameters() == 0 && select.labels.iter().all(Option::is_none) =>
+            {
+                select
+                    .expressions
+                    .into_iter()
+                    .map(|expr| Expression::from(expr).evaluate())
+                    .collect()
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind() -> Result<(), Error> {
+        let prepared = PreparedStatement::new("SELECT ? + 1, ?, NOT ? FROM t")?;
+        assert_eq!(3, prepared.parameters());
+        assert_eq!(
+            "SELECT 2 + 1, 'it''s', NOT NULL FROM t",
+            prepared.bind(&[Value::Integer(2), Value::String("it's".into()), Value::Null])?
+        );
+        assert_matches!(prepared.bind(&[Value::Integer(1)]), Err(Error::Value(_)));
+
+        let prepared = PreparedStatement::new("INSERT INTO t VALUES (?, ?); SET x = ?")?;
+        assert_eq!(
+            "INSERT INTO t VALUES (1, 2.5); SET x = TRUE",
+            prepared.bind(&[Value::Integer(1), Value::Float(2.5), Value::Boolean(true)])?
+        );
+
+        let prepared = PreparedStatement::new("SELECT 1")?;
+        assert_eq!(0, prepared.parameters());
+        assert_eq!("SELECT 1", prepared.bind(&[])?);
+        assert_matches!(
+            PreparedStatement::new("SELECT ?? FROM"),
+            Err(Error::Parse(_))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_values() -> Result<(), Error> {
+        assert_eq!(
+            vec![
+                Value::Integer(1),
+                Value::String("a, b".into()),
+                Value::Float(-2.5),
+                Value::Null,
+                Value::Integer(4),
+            ],
+            PreparedStatement::parse_values("1, 'a, b', -2.5, NULL, 2 * 2")?
+        );
+        assert_eq!(Vec::<Value>::new(), PreparedStatement::parse_values(" ")?);
+        for invalid in &["1,", "?", "1 AS a", "1 FROM t", "1; DROP TABLE t"] {
+            assert_matches!(
+                PreparedStatement::parse_values(invalid),
+                Err(Error::Value(_))
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+//! A compact binary row encoding. Rows are encoded against their table schema
+//! rather than as self-describing MessagePack arrays, such that single columns
+//! can be decoded without decoding the rest of the row. The layout is:
+//!
+//! - A versioned serializer header with the row codec ID (3 bytes).
+//! - The schema version of the table when the row was written (1 byte).
+//! - The number of columns (2 bytes).
+//! - A null bitmap, with one bit per column.
+//! - A fixed-width section with a slot per column: 1 byte for booleans, 8
+//!   bytes for integers and floats, and for strings the 4-byte end offset of
+//!   their contents in the var-width section. Slots of NULL values are zeroed,
+//!   or hold the previous end offset for strings, so slot offsets only depend
+//!   on the schema.
+//! - A var-width section, with the contents of strings.
+//!
+//! Numbers are big-endian. The column count allows rows written before columns
+//! were appended to a table to be decoded, with NULL for the new columns. Rows
+//! written before this encoding existed are MessagePack arrays, which decode()
+//! still reads, likewise with NULL for appended columns. Rows with more columns
+//! than the table are an error.
+
+use super::schema::Table;
+use super::types::{DataType, Row, Value};
+use crate::serializer::{self, HEADER_MAGIC, HEADER_VERSION, ROW_CODEC_ID};
+use crate::Error;
+use std::convert::TryFrom;
+
+/// The header of encoded rows
+const HEADER: [u8; 3] = [HEADER_MAGIC, HEADER_VERSION, ROW_CODEC_ID];
+
+/// Returns the width of a column's slot in the fixed-width section
+fn width(datatype: &DataType) -> usize {
+    match datatype {
+        DataType::Boolean => 1,
+        DataType::Integer | DataType::Float => 8,
+        DataType::String => 4,
+    }
+}
+
+/// Returns the length of the null bitmap for a number of columns
+fn bitmap_len(count: usize) -> usize {
+    (count + 7) >> 3
+}
+
+/// Encodes a row of a table. There must be a value for each column, of the
+/// column's datatype or NULL.
+pub fn encode(table: &Table, row: &[Value]) -> Result<Vec<u8>, Error> {
+    if row.len() != table.columns.len() {
+        return Err(Error::Value(format!(
+            "Table {} has {} columns, got {} values",
+            table.name,
+            table.columns.len(),
+            row.len()
+        )));
+    }
+    let count = u16::try_from(row.len())
+        .map_err(|_| Error::Value(format!("Table {} has too many columns", table.name)))?;
+    let offset = |var: &Vec<u8>| {
+        u32::try_from(var.len())
+            .map(u32::to_be_bytes)
+            .map_err(|_| Error::Value(format!("Row too large for table {}", table.name)))
+    };
+
+    let mut nulls = vec![0; bitmap_len(row.len())];
+    let mut fixed = Vec::new();
+    let mut var = Vec::new();
+    for (i, (column, value)) in table.columns.iter().zip(row).enumerate() {
+        match (&column.datatype, value) {
+            (DataType::Boolean, Value::Boolean(b)) => fixed.push(*b as u8),
+            (DataType::Integer, Value::Integer(i)) => fixed.extend(&i.to_be_bytes()),
+            (DataType::Float, Value::Float(f)) => fixed.extend(&f.to_bits().to_be_bytes()),
+            (DataType::String, Value::String(s)) => {
+                var.extend(s.as_bytes());
+                fixed.extend(&offset(&var)?);
+            }
+            (DataType::String, Value::Null) => {
+                nulls[i / 8] |= 1 << (i % 8);
+                fixed.extend(&offset(&var)?);
+            }
+            (datatype, Value::Null) => {
+                nulls[i / 8] |= 1 << (i % 8);
+                fixed.resize(fixed.len() + width(datatype), 0);
+            }
+            (datatype, value) => {
+                return Err(Error::Value(format!(
+                    "Invalid value {} for {} column {}",
+                    value, datatype, column.name
+                )))
+            }
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(6 + nulls.len() + fixed.len() + var.len());
+    bytes.extend(&HEADER);
+    bytes.push(table.version);
+    bytes.extend(&count.to_be_bytes());
+    bytes.extend(nulls);
+    bytes.extend(fixed);
+    bytes.extend(var);
+    Ok(bytes)
+}
+
+/// Decodes a row of a table, either encoded by encode() or a MessagePack array
+/// written before the row encoding existed.
+pub fn decode(table: &Table, bytes: &[u8]) -> Result<Row, Error> {
+    if !bytes.starts_with(&HEADER) {
+        let mut row: Row = serializer::deserialize(bytes)?;
+        if row.len() > table.columns.len() {
+            return Err(Error::Value(format!(
+                "Row has {} columns, but table {} has {}",
+                row.len(),
+                table.name,
+                table.columns.len()
+            )));
+        }
+        row.resize(table.columns.len(), Value::Null);
+        return Ok(row);
+    }
+    let reader = Reader::new(table, bytes)?;
+    (0..table.columns.len()).map(|i| reader.get(i)).collect()
+}
+
+/// A reader for a row encoded by encode(), which decodes columns on demand
+pub struct Reader<'a> {
+    table: &'a Table,
+    nulls: &'a [u8],
+    fixed: &'a [u8],
+    var: &'a [u8],
+    /// Slot offsets in the fixed-width section, for the columns in the row
+    offsets: Vec<usize>,
+}
+
+impl<'a> Reader<'a> {
+    /// Creates a reader for an encoded row of a table
+    pub fn new(table: &'a Table, bytes: &'a [u8]) -> Result<Self, Error> {
+        let truncated = || Error::Value(format!("Truncated row for table {}", table.name));
+        let (version, count, body) = match bytes.strip_prefix(&HEADER[..]) {
+            Some([version, a, b, body @ ..]) => {
+                (*version, u16::from_be_bytes([*a, *b]) as usize, body)
+            }
+            Some(_) => return Err(truncated()),
+            None => return Err(Error::Value("Not an encoded row".into())),
+        };
+        if version > table.version {
+            return Err(Error::Value(format!(
+                "Row schema version {} is newer than table {} version {}",
+                version, table.name, table.version
+            )));
+        } else if count > table.columns.len() {
+            return Err(Error::Value(format!(
+                "Row has {} columns, but table {} has {}",
+                count,
+                table.name,
+                table.columns.len()
+            )));
+        }
+
+        let mut offsets = Vec::with_capacity(count);
+        let mut end = 0;
+        for column in &table.columns[..count] {
+            offsets.push(end);
+            end += width(&column.datatype);
+        }
+        let nulls_len = bitmap_len(count);
+        if body.len() < nulls_len + end {
+            return Err(truncated());
+        }
+        let (nulls, body) = body.split_at(nulls_len);
+        let (fixed, var) = body.split_at(end);
+        Ok(Self {
+            table,
+            nulls,
+            fixed,
+            var,
+            offsets,
+        })
+    }
+
+    /// Decodes the value of a column, by index. Columns appended to the table
+    /// after the row was written are NULL.
+    pub fn get(&self, index: usize) -> Result<Value, Error> {
+        let column = self.table.columns.get(index).ok_or_else(|| {
+            Error::Value(format!(
+                "Column index {} out of bounds for table {}",
+                index, self.table.name
+            ))
+        })?;
+        if index >= self.offsets.len() || self.nulls[index / 8] & (1 << (index % 8)) != 0 {
+            return Ok(Value::Null);
+        }
+        Ok(match column.datatype {
+            DataType::Boolean => Value::Boolean(self.slot::<1>(index)[0] != 0),
+            DataType::Integer => Value::Integer(i64::from_be_bytes(self.slot(index))),
+            DataType::Float => Value::Float(f64::from_bits(u64::from_be_bytes(self.slot(index)))),
+            DataType::String => {
+                // Strings start where the previous string in the row ends.
+                let start = self.table.columns[..index]
+                    .iter()
+                    .rposition(|c| c.datatype == DataType::String)
+                    .map_or(0, |i| u32::from_be_bytes(self.slot(i)) as usize);
+                let end = u32::from_be_bytes(self.slot(index)) as usize;
+                let bytes = self.var.get(start..end).ok_or_else(|| {
+                    Error::Value(format!(
+                        "Invalid string offsets in row of table {}",
+                        self.table.name
+                    ))
+                })?;
+                Value::String(
+                    std::str::from_utf8(bytes)
+                        .map_err(|err| Error::Value(err.to_string()))?
+                        .to_string(),
+                )
+            }
+        })
+    }
+
+    /// Returns the fixed-width slot of a column
+    fn slot<const N: usize>(&self, index: usize) -> [u8; N] {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.fixed[self.offsets[index]..self.offsets[index] + N]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::schema::Column;
+    use super::*;
+
+    fn table() -> Table {
+        let column = |name: &str, datatype| Column {
+            name: name.into(),
+            datatype,
+            nullable: true,
+        };
+        Table {
+            name: "movies".into(),
+            columns: vec![
+                column("id", DataType::Integer),
+                column("title", DataType::String),
+                column("rating", DataType::Float),
+                column("genre", DataType::String),
+                column("bluray", DataType::Boolean),
+                column("director", DataType::String),
+            ],
+            primary_key: "id".into(),
+            version: 1,
+            grants: Default::default(),
+            column_grants: Default::default(),
+        }
+    }
+
+    fn row() -> Row {
+        vec![
+            Value::Integer(1979),
+            Value::String("Stalker".into()),
+            Value::Float(8.2),
+            Value::Null,
+            Value::Boolean(true),
+            Value::String("Tarkovsky".into()),
+        ]
+    }
+
+    #[test]
+    fn roundtrip() -> Result<(), Error> {
+        let table = table();
+        let bytes = encode(&table, &row())?;
+        assert_eq!(&HEADER[..], &bytes[..3]);
+        assert_eq!(row(), decode(&table, &bytes)?);
+
+        let nulls = vec![Value::Null; table.columns.len()];
+        assert_eq!(nulls, decode(&table, &encode(&table, &nulls)?)?);
+
+        // Columns can be decoded individually
+        let reader = Reader::new(&table, &bytes)?;
+        assert_eq!(Value::String("Tarkovsky".into()), reader.get(5)?);
+        assert_eq!(Value::Null, reader.get(3)?);
+        assert_eq!(Value::Float(8.2), reader.get(2)?);
+        assert_matches!(reader.get(6), Err(Error::Value(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn schema_evolution() -> Result<(), Error> {
+        let mut table = table();
+        let bytes = encode(&table, &row())?;
+
+        // Rows written before columns were appended read them as NULL
+        table.columns.push(Column {
+            name: "added".into(),
+            datatype: DataType::Integer,
+            nullable: true,
+        });
+        table.version += 1;
+        let mut expect = row();
+        expect.push(Value::Null);
+        assert_eq!(expect, decode(&table, &bytes)?);
+
+        // Rows written by a newer schema version can't be read
+        table.version = 0;
+        assert_matches!(decode(&table, &bytes), Err(Error::Value(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn legacy() -> Result<(), Error> {
+        let mut table = table();
+        assert_eq!(row(), decode(&table, &serializer::serialize(row())?)?);
+
+        // Short rows read appended columns as NULL, like encoded rows
+        let mut short = row();
+        short.truncate(4);
+        let bytes = serializer::serialize(&short)?;
+        short.resize(6, Value::Null);
+        assert_eq!(short, decode(&table, &bytes)?);
+
+        // Rows wider than the table can't be read
+        table.columns.truncate(3);
+        assert_eq!(
+            Err(Error::Value(
+                "Row has 4 columns, but table movies has 3".into()
+            )),
+            decode(&table, &bytes)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> Result<(), Error> {
+        let table = table();
+        assert_eq!(
+            Err(Error::Value(
+                "Table movies has 6 columns, got 1 values".into()
+            )),
+            encode(&table, &[Value::Integer(1)])
+        );
+        let mut invalid = row();
+        invalid[0] = Value::Float(1.0);
+        assert_eq!(
+            Err(Error::Value(
+                "Invalid value 1.0 for INTEGER column id".into()
+            )),
+            encode(&table, &invalid)
+        );
+
+        let bytes = encode(&table, &row())?;
+        assert_matches!(decode(&table, &bytes[..8]), Err(Error::Value(_)));
+        assert_matches!(
+            decode(&table, &bytes[..bytes.len() - 1]),
+            Err(Error::Value(_))
+        );
+        assert_matches!(serializer::deserialize::<Row>(&bytes), Err(Error::Value(_)));
+        Ok(())
+    }
+}
@@ -1,12 +1,28 @@
+use super::ast;
 use super::types::DataType;
+use crate::Error;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
 
 /// A table
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
     pub primary_key: String,
+    /// The schema version, to be incremented by schema changes. Rows record
+    /// the version they were written with, see sql::row. Schemas written
+    /// before versioning are version 0.
+    #[serde(default)]
+    pub version: u8,
+    /// The privileges granted on the table, by user name, see GRANT
+    #[serde(default)]
+    pub grants: BTreeMap<String, BTreeSet<Privilege>>,
+    /// The privileges granted on individual columns, by user and column name
+    #[serde(default)]
+    pub column_grants: BTreeMap<String, BTreeMap<String, BTreeSet<Privilege>>>,
 }
 
 impl Table {
@@ -17,38 +33,275 @@ impl Table {
             .unwrap()
     }
 
-    pub fn to_query(&self) -> String {
-        let mut query = format!("CREATE TABLE {} (\n", self.name);
-        for column in self.columns.iter() {
-            query += &format!(
-                "  {} {}",
-                column.name,
-                match column.datatype {
-                    DataType::Boolean => "BOOLEAN",
-                    DataType::Float => "FLOAT",
-                    DataType::Integer => "INTEGER",
-                    DataType::String => "VARCHAR",
+    /// Returns true if the user has been granted the privilege on the given
+    /// columns, either on the whole table or on each of the columns
+    pub fn is_granted(&self, user: &str, privilege: Privilege, columns: &[&str]) -> bool {
+        let contains = |privileges: Option<&BTreeSet<Privilege>>| {
+            privileges
+                .map(|privileges| privileges.contains(&privilege))
+                .unwrap_or(false)
+        };
+        if contains(self.grants.get(user)) {
+            return true;
+        }
+        let grants = match self.column_grants.get(user) {
+            Some(grants) => grants,
+            None => return false,
+        };
+        !columns.is_empty() && columns.iter().all(|column| contains(grants.get(*column)))
+    }
+
+    /// Grants privileges on the table or some of its columns to a user
+    pub fn grant(&mut self, user: &str, privileges: &[ast::PrivilegeSpec]) -> Result<(), Error> {
+        self.check_columns(privileges)?;
+        for spec in privileges {
+            match &spec.columns {
+                Some(columns) => {
+                    let grants = self.column_grants.entry(user.into()).or_default();
+                    for column in columns {
+                        grants
+                            .entry(column.clone())
+                            .or_default()
+                            .insert(spec.privilege);
+                    }
+                }
+                None => {
+                    self.grants
+                        .entry(user.into())
+                        .or_default()
+                        .insert(spec.privilege);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Revokes privileges on the table or some of its columns from a user.
+    /// Table privileges and column privileges are revoked separately.
+    pub fn revoke(&mut self, user: &str, privileges: &[ast::PrivilegeSpec]) -> Result<(), Error> {
+        self.check_columns(privileges)?;
+        for spec in privileges {
+            match &spec.columns {
+                Some(columns) => {
+                    if let Some(grants) = self.column_grants.get_mut(user) {
+                        for column in columns {
+                            if let Some(granted) = grants.get_mut(column) {
+                                granted.remove(&spec.privilege);
+                                if granted.is_empty() {
+                                    grants.remove(column);
+                                }
+                            }
+                        }
+                        if grants.is_empty() {
+                            self.column_grants.remove(user);
+                        }
+                    }
+                }
+                None => {
+                    if let Some(granted) = self.grants.get_mut(user) {
+                        granted.remove(&spec.privilege);
+                        if granted.is_empty() {
+                            self.grants.remove(user);
+                        }
+                    }
                 }
-            );
-            if self.primary_key == column.name {
-                query += " PRIMARY KEY";
             }
-            query += if column.nullable {
-                " NULL"
-            } else {
-                " NOT NULL"
-            };
-            query += ",\n";
         }
-        query += ")";
-        query
+        Ok(())
+    }
+
+    /// Errors if privileges name columns which don't exist in the table
+    fn check_columns(&self, privileges: &[ast::PrivilegeSpec]) -> Result<(), Error> {
+        for column in privileges
+            .iter()
+            .flat_map(|spec| spec.columns.iter().flatten())
+        {
+            if !self.columns.iter().any(|c| &c.name == column) {
+                return Err(Error::Value(format!(
+                    "Column {} does not exist in table {}",
+                    column, self.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the CREATE TABLE query for the table
+    pub fn to_query(&self) -> String {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                let spec = ast::ColumnSpec {
+                    name: column.name.clone(),
+                    datatype: column.datatype.clone(),
+                    primary_key: column.name == self.primary_key,
+                    nullable: Some(column.nullable),
+                };
+                format!("  {}", spec)
+            })
+            .collect::<Vec<_>>();
+        format!("CREATE TABLE {} (\n{}\n)", self.name, columns.join(",\n"))
     }
 }
 
 /// A table column
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Column {
     pub name: String,
     pub datatype: DataType,
     pub nullable: bool,
 }
+
+/// A table privilege, granted to users with GRANT
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Privilege {
+    /// All privileges, i.e. REVOKE ALL
+    pub const ALL: [Privilege; 4] = [
+        Privilege::Select,
+        Privilege::Insert,
+        Privilege::Update,
+        Privilege::Delete,
+    ];
+
+    /// The privileges which can be granted, i.e. GRANT ALL. UPDATE and DELETE
+    /// can't be until there are statements to enforce them, but privileges
+    /// granted by older versions can still be revoked.
+    pub const GRANTABLE: [Privilege; 2] = [Privilege::Select, Privilege::Insert];
+}
+
+impl std::fmt::Display for Privilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Privilege::Select => "SELECT",
+            Privilege::Insert => "INSERT",
+            Privilege::Update => "UPDATE",
+            Privilege::Delete => "DELETE",
+        })
+    }
+}
+
+/// A cache of table schemas keyed on their storage key, which saves a store
+/// read (a consensus read under Raft) for every statement. Entries are
+/// invalidated when their key is written, locally or by an applied Raft entry.
+#[derive(Debug, Default)]
+pub struct SchemaCache {
+    inner: RwLock<SchemaInner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct SchemaInner {
+    tables: HashMap<String, Table>,
+    /// Incremented on every invalidation, such that schemas read before an
+    /// invalidation aren't cached after it.
+    generation: u64,
+}
+
+impl SchemaCache {
+    /// Creates a new, empty schema cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of cache hits and misses
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Fetches a cached schema. Also returns the cache generation, which must
+    /// be passed to put() when caching a schema read after a miss.
+    pub fn get(&self, key: &str) -> Result<(Option<Table>, u64), Error> {
+        let inner = self.inner.read()?;
+        let table = inner.tables.get(key).cloned();
+        match table {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        Ok((table, inner.generation))
+    }
+
+    /// Caches a schema read at the given generation, unless the cache has
+    /// been invalidated since, in which case the schema may be stale.
+    pub fn put(&self, key: String, table: Table, generation: u64) -> Result<(), Error> {
+        let mut inner = self.inner.write()?;
+        if inner.generation == generation {
+            inner.tables.insert(key, table);
+        }
+        Ok(())
+    }
+
+    /// Invalidates the schema stored under a key, e.g. after DDL.
+    pub fn invalidate(&self, key: &str) -> Result<(), Error> {
+        let mut inner = self.inner.write()?;
+        inner.generation += 1;
+        inner.tables.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Parser;
+    use super::*;
+
+    #[test]
+    fn to_query() {
+        let table = Table {
+            name: "movies".into(),
+            columns: vec![
+                Column {
+                    name: "id".into(),
+                    datatype: DataType::Integer,
+                    nullable: false,
+                },
+                Column {
+                    name: "title".into(),
+                    datatype: DataType::String,
+                    nullable: true,
+                },
+            ],
+            primary_key: "id".into(),
+            version: 0,
+            grants: Default::default(),
+            column_grants: Default::default(),
+        };
+        let query = table.to_query();
+        assert_eq!(
+            "CREATE TABLE movies (\n  id INTEGER PRIMARY KEY NOT NULL,\n  title VARCHAR NULL\n)",
+            query
+        );
+        assert_eq!(
+            ast::Statement::CreateTable {
+                name: "movies".into(),
+                columns: vec![
+                    ast::ColumnSpec {
+                        name: "id".into(),
+                        datatype: DataType::Integer,
+                        primary_key: true,
+                        nullable: Some(false),
+                    },
+                    ast::ColumnSpec {
+                        name: "title".into(),
+                        datatype: DataType::String,
+                        primary_key: false,
+                        nullable: Some(true),
+                    },
+                ],
+                temporary: false,
+            },
+            Parser::new(&query).parse().unwrap()
+        );
+    }
+}
@@ -0,0 +1,716 @@
+use super::ast;
+use super::storage::{Storage, DEFAULT_DATABASE};
+use super::types::{Row, Value};
+use crate::raft::ReadConsistency;
+use crate::store::KVMemory;
+use crate::Error;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The name of the virtual table listing running queries
+pub const SESSIONS_TABLE: &str = "system.sessions";
+
+/// How long a client's session is kept after its last query, before its
+/// variables and temporary tables are dropped. Requests aren't tied to
+/// connections, so this stands in for the client disconnecting.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The memory budget of a session's temporary tables in bytes
+pub const TEMPORARY_MAX_BYTES: usize = 64 << 20;
+
+/// A registry of running queries, shared between the gRPC handlers (which
+/// register queries) and plan nodes (which list and kill them).
+#[derive(Clone, Default)]
+pub struct Registry {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    next_id: u64,
+    queries: BTreeMap<u64, Entry>,
+    /// Sessions by client ID
+    sessions: BTreeMap<String, Session>,
+    session_timeout: Duration,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            queries: BTreeMap::new(),
+            sessions: BTreeMap::new(),
+            session_timeout: SESSION_TIMEOUT,
+        }
+    }
+}
+
+impl Inner {
+    /// Drops sessions which have gone without queries for the timeout
+    fn expire(&mut self) {
+        let (queries, timeout) = (&self.queries, self.session_timeout);
+        self.sessions.retain(|client, session| {
+            let expired =
+                session.used.elapsed() >= timeout && !queries.values().any(|e| &e.client == client);
+            if expired {
+                debug!(
+                    "Dropping session of client {} after {:?} idle",
+                    client, timeout
+                );
+            }
+            !expired
+        });
+    }
+}
+
+/// The state of a client's session, kept between its queries
+#[derive(Clone)]
+struct Session {
+    variables: Variables,
+    /// The session's temporary tables, in memory
+    temporary: Storage,
+    /// When the session was last used
+    used: Instant,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            variables: Variables::default(),
+            temporary: Storage::new(KVMemory::new().max_bytes(TEMPORARY_MAX_BYTES)),
+            used: Instant::now(),
+        }
+    }
+
+    /// Returns true if the session has no variables or temporary tables
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.variables.is_empty()? && self.temporary.list_tables()?.is_empty())
+    }
+}
+
+/// A registered query
+struct Entry {
+    client: String,
+    query: String,
+    started: u64,
+    killed: Arc<AtomicBool>,
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Registry")
+    }
+}
+
+impl Registry {
+    /// Creates a new, empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long idle sessions are kept, see SESSION_TIMEOUT
+    pub fn session_timeout(self, timeout: Duration) -> Self {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.session_timeout = timeout;
+        }
+        self
+    }
+
+    /// Registers a running query for a client, in the client's session. The
+    /// query is unregistered when the returned handle is dropped.
+    pub fn register(&self, client: &str, query: &str) -> Result<Query, Error> {
+        let mut inner = self.inner.lock()?;
+        inner.expire();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let killed = Arc::new(AtomicBool::new(false));
+        let session = inner
+            .sessions
+            .entry(client.to_string())
+            .or_insert_with(Session::new)
+            .clone();
+        let variables = session.variables;
+        let deadline = variables.statement_timeout()?.map(|t| Instant::now() + t);
+        let max_rows = variables.max_rows()?;
+        let consistency = variables.read_consistency()?;
+        inner.queries.insert(
+            id,
+            Entry {
+                client: client.to_string(),
+                query: query.to_string(),
+                started: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|t| t.as_secs())
+                    .unwrap_or(0),
+                killed: killed.clone(),
+            },
+        );
+        Ok(Query {
+            id,
+            client: client.to_string(),
+            cancellation: Cancellation {
+                id,
+                killed,
+                deadline,
+            },
+            progress: Progress::default(),
+            variables,
+            temporary: session.temporary,
+            max_rows,
+            consistency,
+            user: None,
+            registry: self.clone(),
+        })
+    }
+
+    /// Returns the current database of a client
+    pub fn database(&self, client: &str) -> Result<String, Error> {
+        match self.inner.lock()?.sessions.get(client) {
+            Some(session) => session.variables.database(),
+            None => Ok(DEFAULT_DATABASE.into()),
+        }
+    }
+
+    /// Kills a running query
+    pub fn kill(&self, id: u64) -> Result<(), Error> {
+        match self.inner.lock()?.queries.get(&id) {
+            Some(entry) => {
+                entry.killed.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            None => Err(Error::Value(format!("Query {} does not exist", id))),
+        }
+    }
+
+    /// Lists running queries as rows of query ID, client ID, query and start time
+    pub fn list(&self) -> Result<Vec<Row>, Error> {
+        Ok(self
+            .inner
+            .lock()?
+            .queries
+            .iter()
+            .map(|(id, entry)| {
+                vec![
+                    Value::Integer(*id as i64),
+                    Value::String(entry.client.clone()),
+                    Value::String(entry.query.clone()),
+                    Value::Integer(entry.started as i64),
+                ]
+            })
+            .collect())
+    }
+}
+
+/// A handle for a running query
+pub struct Query {
+    id: u64,
+    client: String,
+    cancellation: Cancellation,
+    progress: Progress,
+    variables: Variables,
+    temporary: Storage,
+    /// The maximum number of result rows, from the max_rows variable
+    max_rows: Option<usize>,
+    /// The consistency of reads, from the read_consistency variable
+    consistency: ReadConsistency,
+    /// The authenticated user running the query, if authentication is enabled
+    user: Option<User>,
+    registry: Registry,
+}
+
+/// An authenticated user. Administrators may run DDL, GRANT, REVOKE and
+/// KILL, and access all tables, while other users need table privileges.
+#[derive(Clone, Debug, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub admin: bool,
+}
+
+/// The session variables of a client, set with SET and shown with SHOW. They
+/// apply to the client's subsequent queries on the same node.
+#[derive(Clone, Debug, Default)]
+pub struct Variables {
+    values: Arc<Mutex<BTreeMap<String, Value>>>,
+}
+
+impl Variables {
+    /// Returns the default value of a variable, or None if it doesn't exist
+    fn default_value(name: &str) -> Option<Value> {
+        Some(match name {
+            "database" => Value::String(DEFAULT_DATABASE.into()),
+            "isolation_level" => Value::String("serializable".into()),
+            "max_rows" => Value::Integer(0),
+            "read_consistency" => Value::String(ReadConsistency::Strong.to_string()),
+            "role" => Value::String("readwrite".into()),
+            "statement_timeout" => Value::Integer(0),
+            "timezone" => Value::String("UTC".into()),
+            _ => return None,
+        })
+    }
+
+    /// Fetches a variable, or its default if it hasn't been set
+    pub fn get(&self, name: &str) -> Result<Value, Error> {
+        let name = name.to_lowercase();
+        let default = Self::default_value(&name)
+            .ok_or_else(|| Error::Value(format!("Unknown variable {}", name)))?;
+        Ok(self.values.lock()?.get(&name).cloned().unwrap_or(default))
+    }
+
+    /// Sets a variable, checking the value
+    pub fn set(&self, name: &str, value: Value) -> Result<(), Error> {
+        let name = name.to_lowercase();
+        if Self::default_value(&name).is_none() {
+            return Err(Error::Value(format!("Unknown variable {}", name)));
+        }
+        let value = match (name.as_str(), value) {
+            ("database", _) => {
+                return Err(Error::Value(
+                    "The database can only be changed with USE".into(),
+                ))
+            }
+            ("max_rows", Value::Integer(i)) | ("statement_timeout", Value::Integer(i))
+                if i >= 0 =>
+            {
+                Value::Integer(i)
+            }
+            ("isolation_level", Value::String(s)) if s.eq_ignore_ascii_case("serializable") => {
+                Value::String(s.to_lowercase())
+            }
+            ("read_consistency", Value::String(s)) => {
+                Value::String(s.parse::<ReadConsistency>()?.to_string())
+            }
+            ("role", Value::String(s))
+                if s.eq_ignore_ascii_case("readonly") || s.eq_ignore_ascii_case("readwrite") =>
+            {
+                Value::String(s.to_lowercase())
+            }
+            ("timezone", Value::String(s)) if !s.is_empty() => Value::String(s),
+            (_, value) => {
+                return Err(Error::Value(format!(
+                    "Invalid value {} for variable {}",
+                    value, name
+                )))
+            }
+        };
+        self.values.lock()?.insert(name, value);
+        Ok(())
+    }
+
+    /// Returns the current database
+    pub fn database(&self) -> Result<String, Error> {
+        match self.get("database")? {
+            Value::String(database) => Ok(database),
+            value => Err(Error::Internal(format!("Invalid database {}", value))),
+        }
+    }
+
+    /// Changes the current database, which must exist
+    pub fn use_database(&self, database: &str) -> Result<(), Error> {
+        self.values
+            .lock()?
+            .insert("database".into(), Value::String(database.into()));
+        Ok(())
+    }
+
+    /// Errors if the session's role is read-only and the statement mutates
+    /// the database
+    pub fn check_role(&self, statement: &ast::Statement) -> Result<(), Error> {
+        if statement.is_mutation() && self.get("role")? == Value::String("readonly".into()) {
+            return Err(Error::ReadOnly(
+                "Session role is read-only, refusing to execute mutating statement".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns true if no variables have been set
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.values.lock()?.is_empty())
+    }
+
+    /// Returns the statement timeout, if any
+    fn statement_timeout(&self) -> Result<Option<Duration>, Error> {
+        match self.get("statement_timeout")? {
+            Value::Integer(ms) if ms > 0 => Ok(Some(Duration::from_millis(ms as u64))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the maximum number of result rows, if any
+    fn max_rows(&self) -> Result<Option<usize>, Error> {
+        match self.get("max_rows")? {
+            Value::Integer(n) if n > 0 => Ok(Some(n as usize)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the read consistency
+    fn read_consistency(&self) -> Result<ReadConsistency, Error> {
+        match self.get("read_consistency")? {
+            Value::String(s) => s.parse(),
+            value => Err(Error::Internal(format!(
+                "Invalid read consistency {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// A cancellation token for a running query, which trips when the query is
+/// killed or its deadline passes. It's passed to plan nodes and storage
+/// iterators, which check it while executing.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    id: u64,
+    killed: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    /// Errors if the query has been killed or has timed out
+    pub fn check(&self) -> Result<(), Error> {
+        if self.killed.load(Ordering::SeqCst) {
+            return Err(Error::Value(format!("Query {} was killed", self.id)));
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(Error::Value(format!("Query {} timed out", self.id)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Progress counters for a running query, updated during execution
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    scanned: Arc<AtomicU64>,
+    returned: Arc<AtomicU64>,
+}
+
+impl Progress {
+    /// Counts rows scanned from storage
+    pub fn add_scanned(&self, rows: u64) {
+        self.scanned.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Counts result rows returned to the client
+    pub fn add_returned(&self, rows: u64) {
+        self.returned.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Returns the number of rows scanned so far
+    pub fn scanned(&self) -> u64 {
+        self.scanned.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of result rows returned so far
+    pub fn returned(&self) -> u64 {
+        self.returned.load(Ordering::Relaxed)
+    }
+}
+
+impl Query {
+    /// Returns the query ID
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sets a timeout for the query, counted from now, overriding the
+    /// statement_timeout variable
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.cancellation.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Sets the read consistency of the query, overriding the read_consistency
+    /// variable
+    pub fn read_consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.consistency = consistency;
+        self
+    }
+
+    /// Sets the authenticated user running the query
+    pub fn user(mut self, user: User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Returns the authenticated user running the query, if any
+    pub fn get_user(&self) -> Option<&User> {
+        self.user.as_ref()
+    }
+
+    /// Returns the client ID of the query
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+
+    /// Returns the principal running the query, i.e. the authenticated user if
+    /// any, otherwise the client ID
+    pub fn principal(&self) -> &str {
+        match &self.user {
+            Some(user) => &user.name,
+            None => &self.client,
+        }
+    }
+
+    /// Returns the read consistency of the query
+    pub fn consistency(&self) -> ReadConsistency {
+        self.consistency
+    }
+
+    /// Returns true if the query has been killed
+    pub fn is_killed(&self) -> bool {
+        self.cancellation.killed.load(Ordering::SeqCst)
+    }
+
+    /// Returns the query's progress counters
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Returns the session variables of the query's client
+    pub fn variables(&self) -> &Variables {
+        &self.variables
+    }
+
+    /// Returns the temporary tables of the query's client
+    pub fn temporary(&self) -> &Storage {
+        &self.temporary
+    }
+
+    /// Returns the query's cancellation token
+    pub fn cancellation(&self) -> &Cancellation {
+        &self.cancellation
+    }
+
+    /// Errors if the query has been killed or has timed out
+    pub fn check(&self) -> Result<(), Error> {
+        self.cancellation.check()
+    }
+
+    /// Wraps a result iterator such that it ends with an error once the query
+    /// is killed or times out, and counts returned rows. The rows are limited
+    /// by the max_rows variable.
+    pub fn guard<I>(self, iter: I) -> impl Iterator<Item = Result<Row, Error>>
+    where
+        I: Iterator<Item = Result<Row, Error>>,
+    {
+        iter.take(self.max_rows.unwrap_or(usize::MAX))
+            .scan(false, move |done, r| {
+                if *done {
+                    None
+                } else if let Err(err) = self.check() {
+                    *done = true;
+                    Some(Err(err))
+                } else {
+                    if r.is_ok() {
+                        self.progress.add_returned(1);
+                    }
+                    Some(r)
+                }
+            })
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.registry.inner.lock() {
+            inner.queries.remove(&self.id);
+            let running = inner.queries.values().any(|e| e.client == self.client);
+            if let Some(session) = inner.sessions.get_mut(&self.client) {
+                session.used = Instant::now();
+                // Forget clients without session state once their queries are done
+                if !running && session.is_empty().unwrap_or(false) {
+                    inner.sessions.remove(&self.client);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        let registry = Registry::new();
+        let a = registry.register("a", "SELECT 1").unwrap();
+        let b = registry.register("b", "SELECT 2").unwrap();
+        assert_eq!(1, a.id());
+        assert_eq!(2, b.id());
+
+        let rows = registry.list().unwrap();
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            vec![
+                Value::Integer(1),
+                Value::String("a".into()),
+                Value::String("SELECT 1".into())
+            ],
+            rows[0][0..3].to_vec()
+        );
+
+        drop(a);
+        let rows = registry.list().unwrap();
+        assert_eq!(1, rows.len());
+        assert_eq!(Value::Integer(2), rows[0][0]);
+    }
+
+    #[test]
+    fn kill() {
+        let registry = Registry::new();
+        let query = registry.register("a", "SELECT 1").unwrap();
+        assert_matches!(registry.kill(7), Err(Error::Value(_)));
+
+        let rows = vec![Ok(vec![Value::Integer(1)]), Ok(vec![Value::Integer(2)])];
+        let progress = query.progress().clone();
+        let mut iter = query.guard(rows.into_iter());
+        assert_eq!(Some(Ok(vec![Value::Integer(1)])), iter.next());
+        assert_eq!(1, progress.returned());
+        registry.kill(1).unwrap();
+        assert_matches!(iter.next(), Some(Err(Error::Value(_))));
+        assert_eq!(None, iter.next());
+
+        drop(iter);
+        assert!(registry.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn variables() {
+        let registry = Registry::new();
+        let query = registry.register("a", "SET max_rows = 2").unwrap();
+        let variables = query.variables().clone();
+        assert_eq!(Value::Integer(0), variables.get("max_rows").unwrap());
+        assert_eq!(
+            Value::String("UTC".into()),
+            variables.get("TimeZone").unwrap()
+        );
+        assert_matches!(variables.get("foo"), Err(Error::Value(_)));
+        assert_matches!(
+            variables.set("foo", Value::Integer(1)),
+            Err(Error::Value(_))
+        );
+        assert_matches!(
+            variables.set("database", Value::String("other".into())),
+            Err(Error::Value(_))
+        );
+        assert_eq!(DEFAULT_DATABASE, registry.database("a").unwrap());
+        variables.use_database("other").unwrap();
+        assert_eq!("other", registry.database("a").unwrap());
+        assert_matches!(
+            variables.set("max_rows", Value::Integer(-1)),
+            Err(Error::Value(_))
+        );
+        assert_matches!(
+            variables.set("isolation_level", Value::String("read committed".into())),
+            Err(Error::Value(_))
+        );
+        assert_matches!(
+            variables.set("read_consistency", Value::String("linearizable".into())),
+            Err(Error::Value(_))
+        );
+        variables
+            .set("read_consistency", Value::String("Eventual".into()))
+            .unwrap();
+        variables.set("MAX_ROWS", Value::Integer(2)).unwrap();
+
+        // A read-only role rejects mutating statements
+        let insert = crate::sql::Parser::new("INSERT INTO t VALUES (1)")
+            .parse()
+            .unwrap();
+        assert_matches!(
+            variables.set("role", Value::String("admin".into())),
+            Err(Error::Value(_))
+        );
+        variables
+            .set("role", Value::String("ReadOnly".into()))
+            .unwrap();
+        assert_matches!(variables.check_role(&insert), Err(Error::ReadOnly(_)));
+        variables
+            .set("role", Value::String("readwrite".into()))
+            .unwrap();
+        assert_eq!(Ok(()), variables.check_role(&insert));
+        variables
+            .set("statement_timeout", Value::Integer(3_600_000))
+            .unwrap();
+        drop(query);
+
+        // Variables apply to later queries from the same client
+        let query = registry.register("a", "SELECT 1").unwrap();
+        assert_eq!(
+            Value::Integer(2),
+            query.variables().get("max_rows").unwrap()
+        );
+        assert!(query.cancellation().deadline.is_some());
+        assert_eq!(ReadConsistency::Eventual, query.consistency());
+        assert_eq!(
+            Value::String("eventual".into()),
+            query.variables().get("read_consistency").unwrap()
+        );
+        let rows = (1..=3).map(|i| Ok(vec![Value::Integer(i)]));
+        assert_eq!(2, query.guard(rows).count());
+
+        let query = registry.register("b", "SELECT 1").unwrap();
+        assert_eq!(
+            Value::Integer(0),
+            query.variables().get("max_rows").unwrap()
+        );
+        assert!(query.cancellation().deadline.is_none());
+        assert_eq!(ReadConsistency::Strong, query.consistency());
+        drop(query);
+        assert_eq!(1, registry.inner.lock().unwrap().sessions.len());
+    }
+
+    #[test]
+    fn temporary() {
+        let registry = Registry::new().session_timeout(Duration::from_secs(3600));
+        let query = registry.register("a", "CREATE TEMPORARY TABLE t").unwrap();
+        query
+            .temporary()
+            .clone()
+            .create_table(&crate::sql::schema::Table {
+                name: "t".into(),
+                columns: Vec::new(),
+                primary_key: "id".into(),
+                version: 0,
+                grants: Default::default(),
+                column_grants: Default::default(),
+            })
+            .unwrap();
+        drop(query);
+
+        // Temporary tables are private to the client's session
+        let query = registry.register("a", "SELECT * FROM t").unwrap();
+        assert!(query.temporary().table_exists("t").unwrap());
+        let other = registry.register("b", "SELECT * FROM t").unwrap();
+        assert!(!other.temporary().table_exists("t").unwrap());
+        drop(other);
+        drop(query);
+        assert_eq!(1, registry.inner.lock().unwrap().sessions.len());
+
+        // Idle sessions are dropped, along with their temporary tables
+        let registry = registry.session_timeout(Duration::from_millis(0));
+        let query = registry.register("b", "SELECT 1").unwrap();
+        assert!(!registry.inner.lock().unwrap().sessions.contains_key("a"));
+        drop(query);
+        assert!(registry.inner.lock().unwrap().sessions.is_empty());
+    }
+
+    #[test]
+    fn timeout() {
+        let registry = Registry::new();
+        let query = registry.register("a", "SELECT 1").unwrap();
+        assert!(query.check().is_ok());
+        assert!(Cancellation::default().check().is_ok());
+
+        let query = query.timeout(Duration::from_secs(3600));
+        assert!(query.cancellation().check().is_ok());
+        let query = query.timeout(Duration::from_secs(0));
+        assert_matches!(query.cancellation().check(), Err(Error::Value(_)));
+        assert!(!query.is_killed());
+    }
+}
@@ -1,16 +1,75 @@
-use super::schema;
+use super::ast::PrivilegeSpec;
+use super::row;
+use super::schema::{self, SchemaCache};
+use super::session::Cancellation;
 use super::types;
 use crate::serializer::deserialize;
 use crate::serializer::serialize;
-use crate::store::Store;
+use crate::store::{Bytes, Range, Store, WriteBatch};
 use crate::Error;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
 
 const TABLE_PREFIX: &str = "schema.table";
+const DATABASE_PREFIX: &str = "schema.database";
+
+/// The estimated encoded size of a write batch besides its writes, and of a
+/// write besides its key and value, see chunk_writes().
+const BATCH_OVERHEAD: usize = 64;
+const WRITE_OVERHEAD: usize = 16;
+
+/// The database used by sessions which haven't selected one. Its tables and
+/// rows are stored without a namespace prefix, as before databases existed.
+pub const DEFAULT_DATABASE: &str = "default";
+
+/// The result of an offline storage inspection, see Storage::inspect().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorageReport {
+    /// Tables and their number of rows.
+    pub tables: Vec<(String, u64)>,
+    /// Keys of rows whose table schema is missing.
+    pub orphans: Vec<String>,
+    /// Any unreadable records.
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for StorageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Tables: {}", self.tables.len())?;
+        for (table, rows) in &self.tables {
+            writeln!(f, "- {} ({} rows)", table, rows)?;
+        }
+        write!(f, "Orphaned rows: {}", self.orphans.len())?;
+        for key in &self.orphans {
+            write!(f, "\n- {:?}", key)?;
+        }
+        for problem in &self.problems {
+            write!(f, "\nProblem: {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes buffered by a batch() handle
+#[derive(Default)]
+struct Buffer {
+    /// Writes by key, where None is a delete
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+    /// Keys of created rows, which must not exist in the store on commit
+    created: Vec<String>,
+}
 
 #[derive(Clone)]
 pub struct Storage {
     kv: Arc<RwLock<Box<dyn Store>>>,
+    /// The key prefix of the current database, empty for the default one
+    namespace: String,
+    schemas: Arc<SchemaCache>,
+    /// Writes buffered until commit(), if batching
+    batch: Option<Arc<Mutex<Buffer>>>,
+    /// The maximum size of a store write batch in bytes, if any, see
+    /// max_write_size()
+    max_write_size: Option<usize>,
 }
 
 impl std::fmt::Debug for Storage {
@@ -24,22 +83,136 @@ impl Storage {
     pub fn new<S: Store>(store: S) -> Self {
         Storage {
             kv: Arc::new(RwLock::new(Box::new(store))),
+            namespace: String::new(),
+            schemas: Arc::new(SchemaCache::new()),
+            batch: None,
+            max_write_size: None,
+        }
+    }
+
+    /// Uses the given schema cache, e.g. one invalidated by applied Raft
+    /// entries via schema_invalidator().
+    pub fn schema_cache(mut self, schemas: Arc<SchemaCache>) -> Self {
+        self.schemas = schemas;
+        self
+    }
+
+    /// Splits bulk deletes into store write batches of at most the given size
+    /// in bytes, e.g. the Raft maximum command size.
+    pub fn max_write_size(mut self, max_write_size: usize) -> Self {
+        self.max_write_size = Some(max_write_size);
+        self
+    }
+
+    /// Returns a callback which invalidates cached schemas when given the key
+    /// of a write, for use as a state machine write hook.
+    pub fn schema_invalidator(schemas: Arc<SchemaCache>) -> impl Fn(&str) + Send + Sync {
+        move |key| {
+            if Self::is_table_key(key) {
+                if let Err(err) = schemas.invalidate(key) {
+                    error!("Failed to invalidate schema {}: {}", key, err);
+                }
+            }
+        }
+    }
+
+    /// Returns a storage handle which buffers writes until commit(), and sees
+    /// its own buffered writes. Nothing is written if it's dropped instead.
+    pub fn batch(&self) -> Self {
+        Storage {
+            kv: self.kv.clone(),
+            namespace: self.namespace.clone(),
+            schemas: self.schemas.clone(),
+            batch: Some(Arc::new(Mutex::new(Buffer::default()))),
+            max_write_size: self.max_write_size,
+        }
+    }
+
+    /// Writes the buffered writes of a batch() handle to the store as a single
+    /// write batch, i.e. a single Raft command for Raft stores. Nothing is
+    /// written if a created row was concurrently created by someone else.
+    pub fn commit(&self) -> Result<(), Error> {
+        let buffer = match &self.batch {
+            Some(batch) => std::mem::take(&mut *batch.lock()?),
+            None => return Ok(()),
+        };
+        let tables: Vec<String> = buffer
+            .writes
+            .keys()
+            .filter(|key| Self::is_table_key(key))
+            .cloned()
+            .collect();
+        let existing = self
+            .kv
+            .write()?
+            .write_batch_if_absent(buffer.writes.into_iter().collect(), &buffer.created)?;
+        if let Some(key) = existing.first() {
+            return Err(Error::Value(format!("Duplicate row {}", key)));
+        }
+        for key in tables {
+            self.schemas.invalidate(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a storage handle for another store, sharing the schema cache,
+    /// e.g. a Raft store reading at a weaker consistency.
+    pub fn with_store<S: Store>(&self, store: S) -> Self {
+        Storage {
+            kv: Arc::new(RwLock::new(Box::new(store))),
+            namespace: self.namespace.clone(),
+            schemas: self.schemas.clone(),
+            batch: None,
+            max_write_size: self.max_write_size,
+        }
+    }
+
+    /// Returns a storage handle for a database, sharing the underlying store.
+    /// Tables and rows of other databases are not visible through it.
+    pub fn database(&self, name: &str) -> Self {
+        Storage {
+            kv: self.kv.clone(),
+            namespace: Self::namespace(name),
+            schemas: self.schemas.clone(),
+            batch: self.batch.clone(),
+            max_write_size: self.max_write_size,
+        }
+    }
+
+    /// Checks if a database exists
+    pub fn database_exists(&self, name: &str) -> Result<bool, Error> {
+        Ok(name == DEFAULT_DATABASE || self.get(&Self::key_database(name))?.is_some())
+    }
+
+    /// Creates a database
+    pub fn create_database(&mut self, name: &str) -> Result<(), Error> {
+        if self.database_exists(name)? {
+            return Err(Error::Value(format!("Database {} already exists", name)));
+        }
+        self.write(&Self::key_database(name), Some(serialize(name)?))
+    }
+
+    /// Lists databases, including the default one
+    pub fn list_databases(&self) -> Result<Vec<String>, Error> {
+        let mut iter = self.scan(&Self::key_database(""))?;
+        let mut databases = vec![DEFAULT_DATABASE.to_string()];
+        while let Some((_, value)) = iter.next().transpose()? {
+            databases.push(deserialize(&value)?);
         }
+        Ok(databases)
     }
 
     /// Checks if a table exists
     pub fn table_exists(&self, table_name: &str) -> Result<bool, Error> {
-        let table_key = Self::key_table(table_name);
-        let table = self.kv.read()?.get(&table_key)?;
-        Ok(table.is_some())
+        Ok(self.fetch_table(table_name)?.is_some())
     }
 
     /// List all the existing tables
     pub fn list_tables(&self) -> Result<Vec<String>, Error> {
-        let mut iter = self.kv.read()?.iter_prefix(TABLE_PREFIX);
+        let mut iter = self.scan(&self.key_table(""))?;
         let mut tables = Vec::new();
         while let Some((_, value)) = iter.next().transpose()? {
-            let schema: schema::Table = deserialize(value)?;
+            let schema: schema::Table = deserialize(&value)?;
             tables.push(schema.name)
         }
         Ok(tables)
@@ -47,67 +220,843 @@ impl Storage {
 
     /// Fetches a table schema
     pub fn get_table(&self, table_name: &str) -> Result<schema::Table, Error> {
-        let table_key = Self::key_table(table_name);
-        let table = self
-            .kv
-            .read()?
-            .get(&table_key)?
-            .ok_or(Error::Value(format!("Table {} does not exist", table_name)))?;
-        deserialize(table)
+        self.fetch_table(table_name)?
+            .ok_or_else(|| Error::Value(format!("Table {} does not exist", table_name)))
     }
 
-    /// Scan rows
+    /// Fetches a table schema if it exists, from the schema cache if possible
+    fn fetch_table(&self, table_name: &str) -> Result<Option<schema::Table>, Error> {
+        let table_key = self.key_table(table_name);
+        // Batches may hold schema writes which aren't committed yet.
+        if self.batch.is_some() {
+            return self
+                .get(&table_key)?
+                .map(|value| deserialize(&value))
+                .transpose();
+        }
+        let (cached, generation) = self.schemas.get(&table_key)?;
+        if cached.is_some() {
+            return Ok(cached);
+        }
+        match self.kv.read()?.get(&table_key)? {
+            Some(value) => {
+                let table: schema::Table = deserialize(&value)?;
+                self.schemas.put(table_key, table.clone(), generation)?;
+                Ok(Some(table))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Scan rows, checking the cancellation token before each row
     pub fn scan_rows(
         &self,
         table_name: &str,
+        cancellation: Cancellation,
     ) -> Box<dyn Iterator<Item = Result<types::Row, Error>> + Sync + Send> {
-        let key = format!("{}.", table_name);
-        let it = self
-            .kv
-            .read()
-            .unwrap()
-            .iter_prefix(&key)
-            .map(|res| match res {
-                Ok((_, raw_row)) => deserialize(raw_row),
+        let key = self.key_row(table_name, "");
+        let (table, range) = match self.get_table(table_name).and_then(|table| {
+            let range = self.scan(&key)?;
+            Ok((table, range))
+        }) {
+            Ok(scan) => scan,
+            Err(err) => return Box::new(std::iter::once(Err(err))),
+        };
+        let it = range.map(move |res| {
+            cancellation.check()?;
+            match res {
+                Ok((_, raw_row)) => row::decode(&table, &raw_row),
                 Err(err) => Err(err),
-            });
+            }
+        });
         Box::new(it)
     }
 
     /// Creates a row in a table
     pub fn create_row(&mut self, table_name: &str, row: types::Row) -> Result<(), Error> {
-        let table = self.get_table(&table_name)?;
-        let id = row
-            .get(table.get_primary_key_index())
-            .ok_or_else(|| Error::Value("No primary key value".into()))?;
-        let row_key = Self::key_row(table_name, &id.to_string());
-        self.kv.write()?.set(&row_key, serialize(row)?)
+        self.create_rows(table_name, vec![row], false)
     }
 
-    /// Creates a table
+    /// Creates rows in a table, written as a single store write batch, i.e.
+    /// a single Raft command for Raft stores. Rows with a primary key which
+    /// already exists are an error, or skipped if ignore_conflicts is set.
+    /// Existing rows are checked by the store as part of the write, i.e. by
+    /// the Raft state machine, so concurrent inserts can't both succeed.
+    pub fn create_rows(
+        &mut self,
+        table_name: &str,
+        rows: Vec<types::Row>,
+        ignore_conflicts: bool,
+    ) -> Result<(), Error> {
+        let table = self.get_table(table_name)?;
+        let primary_key = table.get_primary_key_index();
+        let duplicate = |id: &str| {
+            Error::Value(format!(
+                "Duplicate primary key {} in table {}",
+                id, table_name
+            ))
+        };
+        let mut ids = HashMap::new();
+        let mut batch = WriteBatch::new();
+        for row in rows {
+            let id = row
+                .get(primary_key)
+                .ok_or_else(|| Error::Value("No primary key value".into()))?;
+            let row_key = self.key_row(table_name, &id.to_key());
+            let value = row::encode(&table, &row)?;
+            if ids.contains_key(&row_key) && ignore_conflicts {
+                continue;
+            } else if ids.contains_key(&row_key) {
+                return Err(duplicate(&id.to_string()));
+            }
+            ids.insert(row_key.clone(), id.to_string());
+            batch.push((row_key, Some(value)));
+        }
+
+        // Batches check their own buffered rows, and existing rows both now
+        // and when committed.
+        if let Some(buffer) = &self.batch {
+            let mut buffer = buffer.lock()?;
+            let kv = self.kv.read()?;
+            let mut rows = WriteBatch::new();
+            let mut created = Vec::new();
+            for (row_key, value) in batch {
+                let exists = match buffer.writes.get(&row_key) {
+                    Some(value) => value.is_some(),
+                    None => kv.get(&row_key)?.is_some(),
+                };
+                if exists && ignore_conflicts {
+                    continue;
+                } else if exists {
+                    return Err(duplicate(&ids[&row_key]));
+                }
+                if !buffer.writes.contains_key(&row_key) {
+                    created.push(row_key.clone());
+                }
+                rows.push((row_key, value));
+            }
+            buffer.writes.extend(rows);
+            buffer.created.extend(created);
+            return Ok(());
+        }
+
+        // Conflicting rows are skipped by retrying without them.
+        let mut kv = self.kv.write()?;
+        while !batch.is_empty() {
+            let keys: Vec<String> = batch.iter().map(|(key, _)| key.clone()).collect();
+            let existing = kv.write_batch_if_absent(batch.clone(), &keys)?;
+            if existing.is_empty() {
+                break;
+            } else if !ignore_conflicts {
+                return Err(duplicate(&ids[&existing[0]]));
+            }
+            batch.retain(|(key, _)| !existing.contains(key));
+        }
+        Ok(())
+    }
+
+    /// Creates a table. Rows left behind by a dropped table of the same name,
+    /// which may not match the new schema, are deleted first, with the schema
+    /// written along with the last of them.
     pub fn create_table(&mut self, table: &schema::Table) -> Result<(), Error> {
+        Self::check_table_name(&table.name)?;
         if self.table_exists(&table.name)? {
-            Err(Error::Value(format!("Table {} already exists", table.name)))
-        } else {
-            let table_name = Self::key_table(&table.name);
-            let serialized_table = serialize(table)?;
-            self.kv.write()?.set(&table_name, serialized_table)
+            return Err(Error::Value(format!("Table {} already exists", table.name)));
         }
+        let table_key = self.key_table(&table.name);
+        let mut batch = self.delete_rows(&table.name)?;
+        batch.push((table_key.clone(), Some(serialize(table)?)));
+        self.write_chunked(batch)?;
+        self.schemas.invalidate(&table_key)
     }
 
-    /// Deletes a table
+    /// Deletes a table and its rows. The schema is deleted along with the
+    /// first rows, so if deleting the rest fails, they're left as orphans
+    /// which a later create_table() of the same name deletes.
     pub fn drop_table(&mut self, table_name: &str) -> Result<(), Error> {
-        let table_key = Self::key_table(table_name);
-        self.kv.write()?.delete(&table_key)
+        Self::check_table_name(table_name)?;
+        let table_key = self.key_table(table_name);
+        let mut batch = vec![(table_key.clone(), None)];
+        batch.extend(self.delete_rows(table_name)?);
+        self.write_chunked(batch)?;
+        self.schemas.invalidate(&table_key)
+    }
+
+    /// Errors if a table name is reserved. Rows are keyed on the table name,
+    /// so rows of a table named schema would share the prefix of the schema
+    /// keys, which deleting its rows would delete too.
+    fn check_table_name(table_name: &str) -> Result<(), Error> {
+        let prefix = format!("{}.", table_name);
+        if TABLE_PREFIX.starts_with(&prefix) || DATABASE_PREFIX.starts_with(&prefix) {
+            return Err(Error::Value(format!(
+                "Table name {} is reserved",
+                table_name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns a write batch deleting all rows of a table
+    fn delete_rows(&self, table_name: &str) -> Result<WriteBatch, Error> {
+        let mut batch = WriteBatch::new();
+        let mut rows = self.scan(&self.key_row(table_name, ""))?;
+        while let Some((key, _)) = rows.next().transpose()? {
+            batch.push((key, None));
+        }
+        Ok(batch)
+    }
+
+    /// Grants privileges on a table or its columns to a user
+    pub fn grant(
+        &mut self,
+        table_name: &str,
+        user: &str,
+        privileges: &[PrivilegeSpec],
+    ) -> Result<(), Error> {
+        self.update_table(table_name, |table| table.grant(user, privileges))
+    }
+
+    /// Revokes privileges on a table or its columns from a user
+    pub fn revoke(
+        &mut self,
+        table_name: &str,
+        user: &str,
+        privileges: &[PrivilegeSpec],
+    ) -> Result<(), Error> {
+        self.update_table(table_name, |table| table.revoke(user, privileges))
+    }
+
+    /// Modifies a table schema, writing it back with a compare-and-swap of the
+    /// stored schema, i.e. a single Raft command for Raft stores. The schema is
+    /// read and modified again if it was changed concurrently, which means
+    /// someone else's change succeeded, so concurrent changes aren't lost.
+    /// GRANT and REVOKE can't be batched, so the store is written directly.
+    fn update_table(
+        &mut self,
+        table_name: &str,
+        f: impl Fn(&mut schema::Table) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let table_key = self.key_table(table_name);
+        loop {
+            let current = self
+                .kv
+                .read()?
+                .get(&table_key)?
+                .ok_or_else(|| Error::Value(format!("Table {} does not exist", table_name)))?;
+            let mut table: schema::Table = deserialize(&current)?;
+            f(&mut table)?;
+            let swapped =
+                self.kv
+                    .write()?
+                    .cas(&table_key, Some(&current[..]), serialize(&table)?)?;
+            if swapped {
+                return self.schemas.invalidate(&table_key);
+            }
+        }
+    }
+
+    /// Inspects the stored tables and rows of all databases, without modifying
+    /// them. There are no indexes yet, so only schemas and rows are checked.
+    /// Tables outside the default database are reported as database.table.
+    pub fn inspect(&self) -> Result<StorageReport, Error> {
+        let mut report = StorageReport::default();
+        let mut tables = Vec::new();
+        let mut rows = Vec::new();
+        for item in self.kv.read()?.iter_prefix("") {
+            let (key, value) = match item {
+                Ok(pair) => pair,
+                Err(err) => {
+                    report.problems.push(format!("Unreadable record: {}", err));
+                    continue;
+                }
+            };
+            let database = match key.strip_prefix('@').and_then(|k| k.split_once('.')) {
+                Some((database, _)) => database,
+                None => DEFAULT_DATABASE,
+            };
+            let storage = self.database(database);
+            if key.starts_with(&Self::key_database("")) {
+                if let Err(err) = deserialize::<String>(&value) {
+                    report
+                        .problems
+                        .push(format!("Invalid database {:?}: {}", key, err));
+                }
+            } else if key.starts_with(&storage.key_table("")) {
+                match deserialize::<schema::Table>(&value) {
+                    Ok(table) => tables.push((storage.key_row(&table.name, ""), table, 0)),
+                    Err(err) => report
+                        .problems
+                        .push(format!("Invalid table schema {:?}: {}", key, err)),
+                }
+            } else {
+                rows.push((key, value));
+            }
+        }
+
+        // Rows are decoded with the schema of their table, once all tables
+        // are known. Rows without a table are orphans, which can't be decoded.
+        for (key, value) in rows {
+            match tables
+                .iter_mut()
+                .find(|(prefix, _, _)| key.starts_with(prefix.as_str()))
+            {
+                Some((_, table, count)) => match row::decode(table, &value) {
+                    Ok(_) => *count += 1,
+                    Err(err) => report
+                        .problems
+                        .push(format!("Invalid row {:?}: {}", key, err)),
+                },
+                None => report.orphans.push(key),
+            }
+        }
+        report.tables = tables
+            .into_iter()
+            .map(|(prefix, _, rows)| {
+                let table = prefix.trim_start_matches('@').trim_end_matches('.');
+                (table.to_string(), rows)
+            })
+            .collect();
+        Ok(report)
+    }
+
+    /// Deletes orphaned rows found by inspect(), returning the number deleted.
+    pub fn delete_orphans(&mut self, report: &StorageReport) -> Result<u64, Error> {
+        let mut kv = self.kv.write()?;
+        for key in &report.orphans {
+            kv.delete(key)?;
+        }
+        Ok(report.orphans.len() as u64)
+    }
+
+    /// Fetches a key, seeing buffered batch writes
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        if let Some(batch) = &self.batch {
+            if let Some(value) = batch.lock()?.writes.get(key) {
+                return Ok(value.clone().map(Bytes::from));
+            }
+        }
+        self.kv.read()?.get(key)
+    }
+
+    /// Sets or deletes a key, buffering the write if batching
+    fn write(&self, key: &str, value: Option<Vec<u8>>) -> Result<(), Error> {
+        if let Some(batch) = &self.batch {
+            batch.lock()?.writes.insert(key.to_string(), value);
+            return Ok(());
+        }
+        let mut kv = self.kv.write()?;
+        match value {
+            Some(value) => kv.set(key, value),
+            None => kv.delete(key),
+        }
+    }
+
+    /// Applies a write batch, buffering the writes if batching
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        if let Some(buffer) = &self.batch {
+            buffer.lock()?.writes.extend(batch);
+            return Ok(());
+        }
+        self.kv.write()?.write_batch(batch)
+    }
+
+    /// Applies writes in order as write batches of at most max_write_size,
+    /// which are each atomic but not together. Buffered writes are committed
+    /// as a single batch regardless.
+    fn write_chunked(&self, batch: WriteBatch) -> Result<(), Error> {
+        match self.max_write_size {
+            Some(max) if self.batch.is_none() => {
+                for chunk in chunk_writes(batch, max) {
+                    self.write_batch(chunk)?;
+                }
+                Ok(())
+            }
+            _ => self.write_batch(batch),
+        }
+    }
+
+    /// Scans pairs under a key prefix, seeing buffered batch writes
+    fn scan(&self, prefix: &str) -> Result<Box<Range>, Error> {
+        let range = self.kv.read()?.iter_prefix(prefix);
+        let batch = match &self.batch {
+            Some(batch) => batch.lock()?,
+            None => return Ok(range),
+        };
+        let mut pairs = range.collect::<Result<BTreeMap<_, _>, Error>>()?;
+        for (key, value) in batch
+            .writes
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+        {
+            match value {
+                Some(value) => pairs.insert(key.clone(), value.clone().into()),
+                None => pairs.remove(key),
+            };
+        }
+        Ok(Box::new(pairs.into_iter().map(Ok)))
+    }
+
+    /// Checks if a key holds a table schema, in any database
+    fn is_table_key(key: &str) -> bool {
+        let key = key
+            .strip_prefix('@')
+            .map_or(Some(key), |k| k.split_once('.').map(|(_, k)| k));
+        matches!(key, Some(k) if k.starts_with(TABLE_PREFIX))
+    }
+
+    /// Generates the key prefix of a database. Table names start with a
+    /// letter, so the @ keeps namespaced keys apart from default database rows.
+    fn namespace(database: &str) -> String {
+        if database == DEFAULT_DATABASE {
+            String::new()
+        } else {
+            format!("@{}.", database)
+        }
+    }
+
+    /// Generates a key for a database
+    fn key_database(database: &str) -> String {
+        format!("{}.{}", DATABASE_PREFIX, database)
     }
 
     /// Generates a key for a table
-    fn key_table(table: &str) -> String {
-        format!("{}.{}", TABLE_PREFIX, table)
+    fn key_table(&self, table: &str) -> String {
+        format!("{}{}.{}", self.namespace, TABLE_PREFIX, table)
     }
 
     /// Generates a key for a row
-    fn key_row(table: &str, id: &str) -> String {
-        format!("{}.{}", table, id)
+    fn key_row(&self, table: &str, id: &str) -> String {
+        format!("{}{}.{}", self.namespace, table, id)
+    }
+}
+
+/// Splits writes into batches whose estimated encoded size is at most the
+/// given size in bytes, keeping their order. Every batch holds at least one
+/// write, even if it's larger.
+fn chunk_writes(writes: WriteBatch, max_size: usize) -> Vec<WriteBatch> {
+    let mut chunks = Vec::new();
+    let mut chunk = WriteBatch::new();
+    let mut size = BATCH_OVERHEAD;
+    for (key, value) in writes {
+        let write_size = key.len() + value.as_ref().map_or(0, |v| v.len()) + WRITE_OVERHEAD;
+        if !chunk.is_empty() && size + write_size > max_size {
+            chunks.push(std::mem::take(&mut chunk));
+            size = BATCH_OVERHEAD;
+        }
+        chunk.push((key, value));
+        size += write_size;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::schema::{Column, Privilege, Table};
+    use crate::sql::types::{DataType, Value};
+    use crate::store::KVMemory;
+
+    fn table(name: &str) -> Table {
+        Table {
+            name: name.to_string(),
+            columns: vec![Column {
+                name: "id".into(),
+                datatype: DataType::Integer,
+                nullable: false,
+            }],
+            primary_key: "id".into(),
+            version: 0,
+            grants: Default::default(),
+            column_grants: Default::default(),
+        }
+    }
+
+    #[test]
+    fn schema_cache() {
+        let schemas = Arc::new(SchemaCache::new());
+        let invalidate = Storage::schema_invalidator(schemas.clone());
+        let mut storage = Storage::new(KVMemory::new()).schema_cache(schemas.clone());
+        storage.create_table(&table("a")).unwrap();
+        assert_eq!(table("a"), storage.get_table("a").unwrap());
+        assert_eq!(table("a"), storage.get_table("a").unwrap());
+        assert_eq!((1, 2), schemas.stats());
+
+        // Writes bypassing the storage, e.g. applied Raft entries, are only
+        // seen once the write hook invalidates the schema.
+        let mut changed = table("a");
+        changed.primary_key = "changed".into();
+        storage
+            .kv
+            .write()
+            .unwrap()
+            .set("schema.table.a", serialize(&changed).unwrap())
+            .unwrap();
+        assert_eq!(table("a"), storage.get_table("a").unwrap());
+        invalidate("a.1");
+        assert_eq!(table("a"), storage.get_table("a").unwrap());
+        invalidate("schema.table.a");
+        assert_eq!(changed, storage.get_table("a").unwrap());
+
+        // Namespaced schemas are invalidated too
+        let mut other = storage.database("other");
+        other.create_table(&table("b")).unwrap();
+        assert!(other.table_exists("b").unwrap());
+        storage
+            .kv
+            .write()
+            .unwrap()
+            .delete("@other.schema.table.b")
+            .unwrap();
+        assert!(other.table_exists("b").unwrap());
+        invalidate("@other.schema.table.b");
+        assert!(!other.table_exists("b").unwrap());
+
+        storage.drop_table("a").unwrap();
+        assert_matches!(storage.get_table("a"), Err(Error::Value(_)));
+    }
+
+    #[test]
+    fn batch() {
+        let mut storage = Storage::new(KVMemory::new());
+        storage.create_table(&table("a")).unwrap();
+        assert_eq!(table("a"), storage.get_table("a").unwrap());
+
+        // Batches see their own writes, which aren't visible until committed
+        let mut batch = storage.batch();
+        batch.drop_table("a").unwrap();
+        batch.create_table(&table("b")).unwrap();
+        batch.create_database("other").unwrap();
+        assert_matches!(batch.create_table(&table("b")), Err(Error::Value(_)));
+        assert!(!batch.table_exists("a").unwrap());
+        assert_eq!(vec!["b".to_string()], batch.list_tables().unwrap());
+        assert_eq!(vec!["a".to_string()], storage.list_tables().unwrap());
+        assert!(!storage.database_exists("other").unwrap());
+
+        batch.commit().unwrap();
+        assert_eq!(vec!["b".to_string()], storage.list_tables().unwrap());
+        assert_matches!(storage.get_table("a"), Err(Error::Value(_)));
+        assert!(storage.database_exists("other").unwrap());
+
+        // Dropped batches aren't written
+        storage.batch().create_table(&table("c")).unwrap();
+        assert!(!storage.table_exists("c").unwrap());
+    }
+
+    #[test]
+    fn create_rows() {
+        let mut storage = Storage::new(KVMemory::new());
+        storage.create_table(&table("a")).unwrap();
+        let rows = |ids: &[i64]| -> Vec<Vec<Value>> {
+            ids.iter().map(|id| vec![Value::Integer(*id)]).collect()
+        };
+        storage.create_rows("a", rows(&[1, 2]), false).unwrap();
+
+        // Duplicate keys fail the whole insert, including within it
+        assert_eq!(
+            Err(Error::Value("Duplicate primary key 2 in table a".into())),
+            storage.create_rows("a", rows(&[3, 2]), false)
+        );
+        assert_matches!(
+            storage.create_rows("a", rows(&[4, 4]), false),
+            Err(Error::Value(_))
+        );
+        assert_eq!(2, storage.scan_rows("a", Cancellation::default()).count());
+
+        // Unless conflicts are ignored, which skips existing rows
+        storage.create_rows("a", rows(&[2, 3, 3]), true).unwrap();
+        assert_eq!(3, storage.scan_rows("a", Cancellation::default()).count());
+
+        // Batches check their own buffered rows too
+        let mut batch = storage.batch();
+        batch.create_rows("a", rows(&[4]), false).unwrap();
+        assert_matches!(
+            batch.create_rows("a", rows(&[4]), false),
+            Err(Error::Value(_))
+        );
+
+        // And fail to commit rows which were created since
+        storage.create_rows("a", rows(&[4]), false).unwrap();
+        assert_eq!(
+            Err(Error::Value("Duplicate row a.4".into())),
+            batch.commit()
+        );
+    }
+
+    #[test]
+    fn drop_table() {
+        let mut storage = Storage::new(KVMemory::new());
+        storage.create_table(&table("a")).unwrap();
+        storage.create_table(&table("ab")).unwrap();
+        storage.create_row("a", vec![Value::Integer(1)]).unwrap();
+        storage.create_row("ab", vec![Value::Integer(1)]).unwrap();
+
+        // Rows are dropped with the table, so a recreated table is empty
+        storage.drop_table("a").unwrap();
+        assert_eq!(1, storage.kv.read().unwrap().iter_prefix("a").count());
+        storage.create_table(&table("a")).unwrap();
+        assert_eq!(0, storage.scan_rows("a", Cancellation::default()).count());
+        storage.create_row("a", vec![Value::Integer(1)]).unwrap();
+        assert_eq!(1, storage.scan_rows("a", Cancellation::default()).count());
+        assert_eq!(1, storage.scan_rows("ab", Cancellation::default()).count());
+
+        // Rows left behind by tables dropped before that are deleted when a
+        // table of the same name is created, rather than decoded with the
+        // new schema
+        storage
+            .kv
+            .write()
+            .unwrap()
+            .delete("schema.table.a")
+            .unwrap();
+        storage.schemas.invalidate("schema.table.a").unwrap();
+        let mut wider = table("a");
+        wider.columns.insert(
+            0,
+            Column {
+                name: "name".into(),
+                datatype: DataType::String,
+                nullable: true,
+            },
+        );
+        storage.create_table(&wider).unwrap();
+        assert_eq!(0, storage.scan_rows("a", Cancellation::default()).count());
+        assert_eq!(1, storage.scan_rows("ab", Cancellation::default()).count());
+
+        // Rows of a table named schema would share the prefix of schemas
+        assert_matches!(storage.create_table(&table("schema")), Err(Error::Value(_)));
+        assert_matches!(storage.drop_table("schema"), Err(Error::Value(_)));
+        assert!(storage.table_exists("a").unwrap());
+    }
+
+    #[test]
+    fn drop_table_chunked() {
+        let mut storage = Storage::new(KVMemory::new()).max_write_size(1024);
+        storage.create_table(&table("a")).unwrap();
+        storage
+            .create_rows(
+                "a",
+                (0..1000).map(|i| vec![Value::Integer(i)]).collect(),
+                false,
+            )
+            .unwrap();
+        storage.drop_table("a").unwrap();
+        assert!(!storage.table_exists("a").unwrap());
+        assert_eq!(0, storage.kv.read().unwrap().iter_prefix("a.").count());
+    }
+
+    #[test]
+    fn chunk_writes() {
+        let write = |key: &str| (key.to_string(), Some(vec![0; 10]));
+        let writes: WriteBatch = vec![write("a"), write("b"), write("c"), write("d")];
+        // Each write is estimated at 1 + 10 + 16 = 27 bytes, plus 64 per batch
+        assert_eq!(
+            vec![vec![write("a"), write("b")], vec![write("c"), write("d")]],
+            super::chunk_writes(writes.clone(), 64 + 2 * 27)
+        );
+        assert_eq!(
+            vec![writes.clone()],
+            super::chunk_writes(writes.clone(), 1 << 20)
+        );
+        assert_eq!(4, super::chunk_writes(writes, 1).len());
+        assert!(super::chunk_writes(WriteBatch::new(), 1).is_empty());
+    }
+
+    #[test]
+    fn grants() {
+        let all = |privileges: &[Privilege]| -> Vec<PrivilegeSpec> {
+            privileges.iter().map(|&p| p.into()).collect()
+        };
+        let mut storage = Storage::new(KVMemory::new());
+        storage.create_table(&table("a")).unwrap();
+        storage
+            .grant("a", "alice", &all(&[Privilege::Select, Privilege::Insert]))
+            .unwrap();
+        storage
+            .revoke("a", "alice", &all(&[Privilege::Insert]))
+            .unwrap();
+        storage.revoke("a", "bob", &all(&Privilege::ALL)).unwrap();
+        let schema = storage.get_table("a").unwrap();
+        assert!(schema.is_granted("alice", Privilege::Select, &["id"]));
+        assert!(!schema.is_granted("alice", Privilege::Insert, &["id"]));
+        assert!(!schema.is_granted("bob", Privilege::Select, &["id"]));
+
+        // Column privileges only cover their columns, and are revoked
+        // separately from table privileges
+        let column = PrivilegeSpec {
+            privilege: Privilege::Insert,
+            columns: Some(vec!["id".into()]),
+        };
+        storage
+            .grant("a", "bob", std::slice::from_ref(&column))
+            .unwrap();
+        storage
+            .grant("a", "bob", &all(&[Privilege::Insert]))
+            .unwrap();
+        storage
+            .revoke("a", "bob", std::slice::from_ref(&column))
+            .unwrap();
+        let schema = storage.get_table("a").unwrap();
+        assert!(schema.is_granted("bob", Privilege::Insert, &["id"]));
+        assert!(schema.column_grants.is_empty());
+        storage.revoke("a", "bob", &all(&Privilege::ALL)).unwrap();
+        storage.grant("a", "bob", &[column]).unwrap();
+        let schema = storage.get_table("a").unwrap();
+        assert!(schema.is_granted("bob", Privilege::Insert, &["id"]));
+        assert!(!schema.is_granted("bob", Privilege::Insert, &["id", "name"]));
+        assert!(!schema.is_granted("bob", Privilege::Insert, &[]));
+        assert!(!schema.is_granted("bob", Privilege::Select, &["id"]));
+        assert_matches!(
+            storage.grant(
+                "a",
+                "bob",
+                &[PrivilegeSpec {
+                    privilege: Privilege::Select,
+                    columns: Some(vec!["id".into(), "missing".into()]),
+                }]
+            ),
+            Err(Error::Value(_))
+        );
+        assert!(!storage
+            .get_table("a")
+            .unwrap()
+            .is_granted("bob", Privilege::Select, &["id"]));
+
+        // Revoking the last privilege removes the user, and dropped tables
+        // take their grants with them
+        storage.revoke("a", "alice", &all(&Privilege::ALL)).unwrap();
+        assert!(storage.get_table("a").unwrap().grants.is_empty());
+        storage.grant("a", "alice", &all(&Privilege::ALL)).unwrap();
+        storage.drop_table("a").unwrap();
+        storage.create_table(&table("a")).unwrap();
+        let schema = storage.get_table("a").unwrap();
+        assert!(schema.grants.is_empty());
+        assert!(schema.column_grants.is_empty());
+        assert_matches!(
+            storage.grant("b", "alice", &all(&Privilege::ALL)),
+            Err(Error::Value(_))
+        );
+    }
+
+    #[test]
+    fn grants_concurrent() {
+        let storage = Storage::new(KVMemory::new());
+        storage.clone().create_table(&table("a")).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let mut storage = storage.clone();
+                std::thread::spawn(move || {
+                    for i in 0..20 {
+                        let user = format!("user{}.{}", thread, i);
+                        storage
+                            .grant("a", &user, &[Privilege::Select.into()])
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // No grant is lost to a concurrent one
+        assert_eq!(160, storage.get_table("a").unwrap().grants.len());
+    }
+
+    #[test]
+    fn databases() {
+        let mut storage = Storage::new(KVMemory::new());
+        assert!(storage.database_exists(DEFAULT_DATABASE).unwrap());
+        assert!(!storage.database_exists("other").unwrap());
+        storage.create_database("other").unwrap();
+        assert_matches!(storage.create_database("other"), Err(Error::Value(_)));
+        assert_matches!(
+            storage.create_database(DEFAULT_DATABASE),
+            Err(Error::Value(_))
+        );
+        assert_eq!(
+            vec![DEFAULT_DATABASE.to_string(), "other".to_string()],
+            storage.list_databases().unwrap()
+        );
+
+        // Tables with the same name are isolated between databases
+        let mut other = storage.database("other");
+        storage.create_table(&table("a")).unwrap();
+        other.create_table(&table("a")).unwrap();
+        other.create_table(&table("b")).unwrap();
+        storage.create_row("a", vec![Value::Integer(1)]).unwrap();
+        other
+            .create_rows(
+                "a",
+                vec![vec![Value::Integer(2)], vec![Value::Integer(3)]],
+                false,
+            )
+            .unwrap();
+        assert_matches!(
+            other.create_rows("a", vec![vec![Value::Integer(4)], vec![]], false),
+            Err(Error::Value(_))
+        );
+        assert_eq!(vec!["a".to_string()], storage.list_tables().unwrap());
+        assert_eq!(
+            vec!["a".to_string(), "b".to_string()],
+            other.list_tables().unwrap()
+        );
+        assert_eq!(1, storage.scan_rows("a", Cancellation::default()).count());
+        assert_eq!(2, other.scan_rows("a", Cancellation::default()).count());
+        assert!(!storage
+            .database(DEFAULT_DATABASE)
+            .table_exists("b")
+            .unwrap());
+
+        let report = storage.inspect().unwrap();
+        assert_eq!(
+            vec![
+                ("other.a".to_string(), 2),
+                ("other.b".to_string(), 0),
+                ("a".to_string(), 1)
+            ],
+            report.tables
+        );
+        assert!(report.orphans.is_empty());
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn inspect() {
+        let mut storage = Storage::new(KVMemory::new());
+        for name in &["a", "ab"] {
+            storage.create_table(&table(name)).unwrap();
+            for id in 1..=2 {
+                storage.create_row(name, vec![Value::Integer(id)]).unwrap();
+            }
+        }
+        // Rows whose schema is gone, e.g. from before tables were dropped
+        // with their rows
+        storage
+            .kv
+            .write()
+            .unwrap()
+            .delete("schema.table.a")
+            .unwrap();
+        storage.kv.write().unwrap().set("ab.3", vec![0xc1]).unwrap();
+
+        let report = storage.inspect().unwrap();
+        assert_eq!(vec![("ab".to_string(), 2)], report.tables);
+        assert_eq!(vec!["a.1".to_string(), "a.2".to_string()], report.orphans);
+        assert_eq!(
+            vec!["Invalid row \"ab.3\": wrong msgpack marker Reserved".to_string()],
+            report.problems
+        );
+
+        assert_eq!(2, storage.delete_orphans(&report).unwrap());
+        assert!(storage.inspect().unwrap().orphans.is_empty());
+        assert_eq!(
+            2,
+            storage
+                .scan_rows("ab", Cancellation::default())
+                .filter(|r| r.is_ok())
+                .count()
+        );
     }
 }
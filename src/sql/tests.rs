@@ -1,5 +1,7 @@
+use super::audit::AuditLog;
 use super::lexer::{Lexer, Token};
 use super::schema;
+use super::session::{Cancellation, Progress, Registry, User, Variables};
 use super::types::{DataType, Row, Value};
 use super::{Context, Parser, Plan, Storage};
 use crate::store;
@@ -7,6 +9,110 @@ use crate::Error;
 use goldenfile::Mint;
 use std::io::Write;
 
+#[test]
+fn is_incomplete_query() {
+    assert!(super::is_incomplete_query(""));
+    assert!(super::is_incomplete_query("SELECT"));
+    assert!(super::is_incomplete_query("SELECT * FROM"));
+    assert!(super::is_incomplete_query("SELECT 'abc"));
+    assert!(super::is_incomplete_query("SELECT 1 +"));
+    assert!(!super::is_incomplete_query("SELECT * FROM movie"));
+    assert!(!super::is_incomplete_query("SELECT * FROM )"));
+}
+
+/// The directory holding SQL test scripts and their golden output
+const SCRIPT_DIR: &str = "tests/sql";
+
+/// Runs each tests/sql/*.sql script against an empty in-memory engine, and
+/// compares the output with the golden file of the same name ending in .out.
+/// Statements are terminated by a ; at the end of a line, and lines starting
+/// with -- are comments. A "-- user: <name>" comment runs the following
+/// statements as a non-administrator user, and "-- user:" without a name as no
+/// user. Run with REGENERATE_GOLDENFILES=1 to update the output.
+#[test]
+fn scripts() -> Result<(), Error> {
+    let mut mint = Mint::new(SCRIPT_DIR);
+    let mut paths = std::fs::read_dir(SCRIPT_DIR)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    paths.retain(|p| p.extension() == Some(std::ffi::OsStr::new("sql")));
+    paths.sort();
+    assert!(
+        !paths.is_empty(),
+        "No SQL test scripts found in {}",
+        SCRIPT_DIR
+    );
+
+    for path in paths {
+        let script = std::fs::read_to_string(&path)?;
+        let name = path.file_stem().unwrap().to_string_lossy();
+        let mut f = mint.new_goldenfile(format!("{}.out", name))?;
+        let storage = Storage::new(store::KVMemory::new());
+        let registry = Registry::new();
+        let variables = Variables::default();
+        let temporary = Storage::new(store::KVMemory::new());
+        let mut user = None;
+
+        let mut statement = String::new();
+        for line in script.lines() {
+            if let Some(name) = line.trim().strip_prefix("-- user:") {
+                user = Some(name.trim())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| User {
+                        name: name.into(),
+                        admin: false,
+                    });
+                writeln!(f, "{}", line.trim())?;
+                continue;
+            }
+            if statement.is_empty() && (line.trim().is_empty() || line.trim().starts_with("--")) {
+                continue;
+            }
+            statement.push_str(line);
+            statement.push('\n');
+            if !line.trim_end().ends_with(';') {
+                continue;
+            }
+            let query = statement.trim().trim_end_matches(';').to_string();
+            statement.clear();
+
+            writeln!(f, "> {}", query)?;
+            let result = Parser::new(&query)
+                .parse()
+                .and_then(Plan::build)
+                .and_then(|plan| {
+                    plan.execute(Context {
+                        storage: Box::new(storage.clone()),
+                        sessions: registry.clone(),
+                        audit: AuditLog::new(),
+                        progress: Progress::default(),
+                        cancellation: Cancellation::default(),
+                        variables: variables.clone(),
+                        temporary: temporary.clone(),
+                        user: user.clone(),
+                    })
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<Row>, Error>>());
+            match result {
+                Ok(rows) => {
+                    for row in rows {
+                        writeln!(f, "{:?}", row)?;
+                    }
+                }
+                Err(err) => writeln!(f, "Error: {:?}", err)?,
+            }
+            writeln!(f)?;
+        }
+        assert!(
+            statement.trim().is_empty(),
+            "Unterminated statement in {}: {}",
+            path.display(),
+            statement
+        );
+    }
+    Ok(())
+}
+
 // test_sql! tests
 macro_rules! test_sql {
     ( $( $name:ident: $sql:expr, )* ) => {
@@ -29,6 +135,9 @@ macro_rules! test_sql {
                     },
                 ],
                 primary_key: "id".into(),
+                version: 0,
+                grants: Default::default(),
+                column_grants: Default::default(),
             }).unwrap();
             storage.create_table(&schema::Table{
                 name: "movies".into(),
@@ -65,6 +174,9 @@ macro_rules! test_sql {
                     },
                 ],
                 primary_key: "id".into(),
+                version: 0,
+                grants: Default::default(),
+                column_grants: Default::default(),
             }).unwrap();
             storage.create_row("genres", vec![
                 Value::Integer(1),
@@ -140,7 +252,7 @@ macro_rules! test_sql {
             write!(f, "Query: {}\n\n", $sql).unwrap();
 
             write!(f, "Result:").unwrap();
-            let result: Vec<Row> = match plan.execute(Context{storage: Box::new(storage.clone())}).and_then(|i| i.collect()) {
+            let result: Vec<Row> = match plan.execute(Context{storage: Box::new(storage.clone()), sessions: Registry::new(), audit: AuditLog::new(), progress: Progress::default(), cancellation: Cancellation::default(), variables: Variables::default(), temporary: Storage::new(store::KVMemory::new()), user: None}).and_then(|i| i.collect()) {
                 Ok(result) => result,
                 Err(err) => {
                     write!(f, " {:?}", err).unwrap();
@@ -155,7 +267,7 @@ macro_rules! test_sql {
             for table in &storage.list_tables().unwrap() {
                 let schema = &storage.get_table(&table).unwrap();
                 write!(f, "\n{}\n", schema.to_query()).unwrap();
-                for row in storage.scan_rows(&table) {
+                for row in storage.scan_rows(&table, Cancellation::default()) {
                     write!(f, "{:?}\n", row.unwrap()).unwrap();
                 }
             }
@@ -191,7 +303,23 @@ test_sql! {
     expr_literal_numbers: "SELECT 0, 1, -2, --3, +-4, 3.14, 293, 3.14e3, 2.718E-2",
     expr_literal_string_quotes: r#"SELECT 'Literal with ''single'' and "double" quotes'"#,
 
+    grant: "GRANT SELECT, INSERT ON movies TO alice",
+    grant_all: "GRANT ALL PRIVILEGES ON TABLE movies TO alice",
+    grant_columns: "GRANT SELECT (id, title), INSERT ON movies TO alice",
+    grant_error_column: "GRANT SELECT (missing) ON movies TO alice",
+    grant_error_privilege: "GRANT DROP ON movies TO alice",
+    grant_error_update: "GRANT UPDATE ON movies TO alice",
+    grant_error_table: "GRANT SELECT ON missing TO alice",
+
+    kill: "KILL 1",
+    kill_error_bare: "KILL",
+
+    revoke: "REVOKE INSERT ON movies FROM alice",
+    revoke_error_delete_columns: "REVOKE DELETE (title) ON movies FROM alice",
+    revoke_error_to: "REVOKE INSERT ON movies TO alice",
+
     select_all_from_table: "SELECT * FROM movies",
+    select_sessions: "SELECT * FROM system.sessions",
     select_aliases: "SELECT 1, 2 b, 3 AS c",
     select_error_bare: "SELECT",
     select_error_bare_as: "SELECT 1 AS, 2",
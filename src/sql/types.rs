@@ -1,4 +1,7 @@
+use crate::Error;
 use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 
 /// A datatype
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -9,8 +12,23 @@ pub enum DataType {
     String,
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
-/// A value
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            DataType::Boolean => "BOOLEAN",
+            DataType::Integer => "INTEGER",
+            DataType::Float => "FLOAT",
+            DataType::String => "VARCHAR",
+        })
+    }
+}
+
+/// A value. Values have a total order, used for sorting, grouping and keys:
+/// NULL sorts first, then booleans, then numbers, then strings. Integers and
+/// floats are ordered by their exact numeric value, with integers first when
+/// equal, such that equality is still type-strict. For floats, -0.0 equals 0.0,
+/// and NaN equals itself and sorts after all other numbers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Value {
     /// An unknown value
     Null,
@@ -32,7 +50,7 @@ impl std::fmt::Display for Value {
                 Value::Boolean(b) if *b => "TRUE".to_string(),
                 Value::Boolean(_) => "FALSE".to_string(),
                 Value::Integer(i) => i.to_string(),
-                Value::Float(f) => f.to_string(),
+                Value::Float(f) => format!("{:?}", f),
                 Value::String(s) => s.clone(),
             }
             .as_ref(),
@@ -40,6 +58,127 @@ impl std::fmt::Display for Value {
     }
 }
 
+impl Value {
+    /// Returns the rank of the value's type in the value order
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Float(_) => 2,
+            Value::String(_) => 3,
+        }
+    }
+
+    /// Compares two values with SQL semantics: comparisons with NULL are
+    /// unknown (None), numbers compare by their exact value regardless of type,
+    /// and other types can only be compared with the same type. Unlike cmp(),
+    /// Integer(1) and Float(1.0) are equal.
+    pub fn compare(&self, other: &Self) -> Result<Option<Ordering>, Error> {
+        use Value::*;
+        Ok(Some(match (self, other) {
+            (Null, _) | (_, Null) => return Ok(None),
+            (Boolean(lhs), Boolean(rhs)) => lhs.cmp(rhs),
+            (Integer(lhs), Integer(rhs)) => lhs.cmp(rhs),
+            (Integer(lhs), Float(rhs)) => cmp_integer_float(*lhs, *rhs),
+            (Float(lhs), Integer(rhs)) => cmp_integer_float(*rhs, *lhs).reverse(),
+            (Float(lhs), Float(rhs)) => cmp_floats(*lhs, *rhs),
+            (String(lhs), String(rhs)) => lhs.cmp(rhs),
+            (lhs, rhs) => return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs))),
+        }))
+    }
+
+    /// Encodes the value as a primary key component in row keys. The format
+    /// is stable, since it's part of the on-disk layout: floats use their
+    /// shortest decimal representation without a trailing .0, with -0.0 as 0.
+    pub fn to_key(&self) -> String {
+        match self {
+            Value::Null => "NULL".into(),
+            Value::Boolean(true) => "TRUE".into(),
+            Value::Boolean(false) => "FALSE".into(),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) if *f == 0.0 => "0".into(),
+            Value::Float(f) => f.to_string(),
+            Value::String(s) => s.clone(),
+        }
+    }
+}
+
+/// Compares two floats by a total order, where -0.0 equals 0.0, and NaN
+/// equals itself and sorts after all other numbers.
+fn cmp_floats(lhs: f64, rhs: f64) -> Ordering {
+    match (lhs.is_nan(), rhs.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => lhs.partial_cmp(&rhs).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Compares an integer and a float by their exact numeric value, without
+/// rounding the integer to a float. NaN sorts after all integers.
+fn cmp_integer_float(lhs: i64, rhs: f64) -> Ordering {
+    // 2^63 as a float, the bounds of the i64 range
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if rhs.is_nan() || rhs >= LIMIT {
+        return Ordering::Less;
+    } else if rhs < -LIMIT {
+        return Ordering::Greater;
+    }
+    let trunc = rhs.trunc();
+    match lhs.cmp(&(trunc as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(rhs - trunc)).unwrap_or(Ordering::Equal),
+        ordering => ordering,
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        use Value::*;
+        match (self, other) {
+            (Null, Null) => Ordering::Equal,
+            (Boolean(lhs), Boolean(rhs)) => lhs.cmp(rhs),
+            (Integer(lhs), Integer(rhs)) => lhs.cmp(rhs),
+            (Integer(lhs), Float(rhs)) => cmp_integer_float(*lhs, *rhs).then(Ordering::Less),
+            (Float(lhs), Integer(rhs)) => cmp_integer_float(*rhs, *lhs)
+                .reverse()
+                .then(Ordering::Greater),
+            (Float(lhs), Float(rhs)) => cmp_floats(*lhs, *rhs),
+            (String(lhs), String(rhs)) => lhs.cmp(rhs),
+            (lhs, rhs) => lhs.type_rank().cmp(&rhs.type_rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Null => {}
+            Value::Boolean(b) => b.hash(state),
+            Value::Integer(i) => i.hash(state),
+            // Hash equal floats the same, i.e. -0.0 as 0.0 and all NaNs alike
+            Value::Float(f) if *f == 0.0 => 0.0_f64.to_bits().hash(state),
+            Value::Float(f) if f.is_nan() => f64::NAN.to_bits().hash(state),
+            Value::Float(f) => f.to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+        }
+    }
+}
+
 impl From<bool> for Value {
     fn from(v: bool) -> Self {
         Value::Boolean(v)
@@ -72,3 +211,131 @@ impl From<&str> for Value {
 
 /// A row of values
 pub type Row = Vec<Value>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn ordering() {
+        use Value::*;
+        let sorted = vec![
+            Null,
+            Boolean(false),
+            Boolean(true),
+            Float(f64::NEG_INFINITY),
+            Integer(i64::MIN),
+            Integer(-1),
+            Float(-0.5),
+            Integer(0),
+            Float(-0.0),
+            Float(0.5),
+            Integer(9_007_199_254_740_992),
+            Float(9_007_199_254_740_992.0),
+            Integer(9_007_199_254_740_993),
+            Integer(i64::MAX),
+            Float(f64::INFINITY),
+            Float(f64::NAN),
+            String("".into()),
+            String("a".into()),
+        ];
+        let mut values = sorted.clone();
+        values.reverse();
+        values.sort();
+        assert_eq!(sorted, values);
+
+        assert_eq!(Float(0.0), Float(-0.0));
+        assert_eq!(Float(f64::NAN), Float(-f64::NAN));
+        assert_ne!(Integer(1), Float(1.0));
+
+        let set: HashSet<Value> = vec![Float(0.0), Float(-0.0), Float(f64::NAN), Float(-f64::NAN)]
+            .into_iter()
+            .collect();
+        assert_eq!(2, set.len());
+    }
+
+    #[test]
+    fn compare() {
+        use Value::*;
+        let cases = vec![
+            (Null, Null, None),
+            (Null, Integer(1), None),
+            (String("a".into()), Null, None),
+            (Boolean(false), Boolean(true), Some(Ordering::Less)),
+            (Boolean(true), Boolean(true), Some(Ordering::Equal)),
+            (Integer(1), Integer(2), Some(Ordering::Less)),
+            (Integer(1), Float(1.0), Some(Ordering::Equal)),
+            (Float(1.0), Integer(1), Some(Ordering::Equal)),
+            (Integer(1), Float(1.5), Some(Ordering::Less)),
+            (Float(-1.5), Integer(-1), Some(Ordering::Less)),
+            (Integer(-1), Float(-1.5), Some(Ordering::Greater)),
+            (Integer(i64::MAX), Float(9.3e18), Some(Ordering::Less)),
+            (Integer(i64::MIN), Float(-9.3e18), Some(Ordering::Greater)),
+            (
+                Integer(i64::MAX),
+                Float(f64::INFINITY),
+                Some(Ordering::Less),
+            ),
+            (
+                Integer(i64::MIN),
+                Float(f64::NEG_INFINITY),
+                Some(Ordering::Greater),
+            ),
+            (Integer(i64::MAX), Float(f64::NAN), Some(Ordering::Less)),
+            (Integer(0), Float(-0.0), Some(Ordering::Equal)),
+            (Float(0.0), Float(-0.0), Some(Ordering::Equal)),
+            (Float(f64::NAN), Float(f64::NAN), Some(Ordering::Equal)),
+            (
+                Float(f64::NAN),
+                Float(f64::INFINITY),
+                Some(Ordering::Greater),
+            ),
+            (
+                Integer(9_007_199_254_740_993),
+                Float(9_007_199_254_740_992.0),
+                Some(Ordering::Greater),
+            ),
+            (String("a".into()), String("b".into()), Some(Ordering::Less)),
+            (
+                String("b".into()),
+                String("ab".into()),
+                Some(Ordering::Greater),
+            ),
+        ];
+        for (lhs, rhs, expect) in cases {
+            assert_eq!(
+                expect,
+                lhs.compare(&rhs).unwrap(),
+                "{:?} <=> {:?}",
+                lhs,
+                rhs
+            );
+        }
+
+        let incomparable = vec![
+            (Boolean(true), Integer(1)),
+            (Integer(1), String("1".into())),
+            (Float(1.0), Boolean(true)),
+            (String("TRUE".into()), Boolean(true)),
+        ];
+        for (lhs, rhs) in incomparable {
+            assert_matches!(lhs.compare(&rhs), Err(Error::Value(_)));
+            assert_matches!(rhs.compare(&lhs), Err(Error::Value(_)));
+        }
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!("1.0", Value::Float(1.0).to_string());
+        assert_eq!("0.1", Value::Float(0.1).to_string());
+        assert_eq!("1e20", Value::Float(1e20).to_string());
+        assert_eq!("-0.0", Value::Float(-0.0).to_string());
+
+        // Keys keep their original format
+        assert_eq!("1", Value::Float(1.0).to_key());
+        assert_eq!("0", Value::Float(-0.0).to_key());
+        assert_eq!("100000000000000000000", Value::Float(1e20).to_key());
+        assert_eq!("7", Value::Integer(7).to_key());
+    }
+}
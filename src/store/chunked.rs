@@ -0,0 +1,352 @@
+use super::{Bytes, Direction, Range, Store};
+use crate::Error;
+
+/// The default maximum chunk size
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The key prefix of value chunks, which are hidden from iteration. Keys
+/// starting with a NUL byte are therefore reserved.
+const CHUNK_PREFIX: &str = "\u{0}chunk\u{0}";
+
+/// The record flag for values stored inline
+const FLAG_INLINE: u8 = 0x00;
+/// The record flag for manifests of chunked values
+const FLAG_MANIFEST: u8 = 0x01;
+
+/// A value being written chunk by chunk, see Chunked::start_upload().
+#[derive(Debug)]
+pub struct Upload {
+    key: String,
+    id: u64,
+    buffer: Vec<u8>,
+    count: u64,
+    size: u64,
+}
+
+impl Upload {
+    /// Returns the key the value is written to.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// A store wrapper which transparently splits values larger than the chunk
+/// size across multiple chunk keys, with a manifest record under the value
+/// key holding the number of chunks.
+#[derive(Debug)]
+pub struct Chunked<S: Store> {
+    inner: S,
+    chunk_size: usize,
+}
+
+impl<S: Store> Chunked<S> {
+    /// Creates a new chunked store wrapping an inner store.
+    pub fn new(inner: S, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "Chunk size must be positive");
+        Self { inner, chunk_size }
+    }
+
+    /// Returns the chunks of a value, for streaming. Values stored inline
+    /// are returned as a single chunk.
+    pub fn get_chunks(&self, key: &str) -> Result<Option<Vec<Bytes>>, Error> {
+        let record = match self.inner.get(key)? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        match decode_flag(&record)? {
+            FLAG_INLINE => Ok(Some(vec![record.slice_from(1)])),
+            _ => (0..decode_manifest(&record)?)
+                .map(|i| {
+                    self.inner.get(&chunk_key(key, i))?.ok_or_else(|| {
+                        Error::Value(format!("Missing chunk {} of value {}", i, key))
+                    })
+                })
+                .collect::<Result<_, Error>>()
+                .map(Some),
+        }
+    }
+
+    /// Starts writing a value chunk by chunk, e.g. as it is streamed, without
+    /// buffering the whole value in memory. Complete chunks are staged under
+    /// hidden keys, and the current value is kept until the upload finishes.
+    pub fn start_upload(&self, key: &str) -> Upload {
+        Upload {
+            key: key.to_string(),
+            id: rand::random(),
+            buffer: Vec::new(),
+            count: 0,
+            size: 0,
+        }
+    }
+
+    /// Appends data to an upload, staging each complete chunk.
+    pub fn write_upload(&mut self, upload: &mut Upload, data: &[u8]) -> Result<(), Error> {
+        upload.size += data.len() as u64;
+        upload.buffer.extend_from_slice(data);
+        while upload.buffer.len() >= self.chunk_size {
+            let rest = upload.buffer.split_off(self.chunk_size);
+            let chunk = std::mem::replace(&mut upload.buffer, rest);
+            self.inner
+                .set(&upload_key(upload.id, upload.count), chunk)?;
+            upload.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Finishes an upload, replacing the value with the staged chunks.
+    pub fn finish_upload(&mut self, mut upload: Upload) -> Result<(), Error> {
+        if upload.count == 0 {
+            return self.set(&upload.key, upload.buffer);
+        }
+        if !upload.buffer.is_empty() {
+            let chunk = std::mem::take(&mut upload.buffer);
+            self.inner
+                .set(&upload_key(upload.id, upload.count), chunk)?;
+            upload.count += 1;
+        }
+        self.delete_chunks(&upload.key, upload.count)?;
+        for i in 0..upload.count {
+            let staged = upload_key(upload.id, i);
+            let chunk = self.inner.get(&staged)?.ok_or_else(|| {
+                Error::Value(format!(
+                    "Missing staged chunk {} of value {}",
+                    i, upload.key
+                ))
+            })?;
+            self.inner.set(&chunk_key(&upload.key, i), chunk.to_vec())?;
+            self.inner.delete(&staged)?;
+        }
+        let mut manifest = vec![FLAG_MANIFEST];
+        manifest.extend_from_slice(&upload.count.to_be_bytes());
+        self.inner.set(&upload.key, manifest)
+    }
+
+    /// Aborts an upload, deleting the staged chunks.
+    pub fn abort_upload(&mut self, upload: Upload) -> Result<(), Error> {
+        for i in 0..upload.count {
+            self.inner.delete(&upload_key(upload.id, i))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of chunks stored for a key.
+    fn chunk_count(&self, key: &str) -> Result<u64, Error> {
+        match self.inner.get(key)? {
+            Some(record) if decode_flag(&record)? == FLAG_MANIFEST => decode_manifest(&record),
+            _ => Ok(0),
+        }
+    }
+
+    /// Deletes chunks of a key, starting at the given index.
+    fn delete_chunks(&mut self, key: &str, from: u64) -> Result<(), Error> {
+        for i in from..self.chunk_count(key)? {
+            self.inner.delete(&chunk_key(key, i))?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Store> Store for Chunked<S> {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        // Values span several keys, so this relies on the exclusive access
+        // given by &mut self rather than on the inner store.
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), Error> {
+        self.delete_chunks(key, 0)?;
+        self.inner.delete(key)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        Ok(self.get_chunks(key)?.map(|mut chunks| match chunks.len() {
+            1 => chunks.remove(0),
+            _ => chunks.concat().into(),
+        }))
+    }
+
+    fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        if value.len() <= self.chunk_size {
+            self.delete_chunks(key, 0)?;
+            let mut record = Vec::with_capacity(value.len() + 1);
+            record.push(FLAG_INLINE);
+            record.extend(value);
+            return self.inner.set(key, record);
+        }
+        let count = value.chunks(self.chunk_size).len() as u64;
+        self.delete_chunks(key, count)?;
+        for (i, chunk) in value.chunks(self.chunk_size).enumerate() {
+            self.inner.set(&chunk_key(key, i as u64), chunk.to_vec())?;
+        }
+        let mut manifest = vec![FLAG_MANIFEST];
+        manifest.extend_from_slice(&count.to_be_bytes());
+        self.inner.set(key, manifest)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        // Chunk keys are filtered out below, so the limit can only be pushed
+        // down to the inner store if the prefix can't match them.
+        let inner_limit = if CHUNK_PREFIX.starts_with(prefix) || prefix.starts_with(CHUNK_PREFIX) {
+            None
+        } else {
+            limit
+        };
+        let pairs = self
+            .inner
+            .scan_prefix(prefix, direction, inner_limit)
+            .filter(|r| match r {
+                Ok((key, _)) => !key.starts_with(CHUNK_PREFIX),
+                Err(_) => true,
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|r| {
+                let (key, _) = r?;
+                let value = self.get(&key)?.ok_or(Error::NotFound)?;
+                Ok((key, value))
+            })
+            .collect::<Vec<_>>();
+        Box::new(pairs.into_iter())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(Chunked::new(
+            self.inner.snapshot()?,
+            self.chunk_size,
+        )))
+    }
+}
+
+/// Returns the key of a value chunk
+fn chunk_key(key: &str, index: u64) -> String {
+    format!("{}{}\u{0}{:016x}", CHUNK_PREFIX, key, index)
+}
+
+/// Returns the key of a staged upload chunk. Value keys can't start with a NUL
+/// byte, so these don't clash with value chunk keys.
+fn upload_key(id: u64, index: u64) -> String {
+    format!(
+        "{}\u{0}upload\u{0}{:016x}\u{0}{:016x}",
+        CHUNK_PREFIX, id, index
+    )
+}
+
+/// Returns the flag of a record
+fn decode_flag(record: &[u8]) -> Result<u8, Error> {
+    match record.first() {
+        Some(&flag) if flag == FLAG_INLINE || flag == FLAG_MANIFEST => Ok(flag),
+        Some(flag) => Err(Error::Value(format!("Unknown chunk record flag {}", flag))),
+        None => Err(Error::Value("Missing chunk record flag".into())),
+    }
+}
+
+/// Returns the number of chunks in a manifest record
+fn decode_manifest(record: &[u8]) -> Result<u64, Error> {
+    let mut count = [0; 8];
+    if record.len() != count.len() + 1 {
+        return Err(Error::Value("Invalid chunk manifest".into()));
+    }
+    count.copy_from_slice(&record[1..]);
+    Ok(u64::from_be_bytes(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::Suite;
+    use super::super::KVMemory;
+    use super::*;
+
+    #[test]
+    fn suite() {
+        Suite::new(|| Box::new(Chunked::new(KVMemory::new(), 1))).test()
+    }
+
+    #[test]
+    fn chunked() {
+        let mut s = Chunked::new(KVMemory::new(), 4);
+        s.set("a", vec![0x01, 0x02, 0x03]).unwrap();
+        s.set("b", (0..10).collect()).unwrap();
+        assert_eq!(
+            Some(vec![Bytes::from(vec![0x01, 0x02, 0x03])]),
+            s.get_chunks("a").unwrap()
+        );
+        assert_eq!(
+            Some(vec![
+                Bytes::from(vec![0, 1, 2, 3]),
+                Bytes::from(vec![4, 5, 6, 7]),
+                Bytes::from(vec![8, 9])
+            ]),
+            s.get_chunks("b").unwrap()
+        );
+        assert_eq!(Some((0..10).collect()), s.get("b").unwrap());
+        assert_eq!(None, s.get_chunks("c").unwrap());
+
+        // Chunks are hidden from iteration
+        assert_eq!(
+            vec![
+                ("a".to_string(), vec![0x01, 0x02, 0x03].into()),
+                ("b".to_string(), (0..10).collect()),
+            ],
+            s.iter_prefix("")
+                .collect::<Result<Vec<_>, Error>>()
+                .unwrap()
+        );
+
+        // Stale chunks are removed when values shrink or are deleted
+        s.set("b", (0..6).collect()).unwrap();
+        assert_eq!(Some((0..6).collect()), s.get("b").unwrap());
+        assert_eq!(None, s.inner.get(&chunk_key("b", 2)).unwrap());
+        s.set("b", vec![0x01]).unwrap();
+        assert_eq!(None, s.inner.get(&chunk_key("b", 0)).unwrap());
+        s.set("a", (0..10).collect()).unwrap();
+        s.delete("a").unwrap();
+        assert_eq!(1, s.inner.iter_prefix("").count());
+    }
+
+    #[test]
+    fn upload() {
+        let mut s = Chunked::new(KVMemory::new(), 4);
+        s.set("a", (0..14).collect()).unwrap();
+
+        // The old value is kept until the upload finishes
+        let mut upload = s.start_upload("a");
+        for data in &[vec![0, 1, 2], vec![3, 4, 5, 6, 7], vec![8]] {
+            s.write_upload(&mut upload, data).unwrap();
+        }
+        assert_eq!(9, upload.size());
+        assert_eq!(Some((0..14).collect()), s.get("a").unwrap());
+        s.finish_upload(upload).unwrap();
+        assert_eq!(
+            Some(vec![
+                Bytes::from(vec![0, 1, 2, 3]),
+                Bytes::from(vec![4, 5, 6, 7]),
+                Bytes::from(vec![8])
+            ]),
+            s.get_chunks("a").unwrap()
+        );
+        assert_eq!(None, s.inner.get(&chunk_key("a", 3)).unwrap());
+        assert_eq!(4, s.inner.iter_prefix("").count());
+
+        // Small values are stored inline
+        let mut upload = s.start_upload("b");
+        s.write_upload(&mut upload, &[0x01, 0x02]).unwrap();
+        s.finish_upload(upload).unwrap();
+        assert_eq!(Some(vec![0x01, 0x02].into()), s.get("b").unwrap());
+
+        // Aborted uploads leave no staged chunks behind
+        let mut upload = s.start_upload("b");
+        s.write_upload(&mut upload, &(0..10).collect::<Vec<u8>>())
+            .unwrap();
+        s.abort_upload(upload).unwrap();
+        assert_eq!(Some(vec![0x01, 0x02].into()), s.get("b").unwrap());
+        assert_eq!(5, s.inner.iter_prefix("").count());
+    }
+}
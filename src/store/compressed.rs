@@ -0,0 +1,209 @@
+use super::{Bytes, Direction, Range, Store};
+use crate::Error;
+
+/// Values smaller than this are not compressed, since the savings are
+/// negligible.
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// A compression algorithm. The discriminant is used as the per-record flag,
+/// both in store records and Raft entries on the wire.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(Error::Config(format!(
+                "Invalid compression {}, expected one of none, lz4 or zstd",
+                s
+            ))),
+        }
+    }
+}
+
+impl Compression {
+    /// Returns the compression for a record flag.
+    pub fn from_flag(flag: u8) -> Result<Self, Error> {
+        match flag {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Lz4),
+            2 => Ok(Self::Zstd),
+            f => Err(Error::Value(format!("Unknown compression flag {}", f))),
+        }
+    }
+
+    /// Compresses a value if it is above the compression threshold and gets
+    /// smaller, returning the compression actually used and the payload.
+    pub fn compress(self, value: Vec<u8>) -> Result<(Self, Vec<u8>), Error> {
+        if value.len() < COMPRESSION_THRESHOLD {
+            return Ok((Self::None, value));
+        }
+        let compressed = match self {
+            Self::None => return Ok((Self::None, value)),
+            Self::Lz4 => lz4_flex::compress_prepend_size(&value),
+            Self::Zstd => zstd::stream::encode_all(value.as_slice(), 0)?,
+        };
+        if compressed.len() < value.len() {
+            Ok((self, compressed))
+        } else {
+            Ok((Self::None, value))
+        }
+    }
+
+    /// Decompresses a payload compressed with this compression.
+    pub fn decompress(self, payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::None => Ok(payload),
+            Self::Lz4 => lz4_flex::decompress_size_prepended(&payload)
+                .map_err(|err| Error::Value(format!("Invalid lz4 payload: {}", err))),
+            Self::Zstd => Ok(zstd::stream::decode_all(payload.as_slice())?),
+        }
+    }
+}
+
+/// A store wrapper which compresses large values. Each record is prefixed by
+/// a compression flag, so records written with different (or no) compression
+/// settings remain readable.
+#[derive(Debug)]
+pub struct Compressed<S: Store> {
+    inner: S,
+    compression: Compression,
+}
+
+impl<S: Store> Compressed<S> {
+    /// Creates a new compressed store wrapping an inner store.
+    pub fn new(inner: S, compression: Compression) -> Self {
+        Self { inner, compression }
+    }
+}
+
+/// Encodes a value as a flagged record.
+pub fn encode(compression: Compression, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let (compression, payload) = compression.compress(value)?;
+    let mut record = Vec::with_capacity(payload.len() + 1);
+    record.push(compression as u8);
+    record.extend(payload);
+    Ok(record)
+}
+
+/// Decodes a flagged record. Uncompressed values share the record buffer.
+pub fn decode(record: Bytes) -> Result<Bytes, Error> {
+    if record.is_empty() {
+        return Err(Error::Value("Missing compression flag in record".into()));
+    }
+    match Compression::from_flag(record[0])? {
+        Compression::None => Ok(record.slice_from(1)),
+        compression => Ok(compression.decompress(record[1..].to_vec())?.into()),
+    }
+}
+
+impl<S: Store> Store for Compressed<S> {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        // Compare the decoded value, but swap against the stored record, such
+        // that the inner store detects concurrent changes.
+        let record = self.inner.get(key)?;
+        let current = record.clone().map(decode).transpose()?;
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        self.inner
+            .cas(key, record.as_deref(), encode(self.compression, value)?)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), Error> {
+        self.inner.delete(key)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        self.inner.get(key)?.map(decode).transpose()
+    }
+
+    fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.inner.set(key, encode(self.compression, value)?)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        Box::new(
+            self.inner
+                .scan_prefix(prefix, direction, limit)
+                .map(|r| r.and_then(|(key, record)| Ok((key, decode(record)?)))),
+        )
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(Compressed::new(
+            self.inner.snapshot()?,
+            self.compression,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::Suite;
+    use super::super::KVMemory;
+    use super::*;
+
+    #[test]
+    fn suite() {
+        Suite::new(|| Box::new(Compressed::new(KVMemory::new(), Compression::Lz4))).test()
+    }
+
+    #[test]
+    fn compression() {
+        let large = vec![0x01; COMPRESSION_THRESHOLD * 4];
+        for &compression in &[Compression::Lz4, Compression::Zstd] {
+            let (used, payload) = compression.compress(large.clone()).unwrap();
+            assert_eq!(compression, used);
+            assert!(payload.len() < large.len());
+            assert_eq!(large, compression.decompress(payload).unwrap());
+
+            // Small values are stored as-is
+            assert_eq!(
+                (Compression::None, vec![0x01]),
+                compression.compress(vec![0x01]).unwrap()
+            );
+        }
+        assert_matches!(Compression::from_flag(7), Err(Error::Value(_)));
+        assert_matches!("gzip".parse::<Compression>(), Err(Error::Config(_)));
+    }
+
+    #[test]
+    fn mixed() {
+        let large = vec![0x01; COMPRESSION_THRESHOLD * 4];
+        let mut s = Compressed::new(KVMemory::new(), Compression::Lz4);
+        s.set("a", large.clone()).unwrap();
+        let record = s.inner.get("a").unwrap().unwrap();
+        assert_eq!(Compression::Lz4 as u8, record[0]);
+        assert!(record.len() < large.len());
+
+        let mut s = Compressed::new(s.inner, Compression::Zstd);
+        s.set("b", large.clone()).unwrap();
+        let mut s = Compressed::new(s.inner, Compression::None);
+        s.set("c", large.clone()).unwrap();
+        assert_eq!(
+            Compression::None as u8,
+            s.inner.get("c").unwrap().unwrap()[0]
+        );
+
+        assert_eq!(
+            vec![
+                ("a".to_string(), large.clone().into()),
+                ("b".to_string(), large.clone().into()),
+                ("c".to_string(), large.into()),
+            ],
+            s.iter_prefix("")
+                .collect::<Result<Vec<_>, Error>>()
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,654 @@
+use super::{Compressed, Compression, Encrypted, File, Keyring, Store, DEFAULT_FLUSH_THRESHOLD};
+use crate::Error;
+use fs2::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The file holding the Raft state machine store
+const STATE_FILE: &str = "statef";
+/// The file holding the Raft log store
+const RAFT_FILE: &str = "raft";
+/// The file holding the cluster ID, if the node has joined a cluster
+const CLUSTER_ID_FILE: &str = "cluster_id";
+/// The file holding the data directory layout version
+const VERSION_FILE: &str = "VERSION";
+/// The lock file, locked exclusively by the process using the data directory
+const LOCK_FILE: &str = "LOCK";
+/// The file recording whether the store values are encrypted
+const ENCRYPTION_FILE: &str = "ENCRYPTION";
+
+/// The data directory layout version written by this binary. Directories
+/// without a version file but with data use the original layout, version 0.
+/// Version 2 prefixes all store values with a compression flag, and version 3
+/// records whether the stores are encrypted and authenticates encrypted
+/// values with their store key.
+pub const LAYOUT_VERSION: u32 = 3;
+
+/// A node data directory, containing the on-disk stores.
+#[derive(Clone, Debug)]
+pub struct DataDir {
+    path: PathBuf,
+    keyring: Option<Arc<Keyring>>,
+    compression: Compression,
+    flush_threshold: u64,
+}
+
+impl DataDir {
+    /// Creates a new data directory handle. The directory is not touched until used.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            keyring: None,
+            compression: Compression::None,
+            flush_threshold: DEFAULT_FLUSH_THRESHOLD,
+        }
+    }
+
+    /// Encrypts store values at rest using the given keyring.
+    pub fn encrypted(mut self, keyring: Arc<Keyring>) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Compresses large store values written from now on. Existing values
+    /// remain readable regardless of the compression setting.
+    pub fn compressed(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the write-ahead log size in bytes at which stores are flushed to
+    /// disk, see File::flush_threshold().
+    pub fn flush_threshold(mut self, bytes: u64) -> Self {
+        self.flush_threshold = bytes;
+        self
+    }
+
+    /// Creates the data directory and empty store files, if they don't exist.
+    /// New data directories are marked with the current layout version and
+    /// whether they are encrypted.
+    pub fn init(&self) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.path)?;
+        if !self.has_data()? {
+            if self.layout_version()?.is_none() {
+                self.set_layout_version(LAYOUT_VERSION)?;
+            }
+            self.set_encryption(self.keyring.is_some())?;
+        }
+        for name in &[STATE_FILE, RAFT_FILE] {
+            self.open_file(name)?;
+        }
+        Ok(())
+    }
+
+    /// Locks the data directory for exclusive use by this process, until the
+    /// returned lock is dropped. Errors if another process holds the lock.
+    pub fn lock(&self) -> Result<Lock, Error> {
+        std::fs::create_dir_all(&self.path)?;
+        let file = self.open_file(LOCK_FILE)?;
+        file.try_lock_exclusive().map_err(|_| {
+            Error::Value(format!(
+                "Data directory {} is locked by another process",
+                self.path.display()
+            ))
+        })?;
+        Ok(Lock { file })
+    }
+
+    /// Checks that the data directory layout can be used by this binary, and
+    /// that its encryption matches the configuration.
+    pub fn check_layout(&self) -> Result<(), Error> {
+        match self.layout_version()? {
+            Some(version) if version > LAYOUT_VERSION => Err(Error::Value(format!(
+                "Data directory {} has layout version {}, but this binary only supports up to {}",
+                self.path.display(),
+                version,
+                LAYOUT_VERSION
+            ))),
+            Some(version) if version == LAYOUT_VERSION => self.check_encryption(),
+            None if !self.has_data()? => Ok(()),
+            version => Err(Error::Value(format!(
+                "Data directory {} has outdated layout version {}, run migrate-data-dir to upgrade it to {}",
+                self.path.display(),
+                version.unwrap_or(0),
+                LAYOUT_VERSION
+            ))),
+        }
+    }
+
+    /// Migrates the data directory to the current layout version, returning
+    /// the version it was migrated from. If encryption is enabled, plaintext
+    /// stores are encrypted.
+    pub fn migrate(&self) -> Result<u32, Error> {
+        let from = match self.layout_version()? {
+            Some(version) => version,
+            None if self.has_data()? => 0,
+            None => LAYOUT_VERSION,
+        };
+        if from > LAYOUT_VERSION {
+            return self.check_layout().map(|_| from);
+        }
+        for version in from..LAYOUT_VERSION {
+            match version {
+                // Version 1 only added the version file, the stores are unchanged.
+                0 => {}
+                1 => {
+                    for name in &[STATE_FILE, RAFT_FILE] {
+                        self.add_compression_flags(name)?;
+                    }
+                }
+                2 => self.migrate_encryption()?,
+                v => {
+                    return Err(Error::Internal(format!(
+                        "No migration for layout version {}",
+                        v
+                    )))
+                }
+            }
+            self.set_layout_version(version + 1)?;
+        }
+        self.migrate_encryption()?;
+        Ok(from)
+    }
+
+    /// Checks that the stores are encrypted if and only if encryption is
+    /// enabled, since values can't be read otherwise.
+    fn check_encryption(&self) -> Result<(), Error> {
+        match (self.encryption()?, self.keyring.is_some()) {
+            (Some(true), true) | (Some(false), false) => Ok(()),
+            (None, _) if !self.has_data()? => Ok(()),
+            (Some(true), false) => Err(Error::Value(format!(
+                "Data directory {} is encrypted, but no encryption key file is configured",
+                self.path.display()
+            ))),
+            (_, true) => Err(Error::Value(format!(
+                "Data directory {} is not encrypted, run migrate-data-dir to encrypt it",
+                self.path.display()
+            ))),
+            (None, false) => Err(Error::Value(format!(
+                "Data directory {} has no {} file, run migrate-data-dir to add it",
+                self.path.display(),
+                ENCRYPTION_FILE
+            ))),
+        }
+    }
+
+    /// Encrypts plaintext stores if encryption is enabled, re-encrypting
+    /// legacy records, and records the encryption in the encryption file. The
+    /// stores are compacted to remove the old records.
+    fn migrate_encryption(&self) -> Result<(), Error> {
+        let keyring = match (&self.keyring, self.encryption()?) {
+            (None, Some(true)) => return self.check_encryption(),
+            (None, _) => return self.set_encryption(false),
+            (Some(_), Some(true)) => return Ok(()),
+            (Some(keyring), _) => keyring,
+        };
+        for name in &[STATE_FILE, RAFT_FILE] {
+            let mut store = Encrypted::new(File::open(self.path.join(name))?, keyring.clone());
+            if store.is_encrypted()? {
+                store.reencrypt()?;
+            } else {
+                store.encrypt_plaintext()?;
+            }
+            store.into_inner().compact()?;
+        }
+        self.set_encryption(true)
+    }
+
+    /// Reads whether the stores are encrypted, if recorded.
+    fn encryption(&self) -> Result<Option<bool>, Error> {
+        match std::fs::read_to_string(self.path.join(ENCRYPTION_FILE)) {
+            Ok(encryption) => match encryption.trim() {
+                "aes256gcm" => Ok(Some(true)),
+                "none" => Ok(Some(false)),
+                e => Err(Error::Value(format!(
+                    "Unknown data directory encryption {}",
+                    e
+                ))),
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Records whether the stores are encrypted.
+    fn set_encryption(&self, encrypted: bool) -> Result<(), Error> {
+        let encryption = if encrypted { "aes256gcm" } else { "none" };
+        std::fs::write(self.path.join(ENCRYPTION_FILE), format!("{}\n", encryption))?;
+        Ok(())
+    }
+
+    /// Reads the layout version, if any.
+    fn layout_version(&self) -> Result<Option<u32>, Error> {
+        match std::fs::read_to_string(self.path.join(VERSION_FILE)) {
+            Ok(version) => Ok(Some(version.trim().parse()?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the layout version.
+    fn set_layout_version(&self, version: u32) -> Result<(), Error> {
+        std::fs::write(self.path.join(VERSION_FILE), format!("{}\n", version))?;
+        Ok(())
+    }
+
+    /// Opens the Raft state machine store, creating it if necessary.
+    pub fn open_state(&self) -> Result<Box<dyn Store>, Error> {
+        self.open_store(STATE_FILE)
+    }
+
+    /// Opens the Raft log store, creating it if necessary.
+    pub fn open_raft(&self) -> Result<Box<dyn Store>, Error> {
+        self.open_store(RAFT_FILE)
+    }
+
+    /// Checks the Raft log store for consistency, see Log::check().
+    pub fn check_raft_log(&self) -> Result<crate::raft::LogReport, Error> {
+        Ok(crate::raft::Log::check(&self.open_raft()?))
+    }
+
+    /// Repairs a torn tail of the Raft log store, returning the removed entry
+    /// indexes, see Log::repair().
+    pub fn repair_raft_log(&self) -> Result<Vec<u64>, Error> {
+        crate::raft::Log::repair(&mut self.open_raft()?)
+    }
+
+    /// Describes the Raft log entries human-readably, one line per entry.
+    #[cfg(feature = "server")]
+    pub fn dump_raft_log(&self) -> Result<Vec<String>, Error> {
+        let log = crate::raft::Log::new(self.open_raft()?)?;
+        let (last_index, _) = log.get_last();
+        let mut lines = Vec::new();
+        for index in 1..=last_index {
+            let line = match log.get(index)? {
+                Some(entry) => format!(
+                    "{} term {}: {}",
+                    index,
+                    entry.term,
+                    match &entry.command {
+                        Some(command) => super::Raft::describe_command(command),
+                        None => "noop".into(),
+                    }
+                ),
+                None => format!("{} missing", index),
+            };
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    /// Inspects the SQL tables and rows in the state machine store, optionally
+    /// deleting orphaned rows, see Storage::inspect().
+    pub fn inspect_sql(&self, repair: bool) -> Result<crate::sql::StorageReport, Error> {
+        let mut storage = crate::sql::Storage::new(self.open_state()?);
+        let report = storage.inspect()?;
+        if repair {
+            storage.delete_orphans(&report)?;
+        }
+        Ok(report)
+    }
+
+    /// Returns the on-disk sizes in bytes of the state machine store and the
+    /// Raft log store, including write-ahead logs.
+    pub fn store_sizes(&self) -> Result<(u64, u64), Error> {
+        let size = |name| -> Result<u64, Error> {
+            let mut size = 0;
+            for file in File::files(self.path.join(name))? {
+                size += std::fs::metadata(file)?.len();
+            }
+            Ok(size)
+        };
+        Ok((size(STATE_FILE)?, size(RAFT_FILE)?))
+    }
+
+    /// Reads the cluster ID, if any.
+    pub fn cluster_id(&self) -> Result<Option<String>, Error> {
+        match std::fs::read_to_string(self.path.join(CLUSTER_ID_FILE)) {
+            Ok(id) if !id.trim().is_empty() => Ok(Some(id.trim().to_string())),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the cluster ID.
+    pub fn set_cluster_id(&self, id: &str) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.path)?;
+        std::fs::write(self.path.join(CLUSTER_ID_FILE), format!("{}\n", id))?;
+        Ok(())
+    }
+
+    /// Copies the store files, including write-ahead logs, into an empty or
+    /// missing backup directory. The node must not be running, since the
+    /// stores are copied as-is.
+    pub fn backup<P: AsRef<Path>>(&self, dest: P) -> Result<(), Error> {
+        let dest = dest.as_ref();
+        for name in &[STATE_FILE, RAFT_FILE] {
+            if !self.path.join(name).exists() {
+                return Err(Error::Value(format!(
+                    "Data directory {} has no {} file",
+                    self.path.display(),
+                    name
+                )));
+            }
+        }
+        std::fs::create_dir_all(dest)?;
+        if std::fs::read_dir(dest)?.next().is_some() {
+            return Err(Error::Value(format!(
+                "Backup directory {} is not empty",
+                dest.display()
+            )));
+        }
+        copy_files(&self.path, dest)
+    }
+
+    /// Restores the store files from a backup directory. The node must not be
+    /// running, and the data directory must not contain any data.
+    pub fn restore<P: AsRef<Path>>(&self, src: P) -> Result<(), Error> {
+        let src = src.as_ref();
+        for name in &[STATE_FILE, RAFT_FILE] {
+            if !src.join(name).is_file() {
+                return Err(Error::Value(format!(
+                    "Backup directory {} has no {} file",
+                    src.display(),
+                    name
+                )));
+            }
+        }
+        if self.has_data()? {
+            return Err(Error::Value(format!(
+                "Data directory {} already contains data, refusing to overwrite it",
+                self.path.display()
+            )));
+        }
+        std::fs::create_dir_all(&self.path)?;
+        copy_files(src, &self.path)
+    }
+
+    /// Compacts the store files. The node must not be running.
+    /// If encryption is enabled, values are re-encrypted with the active key.
+    pub fn compact(&self) -> Result<(), Error> {
+        for name in &[STATE_FILE, RAFT_FILE] {
+            let mut file = File::open(self.path.join(name))?;
+            if let Some(keyring) = &self.keyring {
+                let mut store = Encrypted::new(file, keyring.clone());
+                store.reencrypt()?;
+                file = store.into_inner();
+            }
+            file.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether any of the stores contain data
+    fn has_data(&self) -> Result<bool, Error> {
+        for name in &[STATE_FILE, RAFT_FILE] {
+            if File::open(self.path.join(name))?
+                .iter_prefix("")
+                .next()
+                .is_some()
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Prefixes all values in a store with an uncompressed flag, for the
+    /// migration to layout version 2. Plaintext stores are left in plaintext
+    /// even if encryption is enabled, and are encrypted by a later migration.
+    fn add_compression_flags(&self, name: &str) -> Result<(), Error> {
+        let file = File::open(self.path.join(name))?;
+        let mut store: Box<dyn Store> = match &self.keyring {
+            Some(keyring) => {
+                let mut encrypted = Encrypted::new(file, keyring.clone());
+                if encrypted.is_encrypted()? {
+                    // Upgrade legacy records, which can't be read otherwise.
+                    encrypted.reencrypt()?;
+                    Box::new(encrypted)
+                } else {
+                    Box::new(encrypted.into_inner())
+                }
+            }
+            None => Box::new(file),
+        };
+        let records = store.iter_prefix("").collect::<Result<Vec<_>, Error>>()?;
+        for (key, value) in records {
+            store.set(
+                &key,
+                super::compressed::encode(Compression::None, value.to_vec())?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Opens a store file, creating it if necessary.
+    fn open_store(&self, name: &str) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(Compressed::new(
+            self.open_uncompressed(name)?,
+            self.compression,
+        )))
+    }
+
+    /// Opens a store file without the compression layer, i.e. with raw
+    /// (possibly encrypted) records.
+    fn open_uncompressed(&self, name: &str) -> Result<Box<dyn Store>, Error> {
+        let file = File::open(self.path.join(name))?.flush_threshold(self.flush_threshold);
+        Ok(match &self.keyring {
+            Some(keyring) => Box::new(Encrypted::new(file, keyring.clone())),
+            None => Box::new(file),
+        })
+    }
+
+    /// Opens a file in the data directory for reading and writing, creating it
+    /// if necessary.
+    fn open_file(&self, name: &str) -> Result<std::fs::File, Error> {
+        Ok(std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path.join(name))?)
+    }
+}
+
+/// Copies the store files, cluster ID, version and encryption files between
+/// directories.
+fn copy_files(src: &Path, dest: &Path) -> Result<(), Error> {
+    for name in &[STATE_FILE, RAFT_FILE] {
+        for file in File::files(src.join(name))? {
+            if let Some(file_name) = file.file_name() {
+                std::fs::copy(&file, dest.join(file_name))?;
+            }
+        }
+    }
+    for name in &[CLUSTER_ID_FILE, VERSION_FILE, ENCRYPTION_FILE] {
+        if src.join(name).exists() {
+            std::fs::copy(src.join(name), dest.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+/// An exclusive data directory lock, released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    file: std::fs::File,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        self.file.unlock().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempfile;
+    use super::super::Bytes;
+    use super::*;
+
+    #[test]
+    fn backup_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = DataDir::new(dir.path().join("data"));
+        assert_matches!(data.backup(dir.path().join("backup")), Err(Error::Value(_)));
+        assert_eq!((0, 0), data.store_sizes().unwrap());
+
+        data.init().unwrap();
+        data.open_state().unwrap().set("a", vec![0x01]).unwrap();
+        data.open_raft().unwrap().set("b", vec![0x02]).unwrap();
+        let (state_size, raft_size) = data.store_sizes().unwrap();
+        assert!(state_size > 0 && raft_size > 0);
+        data.set_cluster_id("cluster").unwrap();
+        data.backup(dir.path().join("backup")).unwrap();
+        assert_matches!(data.backup(dir.path().join("backup")), Err(Error::Value(_)));
+        assert_matches!(
+            data.restore(dir.path().join("backup")),
+            Err(Error::Value(_))
+        );
+
+        let restored = DataDir::new(dir.path().join("restored"));
+        restored.init().unwrap();
+        restored.compact().unwrap();
+        assert_matches!(
+            restored.restore(dir.path().join("missing")),
+            Err(Error::Value(_))
+        );
+        restored.restore(dir.path().join("backup")).unwrap();
+        assert_eq!(Some("cluster".to_string()), restored.cluster_id().unwrap());
+        assert_eq!(
+            Some(vec![0x01].into()),
+            restored.open_state().unwrap().get("a").unwrap()
+        );
+        assert_eq!(
+            Some(vec![0x02].into()),
+            restored.open_raft().unwrap().get("b").unwrap()
+        );
+
+        restored.compact().unwrap();
+        assert_eq!(
+            Some(vec![0x01].into()),
+            restored.open_state().unwrap().get("a").unwrap()
+        );
+    }
+
+    #[test]
+    fn lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = DataDir::new(dir.path());
+        let lock = data.lock().unwrap();
+        assert_matches!(data.lock(), Err(Error::Value(_)));
+        drop(lock);
+        data.lock().unwrap();
+    }
+
+    #[test]
+    fn layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = DataDir::new(dir.path());
+        data.check_layout().unwrap();
+        data.init().unwrap();
+        assert_eq!(Some(LAYOUT_VERSION), data.layout_version().unwrap());
+        data.check_layout().unwrap();
+
+        data.set_layout_version(LAYOUT_VERSION + 1).unwrap();
+        assert_matches!(data.check_layout(), Err(Error::Value(_)));
+        assert_matches!(data.migrate(), Err(Error::Value(_)));
+
+        // A directory with data but no version file has the original layout,
+        // without compression flags
+        std::fs::remove_file(dir.path().join(VERSION_FILE)).unwrap();
+        data.open_uncompressed(STATE_FILE)
+            .unwrap()
+            .set("a", vec![0x01])
+            .unwrap();
+        data.init().unwrap();
+        assert_eq!(None, data.layout_version().unwrap());
+        assert_matches!(data.check_layout(), Err(Error::Value(_)));
+        assert_eq!(0, data.migrate().unwrap());
+        data.check_layout().unwrap();
+        assert_eq!(
+            Some(vec![0x01].into()),
+            data.open_state().unwrap().get("a").unwrap()
+        );
+        assert_eq!(LAYOUT_VERSION, data.migrate().unwrap());
+    }
+
+    #[test]
+    fn encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_a = "a 000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        let key_b = "b 1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+        let data = DataDir::new(dir.path()).encrypted(Arc::new(Keyring::parse(key_a).unwrap()));
+        data.init().unwrap();
+        data.open_state()
+            .unwrap()
+            .set("a", b"secret".to_vec())
+            .unwrap();
+        let raw = std::fs::read(dir.path().join(STATE_FILE)).unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+
+        // Compaction re-encrypts values with the active key
+        let rotated = DataDir::new(dir.path()).encrypted(Arc::new(
+            Keyring::parse(&format!("{}\n{}", key_a, key_b)).unwrap(),
+        ));
+        rotated.compact().unwrap();
+        let data = DataDir::new(dir.path()).encrypted(Arc::new(Keyring::parse(key_b).unwrap()));
+        data.check_layout().unwrap();
+        assert_eq!(
+            Some(Bytes::from_static(b"secret")),
+            data.open_state().unwrap().get("a").unwrap()
+        );
+
+        // Encryption can't be disabled
+        let plain = DataDir::new(dir.path());
+        assert_matches!(plain.check_layout(), Err(Error::Value(_)));
+        assert_matches!(plain.migrate(), Err(Error::Value(_)));
+    }
+
+    #[test]
+    fn encrypt_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = "a 000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        let plain = DataDir::new(dir.path());
+        plain.init().unwrap();
+        plain
+            .open_state()
+            .unwrap()
+            .set("a", b"secret".to_vec())
+            .unwrap();
+        plain.open_raft().unwrap().set("b", vec![0x02]).unwrap();
+        assert_eq!(Some(false), plain.encryption().unwrap());
+
+        // Enabling encryption requires a migration, which encrypts the stores
+        let data = DataDir::new(dir.path()).encrypted(Arc::new(Keyring::parse(key).unwrap()));
+        data.init().unwrap();
+        assert_matches!(data.check_layout(), Err(Error::Value(_)));
+        assert_eq!(LAYOUT_VERSION, data.migrate().unwrap());
+        data.check_layout().unwrap();
+        assert_eq!(Some(true), data.encryption().unwrap());
+        let raw = std::fs::read(dir.path().join(STATE_FILE)).unwrap();
+        assert!(!raw.windows(6).any(|w| w == b"secret"));
+        assert_eq!(
+            Some(Bytes::from_static(b"secret")),
+            data.open_state().unwrap().get("a").unwrap()
+        );
+        assert_eq!(
+            Some(vec![0x02].into()),
+            data.open_raft().unwrap().get("b").unwrap()
+        );
+
+        // Directories from before the encryption file are migrated too
+        std::fs::remove_file(dir.path().join(ENCRYPTION_FILE)).unwrap();
+        data.set_layout_version(2).unwrap();
+        assert_matches!(data.check_layout(), Err(Error::Value(_)));
+        assert_eq!(2, data.migrate().unwrap());
+        data.check_layout().unwrap();
+        assert_eq!(
+            Some(Bytes::from_static(b"secret")),
+            data.open_state().unwrap().get("a").unwrap()
+        );
+    }
+}
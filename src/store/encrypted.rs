@@ -0,0 +1,421 @@
+use super::{Bytes, Direction, Range, Store};
+use crate::Error;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// The legacy record format marker for AES-256-GCM encrypted values without
+/// associated data, only read in order to re-encrypt them
+const FORMAT_AES256GCM_LEGACY: u8 = 0x01;
+/// The record format marker for AES-256-GCM encrypted values, which
+/// authenticate the store key and key ID as associated data
+const FORMAT_AES256GCM: u8 = 0x02;
+/// The AES-GCM nonce length
+const NONCE_LEN: usize = 12;
+
+/// A set of encryption keys, identified by key IDs. New values are encrypted
+/// with the active key, while old keys are kept to decrypt existing values
+/// until they have been re-encrypted, allowing key rotation.
+pub struct Keyring {
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ids: Vec<&String> = self.keys.iter().map(|(id, _)| id).collect();
+        write!(f, "Keyring {:?}", ids)
+    }
+}
+
+impl Keyring {
+    /// Loads a keyring from a key file. Each line contains a key ID and a
+    /// hex-encoded 256-bit key separated by whitespace, and the last key is the
+    /// active key. Blank lines and lines starting with # are ignored.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a keyring, see load() for the format.
+    pub fn parse(keys: &str) -> Result<Self, Error> {
+        let mut keyring = Self { keys: Vec::new() };
+        for line in keys.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (id, hex) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(id), Some(hex), None) => (id, hex),
+                _ => {
+                    return Err(Error::Config(
+                        "Expected key ID and hex key in key file".into(),
+                    ))
+                }
+            };
+            if id.len() > u8::MAX as usize {
+                return Err(Error::Config(format!("Key ID {} is too long", id)));
+            }
+            if keyring.keys.iter().any(|(i, _)| i == id) {
+                return Err(Error::Config(format!("Duplicate key ID {}", id)));
+            }
+            let mut key = [0; 32];
+            match decode_hex(hex) {
+                Some(ref bytes) if bytes.len() == key.len() => key.copy_from_slice(bytes),
+                _ => {
+                    return Err(Error::Config(format!(
+                        "Key {} must be 64 hex characters",
+                        id
+                    )))
+                }
+            }
+            keyring
+                .keys
+                .push((id.to_string(), Aes256Gcm::new(&key.into())));
+        }
+        if keyring.keys.is_empty() {
+            return Err(Error::Config("No keys found in key file".into()));
+        }
+        Ok(keyring)
+    }
+
+    /// Returns the active key ID
+    fn active_id(&self) -> &str {
+        &self.keys[self.keys.len() - 1].0
+    }
+
+    /// Encrypts the value of a store key with the active key. The record header
+    /// contains the format, key ID and nonce, and the store key is
+    /// authenticated such that records can't be moved between keys.
+    fn encrypt(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, Error> {
+        let (id, cipher) = &self.keys[self.keys.len() - 1];
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: value,
+            aad: &associated_data(key, id),
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce.into(), payload)
+            .map_err(|_| Error::Internal("Encryption failed".into()))?;
+        let mut record = Vec::with_capacity(2 + id.len() + NONCE_LEN + ciphertext.len());
+        record.push(FORMAT_AES256GCM);
+        record.push(id.len() as u8);
+        record.extend_from_slice(id.as_bytes());
+        record.extend_from_slice(&nonce);
+        record.extend(ciphertext);
+        Ok(record)
+    }
+
+    /// Decrypts the record of a store key, returning the key ID used and the
+    /// value. Legacy records without associated data are only accepted if
+    /// requested, for re-encryption.
+    fn decrypt<'a>(
+        &self,
+        key: &str,
+        record: &'a [u8],
+        legacy: bool,
+    ) -> Result<(&'a str, Vec<u8>), Error> {
+        let invalid = || Error::Value("Invalid encrypted record".into());
+        match record.first() {
+            Some(&FORMAT_AES256GCM) if record.len() >= 2 => {}
+            Some(&FORMAT_AES256GCM_LEGACY) if legacy && record.len() >= 2 => {}
+            Some(&FORMAT_AES256GCM_LEGACY) => {
+                return Err(Error::Value(
+                    "Encrypted record has a legacy format, run migrate-data-dir to upgrade it"
+                        .into(),
+                ))
+            }
+            _ => return Err(invalid()),
+        }
+        let id_end = 2 + record[1] as usize;
+        if record.len() < id_end + NONCE_LEN {
+            return Err(invalid());
+        }
+        let id = std::str::from_utf8(&record[2..id_end]).map_err(|_| invalid())?;
+        let (_, cipher) = self
+            .keys
+            .iter()
+            .find(|(i, _)| i == id)
+            .ok_or_else(|| Error::Value(format!("Unknown encryption key ID {}", id)))?;
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&record[id_end..id_end + NONCE_LEN]);
+        let ciphertext = &record[id_end + NONCE_LEN..];
+        let value = if record[0] == FORMAT_AES256GCM_LEGACY {
+            cipher.decrypt(&nonce.into(), ciphertext)
+        } else {
+            let payload = Payload {
+                msg: ciphertext,
+                aad: &associated_data(key, id),
+            };
+            cipher.decrypt(&nonce.into(), payload)
+        }
+        .map_err(|_| Error::Value(format!("Failed to decrypt record with key {}", id)))?;
+        Ok((id, value))
+    }
+}
+
+/// A store wrapper which encrypts values at rest using a keyring. Keys are
+/// not encrypted.
+#[derive(Debug)]
+pub struct Encrypted<S: Store> {
+    inner: S,
+    keyring: Arc<Keyring>,
+}
+
+impl<S: Store> Encrypted<S> {
+    /// Creates a new encrypted store wrapping an inner store.
+    pub fn new(inner: S, keyring: Arc<Keyring>) -> Self {
+        Self { inner, keyring }
+    }
+
+    /// Re-encrypts all values which are not encrypted with the active key or
+    /// use the legacy record format, returning the number of values
+    /// re-encrypted.
+    pub fn reencrypt(&mut self) -> Result<u64, Error> {
+        let records = self
+            .inner
+            .iter_prefix("")
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut count = 0;
+        for (key, record) in records {
+            let (id, value) = self.keyring.decrypt(&key, &record, true)?;
+            if id != self.keyring.active_id() || record[0] != FORMAT_AES256GCM {
+                self.inner.set(&key, self.keyring.encrypt(&key, &value)?)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Checks whether the inner store holds encrypted values, by decrypting the
+    /// first one. Returns false for empty stores.
+    pub fn is_encrypted(&self) -> Result<bool, Error> {
+        match self.inner.iter_prefix("").next().transpose()? {
+            Some((key, record)) => Ok(self.keyring.decrypt(&key, &record, true).is_ok()),
+            None => Ok(false),
+        }
+    }
+
+    /// Encrypts all values of an inner store written in plaintext, e.g. when
+    /// enabling encryption for an existing data directory, returning the number
+    /// of values encrypted. Errors if any value is already encrypted.
+    pub fn encrypt_plaintext(&mut self) -> Result<u64, Error> {
+        let records = self
+            .inner
+            .iter_prefix("")
+            .collect::<Result<Vec<_>, Error>>()?;
+        for (key, record) in &records {
+            if self.keyring.decrypt(key, record, true).is_ok() {
+                return Err(Error::Value(format!(
+                    "Value for key {} is already encrypted, refusing to encrypt a store with mixed values",
+                    key
+                )));
+            }
+        }
+        for (key, value) in &records {
+            self.inner.set(key, self.keyring.encrypt(key, value)?)?;
+        }
+        Ok(records.len() as u64)
+    }
+
+    /// Returns the inner store.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Store> Store for Encrypted<S> {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        // Encryption isn't deterministic, so compare the decrypted value, but
+        // swap against the stored record to detect concurrent changes.
+        let record = self.inner.get(key)?;
+        let current = match &record {
+            Some(record) => Some(self.keyring.decrypt(key, record, false)?.1),
+            None => None,
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        self.inner
+            .cas(key, record.as_deref(), self.keyring.encrypt(key, &value)?)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), Error> {
+        self.inner.delete(key)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        match self.inner.get(key)? {
+            Some(record) => Ok(Some(self.keyring.decrypt(key, &record, false)?.1.into())),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.inner.set(key, self.keyring.encrypt(key, &value)?)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        let keyring = self.keyring.clone();
+        Box::new(
+            self.inner
+                .scan_prefix(prefix, direction, limit)
+                .map(move |r| {
+                    let (key, record) = r?;
+                    let value = keyring.decrypt(&key, &record, false)?.1;
+                    Ok((key, value.into()))
+                }),
+        )
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(Encrypted::new(
+            self.inner.snapshot()?,
+            self.keyring.clone(),
+        )))
+    }
+}
+
+/// Builds the associated data for a record, i.e. the length-prefixed key ID
+/// followed by the store key.
+fn associated_data(key: &str, id: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(1 + id.len() + key.len());
+    aad.push(id.len() as u8);
+    aad.extend_from_slice(id.as_bytes());
+    aad.extend_from_slice(key.as_bytes());
+    aad
+}
+
+/// Decodes a hex string
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::Suite;
+    use super::super::KVMemory;
+    use super::*;
+
+    const KEY_A: &str = "a 000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_B: &str = "b 1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    #[test]
+    fn suite() {
+        Suite::new(|| {
+            Box::new(Encrypted::new(
+                KVMemory::new(),
+                Arc::new(Keyring::parse(KEY_A).unwrap()),
+            ))
+        })
+        .test()
+    }
+
+    #[test]
+    fn keyring() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("a").is_err());
+        assert!(Keyring::parse("a 0011").is_err());
+        assert!(Keyring::parse(&KEY_A.replace("0a", "zz")).is_err());
+        assert!(Keyring::parse(&format!("{}\n{}", KEY_A, KEY_A)).is_err());
+
+        let keyring = Keyring::parse(&format!("# Keys\n{}\n\n{}\n", KEY_A, KEY_B)).unwrap();
+        assert_eq!("b", keyring.active_id());
+    }
+
+    #[test]
+    fn encrypted() {
+        let keyring = Arc::new(Keyring::parse(KEY_A).unwrap());
+        let mut s = Encrypted::new(KVMemory::new(), keyring);
+        s.set("a", vec![0x01, 0x02, 0x03]).unwrap();
+        let record = s.inner.get("a").unwrap().unwrap();
+        assert_eq!(FORMAT_AES256GCM, record[0]);
+        assert!(!record.windows(3).any(|w| w == [0x01, 0x02, 0x03]));
+
+        // Tampering is detected
+        let mut tampered = record.to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0xff;
+        s.inner.set("b", tampered).unwrap();
+        assert_matches!(s.get("b"), Err(Error::Value(_)));
+
+        // Records can't be moved to other keys
+        s.inner.set("c", record.to_vec()).unwrap();
+        assert_matches!(s.get("c"), Err(Error::Value(_)));
+
+        // Unknown keys are rejected
+        let s = Encrypted::new(s.into_inner(), Arc::new(Keyring::parse(KEY_B).unwrap()));
+        assert_matches!(s.get("a"), Err(Error::Value(_)));
+    }
+
+    #[test]
+    fn rotate() {
+        let mut s = Encrypted::new(KVMemory::new(), Arc::new(Keyring::parse(KEY_A).unwrap()));
+        s.set("a", vec![0x01]).unwrap();
+        s.set("b", vec![0x02]).unwrap();
+
+        let mut s = Encrypted::new(
+            s.into_inner(),
+            Arc::new(Keyring::parse(&format!("{}\n{}", KEY_A, KEY_B)).unwrap()),
+        );
+        assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+        s.set("c", vec![0x03]).unwrap();
+        assert_eq!(2, s.reencrypt().unwrap());
+        assert_eq!(0, s.reencrypt().unwrap());
+
+        let s = Encrypted::new(s.into_inner(), Arc::new(Keyring::parse(KEY_B).unwrap()));
+        assert!(s.is_encrypted().unwrap());
+        assert_eq!(
+            vec![
+                ("a".to_string(), vec![0x01].into()),
+                ("b".to_string(), vec![0x02].into()),
+                ("c".to_string(), vec![0x03].into()),
+            ],
+            s.iter_prefix("")
+                .collect::<Result<Vec<_>, Error>>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn legacy() {
+        let keyring = Arc::new(Keyring::parse(KEY_A).unwrap());
+        let nonce = [0; NONCE_LEN];
+        let mut record = vec![FORMAT_AES256GCM_LEGACY, 1, b'a'];
+        record.extend_from_slice(&nonce);
+        record.extend(
+            keyring.keys[0]
+                .1
+                .encrypt(&nonce.into(), &[0x01][..])
+                .unwrap(),
+        );
+
+        let mut s = Encrypted::new(KVMemory::new(), keyring);
+        s.inner.set("a", record).unwrap();
+        assert!(s.is_encrypted().unwrap());
+        assert_matches!(s.get("a"), Err(Error::Value(_)));
+        assert_eq!(1, s.reencrypt().unwrap());
+        assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+        assert_eq!(0, s.reencrypt().unwrap());
+    }
+
+    #[test]
+    fn encrypt_plaintext() {
+        let keyring = Arc::new(Keyring::parse(KEY_A).unwrap());
+        let mut s = Encrypted::new(KVMemory::new(), keyring);
+        assert!(!s.is_encrypted().unwrap());
+        s.inner.set("a", vec![0x01]).unwrap();
+        s.inner.set("b", vec![0x02]).unwrap();
+        assert!(!s.is_encrypted().unwrap());
+        assert_matches!(s.get("a"), Err(Error::Value(_)));
+
+        assert_eq!(2, s.encrypt_plaintext().unwrap());
+        assert!(s.is_encrypted().unwrap());
+        assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+        assert_eq!(Some(vec![0x02].into()), s.get("b").unwrap());
+        assert_matches!(s.encrypt_plaintext(), Err(Error::Value(_)));
+    }
+}
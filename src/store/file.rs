@@ -1,59 +1,344 @@
-use super::{Iter, Range, Store};
+use super::{Bytes, Direction, Iter, Range, Seq, Snapshot, Store};
 use crate::Error;
 use std::collections::BTreeMap;
-use std::io::Seek;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-/// A prototype on-disk key-value store. The current version keeps all data in
-/// memory and writes out the entire dataset to disk on every write. It is a
-/// stop-gap solution until a proper store can be written.
+/// The default write-ahead log size at which the dataset is flushed to disk
+pub const DEFAULT_FLUSH_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+type Data = BTreeMap<String, Bytes>;
+
+/// Serializes a dataset like a BTreeMap<String, Vec<u8>>, the file format.
+struct Encoded<'a>(&'a Data);
+
+impl serde::Serialize for Encoded<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, Seq(v))))
+    }
+}
+
+/// A prototype on-disk key-value store. The dataset is kept in memory, and
+/// writes are appended to a write-ahead log (WAL) next to the data file and
+/// synced to disk before returning. Once the WAL grows past the flush
+/// threshold, a background thread writes out the entire dataset to the data
+/// file and removes the flushed WAL segments. On open, the WAL is replayed on
+/// top of the data file and compacted into it, so writes survive a crash at
+/// any point. It is a stop-gap solution until a proper store can be written.
 #[derive(Debug)]
 pub struct File {
-    file: std::fs::File,
-    data: BTreeMap<String, Vec<u8>>,
+    path: PathBuf,
+    /// The dataset, shared with snapshots and copied on write while shared.
+    data: Arc<Data>,
+    /// The current WAL segment, created on the first write.
+    wal: Option<std::fs::File>,
+    /// The sequence number of the current WAL segment.
+    wal_seq: u64,
+    /// The number of bytes written to the current WAL segment.
+    wal_bytes: u64,
+    flush_threshold: u64,
+    flusher: Option<Flusher>,
 }
 
 impl File {
-    /// Creates a new file-backed key-value store.
-    pub fn new(file: std::fs::File) -> Result<Self, Error> {
-        let data = if file.metadata()?.len() > 0 {
-            rmp_serde::decode::from_read(file.try_clone()?)?
-        } else {
-            BTreeMap::new()
+    /// Opens a file-backed key-value store at the given path, replaying any
+    /// WAL segments and compacting them into the data file. Files are not
+    /// created until the first write.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut data = match std::fs::File::open(&path) {
+            Ok(file) if file.metadata()?.len() > 0 => {
+                rmp_serde::decode::from_read::<_, BTreeMap<String, Vec<u8>>>(file)?
+                    .into_iter()
+                    .map(|(k, v)| (k, v.into()))
+                    .collect()
+            }
+            Ok(_) => Data::new(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Data::new(),
+            Err(err) => return Err(err.into()),
         };
-        Ok(Self { file, data })
+        let segments = wal_segments(&path)?;
+        for (_, segment) in &segments {
+            replay(segment, &mut data)?;
+        }
+        let data = Arc::new(data);
+        if let Some((seq, _)) = segments.last() {
+            Flush {
+                path: path.clone(),
+                data: data.clone(),
+                wal_seq: *seq,
+            }
+            .run()?;
+        }
+        Ok(Self {
+            wal_seq: segments.last().map(|(seq, _)| seq + 1).unwrap_or(0),
+            path,
+            data,
+            wal: None,
+            wal_bytes: 0,
+            flush_threshold: DEFAULT_FLUSH_THRESHOLD,
+            flusher: None,
+        })
+    }
+
+    /// Sets the WAL size in bytes at which the dataset is flushed to disk in
+    /// the background. 0 flushes after every write.
+    pub fn flush_threshold(mut self, bytes: u64) -> Self {
+        self.flush_threshold = bytes;
+        self
     }
 
-    /// Writes out the entire dataset to the file.
-    fn flush(&mut self) -> Result<(), Error> {
-        self.file.seek(std::io::SeekFrom::Start(0))?;
-        rmp_serde::encode::write(&mut self.file, &self.data)?;
+    /// Lists the files holding the store at the given path, i.e. the data file
+    /// and any WAL segments, for copying them as-is.
+    pub fn files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
+        let path = path.as_ref();
+        let mut files = Vec::new();
+        if path.exists() {
+            files.push(path.to_path_buf());
+        }
+        files.extend(wal_segments(path)?.into_iter().map(|(_, segment)| segment));
+        Ok(files)
+    }
+
+    /// Appends the current value of a key to the WAL, where a missing value is
+    /// a delete, and flushes the dataset in the background once the WAL exceeds
+    /// the flush threshold.
+    fn append(&mut self, key: &str) -> Result<(), Error> {
+        let record = rmp_serde::to_vec(&(key, self.data.get(key).map(|v| Seq(v))))?;
+        let mut frame = Vec::with_capacity(4 + record.len());
+        frame.extend_from_slice(&(record.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&record);
+
+        if self.wal.is_none() {
+            self.wal = Some(
+                std::fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(wal_path(&self.path, self.wal_seq))?,
+            );
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.write_all(&frame)?;
+            wal.sync_data()?;
+        }
+        self.wal_bytes += frame.len() as u64;
+
+        if self.wal_bytes > self.flush_threshold {
+            self.rotate()?;
+            let flush = Flush {
+                path: self.path.clone(),
+                data: self.data.clone(),
+                wal_seq: self.wal_seq - 1,
+            };
+            if self.flusher.is_none() {
+                self.flusher = Some(Flusher::spawn()?);
+            }
+            if let Some(flusher) = &self.flusher {
+                flusher.flush(flush)?;
+            }
+        }
         Ok(())
     }
+
+    /// Closes the current WAL segment, such that the next write starts a new one.
+    fn rotate(&mut self) -> Result<(), Error> {
+        if let Some(wal) = self.wal.take() {
+            wal.sync_data()?;
+            self.wal_seq += 1;
+            self.wal_bytes = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes out the entire dataset to the data file and removes all WAL
+    /// segments, after waiting for any background flush to complete.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        self.flusher = None;
+        self.rotate()?;
+        Flush {
+            path: self.path.clone(),
+            data: self.data.clone(),
+            wal_seq: self.wal_seq,
+        }
+        .run()
+    }
 }
 
 impl Store for File {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        if self.data.get(key).map(|v| &v[..]) != expected {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
     fn delete(&mut self, key: &str) -> Result<(), Error> {
-        self.data.remove(key);
-        self.flush()?;
-        Ok(())
+        Arc::make_mut(&mut self.data).remove(key);
+        self.append(key)
     }
 
-    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
         Ok(self.data.get(key).cloned())
     }
 
     fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
-        self.data.insert(key.to_string(), value);
-        self.flush()?;
-        Ok(())
+        Arc::make_mut(&mut self.data).insert(key.to_string(), value.into());
+        self.append(key)
     }
 
-    fn iter_prefix(&self, prefix: &str) -> Box<Range> {
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
         let from = prefix.to_string();
         let to = from.clone() + &std::char::MAX.to_string();
-        info!("file> from {} to {}", from, to);
-        Box::new(Iter::from(self.data.range(from..to)))
+        Box::new(Iter::scan(self.data.range(from..to), direction, limit))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(Snapshot::new(self.data.clone())))
+    }
+}
+
+/// A flush of the dataset to the data file, covering WAL segments up to and
+/// including the given sequence number.
+struct Flush {
+    path: PathBuf,
+    data: Arc<Data>,
+    wal_seq: u64,
+}
+
+impl Flush {
+    /// Atomically replaces the data file, then removes the flushed WAL segments.
+    /// If interrupted, the remaining segments are simply replayed again on open,
+    /// since replaying writes on top of a dataset which contains them is a noop.
+    fn run(self) -> Result<(), Error> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        rmp_serde::encode::write(&mut file, &Encoded(&self.data))?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+        for (seq, segment) in wal_segments(&self.path)? {
+            if seq <= self.wal_seq {
+                std::fs::remove_file(segment)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A background thread which runs flushes. When flushes queue up, only the
+/// latest is run, since it contains all previous writes. Dropping the flusher
+/// waits for pending flushes to complete.
+#[derive(Debug)]
+struct Flusher {
+    tx: Option<crossbeam_channel::Sender<Flush>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Flusher {
+    /// Spawns a flusher thread
+    fn spawn() -> Result<Self, Error> {
+        let (tx, rx) = crossbeam_channel::unbounded::<Flush>();
+        let thread = std::thread::Builder::new()
+            .name("file-flusher".into())
+            .spawn(move || {
+                while let Ok(mut flush) = rx.recv() {
+                    while let Ok(next) = rx.try_recv() {
+                        flush = next;
+                    }
+                    let path = flush.path.clone();
+                    if let Err(err) = flush.run() {
+                        error!("Failed to flush store {}: {}", path.display(), err);
+                    }
+                }
+            })?;
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues a flush
+    fn flush(&self, flush: Flush) -> Result<(), Error> {
+        if let Some(tx) = &self.tx {
+            tx.send(flush)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Returns the path of a WAL segment
+fn wal_path(path: &Path, seq: u64) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".wal.{}", seq));
+    path.with_file_name(name)
+}
+
+/// Lists the WAL segments of a store, ordered by sequence number
+fn wal_segments(path: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let prefix = format!(
+        "{}.wal.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let dir = match path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut segments = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(seq) = name.strip_prefix(&prefix).and_then(|s| s.parse().ok()) {
+            segments.push((seq, entry.path()));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Replays a WAL segment into a dataset. A truncated record at the end of the
+/// segment, from a crash during a write, is ignored.
+fn replay(path: &Path, data: &mut Data) -> Result<(), Error> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+    let mut pos = 0;
+    while pos + 4 <= bytes.len() {
+        let mut len = [0; 4];
+        len.copy_from_slice(&bytes[pos..pos + 4]);
+        let end = pos + 4 + u32::from_be_bytes(len) as usize;
+        if end > bytes.len() {
+            break;
+        }
+        match rmp_serde::from_read_ref::<_, (String, Option<Vec<u8>>)>(&bytes[pos + 4..end])? {
+            (key, Some(value)) => data.insert(key, value.into()),
+            (key, None) => data.remove(&key),
+        };
+        pos = end;
     }
+    if pos < bytes.len() {
+        warn!(
+            "Ignoring truncated record at end of WAL segment {}",
+            path.display()
+        );
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -61,10 +346,88 @@ mod tests {
     extern crate tempfile;
     use super::super::tests::Suite;
     use super::*;
-    use tempfile::tempfile;
+
+    /// Returns a suite setup opening stores in a temporary directory
+    fn setup(flush_threshold: u64) -> impl Fn() -> Box<dyn Store> {
+        let dir = tempfile::tempdir().unwrap();
+        let count = std::cell::Cell::new(0);
+        move || {
+            count.set(count.get() + 1);
+            let path = dir.path().join(count.get().to_string());
+            Box::new(File::open(path).unwrap().flush_threshold(flush_threshold))
+        }
+    }
 
     #[test]
     fn suite() {
-        Suite::new(|| Box::new(File::new(tempfile().unwrap()).unwrap())).test()
+        Suite::new(setup(DEFAULT_FLUSH_THRESHOLD)).test();
+        Suite::new(setup(0)).test();
+    }
+
+    #[test]
+    fn flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store");
+        let mut s = File::open(&path).unwrap().flush_threshold(64);
+        assert!(File::files(&path).unwrap().is_empty());
+
+        s.set("a", vec![0x01]).unwrap();
+        assert_eq!(vec![wal_path(&path, 0)], File::files(&path).unwrap());
+        s.set("b", vec![0x02; 64]).unwrap();
+        s.delete("a").unwrap();
+        drop(s);
+        assert_eq!(
+            vec![path.clone(), wal_path(&path, 1)],
+            File::files(&path).unwrap()
+        );
+
+        let mut s = File::open(&path).unwrap();
+        assert_eq!(vec![path.clone()], File::files(&path).unwrap());
+        assert_eq!(None, s.get("a").unwrap());
+        assert_eq!(Some(vec![0x02; 64].into()), s.get("b").unwrap());
+        s.set("c", vec![0x03]).unwrap();
+        s.compact().unwrap();
+        assert_eq!(vec![path.clone()], File::files(&path).unwrap());
+
+        let s = File::open(&path).unwrap();
+        assert_eq!(None, s.get("a").unwrap());
+        assert_eq!(Some(vec![0x02; 64].into()), s.get("b").unwrap());
+        assert_eq!(Some(vec![0x03].into()), s.get("c").unwrap());
+    }
+
+    #[test]
+    fn replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store");
+        let mut s = File::open(&path).unwrap();
+        s.set("a", vec![0x01]).unwrap();
+        s.set("b", vec![0x02]).unwrap();
+        drop(s);
+
+        // A write torn by a crash is ignored, and replayed segments are removed.
+        let wal = wal_path(&path, 0);
+        let len = std::fs::metadata(&wal).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&wal)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        let segment = std::fs::read(&wal).unwrap();
+        let s = File::open(&path).unwrap();
+        assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+        assert_eq!(None, s.get("b").unwrap());
+        assert_eq!(vec![path.clone()], File::files(&path).unwrap());
+        drop(s);
+
+        // Segments left behind by an interrupted flush are replayed again.
+        let mut s = File::open(&path).unwrap().flush_threshold(0);
+        s.set("b", vec![0x03]).unwrap();
+        drop(s);
+        assert_eq!(vec![path.clone()], File::files(&path).unwrap());
+        std::fs::write(&wal, segment).unwrap();
+        let s = File::open(&path).unwrap();
+        assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+        assert_eq!(Some(vec![0x03].into()), s.get("b").unwrap());
     }
 }
@@ -1,4 +1,4 @@
-use super::{Iter, Range, Store};
+use super::{Bytes, Direction, Iter, Range, Snapshot, Store, WriteBatch};
 use crate::Error;
 use std::{
     collections::BTreeMap,
@@ -7,40 +7,168 @@ use std::{
 
 /// A Store is a persistent key-value store for values of type V, serialized as
 /// MessagePack. It's currently implemented as a transient in-memory store while
-/// prototyping the interface.
-#[derive(Clone, Debug)]
+/// prototyping the interface. The size of keys and values is accounted for,
+/// and writes beyond an optional memory budget fail with an error.
+#[derive(Clone, Debug, Default)]
 pub struct KVMemory {
-    data: Arc<RwLock<BTreeMap<String, Vec<u8>>>>,
+    data: Arc<RwLock<Data>>,
+    max_bytes: Option<usize>,
+}
+
+/// The stored pairs, and their total size in bytes. The pairs are shared with
+/// snapshots, and copied on write while shared.
+#[derive(Debug, Default)]
+struct Data {
+    pairs: Arc<BTreeMap<String, Bytes>>,
+    bytes: usize,
+}
+
+/// KVMemory memory usage statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryStats {
+    /// The number of keys
+    pub keys: u64,
+    /// The total size of keys and values in bytes
+    pub bytes: u64,
+    /// The memory budget in bytes, if any
+    pub max_bytes: Option<u64>,
 }
 
 impl KVMemory {
     pub fn new() -> Self {
-        Self {
-            data: Arc::new(RwLock::new(BTreeMap::new())),
+        Self::default()
+    }
+
+    /// Limits the total size of keys and values to a memory budget in bytes
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Returns memory usage statistics
+    pub fn stats(&self) -> Result<MemoryStats, Error> {
+        let data = self.data.read()?;
+        Ok(MemoryStats {
+            keys: data.pairs.len() as u64,
+            bytes: data.bytes as u64,
+            max_bytes: self.max_bytes.map(|b| b as u64),
+        })
+    }
+
+    /// Inserts a pair, checking and updating the memory accounting
+    fn insert(&self, data: &mut Data, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        let old = data.pairs.get(key).map_or(0, |v| key.len() + v.len());
+        let bytes = data.bytes - old + key.len() + value.len();
+        if let Some(max_bytes) = self.max_bytes {
+            if bytes > max_bytes {
+                return Err(Error::ResourceExhausted(format!(
+                    "Memory budget of {} bytes exceeded, {} bytes in use",
+                    max_bytes, data.bytes
+                )));
+            }
+        }
+        Arc::make_mut(&mut data.pairs).insert(key.to_string(), value.into());
+        data.bytes = bytes;
+        Ok(())
+    }
+
+    /// Removes a pair, updating the memory accounting
+    fn remove(&self, data: &mut Data, key: &str) {
+        if data.pairs.contains_key(key) {
+            if let Some(value) = Arc::make_mut(&mut data.pairs).remove(key) {
+                data.bytes -= key.len() + value.len();
+            }
+        }
+    }
+
+    /// Applies a batch of writes, rolling back applied writes if one fails,
+    /// e.g. by exceeding the memory budget.
+    fn apply_batch(&self, data: &mut Data, batch: WriteBatch) -> Result<(), Error> {
+        let mut undo = Vec::new();
+        for (key, value) in batch {
+            let previous = data.pairs.get(&key).cloned();
+            let result = match value {
+                Some(value) => self.insert(data, &key, value),
+                None => {
+                    self.remove(data, &key);
+                    Ok(())
+                }
+            };
+            undo.push((key, previous));
+            if let Err(err) = result {
+                // Restoring earlier states stays within the budget.
+                for (key, previous) in undo.into_iter().rev() {
+                    match previous {
+                        Some(value) => self.insert(data, &key, value.to_vec())?,
+                        None => self.remove(data, &key),
+                    }
+                }
+                return Err(err);
+            }
         }
+        Ok(())
     }
 }
 
 impl Store for KVMemory {
-    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
-        Ok(self.data.read()?.get(key).cloned())
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        let mut data = self.data.write()?;
+        if data.pairs.get(key).map(|v| &v[..]) != expected {
+            return Ok(false);
+        }
+        self.insert(&mut data, key, value)?;
+        Ok(true)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        Ok(self.data.read()?.pairs.get(key).cloned())
     }
 
     fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
-        self.data.write()?.insert(key.to_string(), value);
-        Ok(())
+        self.insert(&mut *self.data.write()?, key, value)
     }
 
     fn delete(&mut self, key: &str) -> Result<(), Error> {
-        self.data.write()?.remove(key);
+        self.remove(&mut *self.data.write()?, key);
         Ok(())
     }
 
-    fn iter_prefix(&self, prefix: &str) -> Box<Range> {
+    /// Applies the batch atomically, see apply_batch().
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        self.apply_batch(&mut *self.data.write()?, batch)
+    }
+
+    /// Checks and applies the batch atomically, see apply_batch().
+    fn write_batch_if_absent(
+        &mut self,
+        batch: WriteBatch,
+        absent: &[String],
+    ) -> Result<Vec<String>, Error> {
+        let mut data = self.data.write()?;
+        let existing: Vec<String> = absent
+            .iter()
+            .filter(|key| data.pairs.contains_key(key.as_str()))
+            .cloned()
+            .collect();
+        if existing.is_empty() {
+            self.apply_batch(&mut data, batch)?;
+        }
+        Ok(existing)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
         let from = prefix.to_string();
         let to = from.clone() + &std::char::MAX.to_string();
         info!("from {} to {}", from, to);
-        Box::new(Iter::from(self.data.read().unwrap().range(from..to)))
+        Box::new(Iter::scan(
+            self.data.read().unwrap().pairs.range(from..to),
+            direction,
+            limit,
+        ))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(Snapshot::new(self.data.read()?.pairs.clone())))
     }
 }
 
@@ -53,4 +181,49 @@ mod tests {
     fn suite() {
         Suite::new(|| Box::new(KVMemory::new())).test()
     }
+
+    #[test]
+    fn budget() {
+        let mut s = KVMemory::new().max_bytes(10);
+        s.set("a", vec![0x01; 4]).unwrap();
+        s.set("b", vec![0x02; 4]).unwrap();
+        assert_eq!(
+            MemoryStats {
+                keys: 2,
+                bytes: 10,
+                max_bytes: Some(10)
+            },
+            s.stats().unwrap()
+        );
+        assert_matches!(s.set("c", vec![]), Err(Error::ResourceExhausted(_)));
+        assert_matches!(s.set("a", vec![0x01; 5]), Err(Error::ResourceExhausted(_)));
+        assert_eq!(None, s.get("c").unwrap());
+
+        // Overwrites and deletes release memory
+        s.set("a", vec![0x01; 2]).unwrap();
+        assert_eq!(8, s.stats().unwrap().bytes);
+        s.delete("b").unwrap();
+        s.delete("b").unwrap();
+        assert_eq!(3, s.stats().unwrap().bytes);
+        assert!(s.cas("c", None, vec![0x03; 6]).unwrap());
+        assert_matches!(s.cas("c", None, vec![]), Ok(false));
+        assert_matches!(
+            s.cas("c", Some(&[0x03; 6]), vec![0x03; 7]),
+            Err(Error::ResourceExhausted(_))
+        );
+        assert_eq!(10, s.stats().unwrap().bytes);
+
+        // Batches exceeding the budget are rolled back
+        assert_matches!(
+            s.write_batch(vec![
+                ("a".into(), None),
+                ("d".into(), Some(vec![0x04; 2])),
+                ("e".into(), Some(vec![0x05; 9])),
+            ]),
+            Err(Error::ResourceExhausted(_))
+        );
+        assert_eq!(Some(vec![0x01; 2].into()), s.get("a").unwrap());
+        assert_eq!(None, s.get("d").unwrap());
+        assert_eq!(10, s.stats().unwrap().bytes);
+    }
 }
@@ -1,22 +1,152 @@
+mod chunked;
+mod compressed;
+mod data_dir;
+mod encrypted;
 mod file;
 mod kvmemory;
+#[cfg(feature = "server")]
 mod raft;
+mod snapshot;
 
 use crate::Error;
-pub use file::File;
-pub use kvmemory::KVMemory;
-pub use raft::Raft;
+pub use bytes::Bytes;
+pub use chunked::{Chunked, Upload, DEFAULT_CHUNK_SIZE};
+pub use compressed::{Compressed, Compression};
+pub use data_dir::{DataDir, Lock, LAYOUT_VERSION};
+pub use encrypted::{Encrypted, Keyring};
+pub use file::{File, DEFAULT_FLUSH_THRESHOLD};
+pub use kvmemory::{KVMemory, MemoryStats};
+#[cfg(feature = "server")]
+pub use raft::{ChecksumStatus, Raft, Version};
+use serde_derive::{Deserialize, Serialize};
+pub use snapshot::Snapshot;
 
-type KVPair = (String, Vec<u8>);
-type Range = dyn Iterator<Item = Result<KVPair, Error>> + Sync + Send;
+type KVPair = (String, Bytes);
+/// A batch of writes, applied in order: Some sets a key to a value, and None
+/// deletes it.
+pub type WriteBatch = Vec<(String, Option<Vec<u8>>)>;
+/// An iterator over key/value pairs, in key order
+pub type Range = dyn Iterator<Item = Result<KVPair, Error>> + Sync + Send;
 
+/// Serializes a value like a Vec<u8>, i.e. as a sequence rather than as
+/// binary, for compatibility with files and nodes from before values were Bytes.
+struct Seq<'a>(&'a [u8]);
+
+impl serde::Serialize for Seq<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0)
+    }
+}
+
+/// The direction of a key range scan
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    /// In ascending key order
+    Forward,
+    /// In descending key order
+    Reverse,
+}
+
+/// A key/value store, used e.g. for the Raft log. Values are read as Bytes,
+/// such that stores can hand out shared buffers instead of copies.
 pub trait Store: 'static + Sync + Send + std::fmt::Debug {
+    /// Atomically sets a key to a value if its current value is the expected
+    /// one, where None expects the key to not exist. Returns whether the value
+    /// was set.
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error>;
+    /// Deletes a key, if it exists
     fn delete(&mut self, key: &str) -> Result<(), Error>;
-    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+    /// Gets the value of a key, if it exists
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error>;
+    /// Sets the value of a key, replacing any existing value
     fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error>;
 
+    /// Applies a batch of writes. By default they're applied one by one, so
+    /// a failure can leave the batch partially applied, but stores may apply
+    /// them atomically.
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.set(&key, value)?,
+                None => self.delete(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a batch of writes if none of the given keys exist, like a
+    /// batched cas() expecting absent keys. Returns the keys which already
+    /// exist, in which case nothing is written. By default the check and the
+    /// writes are separate, which is only atomic for stores without other
+    /// writers, but stores may apply them atomically.
+    fn write_batch_if_absent(
+        &mut self,
+        batch: WriteBatch,
+        absent: &[String],
+    ) -> Result<Vec<String>, Error> {
+        let mut existing = Vec::new();
+        for key in absent {
+            if self.get(key)?.is_some() {
+                existing.push(key.clone());
+            }
+        }
+        if existing.is_empty() {
+            self.write_batch(batch)?;
+        }
+        Ok(existing)
+    }
+
     /// Returns an iterator over all pairs in the store under a key prefix
-    fn iter_prefix(&self, prefix: &str) -> Box<Range>;
+    fn iter_prefix(&self, prefix: &str) -> Box<Range> {
+        self.scan_prefix(prefix, Direction::Forward, None)
+    }
+
+    /// Returns an iterator over pairs under a key prefix in the given
+    /// direction, stopping after limit pairs if given. The limit is applied by
+    /// the backend, such that it doesn't read pairs beyond it.
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range>;
+
+    /// Returns a cheap, read-only view of the store at this point in time,
+    /// unaffected by later writes. Writes to the snapshot fail.
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error>;
+}
+
+impl Store for Box<dyn Store> {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        (**self).cas(key, expected, value)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), Error> {
+        (**self).delete(key)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        (**self).get(key)
+    }
+
+    fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        (**self).set(key, value)
+    }
+
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<(), Error> {
+        (**self).write_batch(batch)
+    }
+
+    fn write_batch_if_absent(
+        &mut self,
+        batch: WriteBatch,
+        absent: &[String],
+    ) -> Result<Vec<String>, Error> {
+        (**self).write_batch_if_absent(batch, absent)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        (**self).scan_prefix(prefix, direction, limit)
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        (**self).snapshot()
+    }
 }
 
 /// This is a terrible, temporary iterator implementation which is prepopulated
@@ -26,16 +156,22 @@ struct Iter {
 }
 
 impl Iter {
-    fn from<'a, I>(iter: I) -> Self
+    /// Collects up to limit pairs from a key range, in the given direction
+    fn scan<'a, I>(iter: I, direction: Direction, limit: Option<usize>) -> Self
     where
-        I: DoubleEndedIterator + Iterator<Item = (&'a String, &'a Vec<u8>)>,
+        I: DoubleEndedIterator<Item = (&'a String, &'a Bytes)>,
     {
-        Self {
-            stack: iter
-                .map(|(k, v)| Ok((k.clone(), v.clone())))
-                .rev()
+        let limit = limit.unwrap_or(usize::MAX);
+        let pairs = match direction {
+            Direction::Forward => iter.take(limit).collect::<Vec<_>>(),
+            Direction::Reverse => iter.rev().take(limit).collect(),
+        };
+        Self::from_vec(
+            pairs
+                .into_iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
                 .collect(),
-        }
+        )
     }
 
     fn from_vec(vec: Vec<KVPair>) -> Self {
@@ -53,6 +189,7 @@ impl Iterator for Iter {
     }
 }
 
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
 pub fn get_obj<'de, V: serde::Deserialize<'de>>(
     store: &dyn Store,
     key: &str,
@@ -66,6 +203,7 @@ pub fn get_obj<'de, V: serde::Deserialize<'de>>(
     })
 }
 
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
 pub fn set_obj<V: serde::Serialize>(
     store: &mut dyn Store,
     key: &str,
@@ -99,16 +237,33 @@ pub mod tests {
         }
 
         pub fn test(&self) {
+            self.test_cas();
             self.test_delete();
             self.test_get();
             self.test_iter_prefix();
+            self.test_scan_prefix();
             self.test_set();
+            self.test_snapshot();
+            self.test_write_batch();
+            self.test_write_batch_if_absent();
+        }
+
+        pub fn test_cas(&self) {
+            let mut s = self.setup();
+            assert!(s.cas("a", None, vec![0x01]).unwrap());
+            assert!(!s.cas("a", None, vec![0x02]).unwrap());
+            assert!(!s.cas("a", Some(&[0x02]), vec![0x03]).unwrap());
+            assert_eq!(Some(vec![0x01].into()), s.get("a").unwrap());
+            assert!(s.cas("a", Some(&[0x01]), vec![0x02]).unwrap());
+            assert_eq!(Some(vec![0x02].into()), s.get("a").unwrap());
+            assert!(!s.cas("b", Some(&[0x02]), vec![0x01]).unwrap());
+            assert_eq!(None, s.get("b").unwrap());
         }
 
         pub fn test_delete(&self) {
             let mut s = self.setup();
             s.set("a", vec![0x01]).unwrap();
-            assert_eq!(vec![0x01], s.get("a").unwrap().unwrap());
+            assert_eq!(&[0x01], &s.get("a").unwrap().unwrap()[..]);
             s.delete("a").unwrap();
             assert_eq!(None, s.get("a").unwrap());
             s.delete("b").unwrap();
@@ -117,16 +272,85 @@ pub mod tests {
         pub fn test_get(&self) {
             let mut s = self.setup();
             s.set("a", vec![0x01]).unwrap();
-            assert_eq!(vec![0x01], s.get("a").unwrap().unwrap());
+            assert_eq!(&[0x01], &s.get("a").unwrap().unwrap()[..]);
             assert_eq!(None, s.get("b").unwrap());
         }
 
         pub fn test_set(&self) {
             let mut s = self.setup();
             s.set("a", vec![0x01]).unwrap();
-            assert_eq!(vec![0x01], s.get("a").unwrap().unwrap());
+            assert_eq!(&[0x01], &s.get("a").unwrap().unwrap()[..]);
             s.set("a", vec![0x02]).unwrap();
-            assert_eq!(vec![0x02], s.get("a").unwrap().unwrap());
+            assert_eq!(&[0x02], &s.get("a").unwrap().unwrap()[..]);
+        }
+
+        pub fn test_write_batch(&self) {
+            let mut s = self.setup();
+            s.set("a", vec![0x01]).unwrap();
+            s.write_batch(vec![
+                ("b".into(), Some(vec![0x02])),
+                ("a".into(), None),
+                ("c".into(), Some(vec![0x03])),
+                ("c".into(), Some(vec![0x04])),
+            ])
+            .unwrap();
+            assert_eq!(None, s.get("a").unwrap());
+            assert_eq!(&[0x02], &s.get("b").unwrap().unwrap()[..]);
+            assert_eq!(&[0x04], &s.get("c").unwrap().unwrap()[..]);
+        }
+
+        pub fn test_write_batch_if_absent(&self) {
+            let mut s = self.setup();
+            s.set("a", vec![0x01]).unwrap();
+            let batch = |value: u8| -> WriteBatch {
+                vec![
+                    ("b".into(), Some(vec![value])),
+                    ("c".into(), Some(vec![value])),
+                ]
+            };
+            assert_eq!(
+                vec!["a".to_string()],
+                s.write_batch_if_absent(batch(0x02), &["b".into(), "a".into()])
+                    .unwrap()
+            );
+            assert_eq!(None, s.get("b").unwrap());
+            assert!(s
+                .write_batch_if_absent(batch(0x02), &["b".into(), "c".into()])
+                .unwrap()
+                .is_empty());
+            assert_eq!(&[0x02], &s.get("c").unwrap().unwrap()[..]);
+            assert_eq!(
+                vec!["b".to_string()],
+                s.write_batch_if_absent(batch(0x03), &["b".into()]).unwrap()
+            );
+            assert_eq!(&[0x02], &s.get("b").unwrap().unwrap()[..]);
+        }
+
+        pub fn test_snapshot(&self) {
+            let mut s = self.setup();
+            s.set("a", vec![0x01]).unwrap();
+            s.set("b", vec![0x02]).unwrap();
+            let mut snapshot = s.snapshot().unwrap();
+            s.set("a", vec![0x03]).unwrap();
+            s.delete("b").unwrap();
+            s.set("c", vec![0x04]).unwrap();
+
+            assert_eq!(Some(vec![0x01].into()), snapshot.get("a").unwrap());
+            assert_eq!(Some(vec![0x02].into()), snapshot.get("b").unwrap());
+            assert_eq!(None, snapshot.get("c").unwrap());
+            assert_eq!(
+                vec![
+                    ("a".to_string(), vec![0x01].into()),
+                    ("b".to_string(), vec![0x02].into())
+                ],
+                snapshot
+                    .iter_prefix("")
+                    .collect::<Result<Vec<_>, Error>>()
+                    .unwrap()
+            );
+            assert_matches!(snapshot.set("a", vec![]), Err(Error::Value(_)));
+            assert_matches!(snapshot.delete("a"), Err(Error::Value(_)));
+            assert_eq!(Some(vec![0x03].into()), s.get("a").unwrap());
         }
 
         pub fn test_rmps() {
@@ -149,14 +373,40 @@ pub mod tests {
 
             assert_eq!(
                 vec![
-                    ("b".to_string(), vec![0x02]),
-                    ("ba".to_string(), vec![0x02, 0x01]),
-                    ("bb".to_string(), vec![0x02, 0x02]),
+                    ("b".to_string(), vec![0x02].into()),
+                    ("ba".to_string(), vec![0x02, 0x01].into()),
+                    ("bb".to_string(), vec![0x02, 0x02].into()),
                 ],
                 s.iter_prefix("b")
-                    .collect::<Result<Vec<(String, Vec<u8>)>, Error>>()
+                    .collect::<Result<Vec<(String, Bytes)>, Error>>()
                     .unwrap()
             )
         }
+
+        pub fn test_scan_prefix(&self) {
+            let mut s = self.setup();
+            for key in &["a", "b", "ba", "bb", "c"] {
+                s.set(key, key.as_bytes().to_vec()).unwrap();
+            }
+            let scan = |prefix, direction, limit| {
+                s.scan_prefix(prefix, direction, limit)
+                    .map(|r| r.map(|(k, _)| k))
+                    .collect::<Result<Vec<String>, Error>>()
+                    .unwrap()
+            };
+
+            assert_eq!(vec!["bb", "ba", "b"], scan("b", Direction::Reverse, None));
+            assert_eq!(vec!["b", "ba"], scan("b", Direction::Forward, Some(2)));
+            assert_eq!(vec!["bb", "ba"], scan("b", Direction::Reverse, Some(2)));
+            assert_eq!(vec!["c"], scan("", Direction::Reverse, Some(1)));
+            assert_eq!(Vec::<String>::new(), scan("b", Direction::Forward, Some(0)));
+            assert_eq!(Vec::<String>::new(), scan("d", Direction::Reverse, Some(1)));
+            assert_eq!(
+                vec![("bb".to_string(), Bytes::from_static(b"bb"))],
+                s.scan_prefix("b", Direction::Reverse, Some(1))
+                    .collect::<Result<Vec<_>, Error>>()
+                    .unwrap()
+            );
+        }
     }
 }
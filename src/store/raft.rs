@@ -1,13 +1,49 @@
-use super::{Iter, KVPair, Range, Store};
+use super::{Bytes, Direction, Iter, KVPair, Range, Seq, Store, WriteBatch};
+use crate::clock::{Clock, Timestamp};
 use crate::raft;
-use crate::serializer::{deserialize, serialize};
+use crate::serializer::wire::{deserialize, serialize};
 use crate::Error;
 use serde_derive::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// The command kind of key-value state machine commands, see raft::Envelope.
+pub const KV_COMMAND: &str = "kv";
+/// The command schema version written by this binary.
+pub const KV_COMMAND_VERSION: u32 = 1;
+
+/// Encodes a key-value state machine command in an envelope.
+fn encode_command<V: serde::Serialize>(command: V) -> Result<Vec<u8>, Error> {
+    raft::Envelope::new(KV_COMMAND, KV_COMMAND_VERSION, serialize(command)?).encode()
+}
+
+/// Decodes a key-value state machine command, or returns None if it's an
+/// envelope of another kind or a newer version, which should be skipped.
+/// Commands without an envelope were written by older nodes, and are decoded
+/// as-is.
+fn decode_command<V: serde::de::DeserializeOwned>(command: &[u8]) -> Result<Option<V>, Error> {
+    match raft::Envelope::decode(command) {
+        Some(envelope) if envelope.is_supported(KV_COMMAND, KV_COMMAND_VERSION) => {
+            Ok(Some(deserialize(&envelope.payload)?))
+        }
+        Some(envelope) => {
+            warn!(
+                "Skipping unsupported command kind {} version {}",
+                envelope.kind, envelope.version
+            );
+            Ok(None)
+        }
+        None => Ok(Some(deserialize(command)?)),
+    }
+}
 
 /// A Raft-backed key-value store. The underlying Raft state machine must be
 /// generated from Raft::new_state().
 pub struct Raft {
     raft: raft::Raft,
+    /// The clock used to timestamp mutations, if any.
+    clock: Option<Arc<Clock>>,
+    /// The consistency of reads.
+    consistency: raft::ReadConsistency,
 }
 
 impl std::fmt::Debug for Raft {
@@ -19,39 +55,178 @@ impl std::fmt::Debug for Raft {
 impl Raft {
     /// Creates a new key-value store around a Raft cluster.
     pub fn new(raft: raft::Raft) -> Self {
-        Self { raft }
+        Self {
+            raft,
+            clock: None,
+            consistency: raft::ReadConsistency::Strong,
+        }
+    }
+
+    /// Timestamps mutations with the given hybrid logical clock.
+    pub fn clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Reads at the given consistency, strong by default. Mutations are not
+    /// affected.
+    pub fn consistency(mut self, consistency: raft::ReadConsistency) -> Self {
+        self.consistency = consistency;
+        self
+    }
+
+    /// Submits a mutation, timestamped if a clock is set, returning its result.
+    fn mutate(&self, mutation: Mutation) -> Result<Vec<u8>, Error> {
+        let mutation = match &self.clock {
+            Some(clock) => Mutation::Timestamped(clock.now()?, Box::new(mutation)),
+            None => mutation,
+        };
+        self.raft.mutate(encode_command(mutation)?)
     }
 
     /// Creates an underlying Raft state machine, which is itself a key-value store.
     pub fn new_state<S: Store>(store: S) -> State {
         State::new(store)
     }
+
+    /// Runs a state machine consistency check. Every replica computes a
+    /// checksum of its state machine at the same point in the log, and then
+    /// verifies it against the checksum computed by the leader.
+    pub fn checksum(&self) -> Result<(), Error> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let checksum = deserialize(
+            &self
+                .raft
+                .mutate(encode_command(Mutation::Checksum(id.clone()))?)?,
+        )?;
+        self.mutate(Mutation::VerifyChecksum(id, checksum))?;
+        Ok(())
+    }
+
+    /// Fetches the current state machine version.
+    pub fn version(&self) -> Result<Version, Error> {
+        deserialize(&self.raft.read(encode_command(Read::Version)?)?)
+    }
+
+    /// Describes a Raft log command human-readably, for debugging tools.
+    pub fn describe_command(command: &[u8]) -> String {
+        let envelope = match raft::Envelope::decode(command) {
+            Some(envelope) => envelope,
+            None => match deserialize::<Mutation>(command) {
+                Ok(mutation) => return format!("{:?} (no envelope)", mutation),
+                Err(err) => return format!("{} bytes, undecodable: {}", command.len(), err),
+            },
+        };
+        let mutation = if envelope.is_supported(KV_COMMAND, KV_COMMAND_VERSION) {
+            deserialize::<Mutation>(&envelope.payload).ok()
+        } else {
+            None
+        };
+        match mutation {
+            Some(mutation) => format!("{} v{}: {:?}", envelope.kind, envelope.version, mutation),
+            None => format!(
+                "{} v{}: {} bytes",
+                envelope.kind,
+                envelope.version,
+                envelope.payload.len()
+            ),
+        }
+    }
+}
+
+/// A state machine version, which changes whenever the state machine is
+/// mutated. It consists of a random epoch generated when the state machine is
+/// started, and the number of mutations applied since.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Version {
+    epoch: String,
+    applied: u64,
+}
+
+impl Version {
+    /// Creates a new version
+    pub fn new(epoch: &str, applied: u64) -> Self {
+        Self {
+            epoch: epoch.to_string(),
+            applied,
+        }
+    }
 }
 
 impl Store for Raft {
+    fn cas(&mut self, key: &str, expected: Option<&[u8]>, value: Vec<u8>) -> Result<bool, Error> {
+        deserialize(&self.mutate(Mutation::CompareAndSwap(
+            key.to_string(),
+            expected.map(|e| e.to_vec()),
+            value,
+        ))?)
+    }
+
     fn delete(&mut self, key: &str) -> Result<(), Error> {
-        self.raft
-            .mutate(serialize(Mutation::Delete(key.to_string()))?)?;
+        self.mutate(Mutation::Delete(key.to_string()))?;
         Ok(())
     }
 
-    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
-        Ok(deserialize(
-            self.raft.read(serialize(Read::Get(key.to_string()))?)?,
-        )?)
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        let value: Option<Vec<u8>> = deserialize(&self.raft.read_with(
+            encode_command(Read::Get(key.to_string()))?,
+            self.consistency,
+        )?)?;
+        Ok(value.map(Bytes::from))
     }
 
     fn set(&mut self, key: &str, value: Vec<u8>) -> Result<(), Error> {
-        self.raft
-            .mutate(serialize(Mutation::Set(key.to_string(), value))?)?;
+        self.mutate(Mutation::Set(key.to_string(), value))?;
+        Ok(())
+    }
+
+    /// Submits the batch as a single Raft command, applied atomically.
+    /// Single writes use plain mutations, for compatibility with older nodes.
+    fn write_batch(&mut self, mut batch: WriteBatch) -> Result<(), Error> {
+        let mutation = match batch.len() {
+            1 => match batch.remove(0) {
+                (key, Some(value)) => Mutation::Set(key, value),
+                (key, None) => Mutation::Delete(key),
+            },
+            _ => Mutation::WriteBatch(batch),
+        };
+        self.mutate(mutation)?;
         Ok(())
     }
 
-    fn iter_prefix(&self, prefix: &str) -> Box<Range> {
-        let command = serialize(Read::NaiveLowerBound(prefix.into())).unwrap();
-        let data = self.raft.read(command).unwrap();
-        let items: Vec<KVPair> = deserialize(data).unwrap();
-        Box::new(Iter::from_vec(items))
+    /// Submits the batch as a single Raft command, checked and applied
+    /// atomically by the state machine. Single inserts use cas mutations, for
+    /// compatibility with older nodes.
+    fn write_batch_if_absent(
+        &mut self,
+        batch: WriteBatch,
+        absent: &[String],
+    ) -> Result<Vec<String>, Error> {
+        if let [(key, Some(value))] = &batch[..] {
+            if absent.len() == 1 && &absent[0] == key {
+                let swapped = self.cas(key, None, value.clone())?;
+                return Ok(if swapped { vec![] } else { vec![key.clone()] });
+            }
+        }
+        deserialize(&self.mutate(Mutation::WriteBatchIfAbsent(batch, absent.to_vec()))?)
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        // Plain scans use the original read, for compatibility with older nodes.
+        let read = match (direction, limit) {
+            (Direction::Forward, None) => Read::NaiveLowerBound(prefix.into()),
+            (direction, limit) => Read::Scan(prefix.into(), direction, limit),
+        };
+        let command = encode_command(read).unwrap();
+        let data = self.raft.read_with(command, self.consistency).unwrap();
+        let items: Vec<(String, Vec<u8>)> = deserialize(&data).unwrap();
+        Box::new(Iter::from_vec(
+            items.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        ))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Err(Error::Value("Raft stores don't support snapshots".into()))
     }
 }
 
@@ -62,6 +237,19 @@ enum Mutation {
     Delete(String),
     /// Sets a key to a value
     Set(String, Vec<u8>),
+    /// A mutation with the hybrid logical clock timestamp of its submitter
+    Timestamped(Timestamp, Box<Mutation>),
+    /// Computes a state machine checksum with the given ID, returning it
+    Checksum(String),
+    /// Verifies a previously computed checksum against the leader's checksum
+    VerifyChecksum(String, u64),
+    /// Sets a key to a value if it has the expected value, returning whether it was set
+    CompareAndSwap(String, Option<Vec<u8>>, Vec<u8>),
+    /// Applies a batch of sets and deletes
+    WriteBatch(WriteBatch),
+    /// Applies a batch of sets and deletes if none of the given keys exist,
+    /// returning the ones which do
+    WriteBatchIfAbsent(WriteBatch, Vec<String>),
 }
 
 /// A state machine read
@@ -71,11 +259,36 @@ enum Read {
     Get(String),
     /// Fetches a naive lower bound impl with IterPrefix
     NaiveLowerBound(String),
+    /// Fetches the state machine version
+    Version,
+    /// Fetches pairs under a prefix in the given direction, up to a limit
+    Scan(String, Direction, Option<usize>),
 }
 
+/// The results of state machine consistency checks, see Raft::checksum().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChecksumStatus {
+    /// The number of checksums matching the leader's.
+    pub verified: u64,
+    /// The number of checksums not matching the leader's.
+    pub mismatches: u64,
+}
+
+/// A callback for the keys of applied writes
+type WriteHook = Box<dyn Fn(&str) + Send + Sync>;
+
 /// The underlying state machine for the store
 pub struct State {
     store: Box<dyn Store>,
+    version: Version,
+    /// The clock to update with mutation timestamps, if any.
+    clock: Option<Arc<Clock>>,
+    /// The ID and value of the last computed checksum, awaiting verification.
+    checksum: Option<(String, u64)>,
+    /// The results of consistency checks.
+    checksums: Arc<Mutex<ChecksumStatus>>,
+    /// Called with the key of every applied write, see on_write().
+    on_write: Option<WriteHook>,
 }
 
 impl std::fmt::Debug for State {
@@ -89,17 +302,152 @@ impl State {
     pub fn new<S: Store>(store: S) -> Self {
         State {
             store: Box::new(store),
+            version: Version::new(&uuid::Uuid::new_v4().to_string(), 0),
+            clock: None,
+            checksum: None,
+            checksums: Arc::new(Mutex::new(ChecksumStatus::default())),
+            on_write: None,
+        }
+    }
+
+    /// Calls the given hook with the key of every applied write, e.g. to
+    /// invalidate caches of state machine contents on all nodes.
+    pub fn on_write<F: Fn(&str) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_write = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs the write hook for a key, if any
+    fn written(&self, key: &str) {
+        if let Some(hook) = &self.on_write {
+            hook(key)
+        }
+    }
+
+    /// Returns the results of consistency checks, updated as checks are applied.
+    pub fn checksums(&self) -> Arc<Mutex<ChecksumStatus>> {
+        self.checksums.clone()
+    }
+
+    /// Computes a checksum of the state machine contents, using 64-bit FNV-1a
+    /// over length-prefixed keys and values in key order.
+    fn compute_checksum(&self) -> Result<u64, Error> {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for pair in self.store.iter_prefix("") {
+            let (key, value) = pair?;
+            for bytes in &[key.as_bytes(), &value] {
+                let len = (bytes.len() as u64).to_be_bytes();
+                for byte in len.iter().chain(bytes.iter()) {
+                    hash ^= *byte as u64;
+                    hash = hash.wrapping_mul(0x0100_0000_01b3);
+                }
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Updates the given hybrid logical clock with the timestamps of applied
+    /// mutations, such that it stays ahead of all writes it has seen.
+    pub fn clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Applies a mutation.
+    fn apply(&mut self, mutation: Mutation) -> Result<Vec<u8>, Error> {
+        match mutation {
+            Mutation::Delete(key) => {
+                info!("Deleting {}", key);
+                self.store.delete(&key)?;
+                self.written(&key);
+                Ok(vec![])
+            }
+            Mutation::Set(key, value) => {
+                info!("Setting {} to {:?}", key, value);
+                self.store.set(&key, value)?;
+                self.written(&key);
+                Ok(vec![])
+            }
+            Mutation::CompareAndSwap(key, expected, value) => {
+                info!("Swapping {} from {:?} to {:?}", key, expected, value);
+                let swapped = self.store.cas(&key, expected.as_deref(), value)?;
+                if swapped {
+                    self.written(&key);
+                }
+                Ok(serialize(swapped)?)
+            }
+            Mutation::WriteBatch(batch) => {
+                info!("Writing batch of {} keys", batch.len());
+                let keys: Vec<String> = batch.iter().map(|(key, _)| key.clone()).collect();
+                self.store.write_batch(batch)?;
+                for key in keys {
+                    self.written(&key);
+                }
+                Ok(vec![])
+            }
+            Mutation::WriteBatchIfAbsent(batch, absent) => {
+                info!(
+                    "Writing batch of {} keys if {} keys are absent",
+                    batch.len(),
+                    absent.len()
+                );
+                let keys: Vec<String> = batch.iter().map(|(key, _)| key.clone()).collect();
+                let existing = self.store.write_batch_if_absent(batch, &absent)?;
+                if existing.is_empty() {
+                    for key in keys {
+                        self.written(&key);
+                    }
+                }
+                Ok(serialize(existing)?)
+            }
+            Mutation::Timestamped(timestamp, mutation) => {
+                if let Some(clock) = &self.clock {
+                    clock.update(timestamp)?;
+                }
+                self.apply(*mutation)
+            }
+            Mutation::Checksum(id) => {
+                let checksum = self.compute_checksum()?;
+                debug!("Computed state machine checksum {} as {:x}", id, checksum);
+                self.checksum = Some((id, checksum));
+                Ok(serialize(checksum)?)
+            }
+            Mutation::VerifyChecksum(id, expect) => {
+                match self.checksum.take() {
+                    Some((computed_id, checksum)) if computed_id == id => {
+                        let mut checksums = self.checksums.lock()?;
+                        if checksum == expect {
+                            checksums.verified += 1;
+                        } else {
+                            checksums.mismatches += 1;
+                            error!(
+                                "State machine checksum {} mismatch: computed {:x}, leader computed {:x}",
+                                id, checksum, expect
+                            );
+                        }
+                    }
+                    // E.g. if the node restarted since computing the checksum.
+                    _ => debug!("Skipping verification of unknown checksum {}", id),
+                }
+                Ok(vec![])
+            }
         }
     }
 }
 
+/// Prepares key/value pairs for serialization, with values as sequences.
+fn encode_pairs(pairs: &[KVPair]) -> Vec<(&str, Seq<'_>)> {
+    pairs.iter().map(|(k, v)| (k.as_str(), Seq(v))).collect()
+}
+
 impl raft::State for State {
     fn read(&self, command: Vec<u8>) -> Result<Vec<u8>, Error> {
-        let read: Read = deserialize(command)?;
+        let read: Read = decode_command(&command)?
+            .ok_or_else(|| Error::Value("Unsupported read command".into()))?;
         match read {
             Read::Get(key) => {
                 info!("Getting {}", key);
-                Ok(serialize(self.store.get(&key)?)?)
+                Ok(serialize(self.store.get(&key)?.as_deref().map(Seq))?)
             }
             Read::NaiveLowerBound(prefix) => {
                 info!("raft> naive lower bound");
@@ -107,24 +455,195 @@ impl raft::State for State {
                     .store
                     .iter_prefix(&prefix)
                     .collect::<Result<_, Error>>()?;
-                Ok(serialize(pairs)?)
+                Ok(serialize(encode_pairs(&pairs))?)
+            }
+            Read::Version => Ok(serialize(&self.version)?),
+            Read::Scan(prefix, direction, limit) => {
+                let pairs: Vec<KVPair> = self
+                    .store
+                    .scan_prefix(&prefix, direction, limit)
+                    .collect::<Result<_, Error>>()?;
+                Ok(serialize(encode_pairs(&pairs))?)
             }
         }
     }
 
     fn mutate(&mut self, command: Vec<u8>) -> Result<Vec<u8>, Error> {
-        let mutation: Mutation = deserialize(command)?;
-        match mutation {
-            Mutation::Delete(key) => {
-                info!("Deleting {}", key);
-                self.store.delete(&key)?;
-                Ok(vec![])
-            }
-            Mutation::Set(key, value) => {
-                info!("Setting {} to {:?}", key, value);
-                self.store.set(&key, value)?;
-                Ok(vec![])
-            }
+        // Unsupported commands are skipped, such that all nodes apply the
+        // rest of the log alike.
+        let mutation: Mutation = match decode_command(&command)? {
+            Some(mutation) => mutation,
+            None => return Ok(vec![]),
+        };
+        self.version.applied += 1;
+        self.apply(mutation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::KVMemory;
+    use super::*;
+    use crate::raft::State as _;
+
+    #[test]
+    fn mutate_timestamped() {
+        let clock = Arc::new(Clock::with_physical(|| 100));
+        let mut state = Raft::new_state(KVMemory::new()).clock(clock.clone());
+        let timestamp = Timestamp {
+            wall: 200,
+            logical: 7,
+        };
+        state
+            .mutate(
+                serialize(Mutation::Timestamped(
+                    timestamp,
+                    Box::new(Mutation::Set("a".into(), vec![0x01])),
+                ))
+                .unwrap(),
+            )
+            .unwrap();
+        state
+            .mutate(serialize(Mutation::Set("b".into(), vec![0x02])).unwrap())
+            .unwrap();
+        assert_eq!(Some(vec![0x01].into()), state.store.get("a").unwrap());
+        assert_eq!(Some(vec![0x02].into()), state.store.get("b").unwrap());
+        assert_eq!(2, state.version.applied);
+
+        // The clock moved past the applied mutation's timestamp
+        assert!(clock.now().unwrap() > timestamp);
+    }
+
+    #[test]
+    fn mutate_envelope() {
+        let mut state = Raft::new_state(KVMemory::new());
+        let set = encode_command(Mutation::Set("a".into(), vec![0x01])).unwrap();
+        state.mutate(set.clone()).unwrap();
+        assert_eq!(Some(vec![0x01].into()), state.store.get("a").unwrap());
+        assert_eq!(r#"kv v1: Set("a", [1])"#, Raft::describe_command(&set));
+
+        // Commands without an envelope are still applied
+        let plain = serialize(Mutation::Set("b".into(), vec![0x02])).unwrap();
+        state.mutate(plain.clone()).unwrap();
+        assert_eq!(Some(vec![0x02].into()), state.store.get("b").unwrap());
+        assert_eq!(
+            r#"Set("b", [2]) (no envelope)"#,
+            Raft::describe_command(&plain)
+        );
+        assert_eq!(2, state.version.applied);
+
+        // Other kinds and newer versions are skipped
+        for envelope in [
+            raft::Envelope::new("other", 1, vec![0x01]),
+            raft::Envelope::new(KV_COMMAND, KV_COMMAND_VERSION + 1, set),
+        ] {
+            let command = envelope.encode().unwrap();
+            assert_eq!(Vec::<u8>::new(), state.mutate(command.clone()).unwrap());
+            assert_matches!(state.read(command), Err(Error::Value(_)));
         }
+        assert_eq!(2, state.version.applied);
+        assert_eq!(
+            "other v1: 1 bytes",
+            Raft::describe_command(
+                &raft::Envelope::new("other", 1, vec![0x01])
+                    .encode()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn mutate_cas() {
+        let mut state = Raft::new_state(KVMemory::new());
+        let mut cas = |expected: Option<Vec<u8>>, value| {
+            let mutation = Mutation::CompareAndSwap("a".into(), expected, value);
+            deserialize::<bool>(&state.mutate(serialize(mutation).unwrap()).unwrap()).unwrap()
+        };
+        assert!(cas(None, vec![0x01]));
+        assert!(!cas(None, vec![0x02]));
+        assert!(cas(Some(vec![0x01]), vec![0x02]));
+        assert!(!cas(Some(vec![0x01]), vec![0x03]));
+        assert_eq!(Some(vec![0x02].into()), state.store.get("a").unwrap());
+    }
+
+    #[test]
+    fn mutate_on_write() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let hook = written.clone();
+        let mut state = Raft::new_state(KVMemory::new())
+            .on_write(move |key| hook.lock().unwrap().push(key.to_string()));
+        for mutation in [
+            Mutation::Set("a".into(), vec![0x01]),
+            Mutation::CompareAndSwap("b".into(), Some(vec![0x01]), vec![0x02]),
+            Mutation::CompareAndSwap("c".into(), None, vec![0x01]),
+            Mutation::Delete("a".into()),
+            Mutation::WriteBatch(vec![("d".into(), Some(vec![0x01])), ("c".into(), None)]),
+            Mutation::WriteBatchIfAbsent(vec![("e".into(), Some(vec![0x01]))], vec!["d".into()]),
+            Mutation::WriteBatchIfAbsent(vec![("f".into(), Some(vec![0x01]))], vec!["f".into()]),
+        ] {
+            state.mutate(serialize(mutation).unwrap()).unwrap();
+        }
+        assert_eq!(vec!["a", "c", "a", "d", "c", "f"], *written.lock().unwrap());
+        assert_eq!(Some(vec![0x01].into()), state.store.get("d").unwrap());
+        assert_eq!(None, state.store.get("c").unwrap());
+    }
+
+    #[test]
+    fn read_scan() {
+        let mut state = Raft::new_state(KVMemory::new());
+        for key in &["a", "b", "c"] {
+            state.store.set(key, vec![0x01]).unwrap();
+        }
+        let read = |read| {
+            deserialize::<Vec<(String, Vec<u8>)>>(&state.read(serialize(read).unwrap()).unwrap())
+        };
+        let keys =
+            |pairs: Vec<(String, Vec<u8>)>| pairs.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+
+        assert_eq!(
+            vec!["a", "b", "c"],
+            keys(read(Read::NaiveLowerBound("".into())).unwrap())
+        );
+        assert_eq!(
+            vec!["c", "b"],
+            keys(read(Read::Scan("".into(), Direction::Reverse, Some(2))).unwrap())
+        );
+    }
+
+    #[test]
+    fn mutate_checksum() {
+        let mut state = Raft::new_state(KVMemory::new());
+        let mut mutate = |mutation| state.mutate(serialize(mutation).unwrap()).unwrap();
+        let checksum = |response: Vec<u8>| deserialize::<u64>(&response).unwrap();
+
+        let empty = checksum(mutate(Mutation::Checksum("a".into())));
+        mutate(Mutation::Set("a".into(), vec![0x01]));
+        let a = checksum(mutate(Mutation::Checksum("b".into())));
+        assert_ne!(empty, a);
+        mutate(Mutation::VerifyChecksum("b".into(), a));
+        mutate(Mutation::Set("a".into(), vec![0x02]));
+        let b = checksum(mutate(Mutation::Checksum("c".into())));
+        assert_ne!(a, b);
+        mutate(Mutation::VerifyChecksum("c".into(), a));
+
+        // Unknown checksums are skipped
+        mutate(Mutation::VerifyChecksum("c".into(), a));
+        mutate(Mutation::VerifyChecksum("x".into(), a));
+
+        // Keys and values don't run together
+        let mut other = Raft::new_state(KVMemory::new());
+        other.store.set("a", vec![0x02]).unwrap();
+        assert_eq!(b, other.compute_checksum().unwrap());
+        other.store.delete("a").unwrap();
+        other.store.set("a\u{2}", vec![]).unwrap();
+        assert_ne!(b, other.compute_checksum().unwrap());
+
+        assert_eq!(
+            ChecksumStatus {
+                verified: 1,
+                mismatches: 1
+            },
+            *state.checksums.lock().unwrap()
+        );
     }
 }
@@ -0,0 +1,52 @@
+use super::{Bytes, Direction, Iter, Range, Store};
+use crate::Error;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A read-only, point-in-time view of an in-memory dataset, sharing the data
+/// with the store it was taken from. Stores copy the dataset on their next
+/// write while a snapshot still references it, so taking snapshots is cheap.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    data: Arc<BTreeMap<String, Bytes>>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot of a dataset
+    pub fn new(data: Arc<BTreeMap<String, Bytes>>) -> Self {
+        Self { data }
+    }
+}
+
+/// Returns the error for writes to a snapshot
+fn read_only() -> Error {
+    Error::Value("Can't write to a store snapshot".into())
+}
+
+impl Store for Snapshot {
+    fn cas(&mut self, _: &str, _: Option<&[u8]>, _: Vec<u8>) -> Result<bool, Error> {
+        Err(read_only())
+    }
+
+    fn delete(&mut self, _: &str) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn set(&mut self, _: &str, _: Vec<u8>) -> Result<(), Error> {
+        Err(read_only())
+    }
+
+    fn scan_prefix(&self, prefix: &str, direction: Direction, limit: Option<usize>) -> Box<Range> {
+        let from = prefix.to_string();
+        let to = from.clone() + &std::char::MAX.to_string();
+        Box::new(Iter::scan(self.data.range(from..to), direction, limit))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn Store>, Error> {
+        Ok(Box::new(self.clone()))
+    }
+}
@@ -0,0 +1,11 @@
+-- DDL statements separated by semicolons are applied atomically
+CREATE TABLE movies (id INTEGER PRIMARY KEY); CREATE TABLE genres (id INTEGER PRIMARY KEY);
+SELECT * FROM movies;
+SELECT * FROM genres;
+
+DROP TABLE movies; CREATE TABLE genres (id INTEGER PRIMARY KEY);
+SELECT * FROM movies;
+
+DROP TABLE movies; INSERT INTO genres VALUES (1);
+CREATE TEMPORARY TABLE staging (id INTEGER PRIMARY KEY); CREATE TABLE staged (id INTEGER PRIMARY KEY);
+SELECT * FROM staged;
@@ -0,0 +1,15 @@
+-- Plan trees, one row per node
+CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL);
+INSERT INTO movies VALUES (1, 'Sicario'), (2, 'Stalker');
+
+EXPLAIN SELECT * FROM movies;
+EXPLAIN SELECT 1 AS one, 2 FROM movies;
+EXPLAIN SELECT 1 + 2;
+EXPLAIN INSERT INTO movies VALUES (3, 'Heat');
+EXPLAIN DROP TABLE movies;
+
+-- Without ANALYZE, statements aren't executed
+SELECT * FROM movies;
+
+EXPLAIN EXPLAIN SELECT 1;
+EXPLAIN;
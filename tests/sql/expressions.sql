@@ -0,0 +1,29 @@
+-- Literals
+SELECT NULL, TRUE, FALSE, 1, 3.14, 'Hi! 👋';
+SELECT 'Literal with ''quotes''';
+
+-- Arithmetic
+SELECT 1 + 2, 5 - 7, 3 * 4, 7 / 2, 7 % 3, 2 ^ 10, 4!;
+SELECT 1 + 2 * 3, (1 + 2) * 3, -2 ^ 2, 2 ^ 3 ^ 2, 2 ^ -1;
+SELECT 1.5 + 1, 7.0 / 2, -7 % 3;
+
+-- Logic and comparisons
+SELECT TRUE AND FALSE, TRUE OR FALSE, NOT TRUE;
+SELECT 1 = 1, 1 != 2, 1 < 2, 2 <= 2, 3 > 2, 3 >= 4;
+SELECT 1 = 1.0, 0.0 = -0.0, 0.0 / 0.0 = 0.0 / 0.0, 0.0 / 0.0 > 1e308;
+SELECT 9007199254740993 > 9007199254740992.0, 9007199254740992 = 9007199254740992.0;
+SELECT 'a' < 'b', 'a' = 'a', FALSE < TRUE, TRUE != TRUE;
+SELECT 'a' = 1;
+
+-- NULL handling
+SELECT NULL + 1, NULL = NULL, NOT NULL, -NULL;
+SELECT NULL AND FALSE, NULL AND TRUE, NULL OR TRUE, NULL OR FALSE;
+
+-- Errors
+SELECT 1 + 'a';
+SELECT TRUE + 1;
+SELECT 1 / 0, 1 % 0;
+SELECT 9223372036854775807 + 1;
+SELECT 21!;
+SELECT;
+SELECT *;
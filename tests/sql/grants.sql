@@ -0,0 +1,71 @@
+-- Users need table privileges, granted by administrators
+CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL);
+CREATE TABLE genres (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL);
+INSERT INTO movies VALUES (1, 'Sicario');
+GRANT SELECT ON movies TO alice;
+GRANT ALL ON genres TO bob;
+
+-- user: alice
+SELECT * FROM movies;
+INSERT INTO movies VALUES (2, 'Stalker');
+SELECT * FROM genres;
+SELECT * FROM missing;
+GRANT INSERT ON movies TO alice;
+CREATE TABLE mine (id INTEGER PRIMARY KEY);
+DROP TABLE movies;
+CREATE DATABASE mine;
+KILL 1;
+SELECT * FROM system.sessions;
+SELECT * FROM system.audit;
+
+-- Temporary tables need no privileges
+CREATE TEMPORARY TABLE scratch (id INTEGER PRIMARY KEY);
+INSERT INTO scratch VALUES (1);
+SELECT * FROM scratch;
+DROP TABLE scratch;
+
+-- user: bob
+INSERT INTO genres VALUES (1, 'Thriller');
+SELECT * FROM genres;
+SELECT * FROM movies;
+
+-- user:
+GRANT INSERT ON movies TO alice;
+REVOKE ALL ON genres FROM bob;
+
+-- user: alice
+INSERT INTO movies VALUES (2, 'Stalker');
+SELECT * FROM movies;
+
+-- user: bob
+SELECT * FROM genres;
+
+-- Privileges can't be granted on temporary tables, or missing tables
+-- user:
+CREATE TEMPORARY TABLE scratch (id INTEGER PRIMARY KEY);
+GRANT SELECT ON scratch TO alice;
+GRANT SELECT ON missing TO alice;
+
+-- Column privileges must cover every column, since statements read and write
+-- whole rows
+GRANT SELECT (id) ON movies TO carol;
+GRANT INSERT (id, title) ON movies TO carol;
+
+-- user: carol
+SELECT * FROM movies;
+INSERT INTO movies VALUES (3, 'Primer');
+
+-- user:
+GRANT SELECT (title) ON movies TO carol;
+REVOKE INSERT (title) ON movies FROM carol;
+
+-- user: carol
+SELECT * FROM movies;
+INSERT INTO movies VALUES (4, 'Heat');
+
+-- Only SELECT and INSERT can be granted, on existing columns
+-- user:
+GRANT SELECT (missing) ON movies TO carol;
+GRANT UPDATE ON movies TO carol;
+GRANT DELETE (title) ON movies TO carol;
+REVOKE UPDATE, DELETE ON movies FROM carol;
@@ -0,0 +1,16 @@
+-- Primary key conflicts
+CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL);
+INSERT INTO movies VALUES (1, 'Sicario'), (2, 'Stalker');
+
+INSERT INTO movies VALUES (3, 'Heat'), (1, 'Her');
+INSERT INTO movies VALUES (3, 'Heat'), (3, 'Her');
+SELECT * FROM movies;
+
+INSERT INTO movies VALUES (3, 'Heat'), (1, 'Her') ON CONFLICT DO NOTHING;
+SELECT * FROM movies;
+INSERT INTO movies VALUES (1, 'Her') ON CONFLICT;
+
+-- Values must match the columns
+INSERT INTO movies VALUES (4, 1);
+INSERT INTO movies VALUES (4);
+SELECT * FROM movies;
@@ -0,0 +1,8 @@
+-- Rows are keyed on their table name, so a table named schema would share
+-- the prefix of the table schemas
+CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL);
+INSERT INTO movies VALUES (1, 'Sicario');
+CREATE TABLE schema (id INTEGER PRIMARY KEY);
+DROP TABLE schema;
+CREATE TEMPORARY TABLE schema (id INTEGER PRIMARY KEY);
+SELECT * FROM movies;
@@ -0,0 +1,24 @@
+-- Creates, populates and drops tables
+CREATE TABLE movies (
+    id INTEGER PRIMARY KEY,
+    title VARCHAR NOT NULL,
+    released INTEGER,
+    rating FLOAT,
+    bluray BOOLEAN
+);
+INSERT INTO movies VALUES
+    (1, 'Stalker', 1979, 8.2, FALSE),
+    (2, 'Sicario', 2015, 7.6, TRUE),
+    (3, 'Primer', 2004, 6.9, NULL);
+SELECT * FROM movies;
+SELECT 'constant' AS c FROM movies;
+
+-- Errors
+CREATE TABLE movies (id INTEGER PRIMARY KEY);
+CREATE TABLE nokey (id INTEGER);
+INSERT INTO missing VALUES (1);
+SELECT * FROM missing;
+
+DROP TABLE movies;
+SELECT * FROM movies;
+DROP TABLE movies;
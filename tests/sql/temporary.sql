@@ -0,0 +1,17 @@
+-- Temporary tables live in the session, and shadow regular tables
+CREATE TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL);
+INSERT INTO movies VALUES (1, 'Sicario');
+
+CREATE TEMPORARY TABLE staging (id INTEGER PRIMARY KEY);
+INSERT INTO staging VALUES (1), (2);
+SELECT * FROM staging;
+
+CREATE TEMPORARY TABLE movies (id INTEGER PRIMARY KEY, title VARCHAR NOT NULL);
+SELECT * FROM movies;
+INSERT INTO movies VALUES (2, 'Stalker');
+SELECT * FROM movies;
+DROP TABLE movies;
+SELECT * FROM movies;
+
+CREATE TEMPORARY TABLE staging (id INTEGER PRIMARY KEY);
+CREATE TEMPORARY staging (id INTEGER PRIMARY KEY);
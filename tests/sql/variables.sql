@@ -0,0 +1,20 @@
+-- Session variables, with their defaults
+SHOW max_rows;
+SHOW statement_timeout;
+SHOW timezone;
+SHOW isolation_level;
+
+SET max_rows = 10 * 10;
+SHOW MAX_ROWS;
+SET TimeZone = 'Europe/Madrid';
+SHOW timezone;
+SET isolation_level = 'SERIALIZABLE';
+SHOW isolation_level;
+
+SET max_rows = -1;
+SET statement_timeout = 'soon';
+SET isolation_level = 'read committed';
+SET foo = 1;
+SHOW foo;
+SET max_rows;
+SHOW;